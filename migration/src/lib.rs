@@ -1,12 +1,32 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20220101_000001_create_table;
+mod m20241017_000001_add_album_columns;
+mod m20241020_000001_create_sync_runs;
+mod m20241021_000001_create_metadata_history;
+mod m20241022_000001_add_withdrawn_columns;
+mod m20241023_000001_create_artist_relations;
+mod m20241024_000001_create_artist_aliases;
+mod m20241025_000001_add_original_hash;
+mod m20241026_000001_add_audio_properties;
+mod m20241027_000001_add_loudness;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20220101_000001_create_table::Migration)]
+        vec![
+            Box::new(m20220101_000001_create_table::Migration),
+            Box::new(m20241017_000001_add_album_columns::Migration),
+            Box::new(m20241020_000001_create_sync_runs::Migration),
+            Box::new(m20241021_000001_create_metadata_history::Migration),
+            Box::new(m20241022_000001_add_withdrawn_columns::Migration),
+            Box::new(m20241023_000001_create_artist_relations::Migration),
+            Box::new(m20241024_000001_create_artist_aliases::Migration),
+            Box::new(m20241025_000001_add_original_hash::Migration),
+            Box::new(m20241026_000001_add_audio_properties::Migration),
+            Box::new(m20241027_000001_add_loudness::Migration),
+        ]
     }
 }
@@ -0,0 +1,46 @@
+use crate::sea_orm::{DatabaseBackend, Statement};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+        // アルバムの紹介文を保存するカラムを追加
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE albums ADD COLUMN intro TEXT NOT NULL DEFAULT ''",
+        ))
+        .await?;
+
+        // アルバムの収録曲のIDをJSONの配列で保存するカラムを追加
+        // 未取得の楽曲があってもトラック番号を計算できるようにするため
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE albums ADD COLUMN song_list TEXT NOT NULL DEFAULT '[]'",
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE albums DROP COLUMN song_list",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE albums DROP COLUMN intro",
+        ))
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use crate::sea_orm::{DatabaseBackend, Statement};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+        // sync_runsテーブルを作成
+        // 一覧表示用に件数を持ち、楽曲ごとの結果はreportにJSONで保存する
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"CREATE TABLE IF NOT EXISTS sync_runs (
+                     id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                     created_at TEXT NOT NULL,
+                     succeeded INTEGER NOT NULL,
+                     skipped INTEGER NOT NULL,
+                     failed INTEGER NOT NULL,
+                     bytes_downloaded INTEGER NOT NULL,
+                     elapsed_ms INTEGER NOT NULL,
+                     report TEXT NOT NULL
+            )",
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"DROP TABLE IF EXISTS sync_runs",
+        ))
+        .await?;
+
+        Ok(())
+    }
+}
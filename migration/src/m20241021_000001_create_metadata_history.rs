@@ -0,0 +1,48 @@
+use crate::sea_orm::{DatabaseBackend, Statement};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+        // metadata_historyテーブルを作成
+        // MSR側で変更された楽曲やアルバムのメタデータをフィールド単位で記録する
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"CREATE TABLE IF NOT EXISTS metadata_history (
+                     id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                     created_at TEXT NOT NULL,
+                     target TEXT NOT NULL,
+                     target_id INTEGER NOT NULL,
+                     field TEXT NOT NULL,
+                     old_value TEXT NOT NULL,
+                     new_value TEXT NOT NULL
+            )",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"CREATE INDEX IF NOT EXISTS idx_metadata_history_target
+                     ON metadata_history (target, target_id)",
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"DROP TABLE IF EXISTS metadata_history",
+        ))
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+use crate::sea_orm::{DatabaseBackend, Statement};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+        // MSRから取り下げられた楽曲を記録するカラムを追加
+        // 論理削除とは別に扱い、保存済みの音声データは残しておく
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs ADD COLUMN is_withdrawn BOOLEAN NOT NULL DEFAULT FALSE",
+        ))
+        .await?;
+
+        // MSRから取り下げられたアルバムを記録するカラムを追加
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE albums ADD COLUMN is_withdrawn BOOLEAN NOT NULL DEFAULT FALSE",
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE albums DROP COLUMN is_withdrawn",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs DROP COLUMN is_withdrawn",
+        ))
+        .await?;
+
+        Ok(())
+    }
+}
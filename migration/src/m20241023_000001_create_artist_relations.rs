@@ -0,0 +1,141 @@
+use crate::sea_orm::{DatabaseBackend, Statement};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+        // 同じ名前のアーティストが複数登録されていれば、IDが最小のものに寄せてから削除する
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"UPDATE songs SET artist_id = (
+                     SELECT MIN(duplicated.id) FROM artists
+                         INNER JOIN artists AS duplicated ON artists.name = duplicated.name
+                         WHERE artists.id = songs.artist_id
+                 )
+                 WHERE artist_id IN (SELECT id FROM artists)",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"UPDATE albums SET artist_id = (
+                     SELECT MIN(duplicated.id) FROM artists
+                         INNER JOIN artists AS duplicated ON artists.name = duplicated.name
+                         WHERE artists.id = albums.artist_id
+                 )
+                 WHERE artist_id IN (SELECT id FROM artists)",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"DELETE FROM artists WHERE id NOT IN (SELECT MIN(id) FROM artists GROUP BY name)",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"CREATE UNIQUE INDEX IF NOT EXISTS idx_artists_name ON artists (name)",
+        ))
+        .await?;
+
+        // song_artistsテーブルを作成
+        // positionはクレジットの順番で、0から始まる
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"CREATE TABLE IF NOT EXISTS song_artists (
+                     song_id INTEGER NOT NULL,
+                     artist_id INTEGER NOT NULL,
+                     position INTEGER NOT NULL,
+                     PRIMARY KEY (song_id, position),
+                     CONSTRAINT fk_song_id
+                         FOREIGN KEY (song_id)
+                         REFERENCES songs (id),
+                     CONSTRAINT fk_artist_id
+                         FOREIGN KEY (artist_id)
+                         REFERENCES artists (id)
+            )",
+        ))
+        .await?;
+
+        // album_artistsテーブルを作成
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"CREATE TABLE IF NOT EXISTS album_artists (
+                     album_id INTEGER NOT NULL,
+                     artist_id INTEGER NOT NULL,
+                     position INTEGER NOT NULL,
+                     PRIMARY KEY (album_id, position),
+                     CONSTRAINT fk_album_id
+                         FOREIGN KEY (album_id)
+                         REFERENCES albums (id),
+                     CONSTRAINT fk_artist_id
+                         FOREIGN KEY (artist_id)
+                         REFERENCES artists (id)
+            )",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"CREATE INDEX IF NOT EXISTS idx_song_artists_artist_id ON song_artists (artist_id)",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"CREATE INDEX IF NOT EXISTS idx_album_artists_artist_id ON album_artists (artist_id)",
+        ))
+        .await?;
+
+        // これまで保存できていた先頭のアーティストを移す
+        // アーティスト不明の場合は空の名前で登録していたので、それは移さない
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"INSERT OR IGNORE INTO song_artists (song_id, artist_id, position)
+                 SELECT songs.id, songs.artist_id, 0 FROM songs
+                     INNER JOIN artists ON songs.artist_id = artists.id
+                     WHERE artists.name != ''",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"INSERT OR IGNORE INTO album_artists (album_id, artist_id, position)
+                 SELECT albums.id, albums.artist_id, 0 FROM albums
+                     INNER JOIN artists ON albums.artist_id = artists.id
+                     WHERE artists.name != ''",
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"DROP TABLE IF EXISTS album_artists",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"DROP TABLE IF EXISTS song_artists",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"DROP INDEX IF EXISTS idx_artists_name",
+        ))
+        .await?;
+
+        Ok(())
+    }
+}
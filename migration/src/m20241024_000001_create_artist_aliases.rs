@@ -0,0 +1,48 @@
+use crate::sea_orm::{DatabaseBackend, Statement};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+        // artist_aliasesテーブルを作成
+        // MSRのクレジットの表記揺れを正式な名前のアーティストに読み替える。利用者が編集する。
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"CREATE TABLE IF NOT EXISTS artist_aliases (
+                     id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                     created_at TEXT NOT NULL,
+                     updated_at TEXT NOT NULL,
+                     alias TEXT NOT NULL,
+                     artist_id INTEGER NOT NULL,
+                     CONSTRAINT fk_artist_id
+                         FOREIGN KEY (artist_id)
+                         REFERENCES artists (id)
+            )",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"CREATE UNIQUE INDEX IF NOT EXISTS idx_artist_aliases_alias ON artist_aliases (alias)",
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"DROP TABLE IF EXISTS artist_aliases",
+        ))
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+use crate::sea_orm::{DatabaseBackend, Statement};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+        // 別のフォーマットから変換して保存した場合に、変換前のデータのハッシュ値を記録するカラムを追加
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs ADD COLUMN original_hash TEXT",
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs DROP COLUMN original_hash",
+        ))
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use crate::sea_orm::{DatabaseBackend, Statement};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+        // 音声データから読み取った再生時間や音質を記録するカラムを追加
+        // 読み取れなかった場合や、追加する前に保存した楽曲はNULLになる
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs ADD COLUMN duration_ms INTEGER",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs ADD COLUMN sample_rate INTEGER",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs ADD COLUMN channels INTEGER",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs ADD COLUMN bits_per_sample INTEGER",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs ADD COLUMN bitrate_kbps INTEGER",
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs DROP COLUMN bitrate_kbps",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs DROP COLUMN bits_per_sample",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs DROP COLUMN channels",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs DROP COLUMN sample_rate",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs DROP COLUMN duration_ms",
+        ))
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use crate::sea_orm::{DatabaseBackend, Statement};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+        // 楽曲とアルバム全体のラウドネスを記録するカラムを追加
+        // 解析するまでや、無音などで測れなかった場合はNULLになる
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs ADD COLUMN loudness_lufs REAL",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs ADD COLUMN true_peak REAL",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE albums ADD COLUMN loudness_lufs REAL",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE albums ADD COLUMN true_peak REAL",
+        ))
+        .await?;
+
+        // 測れなかった場合も解析し直さないよう、解析したかどうかを別に記録する
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs ADD COLUMN loudness_analyzed BOOLEAN NOT NULL DEFAULT false",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE albums ADD COLUMN loudness_analyzed BOOLEAN NOT NULL DEFAULT false",
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE albums DROP COLUMN loudness_analyzed",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs DROP COLUMN loudness_analyzed",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE albums DROP COLUMN true_peak",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE albums DROP COLUMN loudness_lufs",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs DROP COLUMN true_peak",
+        ))
+        .await?;
+
+        conn.execute(Statement::from_string(
+            DatabaseBackend::Sqlite,
+            r"ALTER TABLE songs DROP COLUMN loudness_lufs",
+        ))
+        .await?;
+
+        Ok(())
+    }
+}
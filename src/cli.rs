@@ -0,0 +1,556 @@
+pub mod view;
+
+use crate::domain::repository::artist_repository::{ProvideArtistRepository, UsesArtistRepository};
+use crate::domain::repository::metadata_history_repository::{
+    ProvideMetadataHistoryRepository, UsesMetadataHistoryRepository,
+};
+use crate::domain::repository::msr_repository::{ProvideMsrRepository, UsesMsrRepository};
+use crate::domain::repository::song_repository::{ProvideSongRepository, UsesSongRepository};
+use crate::domain::repository::sync_run_repository::{
+    ProvideSyncRunRepository, UsesSyncRunRepository,
+};
+use crate::domain::song::{AlbumId, SongId};
+use crate::errors::domain::DomainError;
+use crate::errors::infra::InfraError;
+use crate::errors::usecase::UsecaseError;
+use crate::errors::Error;
+use crate::kernel::config::KernelConfig;
+use crate::kernel::Kernel;
+use crate::usecase::add_new_song::{
+    fetch_and_save_songs, load_artist_resolver, UsesAddNewSongUseCase,
+};
+use crate::usecase::analyze_loudness::UsesAnalyzeLoudnessUseCase;
+use crate::usecase::reconcile::UsesReconcileUseCase;
+use crate::usecase::update_metadata::UsesUpdateMetadataUseCase;
+use anyhow::Result;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use view::{
+    album_line, artist_line, history_line, song_line, sync_run_line, AlbumView, ArtistView,
+    ExportView, LoudnessView, MetadataUpdateView, ReconcileView, SongView, SyncPlanView,
+    SyncReportView, SyncView, VerifyFailure, VerifyView,
+};
+
+/// `msr list runs`で表示する同期の記録の数
+const RECENT_SYNC_RUNS: u32 = 20;
+/// `msr list history`で表示するメタデータの変更の数
+const RECENT_METADATA_HISTORY: u32 = 100;
+
+/// 終了コード。sysexits.hに合わせている。
+pub mod status {
+    /// 原因が特定できないエラー
+    pub const FAILURE: u8 = 1;
+    /// MSRから取得したデータが不正
+    pub const DATA_ERROR: u8 = 65;
+    /// 指定された楽曲やアルバムが存在しない
+    pub const NOT_FOUND: u8 = 66;
+    /// データベースやファイルシステムのエラー
+    pub const IO_ERROR: u8 = 74;
+}
+
+/// Monster Siren Recordsの楽曲を同期・管理する
+#[derive(Debug, Parser)]
+#[command(name = "msr", version)]
+pub struct Cli {
+    /// 設定ファイルのパス
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// JSONで出力する
+    #[arg(long, global = true)]
+    pub json: bool,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// MSRの新しい楽曲を取得して保存する
+    Sync {
+        /// 音声データを取得せずに、同期の計画だけを表示する
+        #[arg(long)]
+        dry_run: bool,
+        /// 一部の楽曲の取得に失敗しても、残りの楽曲の取得を続ける
+        #[arg(long)]
+        keep_going: bool,
+        /// 同期に成功したら、続けてラウドネスを解析する
+        #[arg(long)]
+        analyze: bool,
+    },
+    /// MSRで変更された楽曲やアルバムのメタデータを反映する
+    Update,
+    /// MSRから消えた楽曲やアルバムを照合する
+    Reconcile {
+        /// 多くの楽曲やアルバムが消えていても中断せずに取り下げる
+        #[arg(long)]
+        force: bool,
+    },
+    /// 保存されている楽曲を表示する
+    Song { id: u32 },
+    /// 保存されているアルバムを表示する
+    Album { id: u32 },
+    /// 保存されている楽曲やアルバムの一覧を表示する
+    List {
+        target: ListTarget,
+        /// MSRから取り下げられた楽曲やアルバムを表示するか
+        #[arg(long, value_enum, default_value_t = WithdrawnFilter::Include)]
+        withdrawn: WithdrawnFilter,
+    },
+    /// MSRから取得して保存する
+    #[command(subcommand)]
+    Download(DownloadTarget),
+    /// 保存されている音声データが壊れていないか検証する
+    Verify,
+    /// 保存されている楽曲のラウドネスを解析し、ReplayGainのタグを書き込む
+    Analyze,
+    /// 保存されているメタデータを書き出す
+    Export,
+    /// アーティストの別名を管理する
+    #[command(subcommand)]
+    Artist(ArtistCommand),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ListTarget {
+    Songs,
+    Albums,
+    /// 過去の同期の記録
+    Runs,
+    /// メタデータの変更の履歴
+    History,
+    /// アーティストと別名
+    Artists,
+}
+
+/// 取り下げられた楽曲やアルバムの表示の仕方
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WithdrawnFilter {
+    /// 取り下げられたものも表示する
+    Include,
+    /// 取り下げられたものを表示しない
+    Exclude,
+    /// 取り下げられたものだけを表示する
+    Only,
+}
+
+impl WithdrawnFilter {
+    pub fn matches(self, withdrawn: bool) -> bool {
+        match self {
+            WithdrawnFilter::Include => true,
+            WithdrawnFilter::Exclude => !withdrawn,
+            WithdrawnFilter::Only => withdrawn,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ArtistCommand {
+    /// クレジットの`alias`を`name`のアーティストとして扱う
+    Alias { alias: String, name: String },
+    /// 別名を削除する
+    Unalias { alias: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DownloadTarget {
+    /// アルバムの楽曲をすべて取得する
+    Album { id: u32 },
+}
+
+/// コマンドを実行する
+pub async fn run(cli: Cli) -> Result<()> {
+    let config = KernelConfig::load(cli.config.as_deref())?;
+    let kernel = Kernel::try_new(config).await?;
+    let json = cli.json;
+
+    match cli.command {
+        Command::Sync { dry_run: true, .. } => {
+            let plan = kernel.plan_sync().await?;
+            print(json, &SyncPlanView::from(&plan))?;
+        }
+        Command::Sync {
+            dry_run: false,
+            keep_going,
+            analyze,
+        } => {
+            let report = kernel.sync_new_songs(keep_going).await?;
+            print(json, &SyncReportView::from(&report))?;
+            if !report.failed.is_empty() {
+                let err = if report.skipped.is_empty() {
+                    UsecaseError::PartialFailure {
+                        failed: report.failed.len(),
+                        total: report.succeeded.len() + report.failed.len(),
+                    }
+                } else {
+                    UsecaseError::SyncAborted {
+                        failed: report.failed.len(),
+                        skipped: report.skipped.len(),
+                    }
+                };
+                return Err(Error::from(err).into());
+            }
+            if analyze {
+                analyze_loudness(&kernel, json).await?;
+            }
+        }
+        Command::Update => {
+            let report = kernel.update_metadata().await?;
+            print(json, &MetadataUpdateView::from(&report))?;
+            if !report.failed.is_empty() {
+                let err = UsecaseError::UpdateIncomplete {
+                    failed: report.failed.len(),
+                    total: report.checked,
+                };
+                return Err(Error::from(err).into());
+            }
+        }
+        Command::Reconcile { force } => {
+            let report = kernel.reconcile(force).await?;
+            print(json, &ReconcileView::from(&report))?;
+        }
+        Command::Song { id } => {
+            let song_id = SongId::new(id)?;
+            let song = kernel
+                .provide_song_repository()
+                .get_song(song_id)
+                .await?
+                .ok_or(InfraError::SongNotFound { id: song_id })?;
+            print(json, &SongView::from(&song))?;
+        }
+        Command::Album { id } => {
+            let album_id = AlbumId::new(id)?;
+            let album = kernel
+                .provide_song_repository()
+                .get_album(album_id)
+                .await?
+                .ok_or(InfraError::AlbumNotFound { id: album_id })?;
+            print(json, &AlbumView::from(&album))?;
+        }
+        Command::List {
+            target: ListTarget::Songs,
+            withdrawn,
+        } => {
+            let songs = kernel.provide_song_repository().get_all_song().await?;
+            let views = songs
+                .iter()
+                .filter(|song| withdrawn.matches(song.withdrawn))
+                .map(SongView::from)
+                .collect::<Vec<_>>();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&views)?);
+            } else {
+                views.iter().for_each(|view| println!("{}", song_line(view)));
+            }
+        }
+        Command::List {
+            target: ListTarget::Runs,
+            ..
+        } => {
+            let runs = kernel
+                .provide_sync_run_repository()
+                .get_recent_sync_runs(RECENT_SYNC_RUNS)
+                .await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&runs)?);
+            } else {
+                runs.iter().for_each(|run| println!("{}", sync_run_line(run)));
+            }
+        }
+        Command::List {
+            target: ListTarget::History,
+            ..
+        } => {
+            let history = kernel
+                .provide_metadata_history_repository()
+                .get_recent_metadata_history(RECENT_METADATA_HISTORY)
+                .await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&history)?);
+            } else {
+                history.iter().for_each(|entry| println!("{}", history_line(entry)));
+            }
+        }
+        Command::List {
+            target: ListTarget::Artists,
+            ..
+        } => {
+            let artists = kernel.provide_artist_repository().get_all_artists().await?;
+            let views = artists.iter().map(ArtistView::from).collect::<Vec<_>>();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&views)?);
+            } else {
+                views.iter().for_each(|view| println!("{}", artist_line(view)));
+            }
+        }
+        Command::Artist(ArtistCommand::Alias { alias, name }) => {
+            kernel
+                .provide_artist_repository()
+                .save_alias(alias, name)
+                .await?;
+        }
+        Command::Artist(ArtistCommand::Unalias { alias }) => {
+            kernel.provide_artist_repository().delete_alias(alias).await?;
+        }
+        Command::List {
+            target: ListTarget::Albums,
+            withdrawn,
+        } => {
+            let albums = kernel.provide_song_repository().get_all_album().await?;
+            let views = albums
+                .iter()
+                .filter(|album| withdrawn.matches(album.withdrawn))
+                .map(AlbumView::from)
+                .collect::<Vec<_>>();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&views)?);
+            } else {
+                views.iter().for_each(|view| println!("{}", album_line(view)));
+            }
+        }
+        Command::Download(DownloadTarget::Album { id }) => {
+            let album_id = AlbumId::new(id)?;
+            let song_ids = kernel
+                .provide_msr_repository()
+                .fetch_album_detail(album_id)
+                .await?
+                .song_list
+                .into_iter()
+                .map(|song| song.id)
+                .collect::<Vec<_>>();
+            let resolver = load_artist_resolver(&kernel).await?;
+            fetch_and_save_songs(&kernel, &song_ids, &resolver).await?;
+            print(json, &SyncView { added: song_ids })?;
+        }
+        Command::Verify => {
+            let song_ids = kernel.provide_song_repository().get_all_songs_id().await?;
+            let mut failed = vec![];
+            for &id in song_ids.iter() {
+                let result = match kernel.provide_song_repository().get_song(id).await {
+                    Ok(Some(song)) => kernel
+                        .provide_song_repository()
+                        .load_audio(&song)
+                        .await
+                        .map(|_| ()),
+                    Ok(None) => Ok(()),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    failed.push(VerifyFailure {
+                        id,
+                        error: format!("{e:#}"),
+                    });
+                }
+            }
+            let view = VerifyView {
+                checked: song_ids.len(),
+                failed,
+            };
+            print(json, &view)?;
+            if !view.failed.is_empty() {
+                return Err(Error::from(UsecaseError::PartialFailure {
+                    failed: view.failed.len(),
+                    total: view.checked,
+                })
+                .into());
+            }
+        }
+        Command::Analyze => analyze_loudness(&kernel, json).await?,
+        Command::Export => {
+            let albums = kernel.provide_song_repository().get_all_album().await?;
+            let songs = kernel.provide_song_repository().get_all_song().await?;
+            let view = ExportView {
+                albums: albums.iter().map(AlbumView::from).collect(),
+                songs: songs.iter().map(SongView::from).collect(),
+            };
+            print(json, &view)?;
+        }
+    }
+    Ok(())
+}
+
+/// ラウドネスを解析して結果を出力する。解析できなかった楽曲があればエラーを返す。
+async fn analyze_loudness(kernel: &Kernel, json: bool) -> Result<()> {
+    let report = kernel.analyze_loudness().await?;
+    print(json, &LoudnessView::from(&report))?;
+    if !report.failed.is_empty() {
+        return Err(Error::from(UsecaseError::PartialFailure {
+            failed: report.failed.len(),
+            total: report.analyzed_songs.len()
+                + report.unmeasurable_songs.len()
+                + report.failed.len(),
+        })
+        .into());
+    }
+    Ok(())
+}
+
+/// `json`が`true`ならJSONで、そうでなければ[`std::fmt::Display`]で出力する
+fn print<T>(json: bool, view: &T) -> Result<()>
+where
+    T: serde::Serialize + std::fmt::Display,
+{
+    if json {
+        println!("{}", serde_json::to_string_pretty(view)?);
+    } else {
+        println!("{view}");
+    }
+    Ok(())
+}
+
+/// エラーに対応する終了コードを返す
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+    if let Some(e) = err.downcast_ref::<Error>() {
+        return error_exit_code(e);
+    }
+    if let Some(e) = err.downcast_ref::<InfraError>() {
+        return infra_exit_code(e);
+    }
+    if err.downcast_ref::<DomainError>().is_some() {
+        return status::DATA_ERROR;
+    }
+    status::FAILURE
+}
+
+fn error_exit_code(err: &Error) -> u8 {
+    match err {
+        Error::Domain { .. } => status::DATA_ERROR,
+        Error::Infra { source } => infra_exit_code(source),
+        Error::Usecase { source } => usecase_exit_code(source),
+    }
+}
+
+fn infra_exit_code(err: &InfraError) -> u8 {
+    match err {
+        InfraError::SongNotFound { .. } | InfraError::AlbumNotFound { .. } => status::NOT_FOUND,
+        _ => status::IO_ERROR,
+    }
+}
+
+fn usecase_exit_code(err: &UsecaseError) -> u8 {
+    match err {
+        UsecaseError::AlbumMissingSong { .. } | UsecaseError::ReconcileAborted { .. } => {
+            status::DATA_ERROR
+        }
+        UsecaseError::SyncAborted { .. }
+        | UsecaseError::PartialFailure { .. }
+        | UsecaseError::UpdateIncomplete { .. } => status::FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        exit_code, status, ArtistCommand, Cli, Command, DownloadTarget, ListTarget,
+        WithdrawnFilter,
+    };
+    use crate::domain::song::{AlbumId, SongId};
+    use crate::errors::domain::DomainError;
+    use crate::errors::infra::InfraError;
+    use crate::errors::usecase::UsecaseError;
+    use crate::errors::Error;
+    use clap::Parser;
+
+    #[test]
+    fn test_parse_args() {
+        let cli = Cli::try_parse_from(["msr", "--config", "msr.toml", "list", "songs", "--json"])
+            .unwrap();
+        assert!(cli.json);
+        assert!(matches!(
+            cli.command,
+            Command::List {
+                target: ListTarget::Songs,
+                withdrawn: WithdrawnFilter::Include
+            }
+        ));
+
+        let cli = Cli::try_parse_from(["msr", "list", "albums", "--withdrawn", "only"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::List {
+                target: ListTarget::Albums,
+                withdrawn: WithdrawnFilter::Only
+            }
+        ));
+
+        let cli = Cli::try_parse_from(["msr", "sync", "--dry-run"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Sync {
+                dry_run: true,
+                keep_going: false,
+                analyze: false
+            }
+        ));
+
+        let cli = Cli::try_parse_from(["msr", "sync", "--keep-going", "--analyze"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Sync {
+                dry_run: false,
+                keep_going: true,
+                analyze: true
+            }
+        ));
+
+        let cli = Cli::try_parse_from(["msr", "reconcile", "--force"]).unwrap();
+        assert!(matches!(cli.command, Command::Reconcile { force: true }));
+
+        let cli = Cli::try_parse_from(["msr", "analyze"]).unwrap();
+        assert!(matches!(cli.command, Command::Analyze));
+
+        let cli = Cli::try_parse_from([
+            "msr",
+            "artist",
+            "alias",
+            "Monster Siren Records",
+            "塞壬唱片-MSR",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Artist(ArtistCommand::Alias { alias, name })
+                if alias == "Monster Siren Records" && name == "塞壬唱片-MSR"
+        ));
+
+        let cli = Cli::try_parse_from(["msr", "download", "album", "249"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Download(DownloadTarget::Album { id: 249 })
+        ));
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let not_found: anyhow::Error = InfraError::SongNotFound {
+            id: SongId::new(1).unwrap(),
+        }
+        .into();
+        assert_eq!(exit_code(&not_found), status::NOT_FOUND);
+
+        let domain: anyhow::Error = Error::from(DomainError::FailedToCreateAudioRawData).into();
+        assert_eq!(exit_code(&domain), status::DATA_ERROR);
+
+        let missing: anyhow::Error = Error::from(UsecaseError::AlbumMissingSong {
+            song_id: SongId::new(1).unwrap(),
+            album_id: AlbumId::new(1).unwrap(),
+        })
+        .into();
+        assert_eq!(exit_code(&missing), status::DATA_ERROR);
+
+        let partial: anyhow::Error = Error::from(UsecaseError::PartialFailure {
+            failed: 1,
+            total: 2,
+        })
+        .into();
+        assert_eq!(exit_code(&partial), status::FAILURE);
+
+        let incomplete: anyhow::Error = Error::from(UsecaseError::UpdateIncomplete {
+            failed: 1,
+            total: 3,
+        })
+        .into();
+        assert_eq!(exit_code(&incomplete), status::FAILURE);
+
+        let other = anyhow::anyhow!("unknown");
+        assert_eq!(exit_code(&other), status::FAILURE);
+    }
+}
@@ -0,0 +1,551 @@
+use crate::domain::artist::{Artist, ArtistId};
+use crate::domain::loudness::Loudness;
+use crate::domain::metadata_history::{MetadataChange, MetadataHistory};
+use crate::domain::probe::AudioProperties;
+use crate::domain::song::{Album, AlbumId, Song, SongId};
+use crate::domain::sync_report::{PhaseTiming, SyncFailure, SyncReport, SyncRun};
+use crate::usecase::analyze_loudness::{LoudnessFailure, LoudnessReport};
+use crate::usecase::reconcile::{ReconcileReport, WithdrawnPolicy};
+use crate::usecase::sync_plan::SyncPlan;
+use crate::usecase::update_metadata::{MetadataUpdateFailure, MetadataUpdateReport};
+use itertools::Itertools;
+use std::fmt::{self, Display};
+
+/// CLIで表示する楽曲の情報
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct SongView {
+    pub id: SongId,
+    pub name: String,
+    pub album_id: AlbumId,
+    pub track_number: u8,
+    pub disk_number: u8,
+    pub format: String,
+    pub path: String,
+    pub artists: Vec<String>,
+    pub withdrawn: bool,
+    /// 再生時間や音質。読み取れていなければ`None`。
+    pub audio: Option<AudioProperties>,
+    /// ラウドネス。解析していなければ`None`。
+    pub loudness: Option<Loudness>,
+}
+
+impl From<&Song> for SongView {
+    fn from(song: &Song) -> Self {
+        Self {
+            id: song.id,
+            name: song.name.clone(),
+            album_id: song.belong_album_id,
+            track_number: song.track_number,
+            disk_number: song.disk_number,
+            format: song.source.format.to_string(),
+            path: song.source.save_path.to_string_lossy().into_owned(),
+            artists: song.artists.clone(),
+            withdrawn: song.withdrawn,
+            audio: song.source.properties,
+            loudness: song.source.loudness.measured(),
+        }
+    }
+}
+
+impl Display for SongView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "id:      {}", self.id)?;
+        writeln!(f, "name:    {}", self.name)?;
+        writeln!(f, "album:   {}", self.album_id)?;
+        writeln!(f, "track:   {} (disk {})", self.track_number, self.disk_number)?;
+        writeln!(f, "format:  {}", self.format)?;
+        if let Some(audio) = &self.audio {
+            writeln!(f, "length:  {}", duration(audio.duration_ms))?;
+            writeln!(f, "audio:   {}", audio_quality(audio))?;
+        }
+        if let Some(loudness) = &self.loudness {
+            writeln!(f, "gain:    {}", replay_gain(loudness))?;
+        }
+        writeln!(f, "artists: {}", self.artists.iter().join(", "))?;
+        if self.withdrawn {
+            writeln!(f, "status:  withdrawn")?;
+        }
+        write!(f, "path:    {}", self.path)
+    }
+}
+
+/// CLIで表示するアルバムの情報
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct AlbumView {
+    pub id: AlbumId,
+    pub name: String,
+    pub intro: String,
+    pub belong: String,
+    pub total_tracks: u8,
+    pub total_disks: u8,
+    pub artists: Vec<String>,
+    pub discs: Vec<Vec<SongId>>,
+    pub withdrawn: bool,
+    /// アルバム全体のラウドネス。解析していなければ`None`。
+    pub loudness: Option<Loudness>,
+}
+
+impl From<&Album> for AlbumView {
+    fn from(album: &Album) -> Self {
+        Self {
+            id: album.id,
+            name: album.name.clone(),
+            intro: album.intro.clone(),
+            belong: album.belong.to_string(),
+            total_tracks: album.total_tracks,
+            total_disks: album.total_disks,
+            artists: album.artists.clone(),
+            discs: album.discs.clone(),
+            withdrawn: album.withdrawn,
+            loudness: album.loudness.measured(),
+        }
+    }
+}
+
+impl Display for AlbumView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "id:      {}", self.id)?;
+        writeln!(f, "name:    {}", self.name)?;
+        writeln!(f, "belong:  {}", self.belong)?;
+        writeln!(f, "tracks:  {} (disks {})", self.total_tracks, self.total_disks)?;
+        writeln!(f, "artists: {}", self.artists.iter().join(", "))?;
+        for (ix, disc) in self.discs.iter().enumerate() {
+            writeln!(f, "disk {}:  {}", ix + 1, disc.iter().join(", "))?;
+        }
+        if let Some(loudness) = &self.loudness {
+            writeln!(f, "gain:    {}", replay_gain(loudness))?;
+        }
+        if self.withdrawn {
+            writeln!(f, "status:  withdrawn")?;
+        }
+        write!(f, "intro:   {}", self.intro)
+    }
+}
+
+/// `msr sync`の結果
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct SyncView {
+    pub added: Vec<SongId>,
+}
+
+impl Display for SyncView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.added.is_empty() {
+            return write!(f, "No new songs");
+        }
+        write!(
+            f,
+            "Added {} songs: {}",
+            self.added.len(),
+            self.added.iter().join(", ")
+        )
+    }
+}
+
+/// `msr sync`の結果
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct SyncReportView {
+    pub succeeded: Vec<SongId>,
+    pub skipped: Vec<SongId>,
+    pub failed: Vec<SyncFailure>,
+    pub phases: Vec<PhaseTiming>,
+    pub bytes_downloaded: u64,
+}
+
+impl From<&SyncReport> for SyncReportView {
+    fn from(report: &SyncReport) -> Self {
+        Self {
+            succeeded: report.succeeded.clone(),
+            skipped: report.skipped.clone(),
+            failed: report.failed.clone(),
+            phases: report.phases.clone(),
+            bytes_downloaded: report.bytes_downloaded,
+        }
+    }
+}
+
+impl Display for SyncReportView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for failure in self.failed.iter() {
+            writeln!(f, "NG {}: {}", failure.id, failure.errors.iter().join(": "))?;
+        }
+        for phase in self.phases.iter() {
+            writeln!(f, "{}: {} ms", phase.phase, phase.elapsed_ms)?;
+        }
+        write!(
+            f,
+            "Succeeded {}, skipped {}, failed {}, downloaded {} bytes",
+            self.succeeded.len(),
+            self.skipped.len(),
+            self.failed.len(),
+            self.bytes_downloaded
+        )
+    }
+}
+
+/// `msr sync --dry-run`の結果
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct SyncPlanView {
+    pub new_songs: Vec<SongId>,
+    pub changed_songs: Vec<SongId>,
+    pub removed_songs: Vec<SongId>,
+    pub touched_albums: Vec<AlbumId>,
+}
+
+impl From<&SyncPlan> for SyncPlanView {
+    fn from(plan: &SyncPlan) -> Self {
+        Self {
+            new_songs: plan.new_songs.clone(),
+            changed_songs: plan.changed_songs.clone(),
+            removed_songs: plan.removed_songs.clone(),
+            touched_albums: plan.touched_albums.clone(),
+        }
+    }
+}
+
+impl Display for SyncPlanView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "new:     {} {}",
+            self.new_songs.len(),
+            self.new_songs.iter().join(", ")
+        )?;
+        writeln!(
+            f,
+            "changed: {} {}",
+            self.changed_songs.len(),
+            self.changed_songs.iter().join(", ")
+        )?;
+        writeln!(
+            f,
+            "removed: {} {}",
+            self.removed_songs.len(),
+            self.removed_songs.iter().join(", ")
+        )?;
+        write!(
+            f,
+            "albums:  {} {}",
+            self.touched_albums.len(),
+            self.touched_albums.iter().join(", ")
+        )
+    }
+}
+
+/// `msr update`の結果
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct MetadataUpdateView {
+    pub changes: Vec<MetadataChange>,
+    pub failed: Vec<MetadataUpdateFailure>,
+}
+
+impl From<&MetadataUpdateReport> for MetadataUpdateView {
+    fn from(report: &MetadataUpdateReport) -> Self {
+        Self {
+            changes: report.changes.clone(),
+            failed: report.failed.clone(),
+        }
+    }
+}
+
+impl Display for MetadataUpdateView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in self.changes.iter() {
+            writeln!(f, "{}", change_line(change))?;
+        }
+        for failure in self.failed.iter() {
+            writeln!(
+                f,
+                "NG {} {}: {}",
+                failure.target,
+                failure.id,
+                failure.errors.iter().join(": ")
+            )?;
+        }
+        write!(
+            f,
+            "Updated {} fields, failed {}",
+            self.changes.len(),
+            self.failed.len()
+        )
+    }
+}
+
+/// `msr reconcile`の結果
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ReconcileView {
+    pub policy: WithdrawnPolicy,
+    pub withdrawn_songs: Vec<SongId>,
+    pub withdrawn_albums: Vec<AlbumId>,
+    pub restored_songs: Vec<SongId>,
+    pub restored_albums: Vec<AlbumId>,
+}
+
+impl From<&ReconcileReport> for ReconcileView {
+    fn from(report: &ReconcileReport) -> Self {
+        Self {
+            policy: report.policy,
+            withdrawn_songs: report.withdrawn_songs.clone(),
+            withdrawn_albums: report.withdrawn_albums.clone(),
+            restored_songs: report.restored_songs.clone(),
+            restored_albums: report.restored_albums.clone(),
+        }
+    }
+}
+
+impl Display for ReconcileView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "policy:           {}", self.policy)?;
+        writeln!(
+            f,
+            "withdrawn songs:  {} {}",
+            self.withdrawn_songs.len(),
+            self.withdrawn_songs.iter().join(", ")
+        )?;
+        writeln!(
+            f,
+            "withdrawn albums: {} {}",
+            self.withdrawn_albums.len(),
+            self.withdrawn_albums.iter().join(", ")
+        )?;
+        writeln!(
+            f,
+            "restored songs:   {} {}",
+            self.restored_songs.len(),
+            self.restored_songs.iter().join(", ")
+        )?;
+        write!(
+            f,
+            "restored albums:  {} {}",
+            self.restored_albums.len(),
+            self.restored_albums.iter().join(", ")
+        )
+    }
+}
+
+/// `msr analyze`の結果
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct LoudnessView {
+    pub analyzed_songs: Vec<SongId>,
+    pub analyzed_albums: Vec<AlbumId>,
+    pub pending_albums: Vec<AlbumId>,
+    pub unmeasurable_songs: Vec<SongId>,
+    pub failed: Vec<LoudnessFailure>,
+}
+
+impl From<&LoudnessReport> for LoudnessView {
+    fn from(report: &LoudnessReport) -> Self {
+        Self {
+            analyzed_songs: report.analyzed_songs.clone(),
+            analyzed_albums: report.analyzed_albums.clone(),
+            pending_albums: report.pending_albums.clone(),
+            unmeasurable_songs: report.unmeasurable_songs.clone(),
+            failed: report.failed.clone(),
+        }
+    }
+}
+
+impl Display for LoudnessView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for failure in self.failed.iter() {
+            writeln!(f, "NG {}: {}", failure.id, failure.error)?;
+        }
+        writeln!(
+            f,
+            "analyzed songs:  {} {}",
+            self.analyzed_songs.len(),
+            self.analyzed_songs.iter().join(", ")
+        )?;
+        writeln!(
+            f,
+            "analyzed albums: {} {}",
+            self.analyzed_albums.len(),
+            self.analyzed_albums.iter().join(", ")
+        )?;
+        writeln!(
+            f,
+            "pending albums:  {} {}",
+            self.pending_albums.len(),
+            self.pending_albums.iter().join(", ")
+        )?;
+        write!(
+            f,
+            "unmeasurable:    {} {}",
+            self.unmeasurable_songs.len(),
+            self.unmeasurable_songs.iter().join(", ")
+        )
+    }
+}
+
+/// `msr verify`で検証に失敗した楽曲
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct VerifyFailure {
+    pub id: SongId,
+    pub error: String,
+}
+
+/// `msr verify`の結果
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct VerifyView {
+    pub checked: usize,
+    pub failed: Vec<VerifyFailure>,
+}
+
+impl Display for VerifyView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for failure in self.failed.iter() {
+            writeln!(f, "NG {}: {}", failure.id, failure.error)?;
+        }
+        write!(
+            f,
+            "Checked {} songs, {} failed",
+            self.checked,
+            self.failed.len()
+        )
+    }
+}
+
+/// `msr export`の結果
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ExportView {
+    pub albums: Vec<AlbumView>,
+    pub songs: Vec<SongView>,
+}
+
+impl Display for ExportView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "album_id\talbum\tdisk\ttrack\tsong_id\tsong\tartists\tpath")?;
+        for song in self.songs.iter() {
+            let album_name = self
+                .albums
+                .iter()
+                .find(|album| album.id == song.album_id)
+                .map(|album| album.name.as_str())
+                .unwrap_or_default();
+            write!(
+                f,
+                "\n{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                song.album_id,
+                album_name,
+                song.disk_number,
+                song.track_number,
+                song.id,
+                song.name,
+                song.artists.iter().join(", "),
+                song.path
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// 一覧表示用の1行
+pub fn song_line(song: &SongView) -> String {
+    format!(
+        "{}\t{}\t{}\t{}{}",
+        song.id,
+        song.name,
+        song.artists.iter().join(", "),
+        song.audio.map_or("-".to_string(), |audio| duration(audio.duration_ms)),
+        withdrawn_marker(song.withdrawn)
+    )
+}
+
+/// 一覧表示用の1行
+pub fn sync_run_line(run: &SyncRun) -> String {
+    format!(
+        "{}\t{}\t{} succeeded\t{} skipped\t{} failed\t{} bytes\t{} ms",
+        run.id,
+        run.created_at,
+        run.report.succeeded.len(),
+        run.report.skipped.len(),
+        run.report.failed.len(),
+        run.report.bytes_downloaded,
+        run.report.elapsed_ms()
+    )
+}
+
+/// 一覧表示用の1行
+pub fn history_line(entry: &MetadataHistory) -> String {
+    format!("{}\t{}", entry.created_at, change_line(&entry.change))
+}
+
+fn change_line(change: &MetadataChange) -> String {
+    format!(
+        "{} {}\t{}: {} -> {}",
+        change.target, change.id, change.field, change.old_value, change.new_value
+    )
+}
+
+/// CLIで表示するアーティストの情報
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ArtistView {
+    pub id: ArtistId,
+    pub name: String,
+    pub aliases: Vec<String>,
+}
+
+impl From<&Artist> for ArtistView {
+    fn from(artist: &Artist) -> Self {
+        Self {
+            id: artist.id,
+            name: artist.name.clone(),
+            aliases: artist.aliases.clone(),
+        }
+    }
+}
+
+/// 一覧表示用の1行
+pub fn artist_line(artist: &ArtistView) -> String {
+    format!(
+        "{}\t{}\t{}",
+        artist.id,
+        artist.name,
+        artist.aliases.iter().join(", ")
+    )
+}
+
+/// 一覧表示用の1行
+pub fn album_line(album: &AlbumView) -> String {
+    format!(
+        "{}\t{}\t{}{}",
+        album.id,
+        album.name,
+        album.artists.iter().join(", "),
+        withdrawn_marker(album.withdrawn)
+    )
+}
+
+/// `分:秒`の形式にする
+fn duration(duration_ms: u32) -> String {
+    let secs = duration_ms / 1000;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// 例えば`48000 Hz, 24 bit, 2 ch, 2304 kbps (hi-res)`
+fn audio_quality(audio: &AudioProperties) -> String {
+    let mut parts = vec![format!("{} Hz", audio.sample_rate)];
+    parts.extend(audio.bits_per_sample.map(|bits| format!("{bits} bit")));
+    parts.push(format!("{} ch", audio.channels));
+    parts.push(format!("{} kbps", audio.bitrate_kbps));
+    let hi_res = if audio.is_hi_res() { " (hi-res)" } else { "" };
+    format!("{}{hi_res}", parts.join(", "))
+}
+
+/// 例えば`-6.52 dB (-11.48 LUFS, peak -0.10 dBTP)`
+fn replay_gain(loudness: &Loudness) -> String {
+    format!(
+        "{} ({:.2} LUFS, peak {:.2} dBTP)",
+        loudness.gain_tag(),
+        loudness.integrated_lufs,
+        loudness.true_peak_dbtp()
+    )
+}
+
+fn withdrawn_marker(withdrawn: bool) -> &'static str {
+    if withdrawn {
+        "\t(withdrawn)"
+    } else {
+        ""
+    }
+}
@@ -1,3 +1,11 @@
+pub mod artist;
+pub mod disc;
+pub mod loudness;
+pub mod metadata_history;
 pub mod msr;
+pub mod probe;
 pub mod repository;
 pub mod song;
+pub mod sync_report;
+pub mod tag;
+pub mod transcode;
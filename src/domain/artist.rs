@@ -0,0 +1,185 @@
+use crate::domain::song::Id;
+use indexmap::IndexSet;
+use std::collections::HashMap;
+
+pub type ArtistId = Id<Artist>;
+
+impl ArtistId {
+    /// DBが採番したIDから作成する
+    pub fn new(id: u32) -> Self {
+        Self::from_inner(id)
+    }
+}
+
+/// アーティスト。MSRのクレジットは表記が揺れるので、正式な名前と別名を持つ。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Artist {
+    pub id: ArtistId,
+    /// 正式な名前。保存する楽曲やアルバムのクレジットにはこの名前を使う。
+    pub name: String,
+    /// 正式な名前に読み替える別名
+    pub aliases: Vec<String>,
+}
+
+/// 連名のクレジットの区切り
+const CREDIT_SEPARATORS: &[&str] = &[
+    "/", "／", "、", "，", ",", "&", "＆", "×", " x ", " X ", " feat. ", " ft. ",
+];
+
+/// MSRのクレジットを正式な名前に揃える
+#[derive(Debug, Clone, Default)]
+pub struct ArtistResolver {
+    /// [`alias_key`]から正式な名前への対応
+    canonical_names: HashMap<String, String>,
+}
+
+impl ArtistResolver {
+    pub fn new(artists: &[Artist]) -> Self {
+        let mut canonical_names = HashMap::new();
+        for artist in artists {
+            canonical_names.insert(alias_key(&artist.name), artist.name.clone());
+        }
+        // 同期で見つかった表記はすべて正式な名前として登録されているので、
+        // 別の正式な名前の別名として登録された表記は別名の方を優先する
+        for artist in artists {
+            for alias in artist.aliases.iter() {
+                canonical_names.insert(alias_key(alias), artist.name.clone());
+            }
+        }
+        Self { canonical_names }
+    }
+
+    /// クレジットを正式な名前の一覧にする。
+    /// クレジット全体が既知のアーティストでなければ連名として分割し、それぞれを別名から読み替える。
+    /// 同じアーティストが重複した場合は最初の1つだけを残す。
+    pub fn resolve(&self, credits: &[String]) -> Vec<String> {
+        let mut resolved = IndexSet::new();
+        for credit in credits {
+            let credit = collapse_whitespace(credit);
+            if credit.is_empty() {
+                continue;
+            }
+            if let Some(name) = self.canonical_names.get(&alias_key(&credit)) {
+                resolved.insert(name.clone());
+                continue;
+            }
+            for part in split_credit(&credit) {
+                let name = self
+                    .canonical_names
+                    .get(&alias_key(&part))
+                    .cloned()
+                    .unwrap_or(part);
+                resolved.insert(name);
+            }
+        }
+        resolved.into_iter().collect()
+    }
+}
+
+/// 連名のクレジットを分割する
+fn split_credit(credit: &str) -> Vec<String> {
+    let mut parts = vec![credit.to_string()];
+    for separator in CREDIT_SEPARATORS {
+        parts = parts
+            .iter()
+            .flat_map(|part| part.split(separator))
+            .map(collapse_whitespace)
+            .filter(|part| !part.is_empty())
+            .collect();
+    }
+    parts
+}
+
+/// 前後の空白を除き、連続する空白を1つにする
+fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// 別名を探すときのキー。空白の有無と大文字小文字の違いを無視する。
+fn alias_key(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Artist, ArtistId, ArtistResolver};
+
+    fn resolver() -> ArtistResolver {
+        ArtistResolver::new(&[
+            Artist {
+                id: ArtistId::new(1),
+                name: "塞壬唱片-MSR".into(),
+                aliases: vec!["Monster Siren Records".into()],
+            },
+            Artist {
+                id: ArtistId::new(2),
+                name: "Adam Gubman".into(),
+                aliases: vec![],
+            },
+            Artist {
+                id: ArtistId::new(3),
+                name: "Simon & Garfunkel".into(),
+                aliases: vec![],
+            },
+        ])
+    }
+
+    fn credits(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_resolve_alias() {
+        assert_eq!(
+            resolver().resolve(&credits(&["monster  siren records", "adam gubman"])),
+            credits(&["塞壬唱片-MSR", "Adam Gubman"])
+        );
+    }
+
+    #[test]
+    fn test_resolve_joint_credit() {
+        assert_eq!(
+            resolver().resolve(&credits(&[
+                "Adam Gubman / Monster Siren Records",
+                "塞壬唱片-MSR"
+            ])),
+            credits(&["Adam Gubman", "塞壬唱片-MSR"])
+        );
+        assert_eq!(
+            resolver().resolve(&credits(&["Someone feat. Another、Third"])),
+            credits(&["Someone", "Another", "Third"])
+        );
+    }
+
+    #[test]
+    fn test_resolve_alias_of_stored_name() {
+        // 既に正式な名前として登録されている表記も、別名として登録すれば読み替える
+        let resolver = ArtistResolver::new(&[
+            Artist {
+                id: ArtistId::new(1),
+                name: "Monster Siren Records".into(),
+                aliases: vec![],
+            },
+            Artist {
+                id: ArtistId::new(2),
+                name: "塞壬唱片-MSR".into(),
+                aliases: vec!["monster siren records".into()],
+            },
+        ]);
+        assert_eq!(
+            resolver.resolve(&credits(&["Monster Siren Records", "塞壬唱片-MSR"])),
+            credits(&["塞壬唱片-MSR"])
+        );
+    }
+
+    #[test]
+    fn test_resolve_known_name_is_not_split() {
+        assert_eq!(
+            resolver().resolve(&credits(&["Simon  &  Garfunkel", " "])),
+            credits(&["Simon & Garfunkel"])
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use crate::domain::msr::SongShort;
+use crate::domain::song::{AlbumId, SongId};
+use crate::errors::domain::DomainError;
+use indexmap::IndexMap;
+
+/// ディスク番号とみなす曲名中の語。大文字小文字は区別しない。
+const DISC_MARKERS: [&str; 3] = ["disc", "disk", "cd"];
+
+/// アルバムごとのディスクの分け方の設定。値は各ディスクの曲数。
+/// 曲名から判断できないアルバムのために設定ファイルで指定する。
+pub type DiscOverrides = IndexMap<AlbumId, Vec<u8>>;
+
+pub trait ProvideDiscOverrides {
+    fn provide_disc_overrides(&self) -> &DiscOverrides;
+}
+
+/// アルバムの収録曲をディスクごとに分ける。
+/// `overrides`に指定があればそれに従い、なければ曲名の`Disc 2`や`CD2`などから判断する。
+/// 判断できない場合は1枚組とする。
+/// * album_id: アルバムID
+/// * song_list: MSRのアルバムの収録曲。収録順に並んでいることを期待している。
+pub fn split_discs(
+    album_id: AlbumId,
+    song_list: &[SongShort],
+    overrides: &DiscOverrides,
+) -> Result<Vec<Vec<SongId>>, DomainError> {
+    let song_ids = song_list.iter().map(|song| song.id).collect::<Vec<_>>();
+
+    if let Some(sizes) = overrides.get(&album_id) {
+        return split_by_sizes(&song_ids, sizes).ok_or(DomainError::InvalidDiscLayout {
+            album_id,
+            expected: song_ids.len(),
+            actual: sizes.iter().map(|&size| size as usize).sum(),
+        });
+    }
+
+    let disc_numbers = song_list
+        .iter()
+        .map(|song| disc_number_in_name(&song.name))
+        .collect::<Vec<_>>();
+    Ok(split_by_disc_numbers(&song_ids, &disc_numbers).unwrap_or_else(|| vec![song_ids]))
+}
+
+/// 各ディスクの曲数に従って分ける。曲数の合計が合わない場合は`None`を返す。
+fn split_by_sizes(song_ids: &[SongId], sizes: &[u8]) -> Option<Vec<Vec<SongId>>> {
+    let total = sizes.iter().map(|&size| size as usize).sum::<usize>();
+    if total != song_ids.len() || sizes.contains(&0) {
+        return None;
+    }
+    let mut rest = song_ids;
+    let discs = sizes
+        .iter()
+        .map(|&size| {
+            let (disc, tail) = rest.split_at(size as usize);
+            rest = tail;
+            disc.to_vec()
+        })
+        .collect();
+    Some(discs)
+}
+
+/// 曲名から読み取ったディスク番号に従って分ける。
+/// 番号のない曲がある場合や、番号が1から順に増えていない場合、2枚以上に分かれない場合は`None`を返す。
+fn split_by_disc_numbers(
+    song_ids: &[SongId],
+    disc_numbers: &[Option<u8>],
+) -> Option<Vec<Vec<SongId>>> {
+    let mut discs: Vec<Vec<SongId>> = vec![vec![]];
+    for (&song_id, &disc_number) in song_ids.iter().zip(disc_numbers) {
+        match disc_number {
+            Some(n) if n as usize == discs.len() + 1 => discs.push(vec![song_id]),
+            Some(n) if n as usize == discs.len() => discs.last_mut()?.push(song_id),
+            _ => return None,
+        }
+    }
+    // 1枚目の曲がない場合は、番号がディスクを表していないとみなす
+    if discs.len() < 2 || discs.iter().any(Vec::is_empty) {
+        return None;
+    }
+    Some(discs)
+}
+
+/// 曲名に含まれる`Disc 2`や`CD.2`などからディスク番号を読み取る
+pub fn disc_number_in_name(name: &str) -> Option<u8> {
+    let lower = name.to_lowercase();
+    for marker in DISC_MARKERS {
+        for (start, _) in lower.match_indices(marker) {
+            // 単語の途中に現れたものは無視する
+            let preceded_by_word = lower[..start]
+                .chars()
+                .next_back()
+                .is_some_and(char::is_alphanumeric);
+            if preceded_by_word {
+                continue;
+            }
+            let rest = lower[start + marker.len()..].trim_start_matches([' ', '.', '-', '#']);
+            let digits = rest
+                .chars()
+                .take_while(char::is_ascii_digit)
+                .collect::<String>();
+            if digits.is_empty() || digits.len() > 2 {
+                continue;
+            }
+            let followed_by_word = rest[digits.len()..]
+                .chars()
+                .next()
+                .is_some_and(char::is_alphanumeric);
+            if followed_by_word {
+                continue;
+            }
+            if let Some(n) = digits.parse::<u8>().ok().filter(|&n| n > 0) {
+                return Some(n);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disc_number_in_name, split_discs, DiscOverrides};
+    use crate::domain::msr::SongShort;
+    use crate::domain::song::{AlbumId, SongId};
+    use crate::errors::domain::DomainError;
+
+    fn songs(names: &[&str]) -> Vec<SongShort> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| SongShort {
+                id: SongId::new(i as u32 + 1).unwrap(),
+                name: name.to_string(),
+                artists: vec![],
+            })
+            .collect()
+    }
+
+    fn ids(ids: &[u32]) -> Vec<SongId> {
+        ids.iter().map(|&id| SongId::new(id).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_disc_number_in_name() {
+        assert_eq!(disc_number_in_name("Operation Lead (Disc 2)"), Some(2));
+        assert_eq!(disc_number_in_name("OST CD.1 - Title"), Some(1));
+        assert_eq!(disc_number_in_name("Disk-3: Ending"), Some(3));
+        assert_eq!(disc_number_in_name("Boiling Blood Pt2"), None);
+        assert_eq!(disc_number_in_name("Theme Part 2"), None);
+        assert_eq!(disc_number_in_name("Abcd1"), None);
+        assert_eq!(disc_number_in_name("Partition 2"), None);
+        assert_eq!(disc_number_in_name("Disc 2024"), None);
+        assert_eq!(disc_number_in_name("Lullabye"), None);
+    }
+
+    #[test]
+    fn test_split_discs_by_name() {
+        let discs = split_discs(
+            AlbumId::new(1).unwrap(),
+            &songs(&[
+                "Intro (Disc 1)",
+                "Battle (Disc 1)",
+                "Theme (Disc 2)",
+                "Ending (Disc 2)",
+            ]),
+            &DiscOverrides::new(),
+        )
+        .unwrap();
+        assert_eq!(discs, vec![ids(&[1, 2]), ids(&[3, 4])]);
+    }
+
+    #[test]
+    fn test_split_discs_not_marked() {
+        // 曲名の`Part`はディスクを表さない
+        let discs = split_discs(
+            AlbumId::new(1).unwrap(),
+            &songs(&["Intro", "Theme Part 1", "Battle", "Theme Part 2", "Ending"]),
+            &DiscOverrides::new(),
+        )
+        .unwrap();
+        assert_eq!(discs.len(), 1);
+
+        // 番号のない曲があれば、1枚組とする
+        let discs = split_discs(
+            AlbumId::new(1).unwrap(),
+            &songs(&["Intro (CD1)", "Battle", "Theme (CD2)"]),
+            &DiscOverrides::new(),
+        )
+        .unwrap();
+        assert_eq!(discs, vec![ids(&[1, 2, 3])]);
+    }
+
+    #[test]
+    fn test_split_discs_not_in_order() {
+        // 番号が順に並んでいなければ、ディスクを表すものではないとみなす
+        let discs = split_discs(
+            AlbumId::new(1).unwrap(),
+            &songs(&["Song Disc.2", "Song Disc.1"]),
+            &DiscOverrides::new(),
+        )
+        .unwrap();
+        assert_eq!(discs, vec![ids(&[1, 2])]);
+    }
+
+    #[test]
+    fn test_split_discs_override() {
+        let overrides = DiscOverrides::from([(AlbumId::new(1).unwrap(), vec![1, 2])]);
+        let discs = split_discs(
+            AlbumId::new(1).unwrap(),
+            &songs(&["a", "b", "c"]),
+            &overrides,
+        )
+        .unwrap();
+        assert_eq!(discs, vec![ids(&[1]), ids(&[2, 3])]);
+
+        let overrides = DiscOverrides::from([(AlbumId::new(1).unwrap(), vec![1, 1])]);
+        assert_eq!(
+            split_discs(
+                AlbumId::new(1).unwrap(),
+                &songs(&["a", "b", "c"]),
+                &overrides
+            ),
+            Err(DomainError::InvalidDiscLayout {
+                album_id: AlbumId::new(1).unwrap(),
+                expected: 3,
+                actual: 2,
+            })
+        );
+    }
+}
@@ -0,0 +1,482 @@
+//! EBU R128（ITU-R BS.1770）によるラウドネスとトゥルーピークの測定。
+//! 復号したPCMのサンプルを順に渡して測定し、ReplayGainの値を求める。
+use std::collections::VecDeque;
+
+/// ReplayGain 2.0の基準のラウドネス（LUFS）
+pub const REPLAY_GAIN_REFERENCE_LUFS: f64 = -18.0;
+/// 絶対ゲート（LUFS）。これより静かなブロックは測定に含めない。
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// 相対ゲート（LU）。絶対ゲートを超えたブロックの平均からこれだけ静かなブロックは含めない。
+const RELATIVE_GATE_LU: f64 = -10.0;
+/// ゲーティングのブロックの長さは、この間隔のサブブロック4つ分（400ms）
+const STEP_MS: u32 = 100;
+const STEPS_PER_BLOCK: usize = 4;
+/// トゥルーピークを求めるときのオーバーサンプリングの倍率
+const OVERSAMPLING: usize = 4;
+/// このサンプリング周波数以上ではオーバーサンプリングせずにサンプルのピークを使う
+const OVERSAMPLING_LIMIT_HZ: u32 = 96000;
+/// 4倍オーバーサンプリングの補間フィルタ（ITU-R BS.1770-4 Annex 2）。位相ごとに12タップ。
+const TRUE_PEAK_FILTER: [[f64; 12]; OVERSAMPLING] = [
+    [
+        0.0017089843750,
+        0.0109863281250,
+        -0.0196533203125,
+        0.0332031250000,
+        -0.0594482421875,
+        0.1373291015625,
+        0.9721679687500,
+        -0.1022949218750,
+        0.0476074218750,
+        -0.0266113281250,
+        0.0148925781250,
+        -0.0083007812500,
+    ],
+    [
+        -0.0291748046875,
+        0.0292968750000,
+        -0.0517578125000,
+        0.0891113281250,
+        -0.1665039062500,
+        0.4650878906250,
+        0.7797851562500,
+        -0.2003173828125,
+        0.1015625000000,
+        -0.0582275390625,
+        0.0330810546875,
+        -0.0189208984375,
+    ],
+    [
+        -0.0189208984375,
+        0.0330810546875,
+        -0.0582275390625,
+        0.1015625000000,
+        -0.2003173828125,
+        0.7797851562500,
+        0.4650878906250,
+        -0.1665039062500,
+        0.0891113281250,
+        -0.0517578125000,
+        0.0292968750000,
+        -0.0291748046875,
+    ],
+    [
+        -0.0083007812500,
+        0.0148925781250,
+        -0.0266113281250,
+        0.0476074218750,
+        -0.1022949218750,
+        0.9721679687500,
+        0.1373291015625,
+        -0.0594482421875,
+        0.0332031250000,
+        -0.0196533203125,
+        0.0109863281250,
+        0.0017089843750,
+    ],
+];
+
+/// 測定したラウドネス
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct Loudness {
+    /// 統合ラウドネス（LUFS）
+    pub integrated_lufs: f64,
+    /// トゥルーピーク。フルスケールを1とした振幅の比。
+    pub true_peak: f64,
+}
+
+impl Loudness {
+    /// [`REPLAY_GAIN_REFERENCE_LUFS`]に揃えるためのゲイン（dB）
+    pub fn replay_gain_db(&self) -> f64 {
+        REPLAY_GAIN_REFERENCE_LUFS - self.integrated_lufs
+    }
+
+    /// トゥルーピーク（dBTP）
+    pub fn true_peak_dbtp(&self) -> f64 {
+        20.0 * self.true_peak.log10()
+    }
+
+    /// REPLAYGAIN_*_GAINタグの値。例えば`-6.52 dB`。
+    pub fn gain_tag(&self) -> String {
+        format!("{:.2} dB", self.replay_gain_db())
+    }
+
+    /// REPLAYGAIN_*_PEAKタグの値。例えば`0.988553`。
+    pub fn peak_tag(&self) -> String {
+        format!("{:.6}", self.true_peak)
+    }
+}
+
+/// ラウドネスの解析の状態
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LoudnessAnalysis {
+    /// まだ解析していない
+    #[default]
+    NotAnalyzed,
+    /// 解析したが、無音などで測れなかった。解析し直しても変わらないので再解析しない。
+    Unmeasurable,
+    Measured(Loudness),
+}
+
+impl LoudnessAnalysis {
+    pub fn is_analyzed(&self) -> bool {
+        *self != Self::NotAnalyzed
+    }
+
+    /// 測れたラウドネス。解析していないか、測れなかった場合は`None`を返す。
+    pub fn measured(&self) -> Option<Loudness> {
+        match self {
+            Self::Measured(loudness) => Some(*loudness),
+            _ => None,
+        }
+    }
+}
+
+/// 解析した結果から作成する。測れなかった場合は`None`を渡す。
+impl From<Option<Loudness>> for LoudnessAnalysis {
+    fn from(loudness: Option<Loudness>) -> Self {
+        match loudness {
+            Some(loudness) => Self::Measured(loudness),
+            None => Self::Unmeasurable,
+        }
+    }
+}
+
+/// 1曲分の測定結果。
+/// アルバム全体のラウドネスを求められるよう、ゲーティングのブロックごとのエネルギーを残しておく。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Measurement {
+    /// ブロックごとの、チャンネルの重みを掛けた平均二乗値の和
+    blocks: Vec<f64>,
+    true_peak: f64,
+}
+
+impl Measurement {
+    /// 曲の統合ラウドネス。
+    /// ゲートを超えるブロックがない場合（無音や400ms未満の音声）は`None`を返す。
+    pub fn loudness(&self) -> Option<Loudness> {
+        Some(Loudness {
+            integrated_lufs: integrated_lufs(&self.blocks)?,
+            true_peak: self.true_peak,
+        })
+    }
+
+    /// 複数の曲を続けて1つの番組とみなした統合ラウドネス。アルバムのラウドネスに使う。
+    pub fn combined(measurements: &[Measurement]) -> Option<Loudness> {
+        let blocks = measurements
+            .iter()
+            .flat_map(|measurement| measurement.blocks.iter().copied())
+            .collect::<Vec<_>>();
+        Some(Loudness {
+            integrated_lufs: integrated_lufs(&blocks)?,
+            true_peak: measurements
+                .iter()
+                .map(|measurement| measurement.true_peak)
+                .fold(0.0, f64::max),
+        })
+    }
+}
+
+/// ラウドネスメーター。チャンネルごとのサンプルを交互に並べた形で少しずつ渡す。
+#[derive(Debug, Clone)]
+pub struct LoudnessMeter {
+    channels: Vec<Channel>,
+    /// サブブロックのサンプル数（チャンネルあたり）
+    step_len: usize,
+    /// 現在のサブブロックに入れたサンプル数
+    step_filled: usize,
+    /// 現在のサブブロックの、チャンネルの重みを掛けた二乗和
+    step_sum: f64,
+    /// 直近のサブブロックの平均二乗値。ブロック1つ分まで持つ。
+    recent_steps: VecDeque<f64>,
+    blocks: Vec<f64>,
+    true_peak: f64,
+    oversampling: bool,
+}
+
+/// チャンネルごとの状態
+#[derive(Debug, Clone)]
+struct Channel {
+    weight: f64,
+    /// K特性のフィルタ。高域のシェルフとハイパスの順に掛ける。
+    filters: [Biquad; 2],
+    /// トゥルーピークの補間に使う直近のサンプル。先頭が最新。
+    history: [f64; 12],
+}
+
+impl LoudnessMeter {
+    /// * channels: チャンネル数。6チャンネルの場合は5.1ch（L、R、C、LFE、Ls、Rs）とみなす。
+    pub fn new(sample_rate: u32, channels: u8) -> Self {
+        let rate = f64::from(sample_rate.max(1));
+        let channels = (0..channels)
+            .map(|ix| Channel {
+                weight: channel_weight(ix, channels),
+                filters: [Biquad::high_shelf(rate), Biquad::high_pass(rate)],
+                history: [0.0; 12],
+            })
+            .collect();
+        Self {
+            channels,
+            step_len: (sample_rate.max(1) * STEP_MS / 1000).max(1) as usize,
+            step_filled: 0,
+            step_sum: 0.0,
+            recent_steps: VecDeque::with_capacity(STEPS_PER_BLOCK),
+            blocks: vec![],
+            true_peak: 0.0,
+            oversampling: sample_rate < OVERSAMPLING_LIMIT_HZ,
+        }
+    }
+
+    /// サンプルを追加する。値はフルスケールを1とする。
+    /// 最後の半端なフレームは捨てるので、フレームの途中で区切って渡さないこと。
+    pub fn add_samples(&mut self, samples: &[f32]) {
+        if self.channels.is_empty() {
+            return;
+        }
+        for frame in samples.chunks_exact(self.channels.len()) {
+            for (channel, &sample) in self.channels.iter_mut().zip(frame) {
+                let sample = f64::from(sample);
+                self.true_peak = self.true_peak.max(sample.abs());
+                if self.oversampling {
+                    self.true_peak = self.true_peak.max(channel.oversampled_peak(sample));
+                }
+                let filtered = channel
+                    .filters
+                    .iter_mut()
+                    .fold(sample, |x, filter| filter.process(x));
+                self.step_sum += channel.weight * filtered * filtered;
+            }
+            self.step_filled += 1;
+            if self.step_filled == self.step_len {
+                self.finish_step();
+            }
+        }
+    }
+
+    /// 測定を終える。最後のブロックに満たないサンプルは測定に含めない。
+    pub fn finish(self) -> Measurement {
+        Measurement {
+            blocks: self.blocks,
+            true_peak: self.true_peak,
+        }
+    }
+
+    fn finish_step(&mut self) {
+        if self.recent_steps.len() == STEPS_PER_BLOCK {
+            self.recent_steps.pop_front();
+        }
+        self.recent_steps
+            .push_back(self.step_sum / self.step_len as f64);
+        if self.recent_steps.len() == STEPS_PER_BLOCK {
+            self.blocks
+                .push(self.recent_steps.iter().sum::<f64>() / STEPS_PER_BLOCK as f64);
+        }
+        self.step_filled = 0;
+        self.step_sum = 0.0;
+    }
+}
+
+impl Channel {
+    /// サンプルを補間フィルタに入れ、補間した4つの値の絶対値の最大を返す
+    fn oversampled_peak(&mut self, sample: f64) -> f64 {
+        self.history.rotate_right(1);
+        self.history[0] = sample;
+        TRUE_PEAK_FILTER
+            .iter()
+            .map(|phase| {
+                phase
+                    .iter()
+                    .zip(&self.history)
+                    .map(|(coefficient, sample)| coefficient * sample)
+                    .sum::<f64>()
+                    .abs()
+            })
+            .fold(0.0, f64::max)
+    }
+}
+
+/// チャンネルの重み。5.1chのLFEは測定せず、サラウンドは1.41倍にする。
+fn channel_weight(ix: u8, channels: u8) -> f64 {
+    match (channels, ix) {
+        (6, 3) => 0.0,
+        (6, 4 | 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// 双2次フィルタ（直接形II転置）
+#[derive(Debug, Clone)]
+struct Biquad {
+    b: [f64; 3],
+    /// a0で正規化したフィードバックの係数。a0は含めない。
+    a: [f64; 2],
+    state: [f64; 2],
+}
+
+impl Biquad {
+    /// K特性の前段。頭部による高域の強調を模した約+4dBのシェルフ。
+    /// 係数はどのサンプリング周波数でも使えるよう、48kHzの規格値に合わせた設計式から求める。
+    fn high_shelf(rate: f64) -> Self {
+        let f0 = 1681.974450955533;
+        let gain_db = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (std::f64::consts::PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Self::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        )
+    }
+
+    /// K特性の後段。低域を落とすハイパス。
+    fn high_pass(rate: f64) -> Self {
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (std::f64::consts::PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Self::new(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        )
+    }
+
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b,
+            a,
+            state: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.state[0];
+        self.state[0] = self.b[1] * x - self.a[0] * y + self.state[1];
+        self.state[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// ブロックごとのエネルギーにゲートを掛け、統合ラウドネスを求める
+fn integrated_lufs(blocks: &[f64]) -> Option<f64> {
+    let absolute_gate = energy(ABSOLUTE_GATE_LUFS);
+    let above_absolute = blocks
+        .iter()
+        .copied()
+        .filter(|&block| block > absolute_gate)
+        .collect::<Vec<_>>();
+    let relative_gate = mean(&above_absolute)? * 10f64.powf(RELATIVE_GATE_LU / 10.0);
+    let above_relative = above_absolute
+        .into_iter()
+        .filter(|&block| block > relative_gate)
+        .collect::<Vec<_>>();
+    Some(lufs(mean(&above_relative)?))
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+fn lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn energy(lufs: f64) -> f64 {
+    10f64.powf((lufs + 0.691) / 10.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoudnessMeter, Measurement};
+
+    /// チャンネルごとに同じ値を並べたサイン波
+    fn sine(sample_rate: u32, channels: u8, freq: f64, dbfs: f64, seconds: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+        (0..(f64::from(sample_rate) * seconds) as usize)
+            .flat_map(|n| {
+                let t = n as f64 / f64::from(sample_rate);
+                let sample = (amplitude * (2.0 * std::f64::consts::PI * freq * t).sin()) as f32;
+                std::iter::repeat_n(sample, usize::from(channels))
+            })
+            .collect()
+    }
+
+    fn measure(sample_rate: u32, channels: u8, samples: &[f32]) -> Measurement {
+        let mut meter = LoudnessMeter::new(sample_rate, channels);
+        // 分けて渡しても結果は変わらない
+        for chunk in samples.chunks(4096 * usize::from(channels)) {
+            meter.add_samples(chunk);
+        }
+        meter.finish()
+    }
+
+    #[test]
+    fn test_sine_loudness() {
+        // EBU Tech 3341の1kHz、-23dBFSのステレオのサイン波は-23LUFS
+        for sample_rate in [44100, 48000] {
+            let samples = sine(sample_rate, 2, 1000.0, -23.0, 3.0);
+            let loudness = measure(sample_rate, 2, &samples).loudness().unwrap();
+            assert!(
+                (loudness.integrated_lufs + 23.0).abs() < 0.1,
+                "{sample_rate}Hz: {}",
+                loudness.integrated_lufs
+            );
+            assert_eq!(loudness.gain_tag(), "4.99 dB");
+            assert!(loudness.peak_tag().starts_with("0.070"));
+        }
+        // モノラルの0dBFSのサイン波は-3.01LUFS
+        let samples = sine(48000, 1, 1000.0, 0.0, 3.0);
+        let loudness = measure(48000, 1, &samples).loudness().unwrap();
+        assert!((loudness.integrated_lufs + 3.01).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_true_peak() {
+        // サンプリング周波数の4分の1で位相を45度ずらすと、サンプルのピークは約0.707になる
+        let samples = (0..48000)
+            .map(|n| {
+                let phase = std::f64::consts::FRAC_PI_2 * n as f64 + std::f64::consts::FRAC_PI_4;
+                phase.sin() as f32
+            })
+            .collect::<Vec<_>>();
+        let loudness = measure(48000, 1, &samples).loudness().unwrap();
+        assert!(loudness.true_peak > 0.95, "{}", loudness.true_peak);
+        assert!(loudness.true_peak_dbtp().abs() < 0.5);
+        assert_eq!(loudness.peak_tag().len(), "0.000000".len());
+    }
+
+    #[test]
+    fn test_gating() {
+        // 無音や400ms未満の音声は測定できない
+        assert_eq!(measure(48000, 2, &vec![0.0; 96000]).loudness(), None);
+        assert_eq!(
+            measure(48000, 1, &sine(48000, 1, 1000.0, -20.0, 0.3)).loudness(),
+            None
+        );
+        // 無音の部分はゲートで除かれる。ゲートがなければ約3dB下がる。
+        let mut samples = sine(48000, 1, 1000.0, -20.0, 2.0);
+        samples.extend(vec![0.0; 96000]);
+        let loudness = measure(48000, 1, &samples).loudness().unwrap();
+        assert!(
+            (loudness.integrated_lufs + 23.0).abs() < 0.5,
+            "{}",
+            loudness.integrated_lufs
+        );
+    }
+
+    #[test]
+    fn test_combined() {
+        let loud = measure(48000, 1, &sine(48000, 1, 1000.0, -10.0, 3.0));
+        let quiet = measure(48000, 1, &sine(48000, 1, 1000.0, -16.0, 3.0));
+        let album = Measurement::combined(&[loud.clone(), quiet.clone()]).unwrap();
+        let (loud, quiet) = (loud.loudness().unwrap(), quiet.loudness().unwrap());
+        assert!(quiet.integrated_lufs < album.integrated_lufs);
+        assert!(album.integrated_lufs < loud.integrated_lufs);
+        assert_eq!(album.true_peak, loud.true_peak);
+        assert_eq!(Measurement::combined(&[]), None);
+    }
+}
@@ -0,0 +1,65 @@
+/// メタデータが変更された対象の種類
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    strum::Display,
+    strum::EnumString,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataTarget {
+    #[strum(serialize = "song")]
+    Song,
+    #[strum(serialize = "album")]
+    Album,
+}
+
+/// フィールド単位のメタデータの変更。
+/// 値は型によらず比較・表示できるようにJSONにしている。
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct MetadataChange {
+    pub target: MetadataTarget,
+    /// 楽曲IDまたはアルバムID
+    pub id: u32,
+    pub field: String,
+    pub old_value: String,
+    pub new_value: String,
+}
+
+impl MetadataChange {
+    /// `old`と`new`が異なれば変更として`changes`に追加する
+    pub fn record<T>(
+        changes: &mut Vec<Self>,
+        target: MetadataTarget,
+        id: u32,
+        field: &str,
+        old: &T,
+        new: &T,
+    ) where
+        T: PartialEq + serde::Serialize + ?Sized,
+    {
+        if old == new {
+            return;
+        }
+        changes.push(Self {
+            target,
+            id,
+            field: field.to_string(),
+            old_value: serde_json::to_string(old).unwrap_or_default(),
+            new_value: serde_json::to_string(new).unwrap_or_default(),
+        });
+    }
+}
+
+/// 記録済みのメタデータの変更
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct MetadataHistory {
+    pub id: u32,
+    /// 記録した日時。ISO 8601形式。
+    pub created_at: String,
+    pub change: MetadataChange,
+}
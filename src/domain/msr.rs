@@ -1,87 +1,78 @@
+//! MSRのAPIから取得した情報。
+//! IDなどは検証済みの型で保持する。APIのレスポンスそのものの形はinfra層で扱う。
+use crate::domain::song::{AlbumId, OriginGame, SongId};
+use bytes::Bytes;
+use std::path::PathBuf;
 use url::Url;
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
-#[serde(rename_all = "camelCase")]
-pub struct MsrResponse<T> {
-    pub code: i32,
-    pub data: T,
-}
-
-#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct SongSummaries {
     pub list: Vec<SongSummary>,
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct SongSummary {
-    #[serde(rename = "cid")]
-    pub id: String,
+    pub id: SongId,
     pub name: String,
-    #[serde(rename = "albumCid")]
-    pub belong_album_id: String,
+    pub belong_album_id: AlbumId,
     pub artists: Vec<String>,
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AlbumSummary {
-    #[serde(rename = "cid")]
-    pub id: String,
+    pub id: AlbumId,
     pub name: String,
     pub cover_url: Url,
-    #[serde(rename = "artistes")]
     pub artists: Vec<String>,
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Song {
-    #[serde(rename = "cid")]
-    pub id: String,
+    pub id: SongId,
     pub name: String,
-    #[serde(rename = "albumCid")]
-    pub belong_album_id: String,
+    pub belong_album_id: AlbumId,
     pub source_url: Url,
     pub lyric_url: Option<Url>,
     pub artists: Vec<String>,
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Album {
-    #[serde(rename = "cid")]
-    pub id: String,
+    pub id: AlbumId,
     pub name: String,
     pub intro: String,
-    pub belong: String,
+    pub belong: OriginGame,
     pub cover_url: Url,
     pub cover_de_url: Url,
-    #[serde(rename = "artistes")]
     pub artists: Vec<String>,
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AlbumDetail {
-    #[serde(rename = "cid")]
-    pub id: String,
+    pub id: AlbumId,
     pub name: String,
     pub intro: String,
-    pub belong: String,
+    pub belong: OriginGame,
     pub cover_url: Url,
     pub cover_de_url: Url,
-    #[serde(rename = "songs")]
     pub song_list: Vec<SongShort>,
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Default)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct SongShort {
-    #[serde(rename = "cid")]
-    pub id: String,
+    pub id: SongId,
     pub name: String,
-    #[serde(rename = "artistes")]
     pub artists: Vec<String>,
 }
+
+/// 一時ファイルにダウンロードした音声データ
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DownloadedAudio {
+    /// 一時ファイルのパス
+    pub staged_path: PathBuf,
+    /// 内容のハッシュ値。[`crate::domain::song::content_hash`]と同じ方法で計算している。
+    pub hash: String,
+    pub size: u64,
+    /// 内容の先頭部分。フォーマットの判定に使う。
+    pub head: Bytes,
+}
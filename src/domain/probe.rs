@@ -0,0 +1,447 @@
+//! 音声データの再生時間や音質の読み取り。
+//! ファイル全体を読まずに済むよう、先頭のヘッダとファイルの長さから求める。
+use crate::domain::song::AudioFormat;
+use crate::domain::tag::flac::{BlockHeader, FlacTagger, BLOCK_HEADER_LEN, MAGIC as FLAC_MAGIC};
+use crate::domain::tag::id3::Id3Tagger;
+use crate::domain::tag::{MetadataLen, Tagger};
+use crate::domain::transcode::flac::{StreamInfo, STREAMINFO, STREAMINFO_LEN};
+use crate::domain::transcode::wav::{self, CHUNK_HEADER_LEN};
+use crate::errors::domain::DomainError;
+
+/// フレームを探す範囲。タグの後に埋め草があっても見つけられるようにする。
+const MP3_SYNC_SEARCH_LEN: usize = 4096;
+/// フレームのヘッダの後ろにあるXingヘッダやVBRIヘッダを読むのに必要な長さ
+const MP3_FRAME_PROBE_LEN: usize = 64;
+/// VBRIヘッダの位置。フレームの先頭からのバイト数。
+const VBRI_OFFSET: usize = 36;
+/// MPEG-1 Layer IIIのビットレート（kbps）
+const MPEG1_BITRATES: [u32; 16] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 0,
+];
+/// MPEG-2、MPEG-2.5 Layer IIIのビットレート（kbps）
+const MPEG2_BITRATES: [u32; 16] = [
+    0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0,
+];
+/// MPEG-1のサンプリング周波数。MPEG-2はこの半分、MPEG-2.5は4分の1になる。
+const MPEG1_SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+/// 音声データの再生時間や音質
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct AudioProperties {
+    /// 再生時間（ミリ秒）。ヘッダから分からない場合は0。
+    pub duration_ms: u32,
+    pub sample_rate: u32,
+    pub channels: u8,
+    /// 量子化ビット数。MP3のように決まっていないフォーマットでは`None`。
+    pub bits_per_sample: Option<u8>,
+    /// タグを除いた音声部分の平均ビットレート（kbps）。再生時間が分からない場合は0。
+    pub bitrate_kbps: u32,
+}
+
+impl AudioProperties {
+    /// CD（44.1kHz、16bit）を超える音質か
+    pub fn is_hi_res(&self) -> bool {
+        self.sample_rate > 44100 || self.bits_per_sample.is_some_and(|bits| bits > 16)
+    }
+
+    /// チャンネルあたりのサンプル数と音声部分のバイト数から作成する
+    fn new(
+        sample_rate: u32,
+        channels: u8,
+        bits_per_sample: Option<u8>,
+        total_samples: u64,
+        audio_len: u64,
+    ) -> Self {
+        let duration_ms = total_samples * 1000 / u64::from(sample_rate.max(1));
+        Self::with_duration(
+            sample_rate,
+            channels,
+            bits_per_sample,
+            duration_ms,
+            audio_len,
+        )
+    }
+
+    fn with_duration(
+        sample_rate: u32,
+        channels: u8,
+        bits_per_sample: Option<u8>,
+        duration_ms: u64,
+        audio_len: u64,
+    ) -> Self {
+        // 1ミリ秒あたりのビット数はkbpsと等しい
+        let bitrate_kbps = (audio_len * 8).checked_div(duration_ms).unwrap_or(0);
+        Self {
+            duration_ms: u32::try_from(duration_ms).unwrap_or(u32::MAX),
+            sample_rate,
+            channels,
+            bits_per_sample,
+            bitrate_kbps: u32::try_from(bitrate_kbps).unwrap_or(u32::MAX),
+        }
+    }
+}
+
+/// [`probe`]の結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    Complete(AudioProperties),
+    /// 判定するには先頭から少なくとも指定したバイト数が必要
+    NeedMore(usize),
+}
+
+/// 音声データの先頭部分から再生時間や音質を読み取る。
+/// 足りなければ必要なバイト数を[`Probe::NeedMore`]で返すので、読み足して呼び直す。
+/// * head: 音声データの先頭部分
+/// * file_len: 音声データ全体のバイト数
+pub fn probe(format: AudioFormat, head: &[u8], file_len: u64) -> Result<Probe, DomainError> {
+    let probe = match format {
+        AudioFormat::Flac => probe_flac(head, file_len)?,
+        AudioFormat::Mp3 => probe_mp3(head, file_len)?,
+        AudioFormat::Wav => probe_wav(head, file_len)?,
+    };
+    match probe {
+        Probe::NeedMore(len) if len <= head.len() || len as u64 > file_len => {
+            Err(malformed(format, "unexpected end of file"))
+        }
+        probe => Ok(probe),
+    }
+}
+
+/// STREAMINFOブロックから読み取る
+fn probe_flac(head: &[u8], file_len: u64) -> Result<Probe, DomainError> {
+    let info_end = FLAC_MAGIC.len() + BLOCK_HEADER_LEN + STREAMINFO_LEN;
+    let Some(info) = head.get(..info_end) else {
+        return Ok(Probe::NeedMore(info_end));
+    };
+    if !info.starts_with(FLAC_MAGIC) {
+        return Err(malformed(AudioFormat::Flac, "missing fLaC marker"));
+    }
+    let header = BlockHeader::parse([info[4], info[5], info[6], info[7]]);
+    if header.block_type != STREAMINFO {
+        return Err(malformed(
+            AudioFormat::Flac,
+            "first block is not STREAMINFO",
+        ));
+    }
+    let info = StreamInfo::parse(&info[FLAC_MAGIC.len() + BLOCK_HEADER_LEN..])?;
+    // カバー画像を含むメタデータブロックはビットレートに含めない
+    let metadata_len = match FlacTagger.metadata_len(head)? {
+        MetadataLen::Complete(len) => len,
+        MetadataLen::NeedMore(len) => return Ok(Probe::NeedMore(len)),
+    };
+    Ok(Probe::Complete(AudioProperties::new(
+        info.format.sample_rate,
+        info.format.channels,
+        Some(info.format.bits_per_sample),
+        info.total_samples,
+        file_len.saturating_sub(metadata_len as u64),
+    )))
+}
+
+/// fmtチャンクとdataチャンクの長さから読み取る
+fn probe_wav(head: &[u8], file_len: u64) -> Result<Probe, DomainError> {
+    let Some(riff) = head.get(..12) else {
+        return Ok(Probe::NeedMore(12));
+    };
+    if &riff[..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(malformed(AudioFormat::Wav, "missing RIFF/WAVE header"));
+    }
+    let mut fmt = None;
+    let mut offset = 12;
+    loop {
+        let Some(header) = head.get(offset..offset + CHUNK_HEADER_LEN) else {
+            return Ok(Probe::NeedMore(offset + CHUNK_HEADER_LEN));
+        };
+        let id = &header[..4];
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        offset += CHUNK_HEADER_LEN;
+        match id {
+            b"fmt " => {
+                let Some(data) = head.get(offset..offset + size) else {
+                    return Ok(Probe::NeedMore(offset + size));
+                };
+                fmt = Some(wav::parse_fmt(data)?);
+            }
+            b"data" => {
+                let fmt =
+                    fmt.ok_or_else(|| malformed(AudioFormat::Wav, "data chunk before fmt chunk"))?;
+                if fmt.block_align == 0 || fmt.sample_rate == 0 {
+                    return Err(malformed(AudioFormat::Wav, "invalid fmt chunk"));
+                }
+                // 書き込み中に止まったWAVはサイズが実際より大きいことがあるので、ファイルの終わりまでにする
+                let data_len = (size as u64).min(file_len.saturating_sub(offset as u64));
+                let bits_per_sample = match fmt.extensible {
+                    Some((_, valid_bits)) if valid_bits != 0 => valid_bits,
+                    _ => fmt.bits_per_sample,
+                };
+                return Ok(Probe::Complete(AudioProperties::new(
+                    fmt.sample_rate,
+                    u8::try_from(fmt.channels).unwrap_or(u8::MAX),
+                    u8::try_from(bits_per_sample).ok(),
+                    data_len / u64::from(fmt.block_align),
+                    data_len,
+                )));
+            }
+            _ => {}
+        }
+        // チャンクは2バイト境界に揃える
+        offset += size + size % 2;
+    }
+}
+
+/// 最初のフレームのヘッダと、あればXingヘッダかVBRIヘッダのフレーム数から読み取る。
+/// Layer IIIのフレームだけに対応する。
+fn probe_mp3(head: &[u8], file_len: u64) -> Result<Probe, DomainError> {
+    let start = match Id3Tagger.metadata_len(head)? {
+        MetadataLen::Complete(len) => len,
+        MetadataLen::NeedMore(len) => return Ok(Probe::NeedMore(len)),
+    };
+    let end = ((start + MP3_SYNC_SEARCH_LEN + MP3_FRAME_PROBE_LEN) as u64).min(file_len) as usize;
+    if head.len() < end {
+        return Ok(Probe::NeedMore(end));
+    }
+    let head = &head[..end];
+    let (offset, frame) = (start..end.saturating_sub(3))
+        .filter_map(|offset| Some((offset, FrameHeader::parse(&head[offset..])?)))
+        .find(|(offset, frame)| {
+            // 偶然同期ワードに見えるバイト列を避けるため、次のフレームも確かめられれば確かめる
+            match head.get(offset + frame.frame_len..) {
+                Some(next) if next.len() >= 4 => FrameHeader::parse(next)
+                    .is_some_and(|next| next.sample_rate == frame.sample_rate),
+                _ => true,
+            }
+        })
+        .ok_or_else(|| malformed(AudioFormat::Mp3, "no MPEG audio frame"))?;
+
+    let audio_len = file_len.saturating_sub(offset as u64);
+    let frames = frame_count(&head[offset..], &frame);
+    let properties = match frames {
+        Some(frames) => AudioProperties::new(
+            frame.sample_rate,
+            frame.channels,
+            None,
+            u64::from(frames) * u64::from(frame.samples_per_frame),
+            audio_len,
+        ),
+        // フレーム数が分からなければ固定ビットレートとみなす
+        None => AudioProperties::with_duration(
+            frame.sample_rate,
+            frame.channels,
+            None,
+            audio_len * 8 / u64::from(frame.bitrate_kbps),
+            audio_len,
+        ),
+    };
+    Ok(Probe::Complete(properties))
+}
+
+/// XingヘッダかVBRIヘッダに書かれたフレーム数
+fn frame_count(frame_data: &[u8], frame: &FrameHeader) -> Option<u32> {
+    let u32_at = |offset: usize| {
+        frame_data
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    let xing = 4 + frame.side_info_len;
+    if let Some(b"Xing" | b"Info") = frame_data.get(xing..xing + 4) {
+        // フラグの最下位ビットがフレーム数の有無を表す
+        let flags = u32_at(xing + 4)?;
+        return if flags & 1 != 0 {
+            u32_at(xing + 8)
+        } else {
+            None
+        };
+    }
+    match frame_data.get(VBRI_OFFSET..VBRI_OFFSET + 4) {
+        Some(b"VBRI") => u32_at(VBRI_OFFSET + 14),
+        _ => None,
+    }
+}
+
+/// MPEGオーディオのLayer IIIのフレームのヘッダ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FrameHeader {
+    bitrate_kbps: u32,
+    sample_rate: u32,
+    channels: u8,
+    samples_per_frame: u32,
+    /// ヘッダの後ろにあるサイド情報の長さ
+    side_info_len: usize,
+    /// ヘッダを含むフレームの長さ
+    frame_len: usize,
+}
+
+impl FrameHeader {
+    /// 先頭の4バイトを読む。Layer IIIの有効なヘッダでなければ`None`を返す。
+    fn parse(data: &[u8]) -> Option<Self> {
+        let header =
+            u32::from_be_bytes([*data.first()?, *data.get(1)?, *data.get(2)?, *data.get(3)?]);
+        if header >> 21 != 0x7FF {
+            return None;
+        }
+        let version = (header >> 19) & 0b11;
+        let layer = (header >> 17) & 0b11;
+        let bitrate_index = ((header >> 12) & 0b1111) as usize;
+        let sample_rate_index = ((header >> 10) & 0b11) as usize;
+        let padding = (header >> 9) & 1;
+        let mono = (header >> 6) & 0b11 == 0b11;
+        // バージョンの01は予約済み、Layer IIIは01
+        if version == 0b01 || layer != 0b01 || sample_rate_index == 3 {
+            return None;
+        }
+        let mpeg1 = version == 0b11;
+        let bitrate_kbps = if mpeg1 {
+            MPEG1_BITRATES[bitrate_index]
+        } else {
+            MPEG2_BITRATES[bitrate_index]
+        };
+        // 0はフリーフォーマットで、フレームの長さが分からないので扱わない
+        if bitrate_kbps == 0 {
+            return None;
+        }
+        let sample_rate = MPEG1_SAMPLE_RATES[sample_rate_index]
+            >> match version {
+                0b11 => 0,
+                0b10 => 1,
+                _ => 2,
+            };
+        let samples_per_frame = if mpeg1 { 1152 } else { 576 };
+        let side_info_len = match (mpeg1, mono) {
+            (true, false) => 32,
+            (true, true) | (false, false) => 17,
+            (false, true) => 9,
+        };
+        let frame_len =
+            (samples_per_frame / 8 * bitrate_kbps * 1000 / sample_rate + padding) as usize;
+        Some(Self {
+            bitrate_kbps,
+            sample_rate,
+            channels: if mono { 1 } else { 2 },
+            samples_per_frame,
+            side_info_len,
+            frame_len,
+        })
+    }
+}
+
+fn malformed(format: AudioFormat, reason: &str) -> DomainError {
+    DomainError::MalformedAudio {
+        format,
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{probe, AudioProperties, Probe};
+    use crate::domain::song::AudioFormat;
+    use crate::domain::transcode::flac;
+    use crate::domain::transcode::PcmFormat;
+    use crate::errors::domain::DomainError;
+
+    /// 先頭から必要なだけ渡して読み取る
+    fn probe_all(format: AudioFormat, raw: &[u8]) -> Result<AudioProperties, DomainError> {
+        let mut len = 0;
+        loop {
+            match probe(format, &raw[..len], raw.len() as u64)? {
+                Probe::Complete(properties) => return Ok(properties),
+                Probe::NeedMore(more) => len = more,
+            }
+        }
+    }
+
+    /// MPEG-1 Layer III、128kbps、44.1kHz、ステレオのフレーム
+    fn mp3_frame() -> Vec<u8> {
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+        frame.resize(417, 0);
+        frame
+    }
+
+    #[test]
+    fn test_probe_flac() {
+        let format = PcmFormat {
+            sample_rate: 44100,
+            channels: 2,
+            bits_per_sample: 16,
+        };
+        let raw = flac::encode(format, &vec![0; 2 * 44100]).unwrap();
+        let properties = probe_all(AudioFormat::Flac, &raw).unwrap();
+        assert_eq!(properties.duration_ms, 1000);
+        assert_eq!(properties.sample_rate, 44100);
+        assert_eq!(properties.channels, 2);
+        assert_eq!(properties.bits_per_sample, Some(16));
+        assert_eq!(properties.bitrate_kbps, (raw.len() as u32 - 42) * 8 / 1000);
+        assert!(!properties.is_hi_res());
+    }
+
+    #[test]
+    fn test_probe_wav() {
+        let mut raw = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0".to_vec();
+        raw.extend_from_slice(&1u16.to_le_bytes());
+        raw.extend_from_slice(&2u16.to_le_bytes());
+        raw.extend_from_slice(&96000u32.to_le_bytes());
+        raw.extend_from_slice(&(96000u32 * 6).to_le_bytes());
+        raw.extend_from_slice(&6u16.to_le_bytes());
+        raw.extend_from_slice(&24u16.to_le_bytes());
+        // 書き込み中に止まったWAVのように、dataチャンクのサイズが実際より大きい
+        raw.extend_from_slice(b"data");
+        raw.extend_from_slice(&u32::MAX.to_le_bytes());
+        raw.resize(raw.len() + 96000 * 6 / 4, 0);
+        let properties = probe_all(AudioFormat::Wav, &raw).unwrap();
+        assert_eq!(properties.duration_ms, 250);
+        assert_eq!(properties.sample_rate, 96000);
+        assert_eq!(properties.bits_per_sample, Some(24));
+        assert_eq!(properties.bitrate_kbps, 96 * 6 * 8);
+        assert!(properties.is_hi_res());
+    }
+
+    #[test]
+    fn test_probe_mp3_cbr() {
+        // ID3v2タグの後に埋め草があっても最初のフレームを見つける
+        let mut raw = b"ID3\x04\0\0\0\0\0\x14".to_vec();
+        raw.extend_from_slice(&[0; 20 + 7]);
+        for _ in 0..100 {
+            raw.extend_from_slice(&mp3_frame());
+        }
+        let properties = probe_all(AudioFormat::Mp3, &raw).unwrap();
+        assert_eq!(
+            properties,
+            AudioProperties {
+                duration_ms: 417 * 100 * 8 / 128,
+                sample_rate: 44100,
+                channels: 2,
+                bits_per_sample: None,
+                bitrate_kbps: 128,
+            }
+        );
+    }
+
+    #[test]
+    fn test_probe_mp3_xing() {
+        let mut raw = mp3_frame();
+        raw[36..40].copy_from_slice(b"Xing");
+        raw[40..44].copy_from_slice(&1u32.to_be_bytes());
+        raw[44..48].copy_from_slice(&1000u32.to_be_bytes());
+        for _ in 0..10 {
+            raw.extend_from_slice(&mp3_frame());
+        }
+        let properties = probe_all(AudioFormat::Mp3, &raw).unwrap();
+        assert_eq!(properties.duration_ms, 1000 * 1152 * 1000 / 44100);
+    }
+
+    #[test]
+    fn test_probe_truncated() {
+        assert_eq!(
+            probe(AudioFormat::Flac, &[], 1000).unwrap(),
+            Probe::NeedMore(42)
+        );
+        assert!(matches!(
+            probe_all(AudioFormat::Wav, b"RIFF\0\0\0\0WAVE"),
+            Err(DomainError::MalformedAudio { .. })
+        ));
+        assert!(matches!(
+            probe_all(AudioFormat::Mp3, &[0; 100]),
+            Err(DomainError::MalformedAudio { .. })
+        ));
+    }
+}
@@ -1,2 +1,5 @@
+pub mod artist_repository;
+pub mod metadata_history_repository;
 pub mod msr_repository;
 pub mod song_repository;
+pub mod sync_run_repository;
@@ -0,0 +1,21 @@
+use crate::domain::artist::Artist;
+use crate::errors::Result;
+use async_trait::async_trait;
+use mockall::automock;
+
+#[automock]
+#[async_trait]
+pub trait UsesArtistRepository: Send + Sync + 'static {
+    /// 別名を含めてすべてのアーティストを取得する
+    async fn get_all_artists(&self) -> Result<Vec<Artist>>;
+    /// `alias`を`name`のアーティストの別名として登録する。
+    /// アーティストがまだなければ登録し、既に別のアーティストの別名であれば付け替える。
+    async fn save_alias(&self, alias: String, name: String) -> Result<()>;
+    /// 別名を削除する。存在しなければ何もしない。
+    async fn delete_alias(&self, alias: String) -> Result<()>;
+}
+
+pub trait ProvideArtistRepository {
+    type ArtistRepository: UsesArtistRepository + Send + Sync + 'static;
+    fn provide_artist_repository(&self) -> &Self::ArtistRepository;
+}
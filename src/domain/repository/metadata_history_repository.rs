@@ -0,0 +1,17 @@
+use crate::domain::metadata_history::{MetadataChange, MetadataHistory};
+use crate::errors::Result;
+use async_trait::async_trait;
+use mockall::automock;
+
+#[automock]
+#[async_trait]
+pub trait UsesMetadataHistoryRepository: Send + Sync + 'static {
+    async fn save_metadata_changes(&self, changes: Vec<MetadataChange>) -> Result<()>;
+    /// 新しい順に最大`limit`件の記録を取得する
+    async fn get_recent_metadata_history(&self, limit: u32) -> Result<Vec<MetadataHistory>>;
+}
+
+pub trait ProvideMetadataHistoryRepository {
+    type MetadataHistoryRepository: UsesMetadataHistoryRepository + Send + Sync + 'static;
+    fn provide_metadata_history_repository(&self) -> &Self::MetadataHistoryRepository;
+}
@@ -1,5 +1,6 @@
 use crate::domain::msr::*;
-use anyhow::Result;
+use crate::domain::song::{AlbumId, SongId};
+use crate::errors::Result;
 use async_trait::async_trait;
 use bytes::Bytes;
 use mockall::automock;
@@ -8,12 +9,14 @@ use url::Url;
 #[automock]
 #[async_trait]
 pub trait UsesMsrRepository: Send + Sync + 'static {
-    async fn fetch_song(&self, song_id: String) -> Result<Song>;
+    async fn fetch_song(&self, song_id: SongId) -> Result<Song>;
     async fn fetch_all_songs(&self) -> Result<SongSummaries>;
-    async fn fetch_album(&self, album_id: String) -> Result<Album>;
-    async fn fetch_album_detail(&self, album_id: String) -> Result<AlbumDetail>;
+    async fn fetch_album(&self, album_id: AlbumId) -> Result<Album>;
+    async fn fetch_album_detail(&self, album_id: AlbumId) -> Result<AlbumDetail>;
     async fn fetch_all_albums(&self) -> Result<Vec<AlbumSummary>>;
     async fn fetch_raw_song(&self, source_url: Url) -> Result<Bytes>;
+    /// 音声データをメモリに載せずに一時ファイルへダウンロードする
+    async fn download_raw_song(&self, source_url: Url) -> Result<DownloadedAudio>;
     async fn fetch_cover_image(&self, cover_url: Url) -> Result<Bytes>;
 }
 
@@ -1,6 +1,9 @@
+use crate::domain::loudness::Measurement;
+use crate::domain::metadata_history::MetadataChange;
 use crate::domain::song::*;
-use anyhow::Result;
+use crate::errors::Result;
 use async_trait::async_trait;
+use bytes::Bytes;
 use mockall::automock;
 
 #[automock]
@@ -8,15 +11,42 @@ use mockall::automock;
 pub trait UsesSongRepository: Send + Sync + 'static {
     async fn get_song(&self, song_id: SongId) -> Result<Option<Song>>;
     async fn save_song(&self, song: Song) -> Result<()>;
+    /// 楽曲を保存し、メタデータの変更を同じトランザクションで記録する
+    async fn save_song_with_changes(&self, song: Song, changes: Vec<MetadataChange>) -> Result<()>;
     async fn delete_song(&self, song_id: SongId) -> Result<()>;
     async fn get_all_songs_id(&self) -> Result<Vec<SongId>>;
     async fn get_all_song(&self) -> Result<Vec<Song>>;
+    /// 楽曲の音声データを読み込む。壊れていないかも確認する。
+    async fn load_audio(&self, song: &Song) -> Result<Bytes>;
+    /// 楽曲の音声データを復号し、ラウドネスを測定する
+    async fn measure_loudness(&self, song: &Song) -> Result<Measurement>;
     async fn save_songs(&self, songs: Vec<Song>) -> Result<()>;
     async fn get_album(&self, album_id: AlbumId) -> Result<Option<Album>>;
     async fn save_album(&self, album: Album) -> Result<()>;
+    /// アルバムを保存し、メタデータの変更を同じトランザクションで記録する
+    async fn save_album_with_changes(
+        &self,
+        album: Album,
+        changes: Vec<MetadataChange>,
+    ) -> Result<()>;
     async fn delete_album(&self, album_id: AlbumId) -> Result<()>;
     async fn get_all_album(&self) -> Result<Vec<Album>>;
     async fn save_all_album(&self, albums: Vec<Album>) -> Result<()>;
+    /// 楽曲がMSRから取り下げられているかを記録する
+    async fn set_song_withdrawn(&self, song_id: SongId, withdrawn: bool) -> Result<()>;
+    /// アルバムがMSRから取り下げられているかを記録する
+    async fn set_album_withdrawn(&self, album_id: AlbumId, withdrawn: bool) -> Result<()>;
+    /// 取り下げを記録した楽曲のID。論理削除したものも含む
+    async fn get_withdrawn_songs_id(&self) -> Result<Vec<SongId>>;
+    /// 取り下げを記録したアルバムのID。論理削除したものも含む
+    async fn get_withdrawn_albums_id(&self) -> Result<Vec<AlbumId>>;
+    /// MSRに戻ってきた楽曲の取り下げを取り消す。
+    /// 論理削除していれば、音声データが残っている場合に限り復元する。
+    async fn restore_song(&self, song_id: SongId) -> Result<()>;
+    /// MSRに戻ってきたアルバムの取り下げを取り消し、論理削除していれば復元する
+    async fn restore_album(&self, album_id: AlbumId) -> Result<()>;
+    /// 楽曲の音声データを削除する。他の楽曲と共有している場合は削除しない。
+    async fn purge_audio(&self, song: &Song) -> Result<()>;
 }
 
 pub trait ProvideSongRepository {
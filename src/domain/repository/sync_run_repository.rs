@@ -0,0 +1,17 @@
+use crate::domain::sync_report::{SyncReport, SyncRun};
+use crate::errors::Result;
+use async_trait::async_trait;
+use mockall::automock;
+
+#[automock]
+#[async_trait]
+pub trait UsesSyncRunRepository: Send + Sync + 'static {
+    async fn save_sync_run(&self, report: SyncReport) -> Result<()>;
+    /// 新しい順に最大`limit`件の記録を取得する
+    async fn get_recent_sync_runs(&self, limit: u32) -> Result<Vec<SyncRun>>;
+}
+
+pub trait ProvideSyncRunRepository {
+    type SyncRunRepository: UsesSyncRunRepository + Send + Sync + 'static;
+    fn provide_sync_run_repository(&self) -> &Self::SyncRunRepository;
+}
@@ -1,23 +1,24 @@
 use bytes::Bytes;
 use deriving_via::DerivingVia;
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
 use std::fmt::Debug;
 use std::path::PathBuf;
 use strum::{Display, EnumString};
+use crate::domain::loudness::LoudnessAnalysis;
+use crate::domain::probe::AudioProperties;
+use crate::domain::transcode;
 use crate::errors::domain::DomainError;
 
 // TODO: commonに分離
 #[derive(DerivingVia)]
 #[deriving(
-    From,
-    FromStr(via: u32),
     Into,
     Copy,
     Default,
     IntoInner(via: u32),
     Display(via: u32),
     Serialize(via: u32),
-    Deserialize(via: u32),
     Eq(via: u32),
     Ord(via: u32),
     Hash(via: u32),
@@ -30,33 +31,90 @@ impl<T> Debug for Id<T> {
     }
 }
 
+impl<T> Id<T> {
+    /// 範囲を確認せずに作成する。桁数の決まっていないIDにだけ使う。
+    pub(in crate::domain) fn from_inner(id: u32) -> Self {
+        Self(id, std::marker::PhantomData)
+    }
+}
+
+/// MSRの楽曲IDの桁数
+pub const SONG_ID_DIGITS: usize = 6;
+/// MSRのアルバムIDの桁数
+pub const ALBUM_ID_DIGITS: usize = 4;
+
+/// `digits`桁以下の数字だけからなる文字列を数値にする
+fn parse_msr_id(s: &str, digits: usize) -> Option<u32> {
+    if s.is_empty() || s.len() > digits || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// `id`が`digits`桁に収まるか
+fn fits_in_digits(id: u32, digits: usize) -> bool {
+    id.to_string().len() <= digits
+}
+
 pub type SongId = Id<Song>;
 
 impl SongId {
-    pub fn new (id: u32) -> Self {
-        // TODO: バリデーション
-        Self(id, std::marker::PhantomData)
+    /// [`SONG_ID_DIGITS`]桁に収まらない場合はエラーになる
+    pub fn new(id: u32) -> Result<Self, DomainError> {
+        if !fits_in_digits(id, SONG_ID_DIGITS) {
+            return Err(DomainError::SongIdOutOfRange { id });
+        }
+        Ok(Self(id, std::marker::PhantomData))
     }
-    pub fn try_new(s :String) -> Result<Self, DomainError> {
-        // TODO: バリデーション
-        s
-            .parse::<Self>()
-            .map_err(|_| DomainError::FailedToParseSongId { s: s.clone() })
+
+    /// MSRの楽曲IDの文字列から作成する。[`SONG_ID_DIGITS`]桁以下の数字のみを受け付ける。
+    pub fn try_new(s: String) -> Result<Self, DomainError> {
+        parse_msr_id(&s, SONG_ID_DIGITS)
+            .map(|id| Self(id, std::marker::PhantomData))
+            .ok_or(DomainError::FailedToParseSongId { s })
+    }
+
+    /// MSRのAPIで使われる0埋めの形式にする。例えば`1`は`"000001"`になる。
+    pub fn to_msr_string(&self) -> String {
+        format!("{:0>width$}", self.0, width = SONG_ID_DIGITS)
+    }
+}
+
+/// [`SongId::new`]と同じく桁数を確認する
+impl<'de> serde::Deserialize<'de> for SongId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::new(u32::deserialize(deserializer)?).map_err(serde::de::Error::custom)
     }
 }
 
 pub type AlbumId = Id<Album>;
 
 impl AlbumId {
-    pub fn new (id: u32) -> Self {
-        // TODO: バリデーション
-        Self(id, std::marker::PhantomData)
+    /// [`ALBUM_ID_DIGITS`]桁に収まらない場合はエラーになる
+    pub fn new(id: u32) -> Result<Self, DomainError> {
+        if !fits_in_digits(id, ALBUM_ID_DIGITS) {
+            return Err(DomainError::AlbumIdOutOfRange { id });
+        }
+        Ok(Self(id, std::marker::PhantomData))
     }
+
+    /// MSRのアルバムIDの文字列から作成する。[`ALBUM_ID_DIGITS`]桁以下の数字のみを受け付ける。
     pub fn try_new(s: String) -> Result<Self, DomainError> {
-        // TODO: バリデーション
-        // TODO: エラーの詰め替え
-        s.parse::<Self>()
-            .map_err(|_| DomainError::FailedToParseAlbumId { s: s.clone() })
+        parse_msr_id(&s, ALBUM_ID_DIGITS)
+            .map(|id| Self(id, std::marker::PhantomData))
+            .ok_or(DomainError::FailedToParseAlbumId { s })
+    }
+
+    /// MSRのAPIで使われる0埋めの形式にする。例えば`1`は`"0001"`になる。
+    pub fn to_msr_string(&self) -> String {
+        format!("{:0>width$}", self.0, width = ALBUM_ID_DIGITS)
+    }
+}
+
+/// [`AlbumId::new`]と同じく桁数を確認する
+impl<'de> serde::Deserialize<'de> for AlbumId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::new(u32::deserialize(deserializer)?).map_err(serde::de::Error::custom)
     }
 }
 
@@ -71,36 +129,200 @@ pub enum AudioFormat {
     Wav,
 }
 
-#[derive(Debug, Clone, Default, PartialEq, Eq)]
+impl AudioFormat {
+    /// 先頭のマジックバイトからフォーマットを判定する
+    pub fn detect(raw: &[u8]) -> Option<Self> {
+        match raw {
+            [b'f', b'L', b'a', b'C', ..] => Some(Self::Flac),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some(Self::Wav),
+            // ID3v2タグ付き、またはMPEGフレームの同期ワードから始まるもの。
+            // レイヤーのビットが0のものはAACのADTSなので除く。
+            [b'I', b'D', b'3', ..] => Some(Self::Mp3),
+            [0xFF, b, ..] if b & 0xE0 == 0xE0 && b & 0x06 != 0 => Some(Self::Mp3),
+            _ => None,
+        }
+    }
+
+    /// 拡張子からフォーマットを判定する。大文字小文字は区別しない。
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        extension.to_ascii_lowercase().parse().ok()
+    }
+}
+
+/// 音声データを保存するディレクトリ。ライブラリのルートからの相対パス。
+pub const AUDIO_DIR: &str = "audio";
+/// カバー画像を保存するディレクトリ。ライブラリのルートからの相対パス。
+pub const COVER_DIR: &str = "covers";
+
+/// 内容のSHA-256ハッシュ値を計算する。
+/// 大きなデータを少しずつ受け取りながら計算するときに使う。
+#[derive(Clone, Default)]
+pub struct ContentHasher(Sha256);
+
+impl Debug for ContentHasher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ContentHasher")
+    }
+}
+
+impl ContentHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.0.update(chunk);
+    }
+
+    /// ハッシュ値を16進数の文字列で返す
+    pub fn finish(self) -> String {
+        self.0
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+}
+
+/// 内容のSHA-256ハッシュ値を16進数の文字列で返す
+pub fn content_hash(raw: &[u8]) -> String {
+    let mut hasher = ContentHasher::new();
+    hasher.update(raw);
+    hasher.finish()
+}
+
+/// 内容のハッシュ値から保存先の相対パスを生成する。
+/// 同じ内容であれば同じパスになるので、重複して保存されることはない。
+/// * dir: 保存先のディレクトリ。[`AUDIO_DIR`]や[`COVER_DIR`]を期待している。
+/// * raw: 保存する内容
+/// * extension: 拡張子
+pub fn content_addressed_path(dir: &str, raw: &[u8], extension: Option<&str>) -> PathBuf {
+    hash_addressed_path(dir, &content_hash(raw), extension)
+}
+
+/// [`content_hash`]で計算したハッシュ値から保存先の相対パスを生成する。
+pub fn hash_addressed_path(dir: &str, hash: &str, extension: Option<&str>) -> PathBuf {
+    let file_name = match extension {
+        Some(extension) => format!("{hash}.{extension}"),
+        None => hash.to_string(),
+    };
+    PathBuf::from(dir).join(&hash[..2.min(hash.len())]).join(file_name)
+}
+
+/// 音声データ。
+/// 大きなデータなので、メモリ上に持たずにファイルシステム上のファイルを参照することもある。
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct AudioRawData {
-    pub raw: Bytes, // reconstructの際に実際にデータを取る必要はないはずなので、ユースケース次第でOptionにするかも
+    /// メモリ上のデータ。`None`の場合は`save_path`か`staged_path`のファイルにのみ存在する。
+    pub raw: Option<Bytes>,
     pub format: AudioFormat,
+    /// ライブラリのルートからの相対パス
     pub save_path: PathBuf,
+    /// ダウンロード直後の一時ファイル。保存時に`save_path`へ移動する。
+    pub staged_path: Option<PathBuf>,
+    /// 別のフォーマットから変換した場合は、変換前のデータの[`content_hash`]
+    pub original_hash: Option<String>,
+    /// 再生時間や音質。保存するまでは分からないので`None`になる。
+    pub properties: Option<AudioProperties>,
+    /// ラウドネスの解析の状態
+    pub loudness: LoudnessAnalysis,
 }
 
 impl AudioRawData {
     pub fn try_new(raw: Bytes) -> Result<Self, DomainError> {
-        // TODO: ファイルハッシュを計算してファイル名を生成する
-        let save_path = PathBuf::from("");
-        // TODO: ファイルフォーマットの判定
-        let format = AudioFormat::Flac;
+        let format = AudioFormat::detect(&raw).ok_or(DomainError::FailedToCreateAudioRawData)?;
+        let save_path = content_addressed_path(AUDIO_DIR, &raw, Some(&format.to_string()));
+        Ok(Self {
+            raw: Some(raw),
+            format,
+            save_path,
+            staged_path: None,
+            original_hash: None,
+            properties: None,
+            loudness: LoudnessAnalysis::NotAnalyzed,
+        })
+    }
+
+    /// 一時ファイルにダウンロードした音声データから作成する。
+    /// * head: データの先頭部分。フォーマットの判定に使う。
+    /// * hash: データ全体の[`content_hash`]
+    /// * staged_path: 一時ファイルのパス
+    pub fn try_from_staged(
+        head: &[u8],
+        hash: &str,
+        staged_path: PathBuf,
+    ) -> Result<Self, DomainError> {
+        let format = AudioFormat::detect(head).ok_or(DomainError::FailedToCreateAudioRawData)?;
+        let save_path = hash_addressed_path(AUDIO_DIR, hash, Some(&format.to_string()));
         Ok(Self {
-            raw,
+            raw: None,
             format,
             save_path,
+            staged_path: Some(staged_path),
+            original_hash: None,
+            properties: None,
+            loudness: LoudnessAnalysis::NotAnalyzed,
         })
     }
 
-    pub fn reconstruct(raw: Bytes, format: AudioFormat, save_path: PathBuf) -> Self {
+    /// 取得元のパスの拡張子と判定したフォーマットが一致するか確認する。
+    /// 拡張子がないか、未知の拡張子の場合は確認できないので成功とする。
+    /// * source_path: 取得元のパス。URLのパス部分を期待している。
+    pub fn check_extension(&self, source_path: &str) -> Result<(), DomainError> {
+        let expected = std::path::Path::new(source_path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(AudioFormat::from_extension);
+        match expected {
+            Some(expected) if expected != self.format => Err(DomainError::AudioFormatMismatch {
+                detected: self.format,
+                expected,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// WAVをFLACに変換した音声データを作成する。変換前のデータのハッシュ値を残しておく。
+    /// * wav: WAVのデータ
+    pub fn try_from_wav(wav: &[u8]) -> Result<Self, DomainError> {
+        let flac = Bytes::from(transcode::wav_to_flac(wav)?);
+        let save_path =
+            content_addressed_path(AUDIO_DIR, &flac, Some(&AudioFormat::Flac.to_string()));
+        Ok(Self {
+            raw: Some(flac),
+            format: AudioFormat::Flac,
+            save_path,
+            staged_path: None,
+            original_hash: Some(content_hash(wav)),
+            properties: None,
+            loudness: LoudnessAnalysis::NotAnalyzed,
+        })
+    }
+
+    /// 保存済みの音声データを参照する。データはファイルシステムから必要になったときに読み込む。
+    /// * original_hash: 別のフォーマットから変換した場合は、変換前のデータのハッシュ値
+    /// * properties: 保存したときに読み取った再生時間や音質
+    /// * loudness: ラウドネスの解析の状態
+    pub fn reconstruct(
+        format: AudioFormat,
+        save_path: PathBuf,
+        original_hash: Option<String>,
+        properties: Option<AudioProperties>,
+        loudness: LoudnessAnalysis,
+    ) -> Self {
         Self {
-            raw,
+            raw: None,
             format,
             save_path,
+            staged_path: None,
+            original_hash,
+            properties,
+            loudness,
         }
     }
 }
 
-#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Song {
     pub id: SongId,
     pub name: String,
@@ -109,6 +331,8 @@ pub struct Song {
     pub disk_number: u8,
     pub source: AudioRawData,
     pub artists: Vec<String>,
+    /// MSRから取り下げられている
+    pub withdrawn: bool,
 }
 
 impl Song {
@@ -116,18 +340,20 @@ impl Song {
         id: SongId,
         name: String,
         belong_album_id: AlbumId,
-        song_list: &[SongId], // Non-empty
+        discs: &[Vec<SongId>], // アルバムの収録曲。ディスクごとに分かれている。
         source: AudioRawData,
         artists: Vec<String>,
     ) -> Result<Self, DomainError> {
         // TODO: 各引数のバリデーション
-        // TODO: song_listが空でないかチェックする
-        let ix = song_list
+        // トラック番号はディスクごとに1から数える
+        let (disk_number, track_number) = discs
             .iter()
-            .position(|&song_id| song_id == id)
+            .enumerate()
+            .find_map(|(disc_ix, disc)| {
+                let ix = disc.iter().position(|&song_id| song_id == id)?;
+                Some(((disc_ix + 1) as u8, (ix + 1) as u8)) // FIXME: エラーハンドリング
+            })
             .ok_or(DomainError::FailedToGetSongInAlbum {song_id: id, album_id: belong_album_id})?;
-        let track_number = (ix + 1) as u8; // FIXME: エラーハンドリング
-        let disk_number = 1; // 1枚組のアルバム以外ので
         Ok(Self {
             id,
             name,
@@ -136,6 +362,7 @@ impl Song {
             disk_number,
             source,
             artists,
+            withdrawn: false,
         })
     }
 
@@ -147,6 +374,7 @@ impl Song {
         disk_number: u8,
         source: AudioRawData,
         artists: Vec<String>,
+        withdrawn: bool,
     ) -> Result<Self, DomainError> {
         // TODO: 各引数のバリデーション
         Ok(Self {
@@ -157,10 +385,45 @@ impl Song {
             disk_number,
             source,
             artists,
+            withdrawn,
         })
     }
 }
 
+/// アルバムのカバー画像。
+/// 保存済みのアルバムを読み込むときは画像を読み込まず、`save_path`のファイルを参照する。
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverImage {
+    /// メモリ上のデータ。`None`の場合は`save_path`のファイルにのみ存在する。
+    pub raw: Option<Bytes>,
+    /// ライブラリのルートからの相対パス
+    pub save_path: PathBuf,
+}
+
+impl CoverImage {
+    pub fn new(raw: Bytes) -> Self {
+        let save_path = content_addressed_path(COVER_DIR, &raw, None);
+        Self {
+            raw: Some(raw),
+            save_path,
+        }
+    }
+
+    /// 保存済みのカバー画像を参照する
+    pub fn reconstruct(save_path: PathBuf) -> Self {
+        Self {
+            raw: None,
+            save_path,
+        }
+    }
+}
+
+impl Default for CoverImage {
+    fn default() -> Self {
+        Self::new(Bytes::new())
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Album {
     pub id: AlbumId,
@@ -169,12 +432,18 @@ pub struct Album {
     pub total_disks: u8,
     pub intro: String, // サイズ的にはshort stringでいいからもっと効率的なデータ構造がほしい
     pub belong: OriginGame,
-    pub cover_image: Bytes,
+    pub cover_image: CoverImage,
     pub artists: Vec<String>,
-    pub song_list: Vec<SongId>,
+    /// ディスクごとの収録曲
+    pub discs: Vec<Vec<SongId>>,
+    /// MSRから取り下げられている
+    pub withdrawn: bool,
+    /// アルバム全体のラウドネスの解析の状態。収録曲がすべて揃ってから解析する。
+    pub loudness: LoudnessAnalysis,
 }
 
 impl Album {
+    /// * discs: ディスクごとの収録曲。[`crate::domain::disc::split_discs`]で分けたものを期待している。
     pub fn try_new(
         id: AlbumId,
         name: String,
@@ -182,22 +451,29 @@ impl Album {
         belong: OriginGame,
         cover_image: Bytes,
         artists: Vec<String>,
-        song_list: Vec<SongId>,
+        discs: Vec<Vec<SongId>>,
     ) -> Result<Self, DomainError> {
         // TODO: 各引数のバリデーション
         Ok(Self {
             id,
             name,
-            total_tracks: song_list.len() as u8,
-            total_disks: 1, // 1枚組のアルバム以外ないので
+            total_tracks: discs.iter().map(Vec::len).sum::<usize>() as u8,
+            total_disks: discs.len() as u8,
             intro,
             belong,
-            cover_image,
+            cover_image: CoverImage::new(cover_image),
             artists,
-            song_list,
+            discs,
+            withdrawn: false,
+            loudness: LoudnessAnalysis::NotAnalyzed,
         })
     }
 
+    /// すべてのディスクの収録曲を収録順に並べたもの
+    pub fn song_list(&self) -> Vec<SongId> {
+        self.discs.concat()
+    }
+
     pub fn try_reconstruct(
         id: AlbumId,
         name: String,
@@ -205,9 +481,11 @@ impl Album {
         total_disks: u8,
         intro: String,
         belong: OriginGame,
-        cover_image: Bytes,
+        cover_image: CoverImage,
         artists: Vec<String>,
-        song_list: Vec<SongId>,
+        discs: Vec<Vec<SongId>>,
+        withdrawn: bool,
+        loudness: LoudnessAnalysis,
     ) -> Result<Self, DomainError> {
         // TODO: 各引数のバリデーション
         Ok(Self {
@@ -219,12 +497,14 @@ impl Album {
             belong,
             cover_image,
             artists,
-            song_list,
+            discs,
+            withdrawn,
+            loudness,
         })
     }
 }
 
-#[derive(Debug, Clone, Hash, Default, PartialEq, Eq, PartialOrd, Ord, strum::EnumString)]
+#[derive(Debug, Clone, Hash, Default, PartialEq, Eq, PartialOrd, Ord, strum::Display, strum::EnumString)]
 pub enum OriginGame {
     #[default]
     #[strum(serialize = "arknights")]
@@ -237,3 +517,128 @@ impl OriginGame {
             .map_err(|_| DomainError::FailedToParseOriginGame { s: s.clone() })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{AlbumId, AudioFormat, AudioRawData, Song, SongId};
+    use crate::errors::domain::DomainError;
+    use bytes::Bytes;
+
+    #[test]
+    fn test_detect_audio_format() {
+        assert_eq!(AudioFormat::detect(b"fLaC\0\0\0\x22"), Some(AudioFormat::Flac));
+        assert_eq!(
+            AudioFormat::detect(b"RIFF\x24\0\0\0WAVEfmt "),
+            Some(AudioFormat::Wav)
+        );
+        assert_eq!(AudioFormat::detect(b"ID3\x04\0\0"), Some(AudioFormat::Mp3));
+        assert_eq!(AudioFormat::detect(&[0xFF, 0xFB, 0x90, 0x64]), Some(AudioFormat::Mp3));
+        assert_eq!(AudioFormat::detect(b"RIFF\x24\0\0\0AVI "), None);
+        // AACのADTSヘッダ
+        assert_eq!(AudioFormat::detect(&[0xFF, 0xF1, 0x50, 0x80]), None);
+        assert_eq!(AudioFormat::detect(&[0xFF, 0xF9, 0x50, 0x80]), None);
+        assert_eq!(AudioFormat::detect(b""), None);
+    }
+
+    #[test]
+    fn test_try_new_unknown_format() {
+        assert_eq!(
+            AudioRawData::try_new(Bytes::from_static(b"<html>")),
+            Err(DomainError::FailedToCreateAudioRawData)
+        );
+    }
+
+    #[test]
+    fn test_check_extension() {
+        let raw = AudioRawData::try_new(Bytes::from_static(b"fLaC\0\0\0\x22")).unwrap();
+        assert_eq!(raw.check_extension("/music/song.flac"), Ok(()));
+        assert_eq!(raw.check_extension("/music/song.FLAC"), Ok(()));
+        assert_eq!(raw.check_extension("/music/song"), Ok(()));
+        assert_eq!(
+            raw.check_extension("/music/song.wav"),
+            Err(DomainError::AudioFormatMismatch {
+                detected: AudioFormat::Flac,
+                expected: AudioFormat::Wav,
+            })
+        );
+    }
+
+    #[test]
+    fn test_song_id() {
+        assert_eq!(
+            SongId::try_new("000001".into()),
+            Ok(SongId::new(1).unwrap())
+        );
+        assert_eq!(
+            SongId::try_new("953011".into()),
+            Ok(SongId::new(953011).unwrap())
+        );
+        assert_eq!(SongId::new(999999).unwrap().to_msr_string(), "999999");
+        assert_eq!(SongId::new(1).unwrap().to_msr_string(), "000001");
+        for s in ["", "1000000", "12a", "+1", " 1"] {
+            assert_eq!(
+                SongId::try_new(s.into()),
+                Err(DomainError::FailedToParseSongId { s: s.into() })
+            );
+        }
+        assert_eq!(
+            SongId::new(1_000_000),
+            Err(DomainError::SongIdOutOfRange { id: 1_000_000 })
+        );
+        assert_eq!(
+            serde_json::from_str::<SongId>("953011").unwrap(),
+            SongId::new(953011).unwrap()
+        );
+        assert!(serde_json::from_str::<SongId>("1000000").is_err());
+    }
+
+    #[test]
+    fn test_album_id() {
+        assert_eq!(
+            AlbumId::try_new("0249".into()),
+            Ok(AlbumId::new(249).unwrap())
+        );
+        assert_eq!(AlbumId::new(249).unwrap().to_msr_string(), "0249");
+        assert_eq!(
+            AlbumId::try_new("12345".into()),
+            Err(DomainError::FailedToParseAlbumId { s: "12345".into() })
+        );
+        assert_eq!(
+            AlbumId::new(10000),
+            Err(DomainError::AlbumIdOutOfRange { id: 10000 })
+        );
+        assert!(serde_json::from_str::<AlbumId>("10000").is_err());
+    }
+
+    #[test]
+    fn test_song_try_new_multi_disc() {
+        let id = |id| SongId::new(id).unwrap();
+        let album_id = AlbumId::new(1).unwrap();
+        let discs = vec![vec![id(1), id(2)], vec![id(3), id(4)]];
+        let song = Song::try_new(
+            id(4),
+            "song".into(),
+            album_id,
+            &discs,
+            AudioRawData::default(),
+            vec![],
+        )
+        .unwrap();
+        assert_eq!((song.disk_number, song.track_number), (2, 2));
+
+        assert_eq!(
+            Song::try_new(
+                id(5),
+                "song".into(),
+                album_id,
+                &discs,
+                AudioRawData::default(),
+                vec![]
+            ),
+            Err(DomainError::FailedToGetSongInAlbum {
+                song_id: id(5),
+                album_id,
+            })
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use crate::domain::song::SongId;
+use std::time::Duration;
+
+/// 同期の結果。楽曲ごとの成否と、各段階にかかった時間をまとめる。
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct SyncReport {
+    /// 保存できた楽曲
+    pub succeeded: Vec<SongId>,
+    /// 他の楽曲の失敗で同期を打ち切ったため、取得しなかった楽曲
+    pub skipped: Vec<SongId>,
+    /// 取得や保存に失敗した楽曲
+    pub failed: Vec<SyncFailure>,
+    /// 各段階にかかった時間。実行した順に並ぶ。
+    pub phases: Vec<PhaseTiming>,
+    /// ダウンロードした音声データの合計バイト数
+    pub bytes_downloaded: u64,
+}
+
+/// 同期に失敗した楽曲
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct SyncFailure {
+    pub id: SongId,
+    /// エラーの原因。外側のエラーから順に並ぶ。
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct PhaseTiming {
+    pub phase: String,
+    pub elapsed_ms: u64,
+}
+
+impl SyncFailure {
+    pub fn new(id: SongId, error: &anyhow::Error) -> Self {
+        Self {
+            id,
+            errors: error.chain().map(ToString::to_string).collect(),
+        }
+    }
+}
+
+impl SyncReport {
+    /// 段階にかかった時間を記録する
+    pub fn record_phase(&mut self, phase: &str, elapsed: Duration) {
+        self.phases.push(PhaseTiming {
+            phase: phase.to_string(),
+            elapsed_ms: u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+        });
+    }
+
+    /// すべての段階にかかった時間の合計
+    pub fn elapsed_ms(&self) -> u64 {
+        self.phases.iter().map(|phase| phase.elapsed_ms).sum()
+    }
+
+    /// 失敗もスキップもなく同期できた
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty() && self.skipped.is_empty()
+    }
+}
+
+/// 過去の同期の記録
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct SyncRun {
+    pub id: u32,
+    /// 記録した日時。ISO 8601形式。
+    pub created_at: String,
+    pub report: SyncReport,
+}
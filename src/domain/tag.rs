@@ -0,0 +1,194 @@
+pub mod flac;
+pub mod id3;
+
+use crate::domain::loudness::Loudness;
+use crate::domain::song::{Album, AlbumId, AudioFormat, Song, SongId};
+use crate::errors::domain::DomainError;
+use bytes::Bytes;
+
+/// ReplayGainのタグのキー。FLACのコメントのキーとMP3のTXXXフレームの説明で共通。
+pub const REPLAY_GAIN_KEYS: [&str; 4] = [
+    "REPLAYGAIN_TRACK_GAIN",
+    "REPLAYGAIN_TRACK_PEAK",
+    "REPLAYGAIN_ALBUM_GAIN",
+    "REPLAYGAIN_ALBUM_PEAK",
+];
+
+/// 音声データに元から書き込まれているタグの扱い
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    strum::Display,
+    strum::EnumString,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum TagPolicy {
+    /// 書き込むタグだけを置き換え、それ以外の既存のタグは残す
+    #[default]
+    Merge,
+    /// 既存のタグをすべて削除してから書き込む
+    Strip,
+}
+
+/// 音声データの先頭にあるタグ部分の長さ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataLen {
+    /// タグ部分の長さ。タグがなければ0になる。
+    Complete(usize),
+    /// 判定するには先頭からこの長さまで必要
+    NeedMore(usize),
+}
+
+/// フォーマットごとのタグの書き込み。
+/// タグは音声データの先頭に置くフォーマットだけを扱うので、先頭のタグ部分だけを書き換えればよい。
+pub trait Tagger: Send + Sync {
+    /// 音声データの先頭部分からタグ部分の長さを判定する
+    fn metadata_len(&self, head: &[u8]) -> Result<MetadataLen, DomainError>;
+
+    /// タグを書き込んだ音声データを返す
+    /// * raw: 音声データ。[`Tagger::metadata_len`]で判定したタグ部分だけでもよい。
+    fn write_tags(
+        &self,
+        raw: &[u8],
+        tags: &TrackTags,
+        policy: TagPolicy,
+    ) -> Result<Vec<u8>, DomainError>;
+}
+
+/// フォーマットに対応する[`Tagger`]を返す。タグを書き込まないフォーマットは`None`を返す。
+pub fn tagger(format: AudioFormat) -> Option<&'static dyn Tagger> {
+    match format {
+        AudioFormat::Flac => Some(&flac::FlacTagger),
+        AudioFormat::Mp3 => Some(&id3::Id3Tagger),
+        AudioFormat::Wav => None,
+    }
+}
+
+/// 音声ファイルに書き込むタグ。フォーマットによらない形で持つ。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackTags {
+    pub title: String,
+    pub album: String,
+    /// 楽曲のアーティスト。クレジットの順に並ぶ。
+    pub artists: Vec<String>,
+    /// アルバムのアーティスト
+    pub album_artists: Vec<String>,
+    /// ディスク内のトラック番号
+    pub track_number: u8,
+    /// アルバム全体の曲数
+    pub track_total: u8,
+    pub disc_number: u8,
+    pub disc_total: u8,
+    pub song_id: SongId,
+    pub album_id: AlbumId,
+    /// アルバムのカバー画像。空の場合は埋め込まない。
+    pub cover_image: Bytes,
+    /// 楽曲のラウドネス。測定していない場合はReplayGainのタグを書き込まない。
+    pub track_loudness: Option<Loudness>,
+    /// アルバム全体のラウドネス
+    pub album_loudness: Option<Loudness>,
+}
+
+impl TrackTags {
+    /// * album: 楽曲が所属するアルバム
+    /// * cover_image: アルバムのカバー画像。保存済みのアルバムの画像は読み込まれていないので別に渡す。
+    pub fn new(song: &Song, album: &Album, cover_image: Bytes) -> Self {
+        Self {
+            title: song.name.clone(),
+            album: album.name.clone(),
+            artists: song.artists.clone(),
+            album_artists: album.artists.clone(),
+            track_number: song.track_number,
+            track_total: album.total_tracks,
+            disc_number: song.disk_number,
+            disc_total: album.total_disks,
+            song_id: song.id,
+            album_id: album.id,
+            cover_image,
+            track_loudness: song.source.loudness.measured(),
+            album_loudness: album.loudness.measured(),
+        }
+    }
+
+    /// 書き込むReplayGainのタグのキーと値。測定していないものは含めない。
+    pub fn replay_gain(&self) -> Vec<(&'static str, String)> {
+        let [track_gain, track_peak, album_gain, album_peak] = REPLAY_GAIN_KEYS;
+        let mut tags = vec![];
+        if let Some(loudness) = self.track_loudness {
+            tags.push((track_gain, loudness.gain_tag()));
+            tags.push((track_peak, loudness.peak_tag()));
+        }
+        if let Some(loudness) = self.album_loudness {
+            tags.push((album_gain, loudness.gain_tag()));
+            tags.push((album_peak, loudness.peak_tag()));
+        }
+        tags
+    }
+
+    /// 既存のタグのうち、書き込む値で置き換えるReplayGainのタグか。
+    /// 楽曲を測定済みであれば、古い値が混ざらないようアルバムの分も含めてすべて置き換える。
+    pub fn replaces_replay_gain(&self, key: &str) -> bool {
+        self.track_loudness.is_some()
+            && REPLAY_GAIN_KEYS
+                .iter()
+                .any(|replay_gain| key.eq_ignore_ascii_case(replay_gain))
+    }
+}
+
+/// カバー画像の先頭からMIMEタイプを判定する。判定できなければ`None`を返す。
+pub fn image_mime_type(image: &[u8]) -> Option<&'static str> {
+    if image.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if image.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if image.len() >= 12 && &image[..4] == b"RIFF" && &image[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TrackTags;
+    use crate::domain::song::{Album, AlbumId, Song, SongId};
+    use bytes::Bytes;
+
+    #[test]
+    fn test_track_tags_multi_disc() {
+        let album = Album {
+            id: AlbumId::new(1).unwrap(),
+            name: "album".into(),
+            total_tracks: 3,
+            total_disks: 2,
+            artists: vec!["album artist".into()],
+            discs: vec![
+                vec![SongId::new(1).unwrap(), SongId::new(2).unwrap()],
+                vec![SongId::new(3).unwrap()],
+            ],
+            ..Default::default()
+        };
+        let song = Song {
+            id: SongId::new(3).unwrap(),
+            name: "song".into(),
+            belong_album_id: AlbumId::new(1).unwrap(),
+            track_number: 1,
+            disk_number: 2,
+            artists: vec!["artist".into()],
+            ..Default::default()
+        };
+        let tags = TrackTags::new(&song, &album, Bytes::new());
+        assert_eq!(tags.track_number, 1);
+        assert_eq!(tags.track_total, 3);
+        assert_eq!(tags.disc_number, 2);
+        assert_eq!(tags.disc_total, 2);
+        assert_eq!(tags.album_artists, vec!["album artist".to_string()]);
+    }
+}
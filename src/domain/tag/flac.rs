@@ -0,0 +1,601 @@
+//! FLACのメタデータブロックの読み書き。
+//! タグはVORBIS_COMMENTブロックに、カバー画像はPICTUREブロックに書き込む。
+//! 音声フレームには触れないので、メタデータ部分だけを渡してもよい。
+use crate::domain::song::AudioFormat;
+use crate::domain::tag::{image_mime_type, MetadataLen, TagPolicy, Tagger, TrackTags};
+use crate::errors::domain::DomainError;
+use bytes::Bytes;
+
+/// FLACのファイルの先頭
+pub const MAGIC: &[u8; 4] = b"fLaC";
+/// メタデータブロックのヘッダの長さ
+pub const BLOCK_HEADER_LEN: usize = 4;
+
+const STREAMINFO: u8 = 0;
+const PADDING: u8 = 1;
+const VORBIS_COMMENT: u8 = 4;
+const PICTURE: u8 = 6;
+/// メタデータブロックの長さの上限。長さは24bitで表す。
+const MAX_BLOCK_LEN: usize = (1 << 24) - 1;
+/// PICTUREブロックの画像の種類のうち、表紙を表す値
+const FRONT_COVER: u32 = 3;
+/// 既存のVORBIS_COMMENTブロックがない場合に使うベンダー文字列
+const VENDOR: &str = "msr";
+/// 書き込むタグのキー。[`TagPolicy::Merge`]でもこれらの既存の値は残さない。
+const MANAGED_KEYS: &[&str] = &[
+    "TITLE",
+    "ALBUM",
+    "ARTIST",
+    "ALBUMARTIST",
+    "TRACKNUMBER",
+    "TRACKTOTAL",
+    "DISCNUMBER",
+    "DISCTOTAL",
+    "MSR_SONG_ID",
+    "MSR_ALBUM_ID",
+];
+
+/// FLACの[`Tagger`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FlacTagger;
+
+impl Tagger for FlacTagger {
+    fn metadata_len(&self, head: &[u8]) -> Result<MetadataLen, DomainError> {
+        if head.len() < MAGIC.len() {
+            return Ok(MetadataLen::NeedMore(MAGIC.len()));
+        }
+        if !head.starts_with(MAGIC) {
+            return Err(malformed("missing fLaC marker"));
+        }
+        let mut offset = MAGIC.len();
+        loop {
+            let Some(header) = head.get(offset..offset + BLOCK_HEADER_LEN) else {
+                return Ok(MetadataLen::NeedMore(offset + BLOCK_HEADER_LEN));
+            };
+            let header = BlockHeader::parse([header[0], header[1], header[2], header[3]]);
+            offset += BLOCK_HEADER_LEN + header.len;
+            if header.is_last {
+                return Ok(MetadataLen::Complete(offset));
+            }
+        }
+    }
+
+    fn write_tags(
+        &self,
+        raw: &[u8],
+        tags: &TrackTags,
+        policy: TagPolicy,
+    ) -> Result<Vec<u8>, DomainError> {
+        write_tags(raw, tags, policy)
+    }
+}
+
+/// メタデータブロックのヘッダ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    /// 最後のメタデータブロックか
+    pub is_last: bool,
+    pub block_type: u8,
+    /// ヘッダを除いたブロックの長さ
+    pub len: usize,
+}
+
+impl BlockHeader {
+    pub fn parse(header: [u8; BLOCK_HEADER_LEN]) -> Self {
+        Self {
+            is_last: header[0] & 0x80 != 0,
+            block_type: header[0] & 0x7F,
+            len: u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize,
+        }
+    }
+}
+
+/// FLACから読み出したタグ
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlacTags {
+    pub vendor: String,
+    /// `KEY=value`のキーと値。書き込まれている順に並ぶ。
+    pub comments: Vec<(String, String)>,
+    pub pictures: Vec<Picture>,
+}
+
+impl FlacTags {
+    /// `key`の値をすべて返す。キーの大文字小文字は区別しない。
+    pub fn get(&self, key: &str) -> Vec<&str> {
+        self.comments
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+}
+
+/// PICTUREブロックの画像
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Picture {
+    pub picture_type: u32,
+    pub mime_type: String,
+    pub description: String,
+    pub data: Bytes,
+}
+
+struct Block<'a> {
+    block_type: u8,
+    data: &'a [u8],
+}
+
+/// タグを書き込んだFLACを返す。
+/// 既存のVORBIS_COMMENTブロックとPICTUREブロックは置き換え、それ以外のブロックと音声フレームはそのまま残す。
+/// [`TagPolicy::Merge`]の場合は、既存のタグのうち書き込まないキーのものと表紙以外の画像を引き継ぐ。
+/// * flac: FLACのデータ。メタデータブロックの部分だけでもよい。
+pub fn write_tags(
+    flac: &[u8],
+    tags: &TrackTags,
+    policy: TagPolicy,
+) -> Result<Vec<u8>, DomainError> {
+    let (blocks, audio_offset) = parse_blocks(flac)?;
+    let mut vendor = None;
+    let mut kept_comments = vec![];
+    let mut kept_pictures = vec![];
+    // 壊れている既存のブロックは引き継がずに捨てる
+    for block in blocks.iter() {
+        match block.block_type {
+            VORBIS_COMMENT => {
+                let Ok((existing_vendor, comments)) = parse_vorbis_comment(block.data) else {
+                    continue;
+                };
+                vendor.get_or_insert(existing_vendor);
+                kept_comments.extend(comments.into_iter().filter(|(key, _)| {
+                    !MANAGED_KEYS
+                        .iter()
+                        .any(|managed| key.eq_ignore_ascii_case(managed))
+                        && !tags.replaces_replay_gain(key)
+                }));
+            }
+            PICTURE => {
+                let Ok(picture) = parse_picture(block.data) else {
+                    continue;
+                };
+                if picture.picture_type != FRONT_COVER || tags.cover_image.is_empty() {
+                    kept_pictures.push(block.data.to_vec());
+                }
+            }
+            _ => {}
+        }
+    }
+    if policy == TagPolicy::Strip {
+        kept_comments.clear();
+        kept_pictures.clear();
+    }
+
+    let mut comments = comments(tags)
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect::<Vec<_>>();
+    comments.extend(kept_comments);
+    let vendor = vendor.unwrap_or_else(|| VENDOR.to_string());
+    let mut new_blocks = vec![(VORBIS_COMMENT, vorbis_comment(&vendor, &comments))];
+    if let Some(mime_type) = image_mime_type(&tags.cover_image) {
+        new_blocks.push((PICTURE, picture(mime_type, &tags.cover_image)));
+    }
+    new_blocks.extend(kept_pictures.into_iter().map(|data| (PICTURE, data)));
+    // パディングは最後に置くのが一般的なので、追加するブロックはパディングの前に入れる
+    let kept = blocks
+        .iter()
+        .filter(|block| !matches!(block.block_type, VORBIS_COMMENT | PICTURE))
+        .map(|block| (block.block_type, block.data.to_vec()))
+        .collect::<Vec<_>>();
+    let insert_at = kept
+        .iter()
+        .position(|(block_type, _)| *block_type == PADDING)
+        .unwrap_or(kept.len());
+    let mut all_blocks = kept;
+    all_blocks.splice(insert_at..insert_at, new_blocks);
+
+    let mut out = MAGIC.to_vec();
+    let last = all_blocks.len() - 1;
+    for (ix, (block_type, data)) in all_blocks.iter().enumerate() {
+        if data.len() > MAX_BLOCK_LEN {
+            return Err(DomainError::TagTooLarge {
+                format: AudioFormat::Flac,
+                len: data.len(),
+            });
+        }
+        let flag = if ix == last { 0x80 } else { 0 };
+        out.push(flag | block_type);
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+        out.extend_from_slice(data);
+    }
+    out.extend_from_slice(&flac[audio_offset..]);
+    Ok(out)
+}
+
+/// FLACに書き込まれているタグを読み出す
+pub fn read_tags(flac: &[u8]) -> Result<FlacTags, DomainError> {
+    let (blocks, _) = parse_blocks(flac)?;
+    let mut tags = FlacTags::default();
+    for block in blocks {
+        match block.block_type {
+            VORBIS_COMMENT => {
+                let (vendor, comments) = parse_vorbis_comment(block.data)?;
+                tags.vendor = vendor;
+                tags.comments.extend(comments);
+            }
+            PICTURE => tags.pictures.push(parse_picture(block.data)?),
+            _ => {}
+        }
+    }
+    Ok(tags)
+}
+
+/// メタデータブロックを読み、音声フレームの開始位置とともに返す
+fn parse_blocks(flac: &[u8]) -> Result<(Vec<Block<'_>>, usize), DomainError> {
+    if !flac.starts_with(MAGIC) {
+        return Err(malformed("missing fLaC marker"));
+    }
+    let mut blocks = vec![];
+    let mut offset = MAGIC.len();
+    loop {
+        let header = flac
+            .get(offset..offset + BLOCK_HEADER_LEN)
+            .ok_or_else(|| malformed("truncated block header"))?;
+        let header = BlockHeader::parse([header[0], header[1], header[2], header[3]]);
+        offset += BLOCK_HEADER_LEN;
+        let data = flac
+            .get(offset..offset + header.len)
+            .ok_or_else(|| malformed("truncated block"))?;
+        offset += header.len;
+        blocks.push(Block {
+            block_type: header.block_type,
+            data,
+        });
+        if header.is_last {
+            break;
+        }
+    }
+    if blocks.first().map(|block| block.block_type) != Some(STREAMINFO) {
+        return Err(malformed("first block is not STREAMINFO"));
+    }
+    Ok((blocks, offset))
+}
+
+/// 書き込むタグの`KEY=value`の一覧
+fn comments(tags: &TrackTags) -> Vec<(&'static str, String)> {
+    let mut comments = vec![("TITLE", tags.title.clone()), ("ALBUM", tags.album.clone())];
+    comments.extend(tags.artists.iter().map(|artist| ("ARTIST", artist.clone())));
+    comments.extend(
+        tags.album_artists
+            .iter()
+            .map(|artist| ("ALBUMARTIST", artist.clone())),
+    );
+    comments.extend([
+        ("TRACKNUMBER", tags.track_number.to_string()),
+        ("TRACKTOTAL", tags.track_total.to_string()),
+        ("DISCNUMBER", tags.disc_number.to_string()),
+        ("DISCTOTAL", tags.disc_total.to_string()),
+        ("MSR_SONG_ID", tags.song_id.to_msr_string()),
+        ("MSR_ALBUM_ID", tags.album_id.to_msr_string()),
+    ]);
+    comments.extend(tags.replay_gain());
+    comments
+}
+
+/// VORBIS_COMMENTブロックの内容を作る。長さはリトルエンディアンで表す。
+fn vorbis_comment(vendor: &str, comments: &[(String, String)]) -> Vec<u8> {
+    let mut data = vec![];
+    push_le_string(&mut data, vendor);
+    data.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for (key, value) in comments {
+        push_le_string(&mut data, &format!("{key}={value}"));
+    }
+    data
+}
+
+fn push_le_string(data: &mut Vec<u8>, s: &str) {
+    data.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    data.extend_from_slice(s.as_bytes());
+}
+
+/// PICTUREブロックの内容を作る。長さなどはビッグエンディアンで表す。
+/// 画像の幅や高さは読み取らず、不明を表す0にする。
+fn picture(mime_type: &str, image: &[u8]) -> Vec<u8> {
+    let mut data = vec![];
+    data.extend_from_slice(&FRONT_COVER.to_be_bytes());
+    push_be_bytes(&mut data, mime_type.as_bytes());
+    push_be_bytes(&mut data, b"");
+    // 幅、高さ、色深度、インデックスカラーの色数
+    for _ in 0..4 {
+        data.extend_from_slice(&0u32.to_be_bytes());
+    }
+    push_be_bytes(&mut data, image);
+    data
+}
+
+fn push_be_bytes(data: &mut Vec<u8>, bytes: &[u8]) {
+    data.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    data.extend_from_slice(bytes);
+}
+
+fn parse_vorbis_comment(data: &[u8]) -> Result<(String, Vec<(String, String)>), DomainError> {
+    let mut reader = Reader { data, offset: 0 };
+    let vendor = reader.string(u32::from_le_bytes)?;
+    let count = reader.u32(u32::from_le_bytes)?;
+    let mut comments = vec![];
+    for _ in 0..count {
+        let comment = reader.string(u32::from_le_bytes)?;
+        let (key, value) = comment
+            .split_once('=')
+            .ok_or_else(|| malformed("comment without '='"))?;
+        comments.push((key.to_string(), value.to_string()));
+    }
+    Ok((vendor, comments))
+}
+
+fn parse_picture(data: &[u8]) -> Result<Picture, DomainError> {
+    let mut reader = Reader { data, offset: 0 };
+    let picture_type = reader.u32(u32::from_be_bytes)?;
+    let mime_type = reader.string(u32::from_be_bytes)?;
+    let description = reader.string(u32::from_be_bytes)?;
+    // 幅、高さ、色深度、インデックスカラーの色数
+    for _ in 0..4 {
+        reader.u32(u32::from_be_bytes)?;
+    }
+    let data = reader.bytes(u32::from_be_bytes)?;
+    Ok(Picture {
+        picture_type,
+        mime_type,
+        description,
+        data: Bytes::copy_from_slice(data),
+    })
+}
+
+/// ブロックの内容を先頭から順に読む
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DomainError> {
+        let taken = self
+            .data
+            .get(self.offset..self.offset + len)
+            .ok_or_else(|| malformed("truncated block content"))?;
+        self.offset += len;
+        Ok(taken)
+    }
+
+    /// * endian: バイト列を数値にする関数。ブロックの種類によってエンディアンが異なる。
+    fn u32(&mut self, endian: fn([u8; 4]) -> u32) -> Result<u32, DomainError> {
+        let bytes = self.take(4)?;
+        Ok(endian([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn bytes(&mut self, endian: fn([u8; 4]) -> u32) -> Result<&'a [u8], DomainError> {
+        let len = self.u32(endian)? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self, endian: fn([u8; 4]) -> u32) -> Result<String, DomainError> {
+        let bytes = self.bytes(endian)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| malformed("invalid UTF-8"))
+    }
+}
+
+fn malformed(reason: &str) -> DomainError {
+    DomainError::MalformedTag {
+        format: AudioFormat::Flac,
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_tags, write_tags, BlockHeader, FlacTagger, FRONT_COVER, MAGIC};
+    use crate::domain::loudness::Loudness;
+    use crate::domain::song::{AlbumId, SongId};
+    use crate::domain::tag::{MetadataLen, TagPolicy, Tagger, TrackTags};
+    use crate::errors::domain::DomainError;
+    use bytes::Bytes;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\ncover image";
+    const AUDIO: &[u8] = b"\xFF\xF8audio frames";
+
+    /// STREAMINFO、既存のVORBIS_COMMENT、PADDINGからなるFLAC
+    fn flac() -> Vec<u8> {
+        let mut flac = MAGIC.to_vec();
+        flac.extend_from_slice(&[0x00, 0, 0, 34]);
+        flac.extend_from_slice(&[0u8; 34]);
+        let comment = b"\x06\0\0\0vendor\x02\0\0\0\x09\0\0\0TITLE=old\x0a\0\0\0GENRE=game";
+        flac.extend_from_slice(&[0x04, 0, 0, comment.len() as u8]);
+        flac.extend_from_slice(comment);
+        flac.extend_from_slice(&[0x81, 0, 0, 8]);
+        flac.extend_from_slice(&[0u8; 8]);
+        flac.extend_from_slice(AUDIO);
+        flac
+    }
+
+    fn tags() -> TrackTags {
+        TrackTags {
+            title: "song".into(),
+            album: "album".into(),
+            artists: vec!["artist".into(), "featured".into()],
+            album_artists: vec!["塞壬唱片-MSR".into()],
+            track_number: 2,
+            track_total: 10,
+            disc_number: 1,
+            disc_total: 1,
+            song_id: SongId::new(48794).unwrap(),
+            album_id: AlbumId::new(249).unwrap(),
+            cover_image: Bytes::from_static(PNG),
+            ..Default::default()
+        }
+    }
+
+    fn loudness(integrated_lufs: f64, true_peak: f64) -> Option<Loudness> {
+        Some(Loudness {
+            integrated_lufs,
+            true_peak,
+        })
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let tagged = write_tags(&flac(), &tags(), TagPolicy::Merge).unwrap();
+        assert!(tagged.ends_with(AUDIO));
+
+        let read = read_tags(&tagged).unwrap();
+        assert_eq!(read.vendor, "vendor");
+        assert_eq!(read.get("TITLE"), vec!["song"]);
+        assert_eq!(read.get("GENRE"), vec!["game"]);
+        assert_eq!(read.get("ALBUM"), vec!["album"]);
+        assert_eq!(read.get("ARTIST"), vec!["artist", "featured"]);
+        assert_eq!(read.get("ALBUMARTIST"), vec!["塞壬唱片-MSR"]);
+        assert_eq!(read.get("TRACKNUMBER"), vec!["2"]);
+        assert_eq!(read.get("TRACKTOTAL"), vec!["10"]);
+        assert_eq!(read.get("DISCNUMBER"), vec!["1"]);
+        assert_eq!(read.get("DISCTOTAL"), vec!["1"]);
+        assert_eq!(read.get("MSR_SONG_ID"), vec!["048794"]);
+        assert_eq!(read.get("MSR_ALBUM_ID"), vec!["0249"]);
+        assert_eq!(read.pictures.len(), 1);
+        assert_eq!(read.pictures[0].picture_type, FRONT_COVER);
+        assert_eq!(read.pictures[0].mime_type, "image/png");
+        assert_eq!(read.pictures[0].data, Bytes::from_static(PNG));
+    }
+
+    #[test]
+    fn test_rewrite_replaces_blocks() {
+        let tagged = write_tags(&flac(), &tags(), TagPolicy::Merge).unwrap();
+        let retagged = write_tags(
+            &tagged,
+            &TrackTags {
+                title: "renamed".into(),
+                ..tags()
+            },
+            TagPolicy::Merge,
+        )
+        .unwrap();
+        let read = read_tags(&retagged).unwrap();
+        assert_eq!(read.get("TITLE"), vec!["renamed"]);
+        assert_eq!(read.pictures.len(), 1);
+        // 同じタグを書き込んでも内容は変わらない
+        assert_eq!(
+            write_tags(&tagged, &tags(), TagPolicy::Merge).unwrap(),
+            tagged
+        );
+    }
+
+    #[test]
+    fn test_block_order() {
+        let tagged = write_tags(&flac(), &tags(), TagPolicy::Merge).unwrap();
+        let mut offset = MAGIC.len();
+        let mut block_types = vec![];
+        loop {
+            let header = BlockHeader::parse([
+                tagged[offset],
+                tagged[offset + 1],
+                tagged[offset + 2],
+                tagged[offset + 3],
+            ]);
+            block_types.push(header.block_type);
+            offset += 4 + header.len;
+            if header.is_last {
+                break;
+            }
+        }
+        // STREAMINFO、VORBIS_COMMENT、PICTURE、PADDINGの順になる
+        assert_eq!(block_types, vec![0, 4, 6, 1]);
+        assert_eq!(&tagged[offset..], AUDIO);
+    }
+
+    #[test]
+    fn test_replay_gain() {
+        // 測定していなければ既存のReplayGainのタグを残す
+        let existing = write_tags(
+            &flac(),
+            &TrackTags {
+                track_loudness: loudness(-12.0, 0.5),
+                ..tags()
+            },
+            TagPolicy::Merge,
+        )
+        .unwrap();
+        let read = read_tags(&write_tags(&existing, &tags(), TagPolicy::Merge).unwrap()).unwrap();
+        assert_eq!(read.get("REPLAYGAIN_TRACK_GAIN"), vec!["-6.00 dB"]);
+
+        // 測定済みなら、アルバムの分も含めて置き換える
+        let tagged = write_tags(
+            &existing,
+            &TrackTags {
+                track_loudness: loudness(-9.5, 0.988553),
+                album_loudness: loudness(-20.25, 1.02),
+                ..tags()
+            },
+            TagPolicy::Merge,
+        )
+        .unwrap();
+        let read = read_tags(&tagged).unwrap();
+        assert_eq!(read.get("REPLAYGAIN_TRACK_GAIN"), vec!["-8.50 dB"]);
+        assert_eq!(read.get("REPLAYGAIN_TRACK_PEAK"), vec!["0.988553"]);
+        assert_eq!(read.get("REPLAYGAIN_ALBUM_GAIN"), vec!["2.25 dB"]);
+        assert_eq!(read.get("REPLAYGAIN_ALBUM_PEAK"), vec!["1.020000"]);
+    }
+
+    #[test]
+    fn test_strip_policy() {
+        let tagged = write_tags(&flac(), &tags(), TagPolicy::Strip).unwrap();
+        let read = read_tags(&tagged).unwrap();
+        assert_eq!(read.get("TITLE"), vec!["song"]);
+        assert!(read.get("GENRE").is_empty());
+    }
+
+    #[test]
+    fn test_metadata_len() {
+        let tagged = write_tags(&flac(), &tags(), TagPolicy::Merge).unwrap();
+        assert_eq!(
+            FlacTagger.metadata_len(&tagged[..6]).unwrap(),
+            MetadataLen::NeedMore(8)
+        );
+        assert_eq!(
+            FlacTagger.metadata_len(&tagged).unwrap(),
+            MetadataLen::Complete(tagged.len() - AUDIO.len())
+        );
+    }
+
+    #[test]
+    fn test_metadata_only() {
+        // 音声フレームがなくてもメタデータだけ書き換えられる
+        let flac = flac();
+        let metadata = &flac[..flac.len() - AUDIO.len()];
+        let tagged = write_tags(metadata, &tags(), TagPolicy::Merge).unwrap();
+        assert_eq!(read_tags(&tagged).unwrap().get("TITLE"), vec!["song"]);
+    }
+
+    #[test]
+    fn test_without_cover() {
+        let tagged = write_tags(
+            &flac(),
+            &TrackTags {
+                cover_image: Bytes::new(),
+                ..tags()
+            },
+            TagPolicy::Merge,
+        )
+        .unwrap();
+        assert!(read_tags(&tagged).unwrap().pictures.is_empty());
+    }
+
+    #[test]
+    fn test_not_flac() {
+        assert!(matches!(
+            write_tags(b"ID3\x04", &tags(), TagPolicy::Merge),
+            Err(DomainError::MalformedTag { .. })
+        ));
+        let mut truncated = flac();
+        truncated.truncate(20);
+        assert!(matches!(
+            read_tags(&truncated),
+            Err(DomainError::MalformedTag { .. })
+        ));
+    }
+}
@@ -0,0 +1,712 @@
+//! MP3のID3v2.4タグの読み書き。
+//! タグは音声データの先頭に書き込み、末尾のID3v1タグには触れない。
+//! 既存のタグはID3v2.3と2.4のものを読み、それ以外のバージョンのものは引き継がない。
+//! ID3v2.3のタグから引き継ぐフレームはID3v2.4の形式に変換し、非同期化されたタグは元に戻してから読む。
+use crate::domain::song::AudioFormat;
+use crate::domain::tag::{image_mime_type, MetadataLen, TagPolicy, Tagger, TrackTags};
+use crate::errors::domain::DomainError;
+
+/// ID3v2タグの先頭
+pub const MAGIC: &[u8; 3] = b"ID3";
+/// タグのヘッダとフレームのヘッダの長さ。フッタも同じ長さ。
+pub const HEADER_LEN: usize = 10;
+
+/// 書き込むタグのメジャーバージョン
+const VERSION: u8 = 4;
+const FLAG_UNSYNCHRONISATION: u8 = 0x80;
+const FLAG_EXTENDED_HEADER: u8 = 0x40;
+const FLAG_FOOTER: u8 = 0x10;
+/// ID3v2.4のフレームのフラグのうち、内容を読めなくするもの（グループ化、圧縮、暗号化）
+const V4_FORMAT_FLAGS: u16 = 0x004C;
+/// ID3v2.4のフレームのフラグのうち、フレームが非同期化されていることを表すもの
+const V4_FRAME_UNSYNCHRONISATION: u16 = 0x0002;
+/// ID3v2.4のフレームのフラグのうち、内容の先頭にデータ長があることを表すもの
+const V4_DATA_LENGTH_INDICATOR: u16 = 0x0001;
+/// ID3v2.3のフレームのフラグのうち、内容の形式を変えるもの（圧縮、暗号化、グループ化）
+const V3_FORMAT_FLAGS: u16 = 0x00E0;
+/// syncsafe整数で表せる長さの上限
+const MAX_SYNCSAFE: usize = (1 << 28) - 1;
+/// テキストのエンコーディングのうちUTF-8を表す値
+const UTF8: u8 = 3;
+/// APICフレームの画像の種類のうち、表紙を表す値
+const FRONT_COVER: u8 = 3;
+/// 書き込むテキストフレーム。[`TagPolicy::Merge`]でもこれらの既存のフレームは残さない。
+const MANAGED_FRAMES: &[&[u8; 4]] = &[b"TIT2", b"TALB", b"TPE1", b"TPE2", b"TRCK", b"TPOS"];
+/// MSRの楽曲IDを書き込むTXXXフレームの説明
+const SONG_ID_DESCRIPTION: &str = "MSR_SONG_ID";
+/// MSRのアルバムIDを書き込むTXXXフレームの説明
+const ALBUM_ID_DESCRIPTION: &str = "MSR_ALBUM_ID";
+/// ID3v2.4で廃止されたフレーム。
+/// 日付はTDRCとTDORに変換し、形式の変わったRVADやEQUAなどは変換できないので引き継がない。
+const V3_ONLY_FRAMES: &[&[u8; 4]] = &[
+    b"TYER", b"TDAT", b"TIME", b"TORY", b"TRDA", b"TSIZ", b"RVAD", b"EQUA",
+];
+
+/// MP3の[`Tagger`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Id3Tagger;
+
+impl Tagger for Id3Tagger {
+    fn metadata_len(&self, head: &[u8]) -> Result<MetadataLen, DomainError> {
+        if head.len() < HEADER_LEN {
+            // 先頭が`ID3`でないと分かればタグはない
+            if !MAGIC.starts_with(&head[..head.len().min(MAGIC.len())]) {
+                return Ok(MetadataLen::Complete(0));
+            }
+            return Ok(MetadataLen::NeedMore(HEADER_LEN));
+        }
+        let len = parse_header(head)?.map_or(0, |header| header.total_len());
+        Ok(MetadataLen::Complete(len))
+    }
+
+    fn write_tags(
+        &self,
+        raw: &[u8],
+        tags: &TrackTags,
+        policy: TagPolicy,
+    ) -> Result<Vec<u8>, DomainError> {
+        write_tags(raw, tags, policy)
+    }
+}
+
+/// ID3v2のフレーム
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub id: [u8; 4],
+    /// フレームのヘッダを除いた内容
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    /// UTF-8のテキストフレーム。複数の値はID3v2.4の仕様に従ってNUL文字で区切る。
+    fn text(id: &[u8; 4], values: &[String]) -> Self {
+        let mut data = vec![UTF8];
+        data.extend_from_slice(values.join("\0").as_bytes());
+        Self { id: *id, data }
+    }
+
+    /// 説明と値を持つユーザー定義のテキストフレーム
+    fn user_text(description: &str, value: &str) -> Self {
+        Self::text(b"TXXX", &[description.to_string(), value.to_string()])
+    }
+
+    /// 表紙の画像のフレーム。説明は空にする。
+    fn front_cover(mime_type: &str, image: &[u8]) -> Self {
+        let mut data = vec![UTF8];
+        data.extend_from_slice(mime_type.as_bytes());
+        data.extend_from_slice(&[0, FRONT_COVER, 0]);
+        data.extend_from_slice(image);
+        Self { id: *b"APIC", data }
+    }
+
+    /// テキストフレームの値の一覧。TXXXフレームの場合は説明、値の順に並ぶ。
+    /// 対応していないエンコーディングの場合は`None`を返す。
+    pub fn text_values(&self) -> Option<Vec<String>> {
+        let (&encoding, text) = self.data.split_first()?;
+        let text = decode_text(encoding, text)?;
+        Some(
+            text.trim_end_matches('\0')
+                .split('\0')
+                .map(str::to_string)
+                .collect(),
+        )
+    }
+
+    /// APICフレームの画像の種類
+    pub fn picture_type(&self) -> Option<u8> {
+        // MIMEタイプはエンコーディングによらずISO-8859-1で書かれている
+        let mime_end = self.data.iter().skip(1).position(|&b| b == 0)? + 1;
+        self.data.get(mime_end + 1).copied()
+    }
+
+    /// [`TagPolicy::Merge`]でも引き継がない、書き込む内容と重複するフレームか
+    fn is_managed(&self, tags: &TrackTags) -> bool {
+        match &self.id {
+            b"TXXX" => self
+                .text_values()
+                .and_then(|values| values.into_iter().next())
+                .is_some_and(|description| {
+                    description.eq_ignore_ascii_case(SONG_ID_DESCRIPTION)
+                        || description.eq_ignore_ascii_case(ALBUM_ID_DESCRIPTION)
+                        || tags.replaces_replay_gain(&description)
+                }),
+            b"APIC" => {
+                image_mime_type(&tags.cover_image).is_some()
+                    && self.picture_type() == Some(FRONT_COVER)
+            }
+            id => MANAGED_FRAMES.contains(&id),
+        }
+    }
+}
+
+/// タグのヘッダ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Header {
+    version: u8,
+    flags: u8,
+    /// ヘッダとフッタを除いたタグの長さ
+    size: usize,
+}
+
+impl Header {
+    /// ヘッダとフッタを含めたタグ全体の長さ
+    fn total_len(&self) -> usize {
+        let footer = if self.flags & FLAG_FOOTER != 0 {
+            HEADER_LEN
+        } else {
+            0
+        };
+        HEADER_LEN + self.size + footer
+    }
+}
+
+/// タグを書き込んだMP3を返す。
+/// 既存のID3v2タグは置き換え、音声フレームはそのまま残す。
+/// [`TagPolicy::Merge`]の場合は、既存のフレームのうち書き込む内容と重複しないものを引き継ぐ。
+/// * mp3: MP3のデータ。先頭のタグ部分だけでもよい。
+pub fn write_tags(mp3: &[u8], tags: &TrackTags, policy: TagPolicy) -> Result<Vec<u8>, DomainError> {
+    let (existing, audio_offset) = match parse_header(mp3)? {
+        // 壊れている既存のフレームは引き継がずに捨てる
+        Some(header) => (
+            parse_frames(mp3, &header)
+                .map(|frames| match header.version {
+                    3 => upgrade_v3_frames(frames),
+                    _ => frames,
+                })
+                .unwrap_or_default(),
+            header.total_len(),
+        ),
+        None => (vec![], 0),
+    };
+    let audio = mp3
+        .get(audio_offset..)
+        .ok_or_else(|| malformed("truncated tag"))?;
+
+    let mut frames = frames(tags);
+    if policy == TagPolicy::Merge {
+        frames.extend(existing.into_iter().filter(|frame| !frame.is_managed(tags)));
+    }
+    let mut body = vec![];
+    for frame in frames.iter() {
+        body.extend_from_slice(&frame.id);
+        body.extend_from_slice(&encode_syncsafe(frame.data.len())?);
+        body.extend_from_slice(&[0, 0]);
+        body.extend_from_slice(&frame.data);
+    }
+
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&[VERSION, 0, 0]);
+    out.extend_from_slice(&encode_syncsafe(body.len())?);
+    out.extend_from_slice(&body);
+    out.extend_from_slice(audio);
+    Ok(out)
+}
+
+/// MP3に書き込まれているフレームを読み出す。タグがなければ空になる。
+pub fn read_frames(mp3: &[u8]) -> Result<Vec<Frame>, DomainError> {
+    match parse_header(mp3)? {
+        Some(header) => parse_frames(mp3, &header),
+        None => Ok(vec![]),
+    }
+}
+
+/// 書き込むフレームの一覧
+fn frames(tags: &TrackTags) -> Vec<Frame> {
+    let mut frames = vec![
+        Frame::text(b"TIT2", std::slice::from_ref(&tags.title)),
+        Frame::text(b"TALB", std::slice::from_ref(&tags.album)),
+    ];
+    if !tags.artists.is_empty() {
+        frames.push(Frame::text(b"TPE1", &tags.artists));
+    }
+    if !tags.album_artists.is_empty() {
+        frames.push(Frame::text(b"TPE2", &tags.album_artists));
+    }
+    frames.extend([
+        Frame::text(
+            b"TRCK",
+            &[format!("{}/{}", tags.track_number, tags.track_total)],
+        ),
+        Frame::text(
+            b"TPOS",
+            &[format!("{}/{}", tags.disc_number, tags.disc_total)],
+        ),
+        Frame::user_text(SONG_ID_DESCRIPTION, &tags.song_id.to_msr_string()),
+        Frame::user_text(ALBUM_ID_DESCRIPTION, &tags.album_id.to_msr_string()),
+    ]);
+    frames.extend(
+        tags.replay_gain()
+            .into_iter()
+            .map(|(description, value)| Frame::user_text(description, &value)),
+    );
+    if let Some(mime_type) = image_mime_type(&tags.cover_image) {
+        frames.push(Frame::front_cover(mime_type, &tags.cover_image));
+    }
+    frames
+}
+
+/// 先頭がID3v2タグであればヘッダを読む
+fn parse_header(mp3: &[u8]) -> Result<Option<Header>, DomainError> {
+    if !mp3.starts_with(MAGIC) {
+        return Ok(None);
+    }
+    let header = mp3
+        .get(..HEADER_LEN)
+        .ok_or_else(|| malformed("truncated header"))?;
+    Ok(Some(Header {
+        version: header[3],
+        flags: header[5],
+        size: decode_syncsafe(&header[6..10])?,
+    }))
+}
+
+/// タグのフレームを読む。非同期化されたフレームは元に戻す。
+/// 圧縮や暗号化などのフラグが立ったフレームは、書き換えずに引き継げないので読み飛ばす。
+fn parse_frames(mp3: &[u8], header: &Header) -> Result<Vec<Frame>, DomainError> {
+    if !matches!(header.version, 3 | 4) {
+        return Ok(vec![]);
+    }
+    let body = mp3
+        .get(HEADER_LEN..HEADER_LEN + header.size)
+        .ok_or_else(|| malformed("truncated tag"))?;
+    let unsynchronised = header.flags & FLAG_UNSYNCHRONISATION != 0;
+    // ID3v2.3では非同期化はタグ全体にかかるので、フレームを読む前に戻す
+    let resynchronised;
+    let body = if header.version == 3 && unsynchronised {
+        resynchronised = resynchronise(body);
+        &resynchronised[..]
+    } else {
+        body
+    };
+    let mut offset = 0;
+    if header.flags & FLAG_EXTENDED_HEADER != 0 {
+        let size = body
+            .get(..4)
+            .ok_or_else(|| malformed("truncated extended header"))?;
+        // ID3v2.4では拡張ヘッダ自身を含む長さ、ID3v2.3では含まない長さを持つ
+        offset = match header.version {
+            4 => decode_syncsafe(size)?,
+            _ => u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize + 4,
+        };
+    }
+
+    let mut frames = vec![];
+    while let Some(frame_header) = body.get(offset..offset + HEADER_LEN) {
+        // 残りはパディング
+        if frame_header[0] == 0 {
+            break;
+        }
+        let id = [
+            frame_header[0],
+            frame_header[1],
+            frame_header[2],
+            frame_header[3],
+        ];
+        if !id
+            .iter()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+        {
+            return Err(malformed("invalid frame id"));
+        }
+        let (size, format_flags) = match header.version {
+            4 => (decode_syncsafe(&frame_header[4..8])?, V4_FORMAT_FLAGS),
+            _ => (
+                u32::from_be_bytes([
+                    frame_header[4],
+                    frame_header[5],
+                    frame_header[6],
+                    frame_header[7],
+                ]) as usize,
+                V3_FORMAT_FLAGS,
+            ),
+        };
+        let flags = u16::from_be_bytes([frame_header[8], frame_header[9]]);
+        offset += HEADER_LEN;
+        let data = body
+            .get(offset..offset + size)
+            .ok_or_else(|| malformed("truncated frame"))?;
+        offset += size;
+        if flags & format_flags != 0 {
+            continue;
+        }
+        let data = match header.version {
+            // ID3v2.4では非同期化はフレームごとにかかる。
+            // タグのヘッダのフラグは、すべてのフレームが非同期化されていることを表す
+            4 => {
+                let data = if flags & V4_DATA_LENGTH_INDICATOR != 0 {
+                    data.get(4..).ok_or_else(|| malformed("truncated frame"))?
+                } else {
+                    data
+                };
+                if unsynchronised || flags & V4_FRAME_UNSYNCHRONISATION != 0 {
+                    resynchronise(data)
+                } else {
+                    data.to_vec()
+                }
+            }
+            _ => data.to_vec(),
+        };
+        frames.push(Frame { id, data });
+    }
+    Ok(frames)
+}
+
+/// 非同期化で`0xFF`の後に挟まれた`0x00`を取り除く
+fn resynchronise(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut after_ff = false;
+    for &b in data {
+        if !(after_ff && b == 0) {
+            out.push(b);
+        }
+        after_ff = b == 0xFF;
+    }
+    out
+}
+
+/// ID3v2.3のフレームをID3v2.4の形式にする。
+/// TYER、TDAT、TIMEはTDRCに、TORYはTDORにまとめ、IPLSはTIPLにする。
+/// 変換できないID3v2.3だけのフレームは捨てる。
+fn upgrade_v3_frames(frames: Vec<Frame>) -> Vec<Frame> {
+    let text = |id: &[u8; 4]| {
+        frames
+            .iter()
+            .find(|frame| &frame.id == id)
+            .and_then(Frame::text_values)
+            .and_then(|values| values.into_iter().next())
+            .filter(|value| value.bytes().all(|b| b.is_ascii_digit()))
+    };
+    // 年はYYYY、日付はDDMM、時刻はHHMMの形式
+    let recording_time = text(b"TYER").filter(|year| year.len() == 4).map(|year| {
+        match text(b"TDAT").filter(|date| date.len() == 4) {
+            Some(date) => {
+                let date = format!("{year}-{}-{}", &date[2..], &date[..2]);
+                match text(b"TIME").filter(|time| time.len() == 4) {
+                    Some(time) => format!("{date}T{}:{}", &time[..2], &time[2..]),
+                    None => date,
+                }
+            }
+            None => year,
+        }
+    });
+    let original_release_time = text(b"TORY").filter(|year| year.len() == 4);
+
+    let mut upgraded = frames
+        .into_iter()
+        .filter(|frame| !V3_ONLY_FRAMES.contains(&&frame.id))
+        .map(|frame| match &frame.id {
+            // 内容の形式は同じ
+            b"IPLS" => Frame {
+                id: *b"TIPL",
+                data: frame.data,
+            },
+            _ => frame,
+        })
+        .collect::<Vec<_>>();
+    for (id, value) in [(b"TDRC", recording_time), (b"TDOR", original_release_time)] {
+        if let Some(value) = value {
+            if !upgraded.iter().any(|frame| &frame.id == id) {
+                upgraded.push(Frame::text(id, &[value]));
+            }
+        }
+    }
+    upgraded
+}
+
+/// 各バイトの下位7bitで表す整数を読む
+fn decode_syncsafe(bytes: &[u8]) -> Result<usize, DomainError> {
+    bytes.iter().try_fold(0usize, |acc, &b| {
+        if b & 0x80 != 0 {
+            return Err(malformed("invalid syncsafe integer"));
+        }
+        Ok((acc << 7) | b as usize)
+    })
+}
+
+fn encode_syncsafe(len: usize) -> Result<[u8; 4], DomainError> {
+    if len > MAX_SYNCSAFE {
+        return Err(DomainError::TagTooLarge {
+            format: AudioFormat::Mp3,
+            len,
+        });
+    }
+    Ok([
+        (len >> 21) as u8 & 0x7F,
+        (len >> 14) as u8 & 0x7F,
+        (len >> 7) as u8 & 0x7F,
+        len as u8 & 0x7F,
+    ])
+}
+
+/// テキストフレームの内容を文字列にする。
+/// エンコーディングは0がISO-8859-1、1がBOM付きのUTF-16、2がUTF-16BE、3がUTF-8。
+fn decode_text(encoding: u8, text: &[u8]) -> Option<String> {
+    match encoding {
+        0 => Some(text.iter().map(|&b| char::from(b)).collect()),
+        1 | 2 => {
+            let (big_endian, text) = match text {
+                [0xFF, 0xFE, rest @ ..] => (false, rest),
+                [0xFE, 0xFF, rest @ ..] => (true, rest),
+                _ => (encoding == 2, text),
+            };
+            let units = text
+                .chunks_exact(2)
+                .map(|unit| {
+                    if big_endian {
+                        u16::from_be_bytes([unit[0], unit[1]])
+                    } else {
+                        u16::from_le_bytes([unit[0], unit[1]])
+                    }
+                })
+                .collect::<Vec<_>>();
+            String::from_utf16(&units).ok()
+        }
+        UTF8 => String::from_utf8(text.to_vec()).ok(),
+        _ => None,
+    }
+}
+
+fn malformed(reason: &str) -> DomainError {
+    DomainError::MalformedTag {
+        format: AudioFormat::Mp3,
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_frames, write_tags, Frame, Id3Tagger, FRONT_COVER};
+    use crate::domain::loudness::Loudness;
+    use crate::domain::song::{AlbumId, SongId};
+    use crate::domain::tag::{MetadataLen, TagPolicy, Tagger, TrackTags};
+    use crate::errors::domain::DomainError;
+    use bytes::Bytes;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\ncover image";
+    const AUDIO: &[u8] = b"\xFF\xFBaudio frames";
+
+    /// ISO-8859-1のTIT2とTCONを持つID3v2.3タグ付きのMP3
+    fn mp3() -> Vec<u8> {
+        v3_mp3(&[(b"TIT2", b"old"), (b"TCON", b"Game")])
+    }
+
+    /// ISO-8859-1のテキストフレームを持つID3v2.3タグ付きのMP3
+    fn v3_mp3(texts: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut body = vec![];
+        for (id, text) in texts {
+            body.extend_from_slice(*id);
+            body.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+            body.extend_from_slice(&[0, 0, 0]);
+            body.extend_from_slice(text);
+        }
+        // パディング
+        body.extend_from_slice(&[0u8; 16]);
+        let mut mp3 = b"ID3\x03\x00\x00\x00\x00\x00".to_vec();
+        mp3.push(body.len() as u8);
+        mp3.extend_from_slice(&body);
+        mp3.extend_from_slice(AUDIO);
+        mp3
+    }
+
+    fn tags() -> TrackTags {
+        TrackTags {
+            title: "song".into(),
+            album: "album".into(),
+            artists: vec!["artist".into(), "featured".into()],
+            album_artists: vec!["塞壬唱片-MSR".into()],
+            track_number: 2,
+            track_total: 10,
+            disc_number: 1,
+            disc_total: 1,
+            song_id: SongId::new(48794).unwrap(),
+            album_id: AlbumId::new(249).unwrap(),
+            cover_image: Bytes::from_static(PNG),
+            ..Default::default()
+        }
+    }
+
+    fn loudness(integrated_lufs: f64, true_peak: f64) -> Option<Loudness> {
+        Some(Loudness {
+            integrated_lufs,
+            true_peak,
+        })
+    }
+
+    fn values(frames: &[Frame], id: &[u8; 4]) -> Vec<Vec<String>> {
+        frames
+            .iter()
+            .filter(|frame| &frame.id == id)
+            .map(|frame| frame.text_values().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let tagged = write_tags(AUDIO, &tags(), TagPolicy::Merge).unwrap();
+        assert!(tagged.starts_with(b"ID3\x04\x00"));
+        assert!(tagged.ends_with(AUDIO));
+
+        let frames = read_frames(&tagged).unwrap();
+        assert_eq!(values(&frames, b"TIT2"), vec![vec!["song"]]);
+        assert_eq!(values(&frames, b"TALB"), vec![vec!["album"]]);
+        assert_eq!(values(&frames, b"TPE1"), vec![vec!["artist", "featured"]]);
+        assert_eq!(values(&frames, b"TPE2"), vec![vec!["塞壬唱片-MSR"]]);
+        assert_eq!(values(&frames, b"TRCK"), vec![vec!["2/10"]]);
+        assert_eq!(values(&frames, b"TPOS"), vec![vec!["1/1"]]);
+        assert_eq!(
+            values(&frames, b"TXXX"),
+            vec![vec!["MSR_SONG_ID", "048794"], vec!["MSR_ALBUM_ID", "0249"]]
+        );
+        let pictures = frames
+            .iter()
+            .filter(|frame| &frame.id == b"APIC")
+            .collect::<Vec<_>>();
+        assert_eq!(pictures.len(), 1);
+        assert_eq!(pictures[0].picture_type(), Some(FRONT_COVER));
+        assert!(pictures[0].data.starts_with(b"\x03image/png\0"));
+        assert!(pictures[0].data.ends_with(PNG));
+    }
+
+    #[test]
+    fn test_merge_policy() {
+        let tagged = write_tags(&mp3(), &tags(), TagPolicy::Merge).unwrap();
+        assert!(tagged.ends_with(AUDIO));
+        let frames = read_frames(&tagged).unwrap();
+        // 書き込むフレームは置き換え、それ以外は引き継ぐ
+        assert_eq!(values(&frames, b"TIT2"), vec![vec!["song"]]);
+        assert_eq!(values(&frames, b"TCON"), vec![vec!["Game"]]);
+        // 同じタグを書き込んでも内容は変わらない
+        assert_eq!(
+            write_tags(&tagged, &tags(), TagPolicy::Merge).unwrap(),
+            tagged
+        );
+    }
+
+    #[test]
+    fn test_merge_v3_frames() {
+        let mp3 = v3_mp3(&[
+            (b"TYER", b"2023"),
+            (b"TDAT", b"0105"),
+            (b"TIME", b"1230"),
+            (b"TORY", b"2020"),
+            (b"RVAD", b"\x03\x10"),
+            (b"IPLS", b"mix\0someone"),
+            (b"TCON", b"Game"),
+        ]);
+        let tagged = write_tags(&mp3, &tags(), TagPolicy::Merge).unwrap();
+        let frames = read_frames(&tagged).unwrap();
+        // ID3v2.4にない日付のフレームはまとめて変換する
+        assert_eq!(values(&frames, b"TDRC"), vec![vec!["2023-05-01T12:30"]]);
+        assert_eq!(values(&frames, b"TDOR"), vec![vec!["2020"]]);
+        assert_eq!(values(&frames, b"TIPL"), vec![vec!["mix", "someone"]]);
+        assert_eq!(values(&frames, b"TCON"), vec![vec!["Game"]]);
+        for id in [b"TYER", b"TDAT", b"TIME", b"TORY", b"RVAD", b"IPLS"] {
+            assert!(frames.iter().all(|frame| &frame.id != id));
+        }
+
+        // 日付がなければ年だけにする
+        let mp3 = v3_mp3(&[(b"TYER", b"2023"), (b"TIME", b"1230")]);
+        let tagged = write_tags(&mp3, &tags(), TagPolicy::Merge).unwrap();
+        assert_eq!(
+            values(&read_frames(&tagged).unwrap(), b"TDRC"),
+            vec![vec!["2023"]]
+        );
+    }
+
+    #[test]
+    fn test_merge_unsynchronised() {
+        // ID3v2.3ではタグ全体が非同期化され、フレームの長さは元に戻した後の長さ
+        let mut v3 = b"ID3\x03\x00\x80\x00\x00\x00\x0E".to_vec();
+        v3.extend_from_slice(b"TCON\x00\x00\x00\x03\x00\x00\x00\xFF\x00\xE0");
+        v3.extend_from_slice(AUDIO);
+        let frames = read_frames(&write_tags(&v3, &tags(), TagPolicy::Merge).unwrap()).unwrap();
+        let genre = frames.iter().find(|frame| &frame.id == b"TCON").unwrap();
+        assert_eq!(genre.data, b"\x00\xFF\xE0");
+
+        // ID3v2.4ではフレームごとに非同期化され、データ長が先頭にある
+        let mut v4 = b"ID3\x04\x00\x00\x00\x00\x00\x13".to_vec();
+        v4.extend_from_slice(b"TCON\x00\x00\x00\x09\x00\x03");
+        v4.extend_from_slice(b"\x00\x00\x00\x04\x03\xFF\x00\xE0\x00");
+        v4.extend_from_slice(AUDIO);
+        let frames = read_frames(&write_tags(&v4, &tags(), TagPolicy::Merge).unwrap()).unwrap();
+        let genre = frames.iter().find(|frame| &frame.id == b"TCON").unwrap();
+        assert_eq!(genre.data, b"\x03\xFF\xE0\x00");
+    }
+
+    #[test]
+    fn test_replay_gain() {
+        let tagged = write_tags(
+            &mp3(),
+            &TrackTags {
+                track_loudness: loudness(-14.0, 0.9),
+                ..tags()
+            },
+            TagPolicy::Merge,
+        )
+        .unwrap();
+        let retagged = write_tags(
+            &tagged,
+            &TrackTags {
+                track_loudness: loudness(-9.5, 0.988553),
+                album_loudness: loudness(-20.25, 1.02),
+                ..tags()
+            },
+            TagPolicy::Merge,
+        )
+        .unwrap();
+        assert_eq!(
+            values(&read_frames(&retagged).unwrap(), b"TXXX"),
+            vec![
+                vec!["MSR_SONG_ID", "048794"],
+                vec!["MSR_ALBUM_ID", "0249"],
+                vec!["REPLAYGAIN_TRACK_GAIN", "-8.50 dB"],
+                vec!["REPLAYGAIN_TRACK_PEAK", "0.988553"],
+                vec!["REPLAYGAIN_ALBUM_GAIN", "2.25 dB"],
+                vec!["REPLAYGAIN_ALBUM_PEAK", "1.020000"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strip_policy() {
+        let tagged = write_tags(&mp3(), &tags(), TagPolicy::Strip).unwrap();
+        let frames = read_frames(&tagged).unwrap();
+        assert_eq!(values(&frames, b"TIT2"), vec![vec!["song"]]);
+        assert!(values(&frames, b"TCON").is_empty());
+    }
+
+    #[test]
+    fn test_metadata_len() {
+        let tagged = write_tags(&mp3(), &tags(), TagPolicy::Merge).unwrap();
+        assert_eq!(
+            Id3Tagger.metadata_len(&tagged[..4]).unwrap(),
+            MetadataLen::NeedMore(10)
+        );
+        assert_eq!(
+            Id3Tagger.metadata_len(&tagged).unwrap(),
+            MetadataLen::Complete(tagged.len() - AUDIO.len())
+        );
+        assert_eq!(
+            Id3Tagger.metadata_len(&AUDIO[..2]).unwrap(),
+            MetadataLen::Complete(0)
+        );
+        // タグ部分だけを渡しても書き込める
+        let head = &tagged[..tagged.len() - AUDIO.len()];
+        let retagged = write_tags(head, &tags(), TagPolicy::Merge).unwrap();
+        assert_eq!(retagged, head);
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert!(matches!(
+            write_tags(b"ID3\x04\x00", &tags(), TagPolicy::Merge),
+            Err(DomainError::MalformedTag { .. })
+        ));
+        let mut truncated = mp3();
+        truncated.truncate(20);
+        assert!(matches!(
+            write_tags(&truncated, &tags(), TagPolicy::Merge),
+            Err(DomainError::MalformedTag { .. })
+        ));
+    }
+}
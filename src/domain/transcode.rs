@@ -0,0 +1,69 @@
+pub mod flac;
+pub mod wav;
+
+use crate::domain::song::AudioFormat;
+use crate::errors::domain::DomainError;
+
+/// PCMのサンプルの形式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PcmFormat {
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub bits_per_sample: u8,
+}
+
+/// WAVをFLACに可逆変換する。
+/// 変換したFLACを復号し、PCMのサンプルが元のWAVと完全に一致することを確かめてから返す。
+pub fn wav_to_flac(wav: &[u8]) -> Result<Vec<u8>, DomainError> {
+    let wav = wav::parse(wav)?;
+    let samples = wav.samples();
+    let encoded = flac::encode(wav.format, &samples)?;
+    let (decoded_format, decoded) = flac::decode(&encoded)?;
+    if decoded_format != wav.format || decoded != samples {
+        return Err(DomainError::LosslessCheckFailed {
+            from: AudioFormat::Wav,
+            to: AudioFormat::Flac,
+        });
+    }
+    Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wav_to_flac;
+    use crate::domain::tag::flac::MAGIC;
+
+    /// 16bitステレオのWAV
+    fn wav(samples: &[i16]) -> Vec<u8> {
+        let data = samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&44100u32.to_le_bytes());
+        wav.extend_from_slice(&(44100u32 * 4).to_le_bytes());
+        wav.extend_from_slice(&4u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&data);
+        wav
+    }
+
+    #[test]
+    fn test_wav_to_flac() {
+        let samples = (0..10000)
+            .map(|i| ((i as f64 * 0.05).sin() * 20000.0) as i16)
+            .collect::<Vec<_>>();
+        let wav = wav(&samples);
+        let flac = wav_to_flac(&wav).unwrap();
+        assert!(flac.starts_with(MAGIC));
+        // 滑らかな波形なので元のWAVより小さくなる
+        assert!(flac.len() < wav.len() / 2);
+    }
+}
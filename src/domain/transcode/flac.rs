@@ -0,0 +1,749 @@
+//! FLACの符号化と復号。
+//! 符号化は固定長のブロックとFIXED予測、パーティションを分けないライス符号だけを使う簡単なもの。
+//! 復号は変換結果の確認に使うので、固定長のブロックでチャンネルを独立に符号化したものだけに対応する。
+use crate::domain::song::AudioFormat;
+use crate::domain::tag::flac::{BlockHeader, BLOCK_HEADER_LEN, MAGIC};
+use crate::domain::transcode::PcmFormat;
+use crate::errors::domain::DomainError;
+
+/// フレームあたりのチャンネルごとのサンプル数
+pub const BLOCK_SIZE: usize = 4096;
+/// STREAMINFOブロックの長さ
+pub const STREAMINFO_LEN: usize = 34;
+
+/// STREAMINFOブロックの種類
+pub const STREAMINFO: u8 = 0;
+/// フレームの先頭。14bitの同期コード、予約ビット、固定長のブロックを表すビットからなる。
+const FRAME_SYNC: u64 = 0xFFF8;
+/// フレームのヘッダの末尾に16bitでブロックサイズを書くことを表す値
+const BLOCK_SIZE_16BIT: u64 = 0b0111;
+/// フレームのヘッダの末尾に8bitでブロックサイズを書くことを表す値
+const BLOCK_SIZE_8BIT: u64 = 0b0110;
+/// サブフレームの種類
+const SUBFRAME_CONSTANT: u64 = 0b000000;
+const SUBFRAME_VERBATIM: u64 = 0b000001;
+const SUBFRAME_FIXED: u64 = 0b001000;
+/// FIXED予測の最大次数
+const MAX_FIXED_ORDER: usize = 4;
+/// ライス符号のパラメータの上限。4bitで表し、15はエスケープに使う。
+const MAX_RICE_PARAMETER: u32 = 14;
+
+/// STREAMINFOブロック
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub min_block_size: u16,
+    pub max_block_size: u16,
+    /// 最小のフレームのバイト数。0は不明を表す。
+    pub min_frame_size: u32,
+    /// 最大のフレームのバイト数。0は不明を表す。
+    pub max_frame_size: u32,
+    pub format: PcmFormat,
+    /// チャンネルあたりのサンプル数。0は不明を表す。
+    pub total_samples: u64,
+}
+
+impl StreamInfo {
+    pub fn parse(data: &[u8]) -> Result<Self, DomainError> {
+        if data.len() < STREAMINFO_LEN {
+            return Err(malformed("truncated STREAMINFO"));
+        }
+        let mut reader = BitReader::new(data);
+        Ok(Self {
+            min_block_size: reader.read(16)? as u16,
+            max_block_size: reader.read(16)? as u16,
+            min_frame_size: reader.read(24)? as u32,
+            max_frame_size: reader.read(24)? as u32,
+            format: PcmFormat {
+                sample_rate: reader.read(20)? as u32,
+                channels: reader.read(3)? as u8 + 1,
+                bits_per_sample: reader.read(5)? as u8 + 1,
+            },
+            total_samples: reader.read(36)?,
+        })
+    }
+
+    fn write(&self, writer: &mut BitWriter) {
+        writer.write(u64::from(self.min_block_size), 16);
+        writer.write(u64::from(self.max_block_size), 16);
+        writer.write(u64::from(self.min_frame_size), 24);
+        writer.write(u64::from(self.max_frame_size), 24);
+        writer.write(u64::from(self.format.sample_rate), 20);
+        writer.write(u64::from(self.format.channels) - 1, 3);
+        writer.write(u64::from(self.format.bits_per_sample) - 1, 5);
+        writer.write(self.total_samples, 36);
+        // 復号したPCMのMD5は計算しないので、不明を表す0にする
+        for _ in 0..4 {
+            writer.write(0, 32);
+        }
+    }
+}
+
+/// PCMをFLACに符号化する
+/// * samples: チャンネルごとのサンプルを交互に並べたもの
+pub fn encode(format: PcmFormat, samples: &[i32]) -> Result<Vec<u8>, DomainError> {
+    let channels = usize::from(format.channels);
+    if channels == 0 || !samples.len().is_multiple_of(channels) {
+        return Err(malformed("sample count is not a multiple of channels"));
+    }
+    let mut frames = vec![];
+    let mut min_frame_size = u32::MAX;
+    let mut max_frame_size = 0;
+    for (number, block) in samples.chunks(BLOCK_SIZE * channels).enumerate() {
+        let frame = encode_frame(format, number as u64, block);
+        min_frame_size = min_frame_size.min(frame.len() as u32);
+        max_frame_size = max_frame_size.max(frame.len() as u32);
+        frames.extend_from_slice(&frame);
+    }
+    let info = StreamInfo {
+        min_block_size: BLOCK_SIZE as u16,
+        max_block_size: BLOCK_SIZE as u16,
+        min_frame_size: if frames.is_empty() { 0 } else { min_frame_size },
+        max_frame_size,
+        format,
+        total_samples: (samples.len() / channels) as u64,
+    };
+    let mut out = stream_header(&info);
+    out.extend_from_slice(&frames);
+    Ok(out)
+}
+
+/// PCMをフレームごとにFLACに符号化する。
+/// [`encode`]と異なり全体をメモリに載せないので、大きな音声データを少しずつ変換するのに使う。
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    info: StreamInfo,
+    /// 次に符号化するフレームの番号
+    next_frame: u64,
+    /// 符号化したチャンネルあたりのサンプル数
+    encoded_samples: u64,
+}
+
+impl Encoder {
+    /// * total_samples: チャンネルあたりのサンプル数
+    pub fn new(format: PcmFormat, total_samples: u64) -> Result<Self, DomainError> {
+        if format.channels == 0 {
+            return Err(malformed("no channels"));
+        }
+        Ok(Self {
+            info: StreamInfo {
+                min_block_size: BLOCK_SIZE as u16,
+                max_block_size: BLOCK_SIZE as u16,
+                // フレームの大きさは書き終えるまで分からないので不明とする
+                min_frame_size: 0,
+                max_frame_size: 0,
+                format,
+                total_samples,
+            },
+            next_frame: 0,
+            encoded_samples: 0,
+        })
+    }
+
+    /// 先頭に書き込む`fLaC`とSTREAMINFOブロック
+    pub fn header(&self) -> Vec<u8> {
+        stream_header(&self.info)
+    }
+
+    /// 1ブロック分のサンプルを1フレームに符号化する。
+    /// 符号化したフレームを復号し、元のサンプルと完全に一致することを確かめてから返す。
+    /// * block: チャンネルごとのサンプルを交互に並べたもの。
+    ///   最後のブロック以外は[`BLOCK_SIZE`]サンプルちょうどであることを期待している。
+    pub fn encode_block(&mut self, block: &[i32]) -> Result<Vec<u8>, DomainError> {
+        let channels = usize::from(self.info.format.channels);
+        let block_size = block.len() / channels;
+        if block.is_empty() || !block.len().is_multiple_of(channels) || block_size > BLOCK_SIZE {
+            return Err(malformed("invalid block size"));
+        }
+        if self.encoded_samples + block_size as u64 > self.info.total_samples {
+            return Err(malformed("more samples than STREAMINFO"));
+        }
+        let frame = encode_frame(self.info.format, self.next_frame, block);
+        let mut decoded = Vec::with_capacity(block.len());
+        decode_frame(&mut BitReader::new(&frame), &self.info, &mut decoded)?;
+        if decoded != block {
+            return Err(DomainError::LosslessCheckFailed {
+                from: AudioFormat::Wav,
+                to: AudioFormat::Flac,
+            });
+        }
+        self.next_frame += 1;
+        self.encoded_samples += block_size as u64;
+        Ok(frame)
+    }
+
+    /// STREAMINFOに書いた数のサンプルをすべて符号化したか
+    pub fn is_complete(&self) -> bool {
+        self.encoded_samples == self.info.total_samples
+    }
+}
+
+/// FLACをPCMに復号する。チャンネルごとのサンプルを交互に並べて返す。
+pub fn decode(flac: &[u8]) -> Result<(PcmFormat, Vec<i32>), DomainError> {
+    if !flac.starts_with(MAGIC) {
+        return Err(malformed("missing fLaC marker"));
+    }
+    let mut info = None;
+    let mut offset = MAGIC.len();
+    loop {
+        let header = flac
+            .get(offset..offset + BLOCK_HEADER_LEN)
+            .ok_or_else(|| malformed("truncated block header"))?;
+        let header = BlockHeader::parse([header[0], header[1], header[2], header[3]]);
+        offset += BLOCK_HEADER_LEN;
+        let data = flac
+            .get(offset..offset + header.len)
+            .ok_or_else(|| malformed("truncated block"))?;
+        offset += header.len;
+        if header.block_type == STREAMINFO {
+            info = Some(StreamInfo::parse(data)?);
+        }
+        if header.is_last {
+            break;
+        }
+    }
+    let info = info.ok_or_else(|| malformed("missing STREAMINFO"))?;
+
+    let mut samples = vec![];
+    let mut reader = BitReader::new(&flac[offset..]);
+    while !reader.is_empty() {
+        decode_frame(&mut reader, &info, &mut samples)?;
+    }
+    let channels = u64::from(info.format.channels);
+    if info.total_samples != 0 && samples.len() as u64 != info.total_samples * channels {
+        return Err(malformed("sample count does not match STREAMINFO"));
+    }
+    Ok((info.format, samples))
+}
+
+/// `fLaC`と、最後のメタデータブロックとしてのSTREAMINFOブロック
+fn stream_header(info: &StreamInfo) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    info.write(&mut writer);
+    let mut out = MAGIC.to_vec();
+    out.push(0x80 | STREAMINFO);
+    out.extend_from_slice(&(STREAMINFO_LEN as u32).to_be_bytes()[1..]);
+    out.extend_from_slice(&writer.bytes);
+    out
+}
+
+fn encode_frame(format: PcmFormat, number: u64, block: &[i32]) -> Vec<u8> {
+    let channels = usize::from(format.channels);
+    let block_size = block.len() / channels;
+    let mut writer = BitWriter::default();
+    writer.write(FRAME_SYNC, 16);
+    // サンプリング周波数とビット数はSTREAMINFOに従う
+    writer.write(BLOCK_SIZE_16BIT, 4);
+    writer.write(0, 4);
+    // チャンネルは独立に符号化する
+    writer.write(channels as u64 - 1, 4);
+    writer.write(0, 3);
+    writer.write(0, 1);
+    for byte in utf8_number(number) {
+        writer.write(u64::from(byte), 8);
+    }
+    writer.write(block_size as u64 - 1, 16);
+    let crc = crc8(&writer.bytes);
+    writer.write(u64::from(crc), 8);
+
+    let bits_per_sample = u32::from(format.bits_per_sample);
+    for channel in 0..channels {
+        let samples = block
+            .iter()
+            .skip(channel)
+            .step_by(channels)
+            .map(|&sample| i64::from(sample))
+            .collect::<Vec<_>>();
+        encode_subframe(&mut writer, &samples, bits_per_sample);
+    }
+    writer.align();
+    let crc = crc16(&writer.bytes);
+    writer.write(u64::from(crc), 16);
+    writer.bytes
+}
+
+/// 1チャンネル分のサンプルを、最も短くなる種類のサブフレームで符号化する
+fn encode_subframe(writer: &mut BitWriter, samples: &[i64], bits_per_sample: u32) {
+    if samples.iter().all(|&sample| sample == samples[0]) {
+        write_subframe_header(writer, SUBFRAME_CONSTANT);
+        writer.write_signed(samples[0], bits_per_sample);
+        return;
+    }
+
+    let verbatim_bits = samples.len() as u64 * u64::from(bits_per_sample);
+    let (order, residual, parameter, bits) = (0..=MAX_FIXED_ORDER.min(samples.len() - 1))
+        .map(|order| {
+            let residual = fixed_residual(samples, order);
+            let (parameter, residual_bits) = rice_parameter(&residual);
+            // 予測の初期値、符号化の方式、パーティションの次数、パラメータ、残差
+            let bits = order as u64 * u64::from(bits_per_sample) + 2 + 4 + 4 + residual_bits;
+            (order, residual, parameter, bits)
+        })
+        .min_by_key(|(_, _, _, bits)| *bits)
+        .expect("order 0 is always available");
+    if bits >= verbatim_bits {
+        write_subframe_header(writer, SUBFRAME_VERBATIM);
+        for &sample in samples {
+            writer.write_signed(sample, bits_per_sample);
+        }
+        return;
+    }
+
+    write_subframe_header(writer, SUBFRAME_FIXED | order as u64);
+    for &sample in &samples[..order] {
+        writer.write_signed(sample, bits_per_sample);
+    }
+    // 4bitのパラメータのライス符号で、パーティションは分けない
+    writer.write(0, 2);
+    writer.write(0, 4);
+    writer.write(u64::from(parameter), 4);
+    for &r in residual.iter() {
+        let value = zigzag(r);
+        writer.write_unary(value >> parameter);
+        writer.write(value, parameter);
+    }
+}
+
+/// サブフレームのヘッダは先頭の0、種類、wasted bitsがないことを表す0からなる
+fn write_subframe_header(writer: &mut BitWriter, kind: u64) {
+    writer.write(0, 1);
+    writer.write(kind, 6);
+    writer.write(0, 1);
+}
+
+fn decode_frame(
+    reader: &mut BitReader,
+    info: &StreamInfo,
+    samples: &mut Vec<i32>,
+) -> Result<(), DomainError> {
+    let start = reader.byte_pos();
+    if reader.read(16)? != FRAME_SYNC {
+        return Err(malformed("missing frame sync code"));
+    }
+    let block_size_code = reader.read(4)?;
+    let sample_rate_code = reader.read(4)?;
+    let channel_assignment = reader.read(4)?;
+    let sample_size_code = reader.read(3)?;
+    reader.read(1)?;
+    let channels = usize::from(info.format.channels);
+    if sample_rate_code != 0 || sample_size_code != 0 || channel_assignment as usize + 1 != channels
+    {
+        return Err(unsupported("frame header"));
+    }
+    // フレーム番号は読み飛ばす
+    let first = reader.read(8)? as u8;
+    for _ in 0..first.leading_ones().saturating_sub(1) {
+        reader.read(8)?;
+    }
+    let block_size = match block_size_code {
+        BLOCK_SIZE_8BIT => reader.read(8)? + 1,
+        BLOCK_SIZE_16BIT => reader.read(16)? + 1,
+        _ => return Err(unsupported("block size code")),
+    } as usize;
+    let header_end = reader.byte_pos();
+    if reader.read(8)? != u64::from(crc8(&reader.data[start..header_end])) {
+        return Err(malformed("frame header CRC mismatch"));
+    }
+
+    let bits_per_sample = u32::from(info.format.bits_per_sample);
+    let channel_samples = (0..channels)
+        .map(|_| decode_subframe(reader, block_size, bits_per_sample))
+        .collect::<Result<Vec<_>, _>>()?;
+    reader.align();
+    let end = reader.byte_pos();
+    if reader.read(16)? != u64::from(crc16(&reader.data[start..end])) {
+        return Err(malformed("frame CRC mismatch"));
+    }
+    for ix in 0..block_size {
+        samples.extend(channel_samples.iter().map(|channel| channel[ix] as i32));
+    }
+    Ok(())
+}
+
+fn decode_subframe(
+    reader: &mut BitReader,
+    block_size: usize,
+    bits_per_sample: u32,
+) -> Result<Vec<i64>, DomainError> {
+    if reader.read(1)? != 0 {
+        return Err(malformed("invalid subframe header"));
+    }
+    let kind = reader.read(6)?;
+    if reader.read(1)? != 0 {
+        return Err(unsupported("wasted bits"));
+    }
+    match kind {
+        SUBFRAME_CONSTANT => Ok(vec![reader.read_signed(bits_per_sample)?; block_size]),
+        SUBFRAME_VERBATIM => (0..block_size)
+            .map(|_| reader.read_signed(bits_per_sample))
+            .collect(),
+        kind if (SUBFRAME_FIXED..=SUBFRAME_FIXED + MAX_FIXED_ORDER as u64).contains(&kind) => {
+            let order = (kind - SUBFRAME_FIXED) as usize;
+            if order > block_size {
+                return Err(malformed("predictor order exceeds block size"));
+            }
+            let mut samples = (0..order)
+                .map(|_| reader.read_signed(bits_per_sample))
+                .collect::<Result<Vec<_>, _>>()?;
+            for r in decode_residual(reader, block_size, order)? {
+                let prediction = fixed_prediction(&samples, order);
+                samples.push(prediction + r);
+            }
+            Ok(samples)
+        }
+        _ => Err(unsupported("subframe type")),
+    }
+}
+
+fn decode_residual(
+    reader: &mut BitReader,
+    block_size: usize,
+    order: usize,
+) -> Result<Vec<i64>, DomainError> {
+    let parameter_bits = match reader.read(2)? {
+        0 => 4,
+        1 => 5,
+        _ => return Err(malformed("reserved residual coding method")),
+    };
+    let partitions = 1usize << reader.read(4)?;
+    if !block_size.is_multiple_of(partitions) || block_size / partitions < order {
+        return Err(malformed("invalid partition order"));
+    }
+    let escape = (1 << parameter_bits) - 1;
+    let mut residual = Vec::with_capacity(block_size - order);
+    for partition in 0..partitions {
+        // 最初のパーティションは予測の初期値の分だけ短い
+        let count = block_size / partitions - if partition == 0 { order } else { 0 };
+        let parameter = reader.read(parameter_bits)? as u32;
+        if parameter == escape {
+            let bits = reader.read(5)? as u32;
+            for _ in 0..count {
+                residual.push(reader.read_signed(bits)?);
+            }
+            continue;
+        }
+        for _ in 0..count {
+            let quotient = reader.read_unary()?;
+            let value = (quotient << parameter) | reader.read(parameter)?;
+            residual.push(unzigzag(value));
+        }
+    }
+    Ok(residual)
+}
+
+/// FIXED予測の残差。先頭の`order`個のサンプルは予測の初期値になるので含めない。
+fn fixed_residual(samples: &[i64], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|ix| samples[ix] - fixed_prediction(&samples[..ix], order))
+        .collect()
+}
+
+/// 直前までのサンプルから次のサンプルを予測する
+fn fixed_prediction(previous: &[i64], order: usize) -> i64 {
+    let s = |k: usize| previous[previous.len() - k];
+    match order {
+        0 => 0,
+        1 => s(1),
+        2 => 2 * s(1) - s(2),
+        3 => 3 * s(1) - 3 * s(2) + s(3),
+        _ => 4 * s(1) - 6 * s(2) + 4 * s(3) - s(4),
+    }
+}
+
+/// 残差のライス符号が最も短くなるパラメータと、そのときのビット数
+fn rice_parameter(residual: &[i64]) -> (u32, u64) {
+    let bits = |parameter: u32| {
+        residual
+            .iter()
+            .map(|&r| (zigzag(r) >> parameter) + 1 + u64::from(parameter))
+            .sum::<u64>()
+    };
+    // 平均値のビット数を目安にし、前後のパラメータと比べる
+    let mean = residual.iter().map(|&r| zigzag(r)).sum::<u64>() / residual.len().max(1) as u64;
+    let guess = (u64::BITS - mean.leading_zeros()).min(MAX_RICE_PARAMETER);
+    (guess.saturating_sub(1)..=(guess + 1).min(MAX_RICE_PARAMETER))
+        .map(|parameter| (parameter, bits(parameter)))
+        .min_by_key(|(_, bits)| *bits)
+        .expect("range is not empty")
+}
+
+/// 符号付き整数を0、-1、1、-2、...の順に非負の整数に対応させる
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// フレーム番号をUTF-8と同じ方式で可変長に符号化する
+fn utf8_number(number: u64) -> Vec<u8> {
+    if number < 0x80 {
+        return vec![number as u8];
+    }
+    // n バイトで 5n+1 bit を表せる
+    let mut len = 2;
+    while number >> (5 * len + 1) != 0 {
+        len += 1;
+    }
+    let mut bytes = vec![(0xFF00u16 >> len) as u8 | (number >> (6 * (len - 1))) as u8];
+    for k in (0..len - 1).rev() {
+        bytes.push(0x80 | (number >> (6 * k)) as u8 & 0x3F);
+    }
+    bytes
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// 上位ビットから順に書き込む
+#[derive(Debug, Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// まだバイトにしていないビット
+    pending: u64,
+    pending_len: u32,
+}
+
+impl BitWriter {
+    /// `value`の下位`bits`bitを書き込む。`bits`は56以下。
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits == 0 {
+            return;
+        }
+        self.pending = (self.pending << bits) | (value & ((1 << bits) - 1));
+        self.pending_len += bits;
+        while self.pending_len >= 8 {
+            self.pending_len -= 8;
+            self.bytes.push((self.pending >> self.pending_len) as u8);
+        }
+        self.pending &= (1 << self.pending_len) - 1;
+    }
+
+    /// 2の補数で書き込む
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    /// `zeros`個の0に続けて1を書き込む
+    fn write_unary(&mut self, zeros: u64) {
+        let mut rest = zeros;
+        while rest >= 32 {
+            self.write(0, 32);
+            rest -= 32;
+        }
+        self.write(1, rest as u32 + 1);
+    }
+
+    /// バイト境界まで0で埋める
+    fn align(&mut self) {
+        if self.pending_len > 0 {
+            self.write(0, 8 - self.pending_len);
+        }
+    }
+}
+
+/// 上位ビットから順に読み込む
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// 読み込んだビット数
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len() * 8
+    }
+
+    fn byte_pos(&self) -> usize {
+        self.position / 8
+    }
+
+    /// `bits`bitを読み込む。`bits`は64以下。
+    fn read(&mut self, bits: u32) -> Result<u64, DomainError> {
+        let mut value = 0u64;
+        let mut rest = bits;
+        while rest > 0 {
+            let byte = *self
+                .data
+                .get(self.position / 8)
+                .ok_or_else(|| malformed("unexpected end of frame"))?;
+            let available = 8 - (self.position % 8) as u32;
+            let take = available.min(rest);
+            let chunk = (byte >> (available - take)) & (((1u16 << take) - 1) as u8);
+            value = (value << take) | u64::from(chunk);
+            rest -= take;
+            self.position += take as usize;
+        }
+        Ok(value)
+    }
+
+    /// 2の補数で読み込む
+    fn read_signed(&mut self, bits: u32) -> Result<i64, DomainError> {
+        if bits == 0 {
+            return Ok(0);
+        }
+        let value = self.read(bits)?;
+        Ok(((value << (64 - bits)) as i64) >> (64 - bits))
+    }
+
+    /// 1が現れるまでの0の数を読み込む
+    fn read_unary(&mut self) -> Result<u64, DomainError> {
+        let mut zeros = 0;
+        while self.read(1)? == 0 {
+            zeros += 1;
+        }
+        Ok(zeros)
+    }
+
+    fn align(&mut self) {
+        self.position = self.position.div_ceil(8) * 8;
+    }
+}
+
+fn malformed(reason: &str) -> DomainError {
+    DomainError::MalformedAudio {
+        format: AudioFormat::Flac,
+        reason: reason.to_string(),
+    }
+}
+
+fn unsupported(reason: &str) -> DomainError {
+    DomainError::UnsupportedAudio {
+        format: AudioFormat::Flac,
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, utf8_number, Encoder, StreamInfo, BLOCK_SIZE, STREAMINFO_LEN};
+    use crate::domain::tag::flac::read_tags;
+    use crate::domain::transcode::PcmFormat;
+    use crate::errors::domain::DomainError;
+
+    /// 線形合同法による雑音
+    fn noise(len: usize, bits: u32) -> Vec<i32> {
+        let mut state = 12345u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state as i32) >> (32 - bits)
+            })
+            .collect()
+    }
+
+    fn sine(len: usize, amplitude: f64) -> Vec<i32> {
+        (0..len)
+            .map(|i| ((i as f64 * 0.01).sin() * amplitude) as i32)
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let cases = [
+            (1, 24, noise(BLOCK_SIZE + 100, 24)),
+            (2, 16, sine(2 * (BLOCK_SIZE * 2 + 1), 30000.0)),
+            (2, 16, vec![0; 2 * 300]),
+            (1, 8, sine(500, 127.0)),
+        ];
+        for (channels, bits_per_sample, samples) in cases {
+            let format = PcmFormat {
+                sample_rate: 44100,
+                channels,
+                bits_per_sample,
+            };
+            let flac = encode(format, &samples).unwrap();
+            assert_eq!(decode(&flac).unwrap(), (format, samples.clone()));
+            // メタデータとしても読める
+            assert!(read_tags(&flac).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_encoder() {
+        let format = PcmFormat {
+            sample_rate: 44100,
+            channels: 2,
+            bits_per_sample: 16,
+        };
+        let samples = sine(2 * (BLOCK_SIZE * 2 + 10), 30000.0);
+        let mut encoder = Encoder::new(format, (samples.len() / 2) as u64).unwrap();
+        let mut flac = encoder.header();
+        for block in samples.chunks(BLOCK_SIZE * 2) {
+            flac.extend(encoder.encode_block(block).unwrap());
+        }
+        assert!(encoder.is_complete());
+        assert_eq!(decode(&flac).unwrap(), (format, samples.clone()));
+        assert!(read_tags(&flac).is_ok());
+
+        // STREAMINFOに書いた数より多くは符号化しない
+        assert!(matches!(
+            encoder.encode_block(&samples[..2]),
+            Err(DomainError::MalformedAudio { .. })
+        ));
+    }
+
+    #[test]
+    fn test_stream_info() {
+        let format = PcmFormat {
+            sample_rate: 96000,
+            channels: 2,
+            bits_per_sample: 24,
+        };
+        let flac = encode(format, &sine(2 * 5000, 1e6)).unwrap();
+        let info = StreamInfo::parse(&flac[8..8 + STREAMINFO_LEN]).unwrap();
+        assert_eq!(info.format, format);
+        assert_eq!(info.total_samples, 5000);
+        assert_eq!(info.max_block_size, BLOCK_SIZE as u16);
+        assert!(info.min_frame_size > 0 && info.min_frame_size <= info.max_frame_size);
+    }
+
+    #[test]
+    fn test_corrupted_frame() {
+        let format = PcmFormat {
+            sample_rate: 44100,
+            channels: 1,
+            bits_per_sample: 16,
+        };
+        let mut flac = encode(format, &sine(1000, 10000.0)).unwrap();
+        let last = flac.len() - 3;
+        flac[last] ^= 0x01;
+        assert!(matches!(
+            decode(&flac),
+            Err(DomainError::MalformedAudio { .. })
+        ));
+    }
+
+    #[test]
+    fn test_utf8_number() {
+        assert_eq!(utf8_number(0x7F), vec![0x7F]);
+        assert_eq!(utf8_number(0x80), vec![0xC2, 0x80]);
+        assert_eq!(utf8_number(0x800), vec![0xE0, 0xA0, 0x80]);
+        assert_eq!(utf8_number(0x10000), vec![0xF0, 0x90, 0x80, 0x80]);
+    }
+}
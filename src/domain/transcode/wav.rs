@@ -0,0 +1,314 @@
+//! WAVの読み込み。整数のリニアPCMだけを扱う。
+use crate::domain::song::AudioFormat;
+use crate::domain::transcode::PcmFormat;
+use crate::errors::domain::DomainError;
+
+/// RIFFのチャンクのヘッダの長さ
+pub const CHUNK_HEADER_LEN: usize = 8;
+/// fmtチャンクのフォーマットのうち、リニアPCMを表す値
+const WAVE_FORMAT_PCM: u16 = 1;
+/// fmtチャンクのフォーマットのうち、拡張されたfmtチャンクを表す値。実際のフォーマットはサブフォーマットで表す。
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// 読み込んだWAV
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wav<'a> {
+    pub format: PcmFormat,
+    /// dataチャンクの内容。途中で終わっている最後のサンプルは含めない。
+    pub data: &'a [u8],
+}
+
+impl Wav<'_> {
+    /// チャンネルごとのサンプルを交互に並べたもの。8bitのサンプルは符号付きに直す。
+    pub fn samples(&self) -> Vec<i32> {
+        samples(self.format, self.data)
+    }
+
+    /// チャンネルあたりのサンプル数
+    pub fn total_samples(&self) -> u64 {
+        let frame_len =
+            usize::from(self.format.bits_per_sample / 8) * usize::from(self.format.channels);
+        (self.data.len() / frame_len) as u64
+    }
+}
+
+/// fmtチャンク
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fmt {
+    pub format_tag: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub block_align: u16,
+    pub bits_per_sample: u16,
+    /// [`WAVE_FORMAT_EXTENSIBLE`]の場合の実際のフォーマットと有効なビット数
+    pub extensible: Option<(u16, u16)>,
+}
+
+/// dataチャンクの位置と形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataChunk {
+    pub format: PcmFormat,
+    /// ファイルの先頭からdataチャンクの内容までのバイト数
+    pub offset: usize,
+    /// dataチャンクのヘッダに書かれている長さ。実際のファイルより長いことがある。
+    pub len: u64,
+}
+
+impl DataChunk {
+    /// 1サンプルを全チャンネル分並べたバイト数
+    pub fn frame_len(&self) -> usize {
+        usize::from(self.format.bits_per_sample / 8) * usize::from(self.format.channels)
+    }
+}
+
+/// [`parse_layout`]の結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Complete(DataChunk),
+    /// 先頭からこのバイト数まで読み込めば判断できる
+    NeedMore(usize),
+}
+
+/// WAVの先頭からdataチャンクの位置と形式を読み取る。
+/// 足りなければ必要なバイト数を[`Layout::NeedMore`]で返すので、読み足して呼び直す。
+/// 8、16、24bitの整数のリニアPCM以外は[`DomainError::UnsupportedAudio`]を返す。
+pub fn parse_layout(head: &[u8]) -> Result<Layout, DomainError> {
+    let Some(riff) = head.get(..12) else {
+        return Ok(Layout::NeedMore(12));
+    };
+    if &riff[..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(malformed("missing RIFF/WAVE header"));
+    }
+    let mut fmt = None;
+    let mut offset = 12;
+    loop {
+        let Some(header) = head.get(offset..offset + CHUNK_HEADER_LEN) else {
+            return Ok(Layout::NeedMore(offset + CHUNK_HEADER_LEN));
+        };
+        let id = &header[..4];
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        offset += CHUNK_HEADER_LEN;
+        match id {
+            b"fmt " => {
+                let Some(data) = head.get(offset..offset + size) else {
+                    return Ok(Layout::NeedMore(offset + size));
+                };
+                fmt = Some(parse_fmt(data)?);
+            }
+            b"data" => {
+                let fmt = fmt.ok_or_else(|| malformed("data chunk before fmt chunk"))?;
+                return Ok(Layout::Complete(DataChunk {
+                    format: check_fmt(&fmt)?,
+                    offset,
+                    len: size as u64,
+                }));
+            }
+            _ => {}
+        }
+        // チャンクは2バイト境界に揃える
+        offset += size + size % 2;
+    }
+}
+
+/// WAVを読み込む。
+/// 8、16、24bitの整数のリニアPCM以外は[`DomainError::UnsupportedAudio`]を返す。
+pub fn parse(wav: &[u8]) -> Result<Wav<'_>, DomainError> {
+    let chunk = match parse_layout(wav)? {
+        Layout::Complete(chunk) => chunk,
+        Layout::NeedMore(_) => return Err(malformed("missing data chunk")),
+    };
+    // 書き込み中に止まったWAVはサイズが実際より大きいことがあるので、ファイルの終わりまでにする
+    let end = wav
+        .len()
+        .min(chunk.offset.saturating_add(chunk.len as usize));
+    let data = &wav[chunk.offset..end];
+    let frame_len = chunk.frame_len();
+    Ok(Wav {
+        format: chunk.format,
+        data: &data[..data.len() / frame_len * frame_len],
+    })
+}
+
+/// dataチャンクの内容をサンプルにし、チャンネルごとのサンプルを交互に並べて返す。
+/// 8bitのサンプルは符号付きに直す。
+/// * data: [`DataChunk::frame_len`]の倍数の長さを期待している
+pub fn samples(format: PcmFormat, data: &[u8]) -> Vec<i32> {
+    let width = usize::from(format.bits_per_sample / 8);
+    data.chunks_exact(width)
+        .map(|sample| match sample {
+            [b] => i32::from(*b) - 128,
+            [lo, hi] => i32::from(i16::from_le_bytes([*lo, *hi])),
+            [b0, b1, b2] => i32::from_le_bytes([0, *b0, *b1, *b2]) >> 8,
+            _ => unreachable!("bits_per_sample is checked in parse_layout"),
+        })
+        .collect()
+}
+
+/// fmtチャンクの内容を読み込む。対応している形式かどうかは確かめない。
+pub fn parse_fmt(data: &[u8]) -> Result<Fmt, DomainError> {
+    if data.len() < 16 {
+        return Err(malformed("truncated fmt chunk"));
+    }
+    let u16_at = |ix: usize| u16::from_le_bytes([data[ix], data[ix + 1]]);
+    let format_tag = u16_at(0);
+    let extensible = if format_tag == WAVE_FORMAT_EXTENSIBLE {
+        if data.len() < 26 {
+            return Err(malformed("truncated extensible fmt chunk"));
+        }
+        // サブフォーマットのGUIDの先頭2バイトがフォーマットを表す
+        Some((u16_at(24), u16_at(18)))
+    } else {
+        None
+    };
+    Ok(Fmt {
+        format_tag,
+        channels: u16_at(2),
+        sample_rate: u32::from_le_bytes([data[4], data[5], data[6], data[7]]),
+        block_align: u16_at(12),
+        bits_per_sample: u16_at(14),
+        extensible,
+    })
+}
+
+/// FLACに変換できる形式か確かめる
+fn check_fmt(fmt: &Fmt) -> Result<PcmFormat, DomainError> {
+    let format_tag = match fmt.extensible {
+        Some((sub_format, valid_bits)) => {
+            if valid_bits != fmt.bits_per_sample {
+                return Err(unsupported(format!(
+                    "{valid_bits} valid bits in {}-bit container",
+                    fmt.bits_per_sample
+                )));
+            }
+            sub_format
+        }
+        None => fmt.format_tag,
+    };
+    if format_tag != WAVE_FORMAT_PCM {
+        return Err(unsupported(format!("format tag {format_tag:#06x}")));
+    }
+    if !matches!(fmt.bits_per_sample, 8 | 16 | 24) {
+        return Err(unsupported(format!(
+            "{} bits per sample",
+            fmt.bits_per_sample
+        )));
+    }
+    if !(1..=8).contains(&fmt.channels) {
+        return Err(unsupported(format!("{} channels", fmt.channels)));
+    }
+    // FLACのサンプリング周波数は20bitで表す
+    if fmt.sample_rate == 0 || fmt.sample_rate >= 1 << 20 {
+        return Err(unsupported(format!("sample rate {}", fmt.sample_rate)));
+    }
+    if fmt.block_align != fmt.channels * fmt.bits_per_sample / 8 {
+        return Err(malformed("block align does not match the sample format"));
+    }
+    Ok(PcmFormat {
+        sample_rate: fmt.sample_rate,
+        channels: fmt.channels as u8,
+        bits_per_sample: fmt.bits_per_sample as u8,
+    })
+}
+
+fn malformed(reason: &str) -> DomainError {
+    DomainError::MalformedAudio {
+        format: AudioFormat::Wav,
+        reason: reason.to_string(),
+    }
+}
+
+fn unsupported(reason: String) -> DomainError {
+    DomainError::UnsupportedAudio {
+        format: AudioFormat::Wav,
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, parse_layout, Layout};
+    use crate::domain::transcode::PcmFormat;
+    use crate::errors::domain::DomainError;
+
+    /// fmtチャンクとdataチャンクからWAVを組み立てる。間に奇数長のLISTチャンクを挟む。
+    fn wav(fmt: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        for (id, chunk) in [(b"fmt ", fmt), (b"LIST", &b"odd"[..]), (b"data", data)] {
+            wav.extend_from_slice(id);
+            wav.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            wav.extend_from_slice(chunk);
+            if chunk.len() % 2 == 1 {
+                wav.push(0);
+            }
+        }
+        wav
+    }
+
+    fn fmt(format_tag: u16, channels: u16, bits: u16) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut fmt = vec![];
+        fmt.extend_from_slice(&format_tag.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&48000u32.to_le_bytes());
+        fmt.extend_from_slice(&(48000 * u32::from(block_align)).to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        fmt
+    }
+
+    #[test]
+    fn test_parse_24bit() {
+        // -1、1、最小値、最大値。最後の半端なバイトは捨てる。
+        let data = b"\xFF\xFF\xFF\x01\x00\x00\x00\x00\x80\xFF\xFF\x7F\x00";
+        let raw = wav(&fmt(1, 2, 24), data);
+        let wav = parse(&raw).unwrap();
+        assert_eq!(
+            wav.format,
+            PcmFormat {
+                sample_rate: 48000,
+                channels: 2,
+                bits_per_sample: 24
+            }
+        );
+        assert_eq!(wav.total_samples(), 2);
+        assert_eq!(wav.samples(), vec![-1, 1, -(1 << 23), (1 << 23) - 1]);
+    }
+
+    #[test]
+    fn test_parse_8bit() {
+        let raw = wav(&fmt(1, 1, 8), b"\x00\x80\xFF");
+        assert_eq!(parse(&raw).unwrap().samples(), vec![-128, 0, 127]);
+    }
+
+    #[test]
+    fn test_parse_unsupported() {
+        // IEEE浮動小数点
+        let raw = wav(&fmt(3, 2, 32), &[0u8; 8]);
+        assert!(matches!(
+            parse(&raw),
+            Err(DomainError::UnsupportedAudio { .. })
+        ));
+        assert!(matches!(
+            parse(b"RIFF\0\0\0\0WAVE"),
+            Err(DomainError::MalformedAudio { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_layout() {
+        let raw = wav(&fmt(1, 2, 16), &[0u8; 8]);
+        assert_eq!(parse_layout(&raw[..4]).unwrap(), Layout::NeedMore(12));
+        assert_eq!(parse_layout(&raw[..30]).unwrap(), Layout::NeedMore(36));
+        let Layout::Complete(chunk) = parse_layout(&raw).unwrap() else {
+            panic!("data chunk not found");
+        };
+        // fmtチャンクとLISTチャンクの後ろ
+        assert_eq!(chunk.offset, 12 + 8 + 16 + 8 + 4 + 8);
+        assert_eq!(chunk.len, 8);
+        assert_eq!(chunk.frame_len(), 4);
+        assert_eq!(
+            parse_layout(&raw[..chunk.offset]).unwrap(),
+            Layout::Complete(chunk)
+        );
+    }
+}
@@ -14,5 +14,41 @@ pub enum Error {
     Infra {
         #[from]
         source: infra::InfraError,
+    },
+    #[error(transparent)]
+    Usecase {
+        #[from]
+        source: usecase::UsecaseError,
+    },
+}
+
+/// リポジトリなどが返す結果の型
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl From<sea_orm::DbErr> for Error {
+    fn from(err: sea_orm::DbErr) -> Self {
+        infra::InfraError::from(err).into()
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        infra::InfraError::from(err).into()
+    }
+}
+
+impl From<url::ParseError> for Error {
+    fn from(err: url::ParseError) -> Self {
+        infra::InfraError::from(err).into()
+    }
+}
+
+/// トランザクション内で発生したエラーはそのまま取り出し、接続のエラーは[`infra::InfraError`]にする
+impl From<sea_orm::TransactionError<Error>> for Error {
+    fn from(err: sea_orm::TransactionError<Error>) -> Self {
+        match err {
+            sea_orm::TransactionError::Transaction(e) => e,
+            sea_orm::TransactionError::Connection(e) => e.into(),
+        }
     }
 }
@@ -1,5 +1,5 @@
 use thiserror::Error;
-use crate::domain::song::{AlbumId, SongId};
+use crate::domain::song::{AlbumId, AudioFormat, SongId};
 
 #[derive(Debug, Error, PartialEq)]
 pub enum DomainError {
@@ -7,8 +7,14 @@ pub enum DomainError {
     FailedToParseSongId {s: String},
     #[error("Failed to parse album id: {s}")]
     FailedToParseAlbumId {s: String},
+    #[error("Song id does not fit in 6 digits: {id}")]
+    SongIdOutOfRange {id: u32},
+    #[error("Album id does not fit in 4 digits: {id}")]
+    AlbumIdOutOfRange {id: u32},
     #[error("Failed to create audio raw data")]
     FailedToCreateAudioRawData,
+    #[error("Audio format mismatch: detected {detected}, but the source says {expected}")]
+    AudioFormatMismatch {detected: AudioFormat, expected: AudioFormat},
     #[error("Failed to create song")]
     FailedToCreateSong {id: SongId},
     #[error("Failed to create album")]
@@ -17,4 +23,16 @@ pub enum DomainError {
     FailedToParseOriginGame{s: String},
     #[error("Failed to get song in the album")]
     FailedToGetSongInAlbum {song_id: SongId, album_id: AlbumId},
+    #[error("Invalid disc layout for album {album_id}: {expected} songs, but the discs have {actual}")]
+    InvalidDiscLayout {album_id: AlbumId, expected: usize, actual: usize},
+    #[error("Malformed {format} tag: {reason}")]
+    MalformedTag {format: AudioFormat, reason: String},
+    #[error("{format} tag is too large: {len} bytes")]
+    TagTooLarge {format: AudioFormat, len: usize},
+    #[error("Malformed {format} audio: {reason}")]
+    MalformedAudio {format: AudioFormat, reason: String},
+    #[error("Unsupported {format} audio: {reason}")]
+    UnsupportedAudio {format: AudioFormat, reason: String},
+    #[error("Decoded PCM does not match the original after converting {from} to {to}")]
+    LosslessCheckFailed {from: AudioFormat, to: AudioFormat},
 }
\ No newline at end of file
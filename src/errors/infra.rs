@@ -1,5 +1,6 @@
 use std::path::PathBuf;
 use thiserror::Error;
+use url::Url;
 use crate::domain::song::{AlbumId, SongId};
 
 #[derive(Debug, Error, PartialEq)]
@@ -17,6 +18,22 @@ pub enum InfraError {
     AlbumNotFound { id: AlbumId },
     #[error("Failed to load audio raw data: {path}")]
     FailedToLoadAudioRawData {path: PathBuf},
+    #[error("Failed to decode audio {path}: {cause}")]
+    DecodeAudio { path: PathBuf, cause: String },
+    #[error("MSR API returned error code {code}: {endpoint}")]
+    MsrApiError { code: i32, endpoint: String },
+    #[error("Unexpected HTTP status {status}: {url}")]
+    UnexpectedStatus { status: u16, url: Url },
+    #[error("Malformed response from {url}: {snippet}")]
+    MalformedResponse { url: Url, snippet: String },
+    #[error("IO error: {cause}")]
+    Io { cause: String },
+    #[error("Malformed column {column}: {cause}")]
+    MalformedColumn { column: String, cause: String },
+    #[error("HTTP error: {cause}")]
+    Http { cause: String },
+    #[error("Invalid URL: {cause}")]
+    InvalidUrl { cause: String },
 }
 
 impl<E> From<sea_orm::TransactionError<E>> for InfraError
@@ -28,4 +45,28 @@ where
             cause: err.to_string(),
         }
     }
-}
\ No newline at end of file
+}
+
+impl From<std::io::Error> for InfraError {
+    fn from(err: std::io::Error) -> Self {
+        InfraError::Io {
+            cause: err.to_string(),
+        }
+    }
+}
+
+impl From<reqwest::Error> for InfraError {
+    fn from(err: reqwest::Error) -> Self {
+        InfraError::Http {
+            cause: err.to_string(),
+        }
+    }
+}
+
+impl From<url::ParseError> for InfraError {
+    fn from(err: url::ParseError) -> Self {
+        InfraError::InvalidUrl {
+            cause: err.to_string(),
+        }
+    }
+}
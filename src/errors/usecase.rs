@@ -0,0 +1,16 @@
+use thiserror::Error;
+use crate::domain::song::{AlbumId, SongId};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum UsecaseError {
+    #[error("Album {album_id} does not contain song {song_id}")]
+    AlbumMissingSong {song_id: SongId, album_id: AlbumId},
+    #[error("Sync aborted after {failed} failures, {skipped} songs were skipped")]
+    SyncAborted {failed: usize, skipped: usize},
+    #[error("{failed} of {total} songs failed")]
+    PartialFailure {failed: usize, total: usize},
+    #[error("{failed} of {total} songs and albums failed to update")]
+    UpdateIncomplete {failed: usize, total: usize},
+    #[error("Reconcile aborted, {withdrawn} of {total} {target} disappeared from MSR")]
+    ReconcileAborted {target: &'static str, withdrawn: usize, total: usize},
+}
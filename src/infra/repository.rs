@@ -1,2 +1,7 @@
+pub mod artist;
+pub mod metadata_history;
 pub mod msr;
 pub mod song;
+pub mod sync_run;
+#[cfg(test)]
+mod test_database;
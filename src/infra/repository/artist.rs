@@ -0,0 +1,252 @@
+use crate::domain::artist::{Artist, ArtistId};
+use crate::domain::repository::artist_repository::UsesArtistRepository;
+use crate::errors::Error;
+use crate::errors::Result;
+use crate::infra::repository::song::upsert_by_name;
+use crate::infra::resource::database::{
+    execute_and_values, query_all, read_only_transaction, read_write_transaction, ProvideDatabase,
+};
+use async_trait::async_trait;
+use indexmap::IndexMap;
+
+pub trait DatabaseArtistRepository: ProvideDatabase + Send + Sync + 'static {}
+
+#[async_trait]
+impl<R: DatabaseArtistRepository> UsesArtistRepository for R {
+    async fn get_all_artists(&self) -> Result<Vec<Artist>> {
+        read_only_transaction(self, |txn| {
+            Box::pin(async move {
+                // アーティスト不明の楽曲のために空の名前で登録しているものは除く
+                let rows = query_all(
+                    txn,
+                    r"SELECT id, name FROM artists
+                            WHERE is_deleted = false AND name != ''
+                            ORDER BY id",
+                )
+                .await?;
+                let mut artists = IndexMap::new();
+                for row in rows {
+                    let id = ArtistId::new(row.try_get::<u32>("", "id")?);
+                    let artist = Artist {
+                        id,
+                        name: row.try_get("", "name")?,
+                        aliases: vec![],
+                    };
+                    artists.insert(id, artist);
+                }
+
+                let rows = query_all(
+                    txn,
+                    r"SELECT alias, artist_id FROM artist_aliases
+                            ORDER BY id",
+                )
+                .await?;
+                for row in rows {
+                    let id = ArtistId::new(row.try_get::<u32>("", "artist_id")?);
+                    if let Some(artist) = artists.get_mut(&id) {
+                        artist.aliases.push(row.try_get("", "alias")?);
+                    }
+                }
+                Ok::<_, Error>(artists.into_values().collect())
+            })
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn save_alias(&self, alias: String, name: String) -> Result<()> {
+        read_write_transaction(self, |txn| {
+            Box::pin(async move {
+                let artist_id = upsert_by_name(txn, "artists", "name", name).await?;
+                execute_and_values(
+                    txn,
+                    r"INSERT INTO artist_aliases (created_at, updated_at, alias, artist_id)
+                            VALUES (
+                                strftime('%Y-%m-%dT%H:%M:%fZ', 'now'),
+                                strftime('%Y-%m-%dT%H:%M:%fZ', 'now'),
+                                ?, ?
+                            )
+                            ON CONFLICT (alias) DO UPDATE SET
+                                updated_at = excluded.updated_at,
+                                artist_id = excluded.artist_id
+                            ",
+                    vec![alias.into(), artist_id.into()],
+                )
+                .await?;
+                Ok::<(), Error>(())
+            })
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn delete_alias(&self, alias: String) -> Result<()> {
+        read_write_transaction(self, |txn| {
+            Box::pin(async move {
+                execute_and_values(
+                    txn,
+                    r"DELETE FROM artist_aliases WHERE alias = ?",
+                    vec![alias.into()],
+                )
+                .await?;
+                Ok::<(), Error>(())
+            })
+        })
+        .await
+        .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::artist::ArtistResolver;
+    use crate::domain::repository::artist_repository::UsesArtistRepository;
+    use crate::domain::repository::song_repository::UsesSongRepository;
+    use crate::domain::song::{Album, AlbumId, SongId};
+    use crate::infra::repository::test_database::TestDatabase;
+    use migration::{Migrator, MigratorTrait};
+    use sea_orm::{ConnectionTrait, DatabaseBackend, DatabaseConnection, Statement};
+
+    #[tokio::test]
+    async fn test_alias_of_stored_name() {
+        let db = TestDatabase::connect("artist-alias").await;
+        // 同期で見つかった表記は、それぞれ正式な名前として登録されている
+        for (id, artist) in [(1, "塞壬唱片-MSR"), (2, "Monster Siren Records")] {
+            db.save_album(Album {
+                id: AlbumId::new(id).unwrap(),
+                artists: vec![artist.into()],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+
+        db.save_alias("Monster Siren Records".into(), "塞壬唱片-MSR".into())
+            .await
+            .unwrap();
+        let artists = db.get_all_artists().await.unwrap();
+        let canonical = artists
+            .iter()
+            .find(|artist| artist.name == "塞壬唱片-MSR")
+            .unwrap();
+        assert_eq!(canonical.aliases, vec!["Monster Siren Records".to_string()]);
+        // 別名として登録した表記も正式な名前として残っているが、別名の方を優先する
+        assert!(artists
+            .iter()
+            .any(|artist| artist.name == "Monster Siren Records"));
+        assert_eq!(
+            ArtistResolver::new(&artists).resolve(&["Monster Siren Records".into()]),
+            vec!["塞壬唱片-MSR".to_string()]
+        );
+
+        // 別名を削除すれば元の表記に戻る
+        db.delete_alias("Monster Siren Records".into())
+            .await
+            .unwrap();
+        let artists = db.get_all_artists().await.unwrap();
+        assert_eq!(
+            ArtistResolver::new(&artists).resolve(&["Monster Siren Records".into()]),
+            vec!["Monster Siren Records".to_string()]
+        );
+    }
+
+    /// 1列目の値を整数として読み取る
+    async fn artist_ids(db_connection: &DatabaseConnection, sql: &str) -> Vec<i64> {
+        db_connection
+            .query_all(Statement::from_string(DatabaseBackend::Sqlite, sql))
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.try_get_by_index::<i64>(0).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_migrate_duplicated_artists() {
+        let db_connection = TestDatabase::connect_without_migration().await;
+        // アーティストの関係テーブルを作成する前までマイグレーションする
+        Migrator::up(&db_connection, Some(5)).await.unwrap();
+        db_connection
+            .execute_unprepared(
+                r"INSERT INTO games (id, created_at, updated_at, name)
+                      VALUES (1, '', '', 'arknights');
+                  INSERT INTO artists (id, created_at, updated_at, name)
+                      VALUES (1, '', '', 'A'), (2, '', '', 'B'), (3, '', '', 'A'), (4, '', '', '');
+                  INSERT INTO audio_formats (id, created_at, updated_at, format)
+                      VALUES (1, '', '', 'flac');
+                  INSERT INTO albums (id, created_at, updated_at, name, total_tracks, total_disks,
+                                      cover_image_path, game_id, artist_id)
+                      VALUES (1, '', '', 'album', 3, 1, '', 1, 3);
+                  INSERT INTO songs (id, created_at, updated_at, name, track_number, disk_number,
+                                     source_path, album_id, audio_format_id, artist_id)
+                      VALUES (1, '', '', 'song1', 1, 1, 'audio/1.flac', 1, 1, 3),
+                             (2, '', '', 'song2', 2, 1, 'audio/2.flac', 1, 1, 2),
+                             (3, '', '', 'song3', 3, 1, 'audio/3.flac', 1, 1, 4);",
+            )
+            .await
+            .unwrap();
+        Migrator::up(&db_connection, None).await.unwrap();
+
+        // 重複していたアーティストはIDが最小のものに寄せられる
+        assert_eq!(
+            artist_ids(&db_connection, "SELECT id FROM artists ORDER BY id").await,
+            vec![1, 2, 4]
+        );
+        assert_eq!(
+            artist_ids(&db_connection, "SELECT artist_id FROM songs ORDER BY id").await,
+            vec![1, 2, 4]
+        );
+        assert_eq!(
+            artist_ids(&db_connection, "SELECT artist_id FROM albums").await,
+            vec![1]
+        );
+        // 空の名前のアーティストはクレジットに移さない
+        assert_eq!(
+            artist_ids(
+                &db_connection,
+                "SELECT artist_id FROM song_artists ORDER BY song_id, position"
+            )
+            .await,
+            vec![1, 2]
+        );
+        assert_eq!(
+            artist_ids(
+                &db_connection,
+                "SELECT artist_id FROM album_artists ORDER BY album_id, position"
+            )
+            .await,
+            vec![1]
+        );
+
+        // 同じ名前のアーティストは登録できない
+        assert!(db_connection
+            .execute_unprepared(
+                "INSERT INTO artists (created_at, updated_at, name) VALUES ('', '', 'A')"
+            )
+            .await
+            .is_err());
+
+        // 移したクレジットの後に、クレジットの順番どおりに追加できる
+        let db = TestDatabase::with_connection(db_connection, "migrate-artists");
+        let song_id = SongId::new(2).unwrap();
+        assert_eq!(
+            db.get_song(song_id).await.unwrap().unwrap().artists,
+            vec!["B".to_string()]
+        );
+        db.save_album(Album {
+            id: AlbumId::new(1).unwrap(),
+            artists: vec!["C".into(), "A".into(), "B".into()],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            artist_ids(
+                &db.db_connection,
+                "SELECT artist_id FROM album_artists ORDER BY album_id, position"
+            )
+            .await,
+            vec![5, 1, 2]
+        );
+    }
+}
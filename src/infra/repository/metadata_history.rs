@@ -0,0 +1,91 @@
+use crate::domain::metadata_history::{MetadataChange, MetadataHistory, MetadataTarget};
+use crate::domain::repository::metadata_history_repository::UsesMetadataHistoryRepository;
+use crate::errors::infra::InfraError;
+use crate::errors::Error;
+use crate::errors::Result;
+use crate::infra::resource::database::{
+    execute_and_values, query_all_and_values, read_only_transaction, read_write_transaction,
+    ProvideDatabase,
+};
+use async_trait::async_trait;
+use sea_orm::DatabaseTransaction;
+
+pub trait DatabaseMetadataHistoryRepository: ProvideDatabase + Send + Sync + 'static {}
+
+#[async_trait]
+impl<R: DatabaseMetadataHistoryRepository> UsesMetadataHistoryRepository for R {
+    async fn save_metadata_changes(&self, changes: Vec<MetadataChange>) -> Result<()> {
+        read_write_transaction(self, |txn| {
+            Box::pin(async move { insert_metadata_changes(txn, changes).await })
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn get_recent_metadata_history(&self, limit: u32) -> Result<Vec<MetadataHistory>> {
+        read_only_transaction(self, |txn| {
+            Box::pin(async move {
+                let rows = query_all_and_values(
+                    txn,
+                    r"SELECT id, created_at, target, target_id, field, old_value, new_value
+                            FROM metadata_history
+                            ORDER BY id DESC
+                            LIMIT ?",
+                    [limit.into()],
+                )
+                .await?;
+
+                rows.into_iter()
+                    .map(|row| {
+                        let target = row.try_get::<String>("", "target")?;
+                        let target = target.parse::<MetadataTarget>().map_err(|e| {
+                            InfraError::MalformedColumn {
+                                column: "metadata_history.target".to_string(),
+                                cause: e.to_string(),
+                            }
+                        })?;
+                        Ok(MetadataHistory {
+                            id: row.try_get::<u32>("", "id")?,
+                            created_at: row.try_get::<String>("", "created_at")?,
+                            change: MetadataChange {
+                                target,
+                                id: row.try_get::<u32>("", "target_id")?,
+                                field: row.try_get::<String>("", "field")?,
+                                old_value: row.try_get::<String>("", "old_value")?,
+                                new_value: row.try_get::<String>("", "new_value")?,
+                            },
+                        })
+                    })
+                    .collect::<Result<Vec<_>, Error>>()
+            })
+        })
+        .await
+        .map_err(Into::into)
+    }
+}
+
+/// メタデータの変更を記録する。変更した楽曲やアルバムと同じトランザクションで呼び出す。
+pub(super) async fn insert_metadata_changes(
+    txn: &DatabaseTransaction,
+    changes: Vec<MetadataChange>,
+) -> Result<(), Error> {
+    for change in changes {
+        execute_and_values(
+            txn,
+            r"INSERT INTO metadata_history (
+                    created_at, target, target_id, field, old_value, new_value
+                ) VALUES (
+                    strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), ?, ?, ?, ?, ?
+                )",
+            [
+                change.target.to_string().into(),
+                change.id.into(),
+                change.field.into(),
+                change.old_value.into(),
+                change.new_value.into(),
+            ],
+        )
+        .await?;
+    }
+    Ok(())
+}
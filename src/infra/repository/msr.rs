@@ -1,69 +1,219 @@
-use crate::domain::msr::{Album, AlbumDetail, AlbumSummary, MsrResponse, Song, SongSummaries};
+pub mod dto;
+pub mod rate_limit;
+pub mod resume;
+pub mod retry;
+#[cfg(test)]
+mod test_server;
+
+use crate::domain::msr::{Album, AlbumDetail, AlbumSummary, DownloadedAudio, Song, SongSummaries};
 use crate::domain::repository::msr_repository::UsesMsrRepository;
-use anyhow::Result;
+use crate::domain::song::{content_hash, AlbumId, SongId};
+use crate::errors::domain::DomainError;
+use crate::errors::infra::InfraError;
+use crate::errors::Result;
+use crate::infra::resource::blob_store::BlobStore;
 use async_trait::async_trait;
 use bytes::Bytes;
+use dto::MsrResponse;
+use rate_limit::{RateLimitConfig, TokenBucket};
+use reqwest::header::{IF_RANGE, RANGE};
+use reqwest::StatusCode;
+use resume::{content_range_start, content_range_total, PartialDownload, CHECKPOINT_BYTES};
+use retry::{execute_with_retry, RetryPolicy};
+use std::path::Path;
+use std::sync::Arc;
 use url::Url;
 
+/// [`InfraError::MalformedResponse`]に含めるレスポンスの先頭部分の最大文字数
+const SNIPPET_LEN: usize = 200;
+
 #[derive(Debug, Clone)]
 pub struct WebApiMsrRepository {
     client: reqwest::Client,
     base_url: Url,
+    retry_policy: RetryPolicy,
+    /// APIの呼び出しの流量制限。クローン間で共有する。
+    api_limiter: Arc<TokenBucket>,
+    /// 音声データやカバー画像のダウンロードの流量制限。クローン間で共有する。
+    download_limiter: Arc<TokenBucket>,
+    /// ダウンロードした音声データを一時的に書き込む場所
+    blob_store: BlobStore,
 }
 
 impl WebApiMsrRepository {
-    pub fn new(client: reqwest::Client, base_url: Url) -> Self {
-        Self { client, base_url }
+    pub fn new(client: reqwest::Client, base_url: Url, blob_store: BlobStore) -> Self {
+        let rate_limit = RateLimitConfig::default();
+        Self {
+            client,
+            base_url,
+            retry_policy: RetryPolicy::default(),
+            api_limiter: Arc::new(TokenBucket::new(&rate_limit.api)),
+            download_limiter: Arc::new(TokenBucket::new(&rate_limit.download)),
+            blob_store,
+        }
+    }
+
+    pub fn with_retry_policy(self, retry_policy: RetryPolicy) -> Self {
+        Self {
+            retry_policy,
+            ..self
+        }
+    }
+
+    pub fn with_rate_limit(self, rate_limit: &RateLimitConfig) -> Self {
+        Self {
+            api_limiter: Arc::new(TokenBucket::new(&rate_limit.api)),
+            download_limiter: Arc::new(TokenBucket::new(&rate_limit.download)),
+            ..self
+        }
     }
 }
 
+/// リクエストを送信する。一時的な失敗は[`RetryPolicy`]に従ってリトライする。
+/// 成功以外のステータスコードは[`InfraError::UnexpectedStatus`]になる。
+/// * limiter: 流量制限。APIの呼び出しかダウンロードかで使い分ける。
+async fn send(
+    repo: &WebApiMsrRepository,
+    limiter: &TokenBucket,
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response> {
+    let res = execute(repo, limiter, request).await?;
+    check_status(res)
+}
+
+/// [`send`]と異なり、ステータスコードを確かめずにレスポンスを返す
+async fn execute(
+    repo: &WebApiMsrRepository,
+    limiter: &TokenBucket,
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response> {
+    let request = request.build()?;
+    let res = execute_with_retry(&repo.client, &repo.retry_policy, limiter, request).await?;
+    Ok(res)
+}
+
+/// 成功以外のステータスコードを[`InfraError::UnexpectedStatus`]にする
+fn check_status(res: reqwest::Response) -> Result<reqwest::Response> {
+    if !res.status().is_success() {
+        return Err(InfraError::UnexpectedStatus {
+            status: res.status().as_u16(),
+            url: res.url().clone(),
+        }
+        .into());
+    }
+    Ok(res)
+}
+
+async fn get(
+    repo: &WebApiMsrRepository,
+    limiter: &TokenBucket,
+    url: Url,
+) -> Result<reqwest::Response> {
+    send(repo, limiter, repo.client.get(url)).await
+}
+
+/// 前回中断したダウンロードを再開できるか調べる。
+/// 再開できる場合は、`received`を実際に`.part`に書き込まれている長さに合わせて返す。
+async fn resumable(url: &Url, part_path: &Path) -> Option<PartialDownload> {
+    let mut partial = PartialDownload::load(part_path).await?;
+    if partial.url != url.as_str() || partial.validator().is_none() {
+        return None;
+    }
+    let len = tokio::fs::metadata(part_path).await.ok()?.len();
+    partial.received = partial.received.min(len);
+    (partial.received > 0).then_some(partial)
+}
+
 async fn fetch_bytes(repo: &WebApiMsrRepository, url: Url) -> Result<Bytes> {
-    let res = repo.client.get(url).send().await?.bytes().await?;
+    let res = get(repo, &repo.download_limiter, url)
+        .await?
+        .bytes()
+        .await?;
     Ok(res)
 }
 
+/// MSRのAPIを呼び出し、[`MsrResponse`]の`data`を取り出す。
+/// `code`が0以外の場合は[`InfraError::MsrApiError`]、
+/// 想定と異なる形のレスポンスは[`InfraError::MalformedResponse`]になる。
 async fn fetch_json<U>(repo: &WebApiMsrRepository, url: Url) -> Result<U>
 where
     U: serde::de::DeserializeOwned,
 {
-    let res = repo.client.get(url).send().await?.json().await?;
-    Ok(res)
+    let body = get(repo, &repo.api_limiter, url.clone())
+        .await?
+        .bytes()
+        .await?;
+    let malformed = || InfraError::MalformedResponse {
+        url: url.clone(),
+        snippet: String::from_utf8_lossy(&body)
+            .chars()
+            .take(SNIPPET_LEN)
+            .collect(),
+    };
+    let res = serde_json::from_slice::<MsrResponse<Option<serde_json::Value>>>(&body)
+        .map_err(|_| malformed())?;
+    if res.code != 0 {
+        return Err(InfraError::MsrApiError {
+            code: res.code,
+            endpoint: url.path().to_string(),
+        }
+        .into());
+    }
+    let data = serde_json::from_value(res.data.unwrap_or_default()).map_err(|_| malformed())?;
+    Ok(data)
+}
+
+/// [`fetch_json`]で[`dto`]の型として取得し、ドメインの型に変換する。
+/// IDなどの検証に失敗した場合は[`DomainError`]になる。
+async fn fetch_dto<D, T>(repo: &WebApiMsrRepository, url: Url) -> Result<T>
+where
+    D: serde::de::DeserializeOwned,
+    T: TryFrom<D, Error = DomainError>,
+{
+    let dto = fetch_json::<D>(repo, url).await?;
+    Ok(T::try_from(dto)?)
 }
 
 #[async_trait]
 impl UsesMsrRepository for WebApiMsrRepository {
-    async fn fetch_song(&self, song_id: String) -> Result<Song> {
-        let url = self.base_url.join(format!("song/{}", song_id).as_str())?;
-        let song = fetch_json::<MsrResponse<Song>>(self, url).await?;
-        Ok(song.data)
+    async fn fetch_song(&self, song_id: SongId) -> Result<Song> {
+        let url = self
+            .base_url
+            .join(format!("song/{}", song_id.to_msr_string()).as_str())?;
+        let song = fetch_dto::<dto::Song, _>(self, url).await?;
+        Ok(song)
     }
 
     async fn fetch_all_songs(&self) -> Result<SongSummaries> {
         let url = self.base_url.join("songs")?;
-        let song_summaries = fetch_json::<MsrResponse<SongSummaries>>(self, url).await?;
-        Ok(song_summaries.data)
+        let song_summaries = fetch_dto::<dto::SongSummaries, _>(self, url).await?;
+        Ok(song_summaries)
     }
 
-    async fn fetch_album(&self, album_id: String) -> Result<Album> {
+    async fn fetch_album(&self, album_id: AlbumId) -> Result<Album> {
         let url = self
             .base_url
-            .join(format!("album/{}/data", album_id).as_str())?;
-        let album = fetch_json::<MsrResponse<Album>>(self, url).await?;
-        Ok(album.data)
+            .join(format!("album/{}/data", album_id.to_msr_string()).as_str())?;
+        let album = fetch_dto::<dto::Album, _>(self, url).await?;
+        Ok(album)
     }
 
-    async fn fetch_album_detail(&self, album_id: String) -> Result<AlbumDetail> {
+    async fn fetch_album_detail(&self, album_id: AlbumId) -> Result<AlbumDetail> {
         let url = self
             .base_url
-            .join(format!("album/{}/detail", album_id).as_str())?;
-        let album_detail = fetch_json::<MsrResponse<AlbumDetail>>(self, url).await?;
-        Ok(album_detail.data)
+            .join(format!("album/{}/detail", album_id.to_msr_string()).as_str())?;
+        let album_detail = fetch_dto::<dto::AlbumDetail, _>(self, url).await?;
+        Ok(album_detail)
     }
 
     async fn fetch_all_albums(&self) -> Result<Vec<AlbumSummary>> {
         let url = self.base_url.join("albums")?;
-        let album_summaries = fetch_json::<MsrResponse<Vec<AlbumSummary>>>(self, url).await?;
-        Ok(album_summaries.data)
+        let album_summaries = fetch_json::<Vec<dto::AlbumSummary>>(self, url)
+            .await?
+            .into_iter()
+            .map(AlbumSummary::try_from)
+            .collect::<Result<Vec<_>, DomainError>>()?;
+        Ok(album_summaries)
     }
 
     async fn fetch_raw_song(&self, source_url: Url) -> Result<Bytes> {
@@ -71,8 +221,406 @@ impl UsesMsrRepository for WebApiMsrRepository {
         Ok(raw_audio)
     }
 
+    async fn download_raw_song(&self, source_url: Url) -> Result<DownloadedAudio> {
+        let key = content_hash(source_url.as_str().as_bytes());
+        let part_path = self.blob_store.partial_path(&key);
+
+        // 前回の続きがあれば、サーバ上のファイルが変わっていない場合に限り続きから受け取る
+        let previous = resumable(&source_url, &part_path).await;
+        let mut request = self.client.get(source_url.clone());
+        if let Some(previous) = &previous {
+            request = request
+                .header(RANGE, format!("bytes={}-", previous.received))
+                .header(IF_RANGE, previous.validator().unwrap_or_default());
+        }
+        let mut res = execute(self, &self.download_limiter, request).await?;
+        if let Some(previous) = previous
+            .as_ref()
+            .filter(|_| res.status() == StatusCode::RANGE_NOT_SATISFIABLE)
+        {
+            if content_range_total(res.headers()) == Some(previous.received) {
+                // 前回すべて受信し終えていたので、書きかけのファイルをそのまま使う
+                let staged = self
+                    .blob_store
+                    .stage_at(part_path.clone(), Some(previous.received))
+                    .await?
+                    .finish()
+                    .await?;
+                PartialDownload::remove(&part_path).await;
+                return Ok(DownloadedAudio {
+                    staged_path: staged.path,
+                    hash: staged.hash,
+                    size: staged.size,
+                    head: staged.head,
+                });
+            }
+            // 書きかけのファイルがサーバ上のファイルと合わないので、捨てて最初からダウンロードし直す
+            PartialDownload::remove(&part_path).await;
+            let _ = tokio::fs::remove_file(&part_path).await;
+            res = get(self, &self.download_limiter, source_url.clone()).await?;
+        }
+        let mut res = check_status(res)?;
+        let resume_from = previous.as_ref().map(|p| p.received).filter(|offset| {
+            res.status() == StatusCode::PARTIAL_CONTENT
+                && content_range_start(res.headers()) == Some(*offset)
+        });
+        if res.status() == StatusCode::PARTIAL_CONTENT && resume_from.is_none() {
+            // 要求と異なる範囲が返ってきたので、最初からダウンロードし直す
+            res = get(self, &self.download_limiter, source_url.clone()).await?;
+        }
+
+        let mut partial = PartialDownload::new(source_url.to_string(), res.headers());
+        if let Some(previous) = previous.filter(|_| resume_from.is_some()) {
+            if partial.validator().is_none() {
+                partial.etag = previous.etag;
+                partial.last_modified = previous.last_modified;
+            }
+        }
+        // 200が返ってきた場合はファイルが変わっているので、書きかけのデータは捨てる
+        let mut staged = self
+            .blob_store
+            .stage_at(part_path.clone(), resume_from)
+            .await?;
+        partial.received = staged.size();
+        partial.save(&part_path).await?;
+
+        loop {
+            match res.chunk().await {
+                Ok(Some(chunk)) => {
+                    staged.write(&chunk).await?;
+                    if staged.size() - partial.received >= CHECKPOINT_BYTES {
+                        partial.received = staged.size();
+                        partial.save(&part_path).await?;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    // 次回続きから再開できるように、書きかけのファイルは残しておく
+                    partial.received = staged.suspend().await?;
+                    partial.save(&part_path).await?;
+                    return Err(e.into());
+                }
+            }
+        }
+        let staged = staged.finish().await?;
+        PartialDownload::remove(&part_path).await;
+        Ok(DownloadedAudio {
+            staged_path: staged.path,
+            hash: staged.hash,
+            size: staged.size,
+            head: staged.head,
+        })
+    }
+
     async fn fetch_cover_image(&self, cover_url: Url) -> Result<Bytes> {
         let cover_image = fetch_bytes(self, cover_url).await?;
         Ok(cover_image)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::resume::PartialDownload;
+    use super::retry::RetryPolicy;
+    use super::test_server::{response, TestServer};
+    use super::WebApiMsrRepository;
+    use crate::domain::repository::msr_repository::UsesMsrRepository;
+    use crate::domain::song::{content_hash, SongId};
+    use crate::errors::infra::InfraError;
+    use crate::errors::Error;
+    use crate::infra::resource::blob_store::BlobStore;
+
+    const SONGS: &[u8] = br#"{"code":0,"data":{"list":[]}}"#;
+
+    fn repository(server: &TestServer, max_retries: u32) -> WebApiMsrRepository {
+        let blob_store = BlobStore::new(std::env::temp_dir().join("msr-repository-test"));
+        WebApiMsrRepository::new(reqwest::Client::new(), server.base_url.clone(), blob_store)
+            .with_retry_policy(RetryPolicy {
+                max_retries,
+                initial_backoff_ms: 1,
+                max_backoff_ms: 10,
+            })
+    }
+
+    /// 実際のMSRのレスポンスをドメインの型に変換できるか確かめる。
+    /// ネットワークに接続できる環境で`cargo test -- --ignored`を実行する。
+    #[tokio::test]
+    #[ignore = "requires network access to MSR"]
+    async fn test_live_responses() {
+        let base_url = url::Url::parse("https://monster-siren.hypergryph.com/api/").unwrap();
+        let blob_store = BlobStore::new(std::env::temp_dir().join("msr-repository-test"));
+        let repo = WebApiMsrRepository::new(reqwest::Client::new(), base_url, blob_store);
+
+        let songs = repo.fetch_all_songs().await.unwrap();
+        let albums = repo.fetch_all_albums().await.unwrap();
+        assert!(!songs.list.is_empty());
+        assert!(!albums.is_empty());
+
+        let song = repo.fetch_song(songs.list[0].id).await.unwrap();
+        assert_eq!(song.id, songs.list[0].id);
+        let album = repo.fetch_album(song.belong_album_id).await.unwrap();
+        assert_eq!(album.id, song.belong_album_id);
+        let detail = repo.fetch_album_detail(album.id).await.unwrap();
+        assert!(detail.song_list.iter().any(|s| s.id == song.id));
+    }
+
+    #[tokio::test]
+    async fn test_retry_server_error() {
+        let server = TestServer::spawn(vec![
+            response(503, &[], b""),
+            response(502, &[], b""),
+            response(200, &[], SONGS),
+        ])
+        .await;
+
+        let songs = repository(&server, 3).fetch_all_songs().await.unwrap();
+        assert!(songs.list.is_empty());
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after() {
+        let server = TestServer::spawn(vec![
+            response(429, &[("Retry-After", "0")], b""),
+            response(200, &[], SONGS),
+        ])
+        .await;
+
+        repository(&server, 1).fetch_all_songs().await.unwrap();
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_give_up_after_max_retries() {
+        let server = TestServer::spawn(vec![
+            response(503, &[], b""),
+            response(503, &[], b""),
+            response(200, &[], SONGS),
+        ])
+        .await;
+
+        assert!(repository(&server, 1).fetch_all_songs().await.is_err());
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_on_client_error() {
+        let server =
+            TestServer::spawn(vec![response(404, &[], b""), response(200, &[], SONGS)]).await;
+
+        let err = repository(&server, 3).fetch_all_songs().await.unwrap_err();
+        assert_eq!(
+            err,
+            Error::from(InfraError::UnexpectedStatus {
+                status: 404,
+                url: server.base_url.join("songs").unwrap(),
+            })
+        );
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_msr_api_error() {
+        let server = TestServer::spawn(vec![response(
+            200,
+            &[],
+            br#"{"code":1,"msg":"not found","data":null}"#,
+        )])
+        .await;
+
+        let err = repository(&server, 0)
+            .fetch_song(SongId::new(1).unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            Error::from(InfraError::MsrApiError {
+                code: 1,
+                endpoint: "/song/000001".to_string(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_malformed_response() {
+        let server = TestServer::spawn(vec![response(200, &[], br#"{"code":0,"data":{}}"#)]).await;
+
+        let err = repository(&server, 0).fetch_all_songs().await.unwrap_err();
+        assert_eq!(
+            err,
+            Error::from(InfraError::MalformedResponse {
+                url: server.base_url.join("songs").unwrap(),
+                snippet: r#"{"code":0,"data":{}}"#.to_string(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_raw_song() {
+        let audio = b"fLaC streamed audio data".repeat(1024);
+        let server = TestServer::spawn(vec![response(200, &[], &audio)]).await;
+
+        let downloaded = repository(&server, 0)
+            .download_raw_song(server.base_url.join("song.flac").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(downloaded.hash, content_hash(&audio));
+        assert_eq!(downloaded.size, audio.len() as u64);
+        assert_eq!(&downloaded.head[..4], b"fLaC");
+        assert_eq!(std::fs::read(&downloaded.staged_path).unwrap(), audio);
+        std::fs::remove_file(&downloaded.staged_path).unwrap();
+    }
+
+    /// 書きかけのファイルとサイドカーファイルを用意する
+    async fn prepare_partial(
+        repo: &WebApiMsrRepository,
+        url: &url::Url,
+        data: &[u8],
+    ) -> std::path::PathBuf {
+        let part_path = repo
+            .blob_store
+            .partial_path(&content_hash(url.as_str().as_bytes()));
+        std::fs::create_dir_all(part_path.parent().unwrap()).unwrap();
+        std::fs::write(&part_path, data).unwrap();
+        PartialDownload {
+            url: url.to_string(),
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+            received: data.len() as u64,
+        }
+        .save(&part_path)
+        .await
+        .unwrap();
+        part_path
+    }
+
+    #[tokio::test]
+    async fn test_resume_download() {
+        let audio = b"fLaC resumable audio data".repeat(1024);
+        let (head, rest) = audio.split_at(10_000);
+        let content_range = format!("bytes 10000-{}/{}", audio.len() - 1, audio.len());
+        let server = TestServer::spawn(vec![response(
+            206,
+            &[("ETag", "\"v1\""), ("Content-Range", &content_range)],
+            rest,
+        )])
+        .await;
+        let repo = repository(&server, 0);
+        let url = server.base_url.join("song.flac").unwrap();
+        let part_path = prepare_partial(&repo, &url, head).await;
+
+        let downloaded = repo.download_raw_song(url).await.unwrap();
+        let request = server.requests()[0].to_lowercase();
+        assert!(request.contains("range: bytes=10000-"));
+        assert!(request.contains("if-range: \"v1\""));
+        assert_eq!(downloaded.hash, content_hash(&audio));
+        assert_eq!(downloaded.size, audio.len() as u64);
+        assert_eq!(&downloaded.head[..4], b"fLaC");
+        assert!(PartialDownload::load(&part_path).await.is_none());
+        std::fs::remove_file(&downloaded.staged_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume_download_changed_upstream() {
+        let stale = b"fLaC stale audio data".repeat(512);
+        let audio = b"fLaC updated audio data".repeat(1024);
+        // バリデータが一致しないので、サーバは全体を返す
+        let server = TestServer::spawn(vec![response(200, &[("ETag", "\"v2\"")], &audio)]).await;
+        let repo = repository(&server, 0);
+        let url = server.base_url.join("song.flac").unwrap();
+        prepare_partial(&repo, &url, &stale).await;
+
+        let downloaded = repo.download_raw_song(url).await.unwrap();
+        assert_eq!(downloaded.hash, content_hash(&audio));
+        assert_eq!(std::fs::read(&downloaded.staged_path).unwrap(), audio);
+        std::fs::remove_file(&downloaded.staged_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume_completed_download() {
+        let audio = b"fLaC completed audio data".repeat(1024);
+        // 前回すべて受信し終えていたので、続きの範囲は存在しない
+        let content_range = format!("bytes */{}", audio.len());
+        let server = TestServer::spawn(vec![response(
+            416,
+            &[("Content-Range", &content_range)],
+            b"",
+        )])
+        .await;
+        let repo = repository(&server, 0);
+        let url = server.base_url.join("song.flac").unwrap();
+        let part_path = prepare_partial(&repo, &url, &audio).await;
+
+        let downloaded = repo.download_raw_song(url).await.unwrap();
+        let request = server.requests()[0].to_lowercase();
+        assert!(request.contains(&format!("range: bytes={}-", audio.len())));
+        assert_eq!(downloaded.hash, content_hash(&audio));
+        assert_eq!(downloaded.size, audio.len() as u64);
+        assert!(PartialDownload::load(&part_path).await.is_none());
+        std::fs::remove_file(&downloaded.staged_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume_download_unsatisfiable_range() {
+        let stale = b"fLaC stale audio data".repeat(2048);
+        let audio = b"fLaC shorter audio".repeat(1024);
+        // 書きかけのファイルの方が長いので、最初からダウンロードし直す
+        let content_range = format!("bytes */{}", audio.len());
+        let server = TestServer::spawn(vec![
+            response(416, &[("Content-Range", &content_range)], b""),
+            response(200, &[("ETag", "\"v1\"")], &audio),
+        ])
+        .await;
+        let repo = repository(&server, 0);
+        let url = server.base_url.join("song.flac").unwrap();
+        let part_path = prepare_partial(&repo, &url, &stale).await;
+
+        let downloaded = repo.download_raw_song(url).await.unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[1].to_lowercase().contains("range:"));
+        assert_eq!(downloaded.hash, content_hash(&audio));
+        assert_eq!(std::fs::read(&downloaded.staged_path).unwrap(), audio);
+        assert!(PartialDownload::load(&part_path).await.is_none());
+        std::fs::remove_file(&downloaded.staged_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_download() {
+        let audio = b"fLaC interrupted audio data".repeat(1024);
+        let (head, rest) = audio.split_at(8_000);
+        // Content-Lengthより短いところで接続が切れる
+        let mut truncated = format!(
+            "HTTP/1.1 200 Test\r\nContent-Length: {}\r\nConnection: close\r\nETag: \"v1\"\r\n\r\n",
+            audio.len()
+        )
+        .into_bytes();
+        truncated.extend_from_slice(head);
+        let content_range = format!("bytes 8000-{}/{}", audio.len() - 1, audio.len());
+        let server = TestServer::spawn(vec![
+            truncated,
+            response(
+                206,
+                &[("ETag", "\"v1\""), ("Content-Range", &content_range)],
+                rest,
+            ),
+        ])
+        .await;
+        let repo = repository(&server, 0);
+        let url = server.base_url.join("song.flac").unwrap();
+
+        assert!(repo.download_raw_song(url.clone()).await.is_err());
+        let part_path = repo
+            .blob_store
+            .partial_path(&content_hash(url.as_str().as_bytes()));
+        let partial = PartialDownload::load(&part_path).await.unwrap();
+        assert_eq!(partial.received, head.len() as u64);
+        assert_eq!(partial.etag.as_deref(), Some("\"v1\""));
+
+        let downloaded = repo.download_raw_song(url).await.unwrap();
+        let request = server.requests()[1].to_lowercase();
+        assert!(request.contains("range: bytes=8000-"));
+        assert_eq!(downloaded.hash, content_hash(&audio));
+        std::fs::remove_file(&downloaded.staged_path).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+//! MSRのAPIのレスポンスの形。
+//! IDなどは文字列のまま受け取り、[`TryFrom`]でドメインの型に変換する際に検証する。
+use crate::domain::msr;
+use crate::domain::song::{AlbumId, OriginGame, SongId};
+use crate::errors::domain::DomainError;
+use url::Url;
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MsrResponse<T> {
+    pub code: i32,
+    pub data: T,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SongSummaries {
+    pub list: Vec<SongSummary>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SongSummary {
+    #[serde(rename = "cid")]
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "albumCid")]
+    pub belong_album_id: String,
+    pub artists: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumSummary {
+    #[serde(rename = "cid")]
+    pub id: String,
+    pub name: String,
+    pub cover_url: Url,
+    #[serde(rename = "artistes")]
+    pub artists: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Song {
+    #[serde(rename = "cid")]
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "albumCid")]
+    pub belong_album_id: String,
+    pub source_url: Url,
+    pub lyric_url: Option<Url>,
+    pub artists: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Album {
+    #[serde(rename = "cid")]
+    pub id: String,
+    pub name: String,
+    pub intro: String,
+    pub belong: String,
+    pub cover_url: Url,
+    pub cover_de_url: Url,
+    #[serde(rename = "artistes")]
+    pub artists: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumDetail {
+    #[serde(rename = "cid")]
+    pub id: String,
+    pub name: String,
+    pub intro: String,
+    pub belong: String,
+    pub cover_url: Url,
+    pub cover_de_url: Url,
+    #[serde(rename = "songs")]
+    pub song_list: Vec<SongShort>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SongShort {
+    #[serde(rename = "cid")]
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "artistes")]
+    pub artists: Vec<String>,
+}
+
+impl TryFrom<SongSummaries> for msr::SongSummaries {
+    type Error = DomainError;
+
+    fn try_from(dto: SongSummaries) -> Result<Self, Self::Error> {
+        Ok(Self {
+            list: dto
+                .list
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl TryFrom<SongSummary> for msr::SongSummary {
+    type Error = DomainError;
+
+    fn try_from(dto: SongSummary) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: SongId::try_new(dto.id)?,
+            name: dto.name,
+            belong_album_id: AlbumId::try_new(dto.belong_album_id)?,
+            artists: dto.artists,
+        })
+    }
+}
+
+impl TryFrom<AlbumSummary> for msr::AlbumSummary {
+    type Error = DomainError;
+
+    fn try_from(dto: AlbumSummary) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: AlbumId::try_new(dto.id)?,
+            name: dto.name,
+            cover_url: dto.cover_url,
+            artists: dto.artists,
+        })
+    }
+}
+
+impl TryFrom<Song> for msr::Song {
+    type Error = DomainError;
+
+    fn try_from(dto: Song) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: SongId::try_new(dto.id)?,
+            name: dto.name,
+            belong_album_id: AlbumId::try_new(dto.belong_album_id)?,
+            source_url: dto.source_url,
+            lyric_url: dto.lyric_url,
+            artists: dto.artists,
+        })
+    }
+}
+
+impl TryFrom<Album> for msr::Album {
+    type Error = DomainError;
+
+    fn try_from(dto: Album) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: AlbumId::try_new(dto.id)?,
+            name: dto.name,
+            intro: dto.intro,
+            belong: OriginGame::try_new(dto.belong)?,
+            cover_url: dto.cover_url,
+            cover_de_url: dto.cover_de_url,
+            artists: dto.artists,
+        })
+    }
+}
+
+impl TryFrom<AlbumDetail> for msr::AlbumDetail {
+    type Error = DomainError;
+
+    fn try_from(dto: AlbumDetail) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: AlbumId::try_new(dto.id)?,
+            name: dto.name,
+            intro: dto.intro,
+            belong: OriginGame::try_new(dto.belong)?,
+            cover_url: dto.cover_url,
+            cover_de_url: dto.cover_de_url,
+            song_list: dto
+                .song_list
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl TryFrom<SongShort> for msr::SongShort {
+    type Error = DomainError;
+
+    fn try_from(dto: SongShort) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: SongId::try_new(dto.id)?,
+            name: dto.name,
+            artists: dto.artists,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Album, AlbumDetail, AlbumSummary, MsrResponse, Song, SongSummaries};
+    use crate::domain::msr;
+    use crate::domain::song::{AlbumId, OriginGame, SongId};
+    use crate::errors::domain::DomainError;
+
+    fn parse<D, T>(json: &str) -> Result<T, DomainError>
+    where
+        D: serde::de::DeserializeOwned,
+        T: TryFrom<D, Error = DomainError>,
+    {
+        serde_json::from_str::<MsrResponse<D>>(json)
+            .unwrap()
+            .data
+            .try_into()
+    }
+
+    #[test]
+    fn test_songs() {
+        let songs = parse::<SongSummaries, msr::SongSummaries>(include_str!("fixtures/songs.json"))
+            .unwrap();
+        assert_eq!(songs.list.len(), 2);
+        assert_eq!(songs.list[1].id, SongId::new(48794).unwrap());
+        assert_eq!(songs.list[1].belong_album_id, AlbumId::new(249).unwrap());
+        assert_eq!(songs.list[1].artists.len(), 2);
+    }
+
+    #[test]
+    fn test_song() {
+        let song = parse::<Song, msr::Song>(include_str!("fixtures/song.json")).unwrap();
+        assert_eq!(song.id.to_msr_string(), "048794");
+        assert_eq!(song.belong_album_id.to_msr_string(), "0249");
+        assert!(song.source_url.path().ends_with(".wav"));
+        assert_eq!(song.lyric_url, None);
+    }
+
+    #[test]
+    fn test_albums() {
+        let albums = serde_json::from_str::<MsrResponse<Vec<AlbumSummary>>>(include_str!(
+            "fixtures/albums.json"
+        ))
+        .unwrap()
+        .data
+        .into_iter()
+        .map(msr::AlbumSummary::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+        assert_eq!(albums[0].id, AlbumId::new(6664).unwrap());
+        assert_eq!(albums[1].id, AlbumId::new(249).unwrap());
+    }
+
+    #[test]
+    fn test_album() {
+        let album = parse::<Album, msr::Album>(include_str!("fixtures/album_data.json")).unwrap();
+        assert_eq!(album.id, AlbumId::new(249).unwrap());
+        assert_eq!(album.belong, OriginGame::Arknights);
+
+        let detail =
+            parse::<AlbumDetail, msr::AlbumDetail>(include_str!("fixtures/album_detail.json"))
+                .unwrap();
+        assert_eq!(detail.id, AlbumId::new(249).unwrap());
+        assert_eq!(
+            detail
+                .song_list
+                .iter()
+                .map(|song| song.id)
+                .collect::<Vec<_>>(),
+            vec![SongId::new(48794).unwrap(), SongId::new(48795).unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_invalid_id() {
+        let json = include_str!("fixtures/song.json").replace(r#""0249""#, r#""02490""#);
+        assert_eq!(
+            parse::<Song, msr::Song>(&json),
+            Err(DomainError::FailedToParseAlbumId {
+                s: "02490".to_string()
+            })
+        );
+
+        let json = include_str!("fixtures/album_detail.json").replace("048795", "48795x");
+        assert_eq!(
+            parse::<AlbumDetail, msr::AlbumDetail>(&json),
+            Err(DomainError::FailedToParseSongId {
+                s: "48795x".to_string()
+            })
+        );
+    }
+}
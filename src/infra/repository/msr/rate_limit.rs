@@ -0,0 +1,132 @@
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// トークンバケットの設定
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct RateLimit {
+    /// トークンが1つ補充されるまでの時間（ミリ秒）。0の場合は制限しない。
+    pub interval_ms: u64,
+    /// 貯めておけるトークンの数。連続して送信できるリクエストの数になる。
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            interval_ms: 250,
+            burst: 4,
+        }
+    }
+}
+
+/// MSRへのリクエストの流量制限。
+/// APIの呼び出しと、音声データやカバー画像のような大きなダウンロードで別々に制限する。
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub api: RateLimit,
+    pub download: RateLimit,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            api: RateLimit::default(),
+            download: RateLimit {
+                interval_ms: 1_000,
+                burst: 2,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// トークンバケットによる流量制限。
+/// `Arc`で包んで共有すれば、複数のタスクから同じ制限を使える。
+#[derive(Debug)]
+pub struct TokenBucket {
+    interval: Duration,
+    burst: f64,
+    state: Mutex<BucketState>,
+}
+
+impl TokenBucket {
+    pub fn new(limit: &RateLimit) -> Self {
+        let burst = f64::from(limit.burst.max(1));
+        Self {
+            interval: Duration::from_millis(limit.interval_ms),
+            burst,
+            state: Mutex::new(BucketState {
+                tokens: burst,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// トークンを1つ取得する。トークンがなければ補充されるまで待つ。
+    pub async fn acquire(&self) {
+        if self.interval.is_zero() {
+            return;
+        }
+        loop {
+            let wait = {
+                let mut state = self.state.lock().await;
+                let now = Instant::now();
+                let refilled = now.duration_since(state.last_refill).as_secs_f64()
+                    / self.interval.as_secs_f64();
+                state.tokens = (state.tokens + refilled).min(self.burst);
+                state.last_refill = now;
+                if state.tokens >= 1.0 {
+                    state.tokens -= 1.0;
+                    return;
+                }
+                self.interval.mul_f64(1.0 - state.tokens)
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimit, TokenBucket};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn test_acquire_waits_after_burst() {
+        let bucket = Arc::new(TokenBucket::new(&RateLimit {
+            interval_ms: 50,
+            burst: 2,
+        }));
+        let shared = bucket.clone();
+
+        let start = Instant::now();
+        bucket.acquire().await;
+        shared.acquire().await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+        // バケットは共有されているので、3つ目は補充を待つ
+        shared.acquire().await;
+        assert!(start.elapsed() >= Duration::from_millis(45));
+    }
+
+    #[tokio::test]
+    async fn test_unlimited() {
+        let bucket = TokenBucket::new(&RateLimit {
+            interval_ms: 0,
+            burst: 1,
+        });
+        let start = Instant::now();
+        for _ in 0..100 {
+            bucket.acquire().await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,134 @@
+//! 中断したダウンロードを再開するための情報。
+//! 書きかけのファイル（`.part`）の隣にJSONのサイドカーファイルとして保存する。
+
+use crate::errors::infra::InfraError;
+use reqwest::header::{HeaderMap, HeaderName, CONTENT_RANGE, ETAG, LAST_MODIFIED};
+use std::path::{Path, PathBuf};
+
+/// これだけ受信するたびにサイドカーファイルを更新する
+pub const CHECKPOINT_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct PartialDownload {
+    /// ダウンロード元のURL
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// `.part`に書き込み済みのバイト数
+    pub received: u64,
+}
+
+impl PartialDownload {
+    /// レスポンスのヘッダからバリデータを読み取る
+    pub fn new(url: String, headers: &HeaderMap) -> Self {
+        let header = |name: HeaderName| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            url,
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            received: 0,
+        }
+    }
+
+    /// `If-Range`に指定する値。
+    /// 弱いETagは`If-Range`に使えないので、その場合は`Last-Modified`を使う。
+    /// どちらもなければ、サーバ上のファイルが変わっていないことを確かめられないので再開しない。
+    pub fn validator(&self) -> Option<&str> {
+        self.etag
+            .as_deref()
+            .filter(|etag| !etag.starts_with("W/"))
+            .or(self.last_modified.as_deref())
+    }
+
+    /// サイドカーファイルを読み込む。存在しないか壊れている場合は`None`を返す。
+    pub async fn load(part_path: &Path) -> Option<Self> {
+        let raw = tokio::fs::read(sidecar_path(part_path)).await.ok()?;
+        serde_json::from_slice(&raw).ok()
+    }
+
+    pub async fn save(&self, part_path: &Path) -> Result<(), InfraError> {
+        let raw = serde_json::to_vec(self).map_err(|e| InfraError::Io {
+            cause: e.to_string(),
+        })?;
+        tokio::fs::write(sidecar_path(part_path), raw).await?;
+        Ok(())
+    }
+
+    pub async fn remove(part_path: &Path) {
+        let _ = tokio::fs::remove_file(sidecar_path(part_path)).await;
+    }
+}
+
+fn sidecar_path(part_path: &Path) -> PathBuf {
+    let mut path = part_path.as_os_str().to_owned();
+    path.push(".json");
+    PathBuf::from(path)
+}
+
+/// `Content-Range: bytes <start>-<end>/<total>`の開始位置を読み取る
+pub fn content_range_start(headers: &HeaderMap) -> Option<u64> {
+    let value = headers.get(CONTENT_RANGE)?.to_str().ok()?;
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (start, _) = range.split_once('-')?;
+    start.trim().parse().ok()
+}
+
+/// `Content-Range: bytes <start>-<end>/<total>`や`bytes */<total>`の全体の長さを読み取る
+pub fn content_range_total(headers: &HeaderMap) -> Option<u64> {
+    let value = headers.get(CONTENT_RANGE)?.to_str().ok()?;
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (_, total) = range.split_once('/')?;
+    total.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{content_range_start, content_range_total, PartialDownload};
+    use reqwest::header::{HeaderMap, HeaderValue, CONTENT_RANGE, ETAG, LAST_MODIFIED};
+
+    #[test]
+    fn test_validator() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            LAST_MODIFIED,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        headers.insert(ETAG, HeaderValue::from_static("W/\"weak\""));
+        let partial = PartialDownload::new("http://example.com/a.flac".to_string(), &headers);
+        assert_eq!(partial.validator(), Some("Wed, 21 Oct 2015 07:28:00 GMT"));
+
+        headers.insert(ETAG, HeaderValue::from_static("\"strong\""));
+        let partial = PartialDownload::new("http://example.com/a.flac".to_string(), &headers);
+        assert_eq!(partial.validator(), Some("\"strong\""));
+
+        let partial =
+            PartialDownload::new("http://example.com/a.flac".to_string(), &HeaderMap::new());
+        assert_eq!(partial.validator(), None);
+    }
+
+    #[test]
+    fn test_content_range_start() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_RANGE, HeaderValue::from_static("bytes 100-199/200"));
+        assert_eq!(content_range_start(&headers), Some(100));
+        headers.insert(CONTENT_RANGE, HeaderValue::from_static("bytes */200"));
+        assert_eq!(content_range_start(&headers), None);
+    }
+
+    #[test]
+    fn test_content_range_total() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(content_range_total(&headers), None);
+        headers.insert(CONTENT_RANGE, HeaderValue::from_static("bytes 100-199/200"));
+        assert_eq!(content_range_total(&headers), Some(200));
+        headers.insert(CONTENT_RANGE, HeaderValue::from_static("bytes */200"));
+        assert_eq!(content_range_total(&headers), Some(200));
+        headers.insert(CONTENT_RANGE, HeaderValue::from_static("bytes 0-99/*"));
+        assert_eq!(content_range_total(&headers), None);
+    }
+}
@@ -0,0 +1,172 @@
+use super::rate_limit::TokenBucket;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, Method, Request, Response, StatusCode};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime};
+
+/// 一時的な失敗に対するリトライの方針。
+/// 冪等なリクエスト（GETとHEAD）のみリトライする。
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// 最初のリクエストに加えて何回までリトライするか
+    pub max_retries: u32,
+    /// 1回目のリトライまでの待ち時間（ミリ秒）。リトライのたびに2倍になる。
+    pub initial_backoff_ms: u64,
+    /// 待ち時間の上限（ミリ秒）。`Retry-After`で指定された待ち時間もこれで打ち切る。
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// リトライしない
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// `attempt`回目のリトライまでの待ち時間を返す。
+    /// 指数的に増やした待ち時間の半分から全体までの間でランダムに揺らす。
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let base = self
+            .initial_backoff_ms
+            .saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX))
+            .min(self.max_backoff_ms);
+        let half = base / 2;
+        let jitter = if half == 0 {
+            0
+        } else {
+            random_u64() % (half + 1)
+        };
+        Duration::from_millis(half + jitter)
+    }
+
+    /// `Retry-After`ヘッダから待ち時間を読み取る。秒数とHTTP-dateの両方に対応する。
+    /// サーバーが長すぎる待ち時間を指定しても、[`RetryPolicy::max_backoff_ms`]を超えては待たない。
+    pub fn retry_after(&self, headers: &HeaderMap) -> Option<Duration> {
+        let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+        let wait = match value.parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => httpdate::parse_http_date(value)
+                .ok()?
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO),
+        };
+        Some(wait.min(Duration::from_millis(self.max_backoff_ms)))
+    }
+}
+
+/// リクエストを送信し、一時的な失敗であればリトライする。
+/// リトライしても失敗した場合は最後のレスポンスかエラーを返す。
+/// リトライを含め、送信する前に毎回`limiter`からトークンを取得する。
+pub async fn execute_with_retry(
+    client: &Client,
+    policy: &RetryPolicy,
+    limiter: &TokenBucket,
+    request: Request,
+) -> reqwest::Result<Response> {
+    let idempotent = matches!(*request.method(), Method::GET | Method::HEAD);
+    let mut attempt = 0;
+    loop {
+        limiter.acquire().await;
+        // 最後の試行、またはリトライできないリクエストはそのまま送信する
+        let current = match request.try_clone() {
+            Some(current) if idempotent && attempt < policy.max_retries => current,
+            _ => return client.execute(request).await,
+        };
+
+        let wait = match client.execute(current).await {
+            Ok(response) if is_retryable_status(response.status()) => policy
+                .retry_after(response.headers())
+                .unwrap_or_else(|| policy.backoff(attempt)),
+            Ok(response) => return Ok(response),
+            Err(e) if is_retryable_error(&e) => policy.backoff(attempt),
+            Err(e) => return Err(e),
+        };
+        tokio::time::sleep(wait).await;
+        attempt += 1;
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+fn is_retryable_error(err: &reqwest::Error) -> bool {
+    err.is_timeout() || err.is_connect() || err.is_request() || err.is_body()
+}
+
+/// ジッター用の乱数。暗号学的な強度は必要ないので標準ライブラリのハッシュのシードを使う。
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1_000,
+        };
+        for attempt in 0..10 {
+            let base = (100u64 << attempt).min(1_000);
+            let backoff = policy.backoff(attempt);
+            assert!(backoff >= Duration::from_millis(base / 2));
+            assert!(backoff <= Duration::from_millis(base));
+        }
+        // 桁あふれしない
+        assert!(policy.backoff(100) <= Duration::from_millis(1_000));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 30_000,
+        };
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+            headers
+        };
+
+        assert_eq!(policy.retry_after(&HeaderMap::new()), None);
+        assert_eq!(
+            policy.retry_after(&headers("2")),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            policy.retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")),
+            Some(Duration::ZERO)
+        );
+        // 長すぎる待ち時間は上限で打ち切る
+        assert_eq!(
+            policy.retry_after(&headers("86400")),
+            Some(Duration::from_secs(30))
+        );
+        let far_future = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(86400));
+        assert_eq!(
+            policy.retry_after(&headers(&far_future)),
+            Some(Duration::from_secs(30))
+        );
+    }
+}
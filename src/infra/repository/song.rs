@@ -1,85 +1,379 @@
+pub mod loudness;
+pub mod probe;
+pub mod tag;
+pub mod transcode;
+
+use crate::domain::loudness::{Loudness, LoudnessAnalysis, Measurement};
+use crate::domain::metadata_history::MetadataChange;
+use crate::domain::probe::AudioProperties;
 use crate::domain::repository::song_repository::UsesSongRepository;
-use crate::domain::song::{Album, AlbumId, AudioRawData, Song, SongId};
+use crate::domain::song::{
+    Album, AlbumId, AudioFormat, AudioRawData, CoverImage, OriginGame, Song, SongId,
+};
+use crate::domain::tag::{tagger, TagPolicy, TrackTags};
 use crate::errors::infra::InfraError;
 use crate::errors::Error;
+use crate::errors::Result;
+use crate::infra::repository::metadata_history::insert_metadata_changes;
+use crate::infra::resource::blob_store::{BlobStore, ProvideBlobStore};
 use crate::infra::resource::database::{
-    execute_and_values, query_all_and_values, query_one_and_values, read_only_transaction,
-    read_write_transaction, ProvideDatabase,
+    execute_and_values, query_all, query_all_and_values, query_one_and_values,
+    read_only_transaction, read_write_transaction, ProvideDatabase,
 };
-use anyhow::Result;
 use async_trait::async_trait;
 use bytes::Bytes;
-use sea_orm::{ExecResult, TransactionError};
-use std::path::PathBuf;
+use sea_orm::{DatabaseTransaction, QueryResult};
+use std::path::{Path, PathBuf};
+
+pub trait DatabaseSongRepository: ProvideDatabase + ProvideBlobStore + Send + Sync + 'static {
+    /// 音声データに元から書き込まれているタグの扱い
+    fn tag_policy(&self) -> TagPolicy {
+        TagPolicy::Merge
+    }
+
+    /// ダウンロードしたWAVをFLACに可逆変換して保存するか
+    fn convert_wav_to_flac(&self) -> bool {
+        false
+    }
+
+    fn save_options(&self) -> SaveOptions {
+        SaveOptions {
+            tag_policy: self.tag_policy(),
+            convert_wav_to_flac: self.convert_wav_to_flac(),
+        }
+    }
+}
 
-pub trait DatabaseSongRepository: ProvideDatabase + Send + Sync + 'static {}
+/// 楽曲を保存する際の音声データの扱い
+#[derive(Debug, Clone, Copy)]
+pub struct SaveOptions {
+    pub tag_policy: TagPolicy,
+    pub convert_wav_to_flac: bool,
+}
 
 #[async_trait]
 impl<R: DatabaseSongRepository> UsesSongRepository for R {
     async fn get_song(&self, song_id: SongId) -> Result<Option<Song>> {
+        read_only_transaction(self, |txn| Box::pin(async move { find_song(txn, song_id).await }))
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn save_song(&self, song: Song) -> Result<()> {
+        self.save_song_with_changes(song, vec![]).await
+    }
+
+    async fn save_song_with_changes(
+        &self,
+        song: Song,
+        changes: Vec<MetadataChange>,
+    ) -> Result<()> {
+        let guard = self.provide_blob_store().lock_for_save().await;
+        let prepared = prepare_song(self, &song).await?;
+        let result = read_write_transaction(self, |txn| {
+            let song = song.clone();
+            let prepared = prepared.clone();
+            let changes = changes.clone();
+            Box::pin(async move {
+                upsert_song(txn, &song, &prepared).await?;
+                insert_metadata_changes(txn, changes).await
+            })
+        })
+        .await;
+        drop(guard);
+        if let Err(e) = finish_song(self, &song, &prepared, result.is_ok()).await {
+            warn_cleanup_failure(&e);
+        }
+        result.map_err(Into::into)
+    }
+
+    async fn delete_song(&self, song_id: SongId) -> Result<()> {
         let id: u32 = song_id.into();
+        read_write_transaction(self, |txn| {
+            Box::pin(async move {
+                execute_and_values(
+                    txn,
+                    r"UPDATE songs
+                            SET is_deleted = true,
+                                updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                            WHERE id = ?
+                            ",
+                    vec![id.into()],
+                )
+                .await?;
+                Ok::<(), Error>(())
+            })
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn get_all_songs_id(&self) -> Result<Vec<SongId>> {
+        read_only_transaction(self, |txn| Box::pin(async move { find_all_songs_id(txn).await }))
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn get_all_song(&self) -> Result<Vec<Song>> {
+        read_only_transaction(self, |txn| {
+            Box::pin(async move {
+                let mut songs = vec![];
+                for song_id in find_all_songs_id(txn).await? {
+                    if let Some(song) = find_song(txn, song_id).await? {
+                        songs.push(song);
+                    }
+                }
+                Ok::<_, Error>(songs)
+            })
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn save_songs(&self, songs: Vec<Song>) -> Result<()> {
+        let guard = self.provide_blob_store().lock_for_save().await;
+        let mut prepared = Vec::with_capacity(songs.len());
+        for song in songs.iter() {
+            match prepare_song(self, song).await {
+                Ok(p) => prepared.push(p),
+                Err(e) => {
+                    drop(guard);
+                    for (song, p) in songs.iter().zip(prepared.iter()) {
+                        if let Err(e) = finish_song(self, song, p, false).await {
+                            warn_cleanup_failure(&e);
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        let result = read_write_transaction(self, |txn| {
+            let songs = songs.clone();
+            let prepared = prepared.clone();
+            Box::pin(async move {
+                for (song, prepared) in songs.iter().zip(prepared.iter()) {
+                    upsert_song(txn, song, prepared).await?;
+                }
+                Ok::<_, Error>(())
+            })
+        })
+        .await;
+        drop(guard);
+        for (song, prepared) in songs.iter().zip(prepared.iter()) {
+            if let Err(e) = finish_song(self, song, prepared, result.is_ok()).await {
+                warn_cleanup_failure(&e);
+            }
+        }
+        result.map_err(Into::into)
+    }
+
+    async fn load_audio(&self, song: &Song) -> Result<Bytes> {
+        if let Some(raw) = &song.source.raw {
+            return Ok(raw.clone());
+        }
+        let raw = self
+            .provide_blob_store()
+            .get(&song.source.save_path)
+            .await?;
+        Ok(raw)
+    }
+
+    async fn measure_loudness(&self, song: &Song) -> Result<Measurement> {
+        let measurement = loudness::measure_stored(
+            self.provide_blob_store(),
+            &song.source.save_path,
+            song.source.format,
+        )
+        .await?;
+        Ok(measurement)
+    }
+
+    async fn get_album(&self, album_id: AlbumId) -> Result<Option<Album>> {
         read_only_transaction(self, |txn| {
+            Box::pin(async move { find_album(txn, album_id).await })
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn save_album(&self, album: Album) -> Result<()> {
+        self.save_album_with_changes(album, vec![]).await
+    }
+
+    async fn save_album_with_changes(
+        &self,
+        album: Album,
+        changes: Vec<MetadataChange>,
+    ) -> Result<()> {
+        let store = self.provide_blob_store().clone();
+        read_write_transaction(self, |txn| {
+            Box::pin(async move {
+                upsert_album(txn, &store, &album).await?;
+                insert_metadata_changes(txn, changes).await
+            })
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn delete_album(&self, album_id: AlbumId) -> Result<()> {
+        let id: u32 = album_id.into();
+        read_write_transaction(self, |txn| {
             Box::pin(async move {
-                let song_query = match query_one_and_values(
+                execute_and_values(
                     txn,
-                    r"SELECT id, name, track_number, disk_number, source_path, audio_formats.format AS format, album_id,
-                            FROM songs
-                                INNER JOIN audio_formats ON songs.audio_format_id = audio_formats.id
-                            WHERE songs.id = ? AND songs.is_deleted = false
+                    r"UPDATE albums
+                            SET is_deleted = true,
+                                updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                            WHERE id = ?
                             ",
                     vec![id.into()],
                 )
-                .await.map_err(Into::<InfraError>::into)? {
-                    Some(song_query) => song_query,
-                    None => return Ok(None),
-                };
+                .await?;
+                Ok::<(), Error>(())
+            })
+        })
+        .await
+        .map_err(Into::into)
+    }
 
-                let raw = Bytes::new(); // TODO: ファイルシステムからデータを取得する
-                let song_id_u32 = song_query.try_get::<u32>("", "id").map_err(Into::<InfraError>::into)?;
-                let song_id: SongId = song_id_u32.into();
-                let album_id: AlbumId = song_query.try_get::<u32>("", "album_id").map_err(Into::<InfraError>::into)?.into();
-                // TODO: Domainに移動
-                let audio_format = song_query.try_get::<String>("", "format").map_err(Into::<InfraError>::into)?.parse().unwrap();
-                let path = PathBuf::from(song_query.try_get::<String>("", "source_path").map_err(Into::<InfraError>::into)?);
-                let audio_raw_data = AudioRawData::reconstruct(raw, audio_format, path);
+    async fn set_song_withdrawn(&self, song_id: SongId, withdrawn: bool) -> Result<()> {
+        let id: u32 = song_id.into();
+        read_write_transaction(self, |txn| {
+            Box::pin(async move {
+                execute_and_values(
+                    txn,
+                    r"UPDATE songs
+                            SET is_withdrawn = ?,
+                                updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                            WHERE id = ?
+                            ",
+                    vec![withdrawn.into(), id.into()],
+                )
+                .await?;
+                Ok::<(), Error>(())
+            })
+        })
+        .await
+        .map_err(Into::into)
+    }
 
-                let artists_query = query_all_and_values(
+    async fn set_album_withdrawn(&self, album_id: AlbumId, withdrawn: bool) -> Result<()> {
+        let id: u32 = album_id.into();
+        read_write_transaction(self, |txn| {
+            Box::pin(async move {
+                execute_and_values(
                     txn,
-                    r"SELECT name FROM artists
-                            WHERE artists.id = ? AND artists.is_deleted = false
+                    r"UPDATE albums
+                            SET is_withdrawn = ?,
+                                updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                            WHERE id = ?
                             ",
-                    vec![song_id_u32.into()],
-                ).await.map_err(Into::<InfraError>::into)?;
-                let artists = artists_query
-                    .iter()
-                    .map(|artist_query| artist_query.try_get("", "name").map_err(Into::<InfraError>::into))
-                    .collect::<std::result::Result<Vec<String>, _>>()?;
+                    vec![withdrawn.into(), id.into()],
+                )
+                .await?;
+                Ok::<(), Error>(())
+            })
+        })
+        .await
+        .map_err(Into::into)
+    }
 
-                let song = Song::try_reconstruct(
-                    song_id,
-                    song_query.try_get("", "name").map_err(Into::<InfraError>::into)?,
-                    album_id,
-                    song_query.try_get("", "track_number").map_err(Into::<InfraError>::into)?,
-                    song_query.try_get("", "disk_number").map_err(Into::<InfraError>::into)?,
-                    audio_raw_data,
-                    artists,
-                )?;
+    async fn get_withdrawn_songs_id(&self) -> Result<Vec<SongId>> {
+        read_only_transaction(self, |txn| {
+            Box::pin(async move {
+                query_all(
+                    txn,
+                    r"SELECT id FROM songs WHERE is_withdrawn = true ORDER BY id",
+                )
+                .await?
+                .into_iter()
+                .map(|query| Ok(SongId::new(query.try_get::<u32>("", "id")?)?))
+                .collect::<Result<Vec<_>, Error>>()
+            })
+        })
+        .await
+        .map_err(Into::into)
+    }
 
-                Ok::<_, Error>(Some(song))
+    async fn get_withdrawn_albums_id(&self) -> Result<Vec<AlbumId>> {
+        read_only_transaction(self, |txn| {
+            Box::pin(async move {
+                query_all(
+                    txn,
+                    r"SELECT id FROM albums WHERE is_withdrawn = true ORDER BY id",
+                )
+                .await?
+                .into_iter()
+                .map(|query| Ok(AlbumId::new(query.try_get::<u32>("", "id")?)?))
+                .collect::<Result<Vec<_>, Error>>()
             })
         })
         .await
         .map_err(Into::into)
     }
 
-    async fn save_song(&self, song: Song) -> Result<()> {
+    async fn restore_song(&self, song_id: SongId) -> Result<()> {
+        let id: u32 = song_id.into();
+        let save_path = read_only_transaction(self, |txn| {
+            Box::pin(async move {
+                let found = query_one_and_values(
+                    txn,
+                    r"SELECT source_path FROM songs WHERE id = ?",
+                    vec![id.into()],
+                )
+                .await?;
+                found
+                    .map(|found| found.try_get::<String>("", "source_path"))
+                    .transpose()
+                    .map_err(Error::from)
+            })
+        })
+        .await?;
+        // 音声データを削除していた場合は、次の同期でダウンロードし直して復元する
+        let restorable = match save_path {
+            Some(save_path) => {
+                tokio::fs::try_exists(self.provide_blob_store().resolve(Path::new(&save_path)))
+                    .await
+                    .map_err(InfraError::from)?
+            }
+            None => false,
+        };
         read_write_transaction(self, |txn| {
             Box::pin(async move {
-                let audio_format_id: Result<ExecResult, InfraError> =
-                    execute_and_values(txn, r"", [song.source.format.to_string().into()])
-                        .await.map_err(Into::<InfraError>::into);
+                execute_and_values(
+                    txn,
+                    r"UPDATE songs
+                            SET is_withdrawn = false,
+                                is_deleted = is_deleted AND NOT ?,
+                                updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                            WHERE id = ?
+                            ",
+                    vec![restorable.into(), id.into()],
+                )
+                .await?;
+                Ok::<(), Error>(())
+            })
+        })
+        .await
+        .map_err(Into::into)
+    }
 
-                todo!("Songのinsert");
+    async fn restore_album(&self, album_id: AlbumId) -> Result<()> {
+        let id: u32 = album_id.into();
+        read_write_transaction(self, |txn| {
+            Box::pin(async move {
+                execute_and_values(
+                    txn,
+                    r"UPDATE albums
+                            SET is_withdrawn = false,
+                                is_deleted = false,
+                                updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                            WHERE id = ?
+                            ",
+                    vec![id.into()],
+                )
+                .await?;
                 Ok::<(), Error>(())
             })
         })
@@ -87,39 +381,961 @@ impl<R: DatabaseSongRepository> UsesSongRepository for R {
         .map_err(Into::into)
     }
 
-    async fn delete_song(&self, song_id: SongId) -> Result<()> {
+    async fn purge_audio(&self, song: &Song) -> Result<()> {
+        let id: u32 = song.id.into();
+        let save_path = song.source.save_path.to_string_lossy().into_owned();
+        let _guard = self.provide_blob_store().lock_for_gc().await;
+        // 同じ内容の音声データは同じパスに保存されるので、他の楽曲が参照していないか確認する
+        let shared = read_only_transaction(self, |txn| {
+            Box::pin(async move {
+                let found = query_one_and_values(
+                    txn,
+                    r"SELECT COUNT(*) AS count FROM songs
+                            WHERE source_path = ? AND id != ? AND is_deleted = false
+                            ",
+                    vec![save_path.into(), id.into()],
+                )
+                .await?;
+                let count = match found {
+                    Some(found) => found.try_get::<i64>("", "count")?,
+                    None => 0,
+                };
+                Ok::<bool, Error>(count > 0)
+            })
+        })
+        .await?;
+        if !shared {
+            self.provide_blob_store()
+                .remove(&song.source.save_path)
+                .await?;
+        }
         Ok(())
     }
 
-    async fn get_all_songs_id(&self) -> Result<Vec<SongId>> {
-        Ok(vec![])
+    async fn get_all_album(&self) -> Result<Vec<Album>> {
+        read_only_transaction(self, |txn| {
+            Box::pin(async move {
+                let albums_query = query_all(
+                    txn,
+                    r"SELECT id FROM albums
+                            WHERE is_deleted = false
+                            ORDER BY id
+                            ",
+                )
+                .await?;
+                let mut albums = vec![];
+                for album_query in albums_query {
+                    let album_id = AlbumId::new(album_query.try_get::<u32>("", "id")?)?;
+                    if let Some(album) = find_album(txn, album_id).await? {
+                        albums.push(album);
+                    }
+                }
+                Ok::<_, Error>(albums)
+            })
+        })
+        .await
+        .map_err(Into::into)
     }
 
-    async fn get_all_song(&self) -> Result<Vec<Song>> {
-        Ok(vec![])
+    async fn save_all_album(&self, albums: Vec<Album>) -> Result<()> {
+        let store = self.provide_blob_store().clone();
+        read_write_transaction(self, |txn| {
+            Box::pin(async move {
+                for album in albums.iter() {
+                    upsert_album(txn, &store, album).await?;
+                }
+                Ok::<(), Error>(())
+            })
+        })
+        .await
+        .map_err(Into::into)
     }
+}
 
-    async fn save_songs(&self, songs: Vec<Song>) -> Result<()> {
-        Ok(())
+/// 削除されていない楽曲を1件取得する。
+/// 音声データは読み込まず、必要になったときに[`UsesSongRepository::load_audio`]で読み込む。
+async fn find_song(txn: &DatabaseTransaction, song_id: SongId) -> Result<Option<Song>, Error> {
+    let id: u32 = song_id.into();
+    let song_query = match query_one_and_values(
+        txn,
+        r"SELECT songs.id AS id, songs.name AS name, songs.track_number AS track_number,
+                    songs.disk_number AS disk_number, songs.source_path AS source_path,
+                    audio_formats.format AS format, songs.album_id AS album_id,
+                    songs.is_withdrawn AS is_withdrawn, songs.original_hash AS original_hash,
+                    songs.duration_ms AS duration_ms, songs.sample_rate AS sample_rate,
+                    songs.channels AS channels, songs.bits_per_sample AS bits_per_sample,
+                    songs.bitrate_kbps AS bitrate_kbps, songs.loudness_lufs AS loudness_lufs,
+                    songs.true_peak AS true_peak, songs.loudness_analyzed AS loudness_analyzed
+                FROM songs
+                    INNER JOIN audio_formats ON songs.audio_format_id = audio_formats.id
+                WHERE songs.id = ? AND songs.is_deleted = false
+                ",
+        vec![id.into()],
+    )
+    .await?
+    {
+        Some(song_query) => song_query,
+        None => return Ok(None),
+    };
+
+    let album_id = AlbumId::new(song_query.try_get::<u32>("", "album_id")?)?;
+    // TODO: Domainに移動
+    let audio_format = song_query
+        .try_get::<String>("", "format")?
+        .parse::<AudioFormat>()
+        .map_err(|e| InfraError::MalformedColumn {
+            column: "audio_formats.format".to_string(),
+            cause: e.to_string(),
+        })?;
+    let path = PathBuf::from(song_query.try_get::<String>("", "source_path")?);
+    // 読み取れた場合はまとめて保存しているので、再生時間があれば他もある
+    let properties = match song_query.try_get::<Option<u32>>("", "duration_ms")? {
+        Some(duration_ms) => Some(AudioProperties {
+            duration_ms,
+            sample_rate: song_query.try_get("", "sample_rate")?,
+            channels: song_query.try_get("", "channels")?,
+            bits_per_sample: song_query.try_get("", "bits_per_sample")?,
+            bitrate_kbps: song_query.try_get("", "bitrate_kbps")?,
+        }),
+        None => None,
+    };
+    let audio_raw_data = AudioRawData::reconstruct(
+        audio_format,
+        path,
+        song_query.try_get("", "original_hash")?,
+        properties,
+        loudness_columns(&song_query)?,
+    );
+
+    let artists = find_credits(txn, "song_artists", "song_id", id).await?;
+
+    let song = Song::try_reconstruct(
+        song_id,
+        song_query.try_get("", "name")?,
+        album_id,
+        song_query.try_get("", "track_number")?,
+        song_query.try_get("", "disk_number")?,
+        audio_raw_data,
+        artists,
+        song_query.try_get("", "is_withdrawn")?,
+    )?;
+
+    Ok(Some(song))
+}
+
+/// 削除されていない楽曲のIDをすべて取得する
+async fn find_all_songs_id(txn: &DatabaseTransaction) -> Result<Vec<SongId>, Error> {
+    let songs_query = query_all(
+        txn,
+        r"SELECT id FROM songs
+                WHERE is_deleted = false
+                ORDER BY id
+                ",
+    )
+    .await?;
+    let songs_id = songs_query
+        .iter()
+        .map(|song_query| Ok(SongId::new(song_query.try_get::<u32>("", "id")?)?))
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(songs_id)
+}
+
+/// 書き込みトランザクションを開く前に保存した音声データ
+#[derive(Debug, Clone)]
+struct PreparedAudio {
+    /// FLACに変換した音声データ。変換した場合は元の音声データの代わりに保存する
+    converted: Option<AudioRawData>,
+    save_path: PathBuf,
+    /// タグを書き込んだことで別のパスに置き換わった、保存済みの音声データのパス
+    replaced: Option<PathBuf>,
+    properties: Option<AudioProperties>,
+}
+
+impl PreparedAudio {
+    fn source<'a>(&'a self, song: &'a Song) -> &'a AudioRawData {
+        self.converted.as_ref().unwrap_or(&song.source)
     }
+}
 
-    async fn get_album(&self, album_id: AlbumId) -> Result<Option<Album>> {
-        Ok(None)
+/// 楽曲の音声データをファイルシステムに保存する。
+/// 変換やタグの書き込みには時間がかかるので、書き込みトランザクションを開く前に行う。
+/// 設定されていれば、ダウンロードしたWAVはFLACに変換してから保存する。
+/// タグに対応したフォーマットの場合は楽曲とアルバムの情報を書き込む。
+/// 再生時間や音質が分かっていなければ、保存した音声データから読み取る。
+/// ダウンロードした一時ファイルは、楽曲を登録できるまで残しておく。
+async fn prepare_song<R: DatabaseSongRepository>(
+    repository: &R,
+    song: &Song,
+) -> Result<PreparedAudio, Error> {
+    let store = repository.provide_blob_store();
+    let options = repository.save_options();
+    let converted = if options.convert_wav_to_flac {
+        transcode::convert_wav(store, &song.source).await?
+    } else {
+        None
+    };
+    let mut prepared = PreparedAudio {
+        converted,
+        save_path: PathBuf::new(),
+        replaced: None,
+        properties: None,
+    };
+    match store_audio(repository, options, song, prepared.source(song)).await {
+        Ok((save_path, properties)) => {
+            let source = prepared.source(song);
+            prepared.replaced = (source.raw.is_none()
+                && source.staged_path.is_none()
+                && save_path != source.save_path)
+                .then(|| source.save_path.clone());
+            prepared.save_path = save_path;
+            prepared.properties = properties;
+            Ok(prepared)
+        }
+        Err(e) => {
+            if let Some(staged_path) = prepared.converted.and_then(|c| c.staged_path) {
+                remove_if_exists(&staged_path).await?;
+            }
+            Err(e)
+        }
     }
+}
 
-    async fn save_album(&self, album: Album) -> Result<()> {
-        Ok(())
+async fn store_audio<R: DatabaseSongRepository>(
+    repository: &R,
+    options: SaveOptions,
+    song: &Song,
+    source: &AudioRawData,
+) -> Result<(PathBuf, Option<AudioProperties>), Error> {
+    let store = repository.provide_blob_store();
+    let tagging = match tagger(source.format) {
+        Some(tagger) => {
+            let album_id = song.belong_album_id;
+            read_only_transaction(repository, |txn| {
+                Box::pin(async move { find_album(txn, album_id).await })
+            })
+            .await?
+            .map(|album| (tagger, album))
+        }
+        None => None,
+    };
+    let save_path = match (&tagging, &source.raw, &source.staged_path) {
+        (Some((tagger, album)), _, _) => {
+            let cover_image = load_cover_image(store, &album.cover_image).await?;
+            let tags = TrackTags::new(song, album, cover_image);
+            tag::put_tagged(store, *tagger, source, &tags, options.tag_policy).await?
+        }
+        (None, Some(raw), _) => {
+            store.put(&source.save_path, raw).await?;
+            source.save_path.clone()
+        }
+        (None, None, Some(staged_path)) => {
+            store.copy_in(staged_path, &source.save_path).await?;
+            source.save_path.clone()
+        }
+        // 保存済みの音声データを参照しているだけなので何もしない
+        (None, None, None) => source.save_path.clone(),
+    };
+    let properties = match source.properties {
+        Some(properties) => Some(properties),
+        None => probe::probe_stored(store, &save_path, source.format).await?,
+    };
+    Ok((save_path, properties))
+}
+
+/// 楽曲の登録を終えた後に、音声データの一時ファイルを片付ける。
+/// 登録できた場合はダウンロードや変換に使った一時ファイルと、置き換えた古い音声データを削除する。
+/// 登録できなかった場合は、どの楽曲からも参照されていない保存した音声データと変換したFLACを削除し、
+/// ダウンロードした一時ファイルは呼び出し元に任せる。
+async fn finish_song<R: DatabaseSongRepository>(
+    repository: &R,
+    song: &Song,
+    prepared: &PreparedAudio,
+    saved: bool,
+) -> Result<(), Error> {
+    if let Some(staged_path) = prepared
+        .converted
+        .as_ref()
+        .and_then(|c| c.staged_path.as_ref())
+    {
+        remove_if_exists(staged_path).await?;
     }
+    if saved {
+        if let Some(staged_path) = &song.source.staged_path {
+            remove_if_exists(staged_path).await?;
+        }
+        if let Some(replaced) = &prepared.replaced {
+            remove_unreferenced_audio(repository, replaced).await?;
+        }
+    } else {
+        remove_unreferenced_audio(repository, &prepared.save_path).await?;
+    }
+    Ok(())
+}
 
-    async fn delete_album(&self, album_id: AlbumId) -> Result<()> {
-        Ok(())
+/// 楽曲を登録する。既に存在する場合は更新し、論理削除されていれば復元する。
+/// 音声データは[`prepare_song`]で保存しておいたものを参照する。
+async fn upsert_song(
+    txn: &DatabaseTransaction,
+    song: &Song,
+    prepared: &PreparedAudio,
+) -> Result<(), Error> {
+    let source = prepared.source(song);
+    let save_path = &prepared.save_path;
+    let properties = prepared.properties;
+    // 変換しても音声は変わらないので、変換前に解析したラウドネスをそのまま使う
+    let loudness = song.source.loudness.measured();
+    let audio_format_id =
+        upsert_by_name(txn, "audio_formats", "format", source.format.to_string()).await?;
+    let artist_ids = upsert_artists(txn, &song.artists).await?;
+    let artist_id = primary_artist_id(txn, &artist_ids).await?;
+    let id: u32 = song.id.into();
+    let album_id: u32 = song.belong_album_id.into();
+
+    execute_and_values(
+        txn,
+        r"INSERT INTO songs (
+                    id, created_at, updated_at, name, track_number, disk_number,
+                    source_path, album_id, audio_format_id, artist_id, is_withdrawn, original_hash,
+                    duration_ms, sample_rate, channels, bits_per_sample, bitrate_kbps,
+                    loudness_lufs, true_peak, loudness_analyzed, is_deleted
+                )
+                VALUES (
+                    ?, strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), strftime('%Y-%m-%dT%H:%M:%fZ', 'now'),
+                    ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, false
+                )
+                ON CONFLICT (id) DO UPDATE SET
+                    updated_at = excluded.updated_at,
+                    name = excluded.name,
+                    track_number = excluded.track_number,
+                    disk_number = excluded.disk_number,
+                    source_path = excluded.source_path,
+                    album_id = excluded.album_id,
+                    audio_format_id = excluded.audio_format_id,
+                    artist_id = excluded.artist_id,
+                    is_withdrawn = excluded.is_withdrawn,
+                    original_hash = excluded.original_hash,
+                    duration_ms = excluded.duration_ms,
+                    sample_rate = excluded.sample_rate,
+                    channels = excluded.channels,
+                    bits_per_sample = excluded.bits_per_sample,
+                    bitrate_kbps = excluded.bitrate_kbps,
+                    loudness_lufs = excluded.loudness_lufs,
+                    true_peak = excluded.true_peak,
+                    loudness_analyzed = excluded.loudness_analyzed,
+                    is_deleted = false
+                ",
+        vec![
+            id.into(),
+            song.name.clone().into(),
+            song.track_number.into(),
+            song.disk_number.into(),
+            save_path.to_string_lossy().into_owned().into(),
+            album_id.into(),
+            audio_format_id.into(),
+            artist_id.into(),
+            song.withdrawn.into(),
+            source.original_hash.clone().into(),
+            properties.map(|p| p.duration_ms).into(),
+            properties.map(|p| p.sample_rate).into(),
+            properties.map(|p| p.channels).into(),
+            properties.and_then(|p| p.bits_per_sample).into(),
+            properties.map(|p| p.bitrate_kbps).into(),
+            loudness.map(|l| l.integrated_lufs).into(),
+            loudness.map(|l| l.true_peak).into(),
+            song.source.loudness.is_analyzed().into(),
+        ],
+    )
+    .await?;
+    replace_credits(txn, "song_artists", "song_id", id, &artist_ids).await?;
+    Ok(())
+}
+
+async fn remove_if_exists(path: &Path) -> Result<(), Error> {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(InfraError::from(e).into()),
+        _ => Ok(()),
     }
+}
 
-    async fn get_all_album(&self) -> Result<Vec<Album>> {
-        Ok(vec![])
+/// 片付けに失敗しても楽曲の保存の結果は変わらないので、元の結果を返せるように警告として表示するだけにする
+fn warn_cleanup_failure(err: &Error) {
+    eprintln!("Warning: Failed to clean up audio data: {err}");
+}
+
+/// どの楽曲からも参照されていない音声データを削除する。
+/// トランザクションを確定した後に呼び、置き換えた古い音声データを片付けるのに使う。
+/// 同じ内容の音声データを保存中の楽曲があれば、その楽曲の登録が終わるまで待ってから確認する。
+async fn remove_unreferenced_audio<R: DatabaseSongRepository>(
+    repository: &R,
+    save_path: &Path,
+) -> Result<(), Error> {
+    let _guard = repository.provide_blob_store().lock_for_gc().await;
+    let path = save_path.to_string_lossy().into_owned();
+    let referenced = read_only_transaction(repository, |txn| {
+        Box::pin(async move {
+            let found = query_one_and_values(
+                txn,
+                r"SELECT COUNT(*) AS count FROM songs WHERE source_path = ?",
+                vec![path.into()],
+            )
+            .await?;
+            let count = match found {
+                Some(found) => found.try_get::<i64>("", "count")?,
+                None => 0,
+            };
+            Ok::<bool, Error>(count > 0)
+        })
+    })
+    .await?;
+    if !referenced {
+        repository.provide_blob_store().remove(save_path).await?;
     }
+    Ok(())
+}
 
-    async fn save_all_album(&self, albums: Vec<Album>) -> Result<()> {
-        Ok(())
+/// 削除されていないアルバムを1件取得する。
+/// カバー画像は読み込まず、必要になったときに[`load_cover_image`]で読み込む。
+async fn find_album(txn: &DatabaseTransaction, album_id: AlbumId) -> Result<Option<Album>, Error> {
+    let id: u32 = album_id.into();
+    let album_query = match query_one_and_values(
+        txn,
+        r"SELECT albums.name AS name, albums.total_tracks AS total_tracks,
+                    albums.total_disks AS total_disks, albums.intro AS intro,
+                    albums.song_list AS song_list, albums.cover_image_path AS cover_image_path,
+                    albums.is_withdrawn AS is_withdrawn, albums.loudness_lufs AS loudness_lufs,
+                    albums.true_peak AS true_peak, albums.loudness_analyzed AS loudness_analyzed,
+                    games.name AS game
+                FROM albums
+                    INNER JOIN games ON albums.game_id = games.id
+                WHERE albums.id = ? AND albums.is_deleted = false
+                ",
+        vec![id.into()],
+    )
+    .await?
+    {
+        Some(album_query) => album_query,
+        None => return Ok(None),
+    };
+
+    let cover_image = CoverImage::reconstruct(PathBuf::from(
+        album_query.try_get::<String>("", "cover_image_path")?,
+    ));
+    let belong = OriginGame::try_new(album_query.try_get::<String>("", "game")?)?;
+    let discs =
+        serde_json::from_str::<StoredSongList>(&album_query.try_get::<String>("", "song_list")?)
+            .map_err(|e| InfraError::MalformedColumn {
+                column: "albums.song_list".to_string(),
+                cause: e.to_string(),
+            })?
+            .into_discs();
+    let artists = find_credits(txn, "album_artists", "album_id", id).await?;
+
+    let album = Album::try_reconstruct(
+        album_id,
+        album_query.try_get("", "name")?,
+        album_query.try_get("", "total_tracks")?,
+        album_query.try_get("", "total_disks")?,
+        album_query.try_get("", "intro")?,
+        belong,
+        cover_image,
+        artists,
+        discs,
+        album_query.try_get("", "is_withdrawn")?,
+        loudness_columns(&album_query)?,
+    )?;
+
+    Ok(Some(album))
+}
+
+/// `loudness_lufs`と`true_peak`、`loudness_analyzed`のカラムからラウドネスの解析の状態を読み取る
+fn loudness_columns(query: &QueryResult) -> Result<LoudnessAnalysis, InfraError> {
+    let integrated_lufs = query.try_get::<Option<f64>>("", "loudness_lufs")?;
+    let true_peak = query.try_get::<Option<f64>>("", "true_peak")?;
+    let analyzed = query.try_get::<bool>("", "loudness_analyzed")?;
+    let loudness = integrated_lufs
+        .zip(true_peak)
+        .map(|(integrated_lufs, true_peak)| Loudness {
+            integrated_lufs,
+            true_peak,
+        });
+    Ok(match (loudness, analyzed) {
+        (Some(loudness), _) => LoudnessAnalysis::Measured(loudness),
+        (None, true) => LoudnessAnalysis::Unmeasurable,
+        (None, false) => LoudnessAnalysis::NotAnalyzed,
+    })
+}
+
+/// `albums.song_list`に保存されている収録曲。
+/// ディスクごとに分けて保存しているが、以前は1枚組として平坦に保存していた。
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum StoredSongList {
+    Discs(Vec<Vec<SongId>>),
+    Flat(Vec<SongId>),
+}
+
+impl StoredSongList {
+    fn into_discs(self) -> Vec<Vec<SongId>> {
+        match self {
+            StoredSongList::Discs(discs) => discs,
+            StoredSongList::Flat(song_list) => vec![song_list],
+        }
+    }
+}
+
+/// カバー画像を読み込む。メモリ上にあればそれを使う。
+async fn load_cover_image(store: &BlobStore, cover_image: &CoverImage) -> Result<Bytes, Error> {
+    match &cover_image.raw {
+        Some(raw) => Ok(raw.clone()),
+        None => Ok(store.get(&cover_image.save_path).await?),
+    }
+}
+
+/// アルバムを登録する。既に存在する場合は更新し、論理削除されていれば復元する。
+/// カバー画像がメモリ上にあればファイルシステムに保存し、なければ保存済みのものを参照する。
+async fn upsert_album(
+    txn: &DatabaseTransaction,
+    store: &BlobStore,
+    album: &Album,
+) -> Result<(), Error> {
+    let cover_image_path = &album.cover_image.save_path;
+    if let Some(raw) = &album.cover_image.raw {
+        store.put(cover_image_path, raw).await?;
+    }
+    let game_id = upsert_by_name(txn, "games", "name", album.belong.to_string()).await?;
+    let artist_ids = upsert_artists(txn, &album.artists).await?;
+    let artist_id = primary_artist_id(txn, &artist_ids).await?;
+    let song_list = serde_json::to_string(&album.discs).map_err(|e| {
+        InfraError::MalformedColumn {
+            column: "albums.song_list".to_string(),
+            cause: e.to_string(),
+        }
+    })?;
+    let id: u32 = album.id.into();
+
+    execute_and_values(
+        txn,
+        r"INSERT INTO albums (
+                    id, created_at, updated_at, name, total_tracks, total_disks,
+                    cover_image_path, game_id, artist_id, intro, song_list, is_withdrawn,
+                    loudness_lufs, true_peak, loudness_analyzed, is_deleted
+                )
+                VALUES (
+                    ?, strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), strftime('%Y-%m-%dT%H:%M:%fZ', 'now'),
+                    ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, false
+                )
+                ON CONFLICT (id) DO UPDATE SET
+                    updated_at = excluded.updated_at,
+                    name = excluded.name,
+                    total_tracks = excluded.total_tracks,
+                    total_disks = excluded.total_disks,
+                    cover_image_path = excluded.cover_image_path,
+                    game_id = excluded.game_id,
+                    artist_id = excluded.artist_id,
+                    intro = excluded.intro,
+                    song_list = excluded.song_list,
+                    is_withdrawn = excluded.is_withdrawn,
+                    loudness_lufs = excluded.loudness_lufs,
+                    true_peak = excluded.true_peak,
+                    loudness_analyzed = excluded.loudness_analyzed,
+                    is_deleted = false
+                ",
+        vec![
+            id.into(),
+            album.name.clone().into(),
+            album.total_tracks.into(),
+            album.total_disks.into(),
+            cover_image_path.to_string_lossy().into_owned().into(),
+            game_id.into(),
+            artist_id.into(),
+            album.intro.clone().into(),
+            song_list.into(),
+            album.withdrawn.into(),
+            album.loudness.measured().map(|l| l.integrated_lufs).into(),
+            album.loudness.measured().map(|l| l.true_peak).into(),
+            album.loudness.is_analyzed().into(),
+        ],
+    )
+    .await?;
+    replace_credits(txn, "album_artists", "album_id", id, &artist_ids).await?;
+    Ok(())
+}
+
+/// アーティストをすべて登録し、クレジットの順にIDを返す
+async fn upsert_artists(
+    txn: &DatabaseTransaction,
+    artists: &[String],
+) -> Result<Vec<i64>, InfraError> {
+    let mut artist_ids = vec![];
+    for artist in artists.iter() {
+        artist_ids.push(upsert_by_name(txn, "artists", "name", artist.clone()).await?);
+    }
+    Ok(artist_ids)
+}
+
+/// `songs.artist_id`と`albums.artist_id`に保存する先頭のアーティストのIDを返す。
+/// クレジットは`song_artists`と`album_artists`から読むが、これらのカラムはNOT NULLなので値を入れておく。
+async fn primary_artist_id(
+    txn: &DatabaseTransaction,
+    artist_ids: &[i64],
+) -> Result<i64, InfraError> {
+    match artist_ids.first() {
+        Some(&artist_id) => Ok(artist_id),
+        // アーティスト不明の場合は空の名前で登録する
+        None => upsert_by_name(txn, "artists", "name", String::new()).await,
+    }
+}
+
+/// `table`に保存されている`owner_id`のクレジットを`artist_ids`で置き換える。
+/// `artist_ids`の順番を`position`として保存する。
+async fn replace_credits(
+    txn: &DatabaseTransaction,
+    table: &str,
+    owner_column: &str,
+    owner_id: u32,
+    artist_ids: &[i64],
+) -> Result<(), InfraError> {
+    execute_and_values(
+        txn,
+        format!("DELETE FROM {table} WHERE {owner_column} = ?"),
+        vec![owner_id.into()],
+    )
+    .await?;
+    for (position, &artist_id) in artist_ids.iter().enumerate() {
+        execute_and_values(
+            txn,
+            format!("INSERT INTO {table} ({owner_column}, artist_id, position) VALUES (?, ?, ?)"),
+            vec![owner_id.into(), artist_id.into(), (position as u32).into()],
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// `table`に保存されている`owner_id`のクレジットを、`position`の順にアーティスト名で返す
+async fn find_credits(
+    txn: &DatabaseTransaction,
+    table: &str,
+    owner_column: &str,
+    owner_id: u32,
+) -> Result<Vec<String>, InfraError> {
+    let rows = query_all_and_values(
+        txn,
+        format!(
+            "SELECT artists.name AS name FROM {table}
+                INNER JOIN artists ON {table}.artist_id = artists.id
+                WHERE {table}.{owner_column} = ? AND artists.is_deleted = false
+                ORDER BY {table}.position"
+        ),
+        vec![owner_id.into()],
+    )
+    .await?;
+    let artists = rows
+        .iter()
+        .map(|row| row.try_get::<String>("", "name"))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(artists)
+}
+
+/// `table`の`column`が`value`の行のIDを返す。存在しなければ新しく登録する。
+/// 論理削除されていた場合は復元する。
+pub(super) async fn upsert_by_name(
+    txn: &DatabaseTransaction,
+    table: &str,
+    column: &str,
+    value: String,
+) -> Result<i64, InfraError> {
+    let found = query_one_and_values(
+        txn,
+        format!("SELECT id, is_deleted FROM {table} WHERE {column} = ? ORDER BY id LIMIT 1"),
+        vec![value.clone().into()],
+    )
+    .await?;
+    if let Some(found) = found {
+        let id = found.try_get::<i64>("", "id")?;
+        if found.try_get::<bool>("", "is_deleted")? {
+            execute_and_values(
+                txn,
+                format!(
+                    "UPDATE {table}
+                        SET is_deleted = false,
+                            updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                        WHERE id = ?"
+                ),
+                vec![id.into()],
+            )
+            .await?;
+        }
+        return Ok(id);
+    }
+
+    let inserted = execute_and_values(
+        txn,
+        format!(
+            "INSERT INTO {table} (created_at, updated_at, {column}, is_deleted)
+                VALUES (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), ?, false)"
+        ),
+        vec![value.into()],
+    )
+    .await?;
+    Ok(inserted.last_insert_id() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::loudness::{Loudness, LoudnessAnalysis};
+    use crate::domain::metadata_history::{MetadataChange, MetadataTarget};
+    use crate::domain::repository::metadata_history_repository::UsesMetadataHistoryRepository;
+    use crate::domain::repository::song_repository::UsesSongRepository;
+    use crate::domain::song::{Album, AlbumId, AudioRawData, CoverImage, Song, SongId};
+    use crate::errors::infra::InfraError;
+    use crate::errors::Error;
+    use crate::infra::repository::test_database::TestDatabase;
+    use crate::infra::resource::blob_store::ProvideBlobStore;
+    use bytes::Bytes;
+    use sea_orm::ConnectionTrait;
+
+    async fn staged_song(db: &TestDatabase, album_id: u32, raw: &[u8]) -> Song {
+        let mut staged = db.provide_blob_store().stage().await.unwrap();
+        staged.write(raw).await.unwrap();
+        let staged = staged.finish().await.unwrap();
+        Song {
+            id: SongId::new(1).unwrap(),
+            belong_album_id: AlbumId::new(album_id).unwrap(),
+            source: AudioRawData::try_from_staged(&staged.head, &staged.hash, staged.path).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save_staged_song() {
+        let db = TestDatabase::connect("save-staged-song").await;
+        let store = db.provide_blob_store().clone();
+        db.save_album(Album {
+            id: AlbumId::new(1).unwrap(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // タグを書き込まないWAVは、ダウンロードした内容がそのまま保存される。
+        // 登録できればダウンロードした一時ファイルを削除する
+        let song = staged_song(&db, 1, b"RIFF\0\0\0\0WAVEsaved audio").await;
+        let staged_path = song.source.staged_path.clone().unwrap();
+        db.save_song(song.clone()).await.unwrap();
+        assert!(!staged_path.exists());
+        assert!(store.resolve(&song.source.save_path).exists());
+
+        // 存在しないアルバムの楽曲は登録できずにロールバックされる。
+        // 保存した音声データは削除するが、ダウンロードした一時ファイルは残す
+        let failed = staged_song(&db, 2, b"RIFF\0\0\0\0WAVErolled back audio").await;
+        let staged_path = failed.source.staged_path.clone().unwrap();
+        assert!(db.save_song(failed.clone()).await.is_err());
+        assert!(!store.resolve(&failed.source.save_path).exists());
+        assert!(staged_path.exists());
+        // 登録済みの楽曲の音声データは残っている
+        assert_eq!(
+            db.get_song(song.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .source
+                .save_path,
+            song.source.save_path
+        );
+        assert!(store.resolve(&song.source.save_path).exists());
+    }
+
+    #[tokio::test]
+    async fn test_failed_save_waits_for_saving_song() {
+        let db = TestDatabase::connect("failed-save-waits").await;
+        let store = db.provide_blob_store().clone();
+
+        // 同じ内容の音声データを保存中の楽曲がある間は、失敗した楽曲の音声データを削除しない
+        let guard = store.lock_for_save().await;
+        let failed = staged_song(&db, 2, b"RIFF\0\0\0\0WAVEshared audio").await;
+        let save = db.save_song(failed.clone());
+        tokio::pin!(save);
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(100), &mut save)
+                .await
+                .is_err()
+        );
+        assert!(store.resolve(&failed.source.save_path).exists());
+
+        // 保存中の楽曲が終われば、どこからも参照されていないので削除する
+        drop(guard);
+        assert!(save.await.is_err());
+        assert!(!store.resolve(&failed.source.save_path).exists());
+    }
+
+    #[tokio::test]
+    async fn test_get_album_without_cover_file() {
+        let db = TestDatabase::connect("album-without-cover").await;
+        let store = db.provide_blob_store().clone();
+        let cover_image = CoverImage::new(Bytes::from_static(b"cover image"));
+        db.save_album(Album {
+            id: AlbumId::new(1).unwrap(),
+            cover_image: cover_image.clone(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(store.resolve(&cover_image.save_path).exists());
+
+        // カバー画像が失われていても、アルバムの読み込みや更新はできる
+        store.remove(&cover_image.save_path).await.unwrap();
+        let album = db
+            .get_album(AlbumId::new(1).unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            album.cover_image,
+            CoverImage::reconstruct(cover_image.save_path.clone())
+        );
+        assert_eq!(db.get_all_album().await.unwrap().len(), 1);
+        db.save_album(album).await.unwrap();
+        assert_eq!(
+            db.get_album(AlbumId::new(1).unwrap())
+                .await
+                .unwrap()
+                .unwrap()
+                .cover_image
+                .save_path,
+            cover_image.save_path
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_with_changes() {
+        let db = TestDatabase::connect("save-with-changes").await;
+        let change = |target, id| MetadataChange {
+            target,
+            id,
+            field: "name".into(),
+            old_value: r#""old""#.into(),
+            new_value: r#""new""#.into(),
+        };
+        db.save_album_with_changes(
+            Album {
+                id: AlbumId::new(1).unwrap(),
+                name: "new".into(),
+                ..Default::default()
+            },
+            vec![change(MetadataTarget::Album, 1)],
+        )
+        .await
+        .unwrap();
+        let song = staged_song(&db, 1, b"RIFF\0\0\0\0WAVEsaved audio").await;
+        db.save_song_with_changes(song, vec![change(MetadataTarget::Song, 1)])
+            .await
+            .unwrap();
+
+        // 存在しないアルバムの楽曲は登録できず、変更の履歴も記録しない
+        let failed = staged_song(&db, 2, b"RIFF\0\0\0\0WAVErolled back audio").await;
+        assert!(db
+            .save_song_with_changes(failed, vec![change(MetadataTarget::Song, 2)])
+            .await
+            .is_err());
+
+        let history = db.get_recent_metadata_history(10).await.unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|history| (history.change.target, history.change.id))
+                .collect::<Vec<_>>(),
+            vec![(MetadataTarget::Song, 1), (MetadataTarget::Album, 1)]
+        );
+        assert_eq!(
+            db.get_album(AlbumId::new(1).unwrap())
+                .await
+                .unwrap()
+                .unwrap()
+                .name,
+            "new"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore_song() {
+        let db = TestDatabase::connect("restore-song").await;
+        db.save_album(Album {
+            id: AlbumId::new(1).unwrap(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let song = staged_song(&db, 1, b"RIFF\0\0\0\0WAVErestored audio").await;
+        db.save_song(song.clone()).await.unwrap();
+        let song = db.get_song(song.id).await.unwrap().unwrap();
+
+        // 論理削除していても、音声データが残っていれば復元する
+        db.set_song_withdrawn(song.id, true).await.unwrap();
+        db.delete_song(song.id).await.unwrap();
+        assert_eq!(db.get_withdrawn_songs_id().await.unwrap(), vec![song.id]);
+        db.restore_song(song.id).await.unwrap();
+        assert!(!db.get_song(song.id).await.unwrap().unwrap().withdrawn);
+        assert!(db.get_withdrawn_songs_id().await.unwrap().is_empty());
+
+        // 音声データを削除していれば、取り下げだけ取り消して次の同期に任せる
+        db.set_song_withdrawn(song.id, true).await.unwrap();
+        db.purge_audio(&song).await.unwrap();
+        db.delete_song(song.id).await.unwrap();
+        db.restore_song(song.id).await.unwrap();
+        assert_eq!(db.get_song(song.id).await.unwrap(), None);
+        assert!(db.get_withdrawn_songs_id().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_loudness_analysis_round_trip() {
+        let db = TestDatabase::connect("loudness-analysis").await;
+        for (id, loudness) in [
+            (1, LoudnessAnalysis::NotAnalyzed),
+            (2, LoudnessAnalysis::Unmeasurable),
+            (
+                3,
+                LoudnessAnalysis::Measured(Loudness {
+                    integrated_lufs: -12.5,
+                    true_peak: 0.9,
+                }),
+            ),
+        ] {
+            let id = AlbumId::new(id).unwrap();
+            db.save_album(Album {
+                id,
+                loudness,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+            assert_eq!(db.get_album(id).await.unwrap().unwrap().loudness, loudness);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_song_with_unknown_format() {
+        let db = TestDatabase::connect("unknown-format").await;
+        db.save_album(Album {
+            id: AlbumId::new(1).unwrap(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let song = staged_song(&db, 1, b"RIFF\0\0\0\0WAVEunknown format").await;
+        db.save_song(song.clone()).await.unwrap();
+        db.db_connection
+            .execute_unprepared("UPDATE audio_formats SET format = 'ogg'")
+            .await
+            .unwrap();
+
+        // パニックせずにエラーを返す
+        let err = db.get_song(song.id).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Infra {
+                source: InfraError::MalformedColumn { column, .. }
+            } if column == "audio_formats.format"
+        ));
     }
 }
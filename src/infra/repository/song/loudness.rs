@@ -0,0 +1,145 @@
+//! 保存した音声データを復号してのラウドネスの測定。
+
+use crate::domain::loudness::{LoudnessMeter, Measurement};
+use crate::domain::song::AudioFormat;
+use crate::errors::infra::InfraError;
+use crate::errors::Error;
+use crate::infra::resource::blob_store::BlobStore;
+use std::path::{Path, PathBuf};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// 保存した音声データを最後まで復号し、ラウドネスを測定する。
+/// 復号は時間がかかるので、ブロッキング処理用のスレッドで行う。
+pub async fn measure_stored(
+    store: &BlobStore,
+    save_path: &Path,
+    format: AudioFormat,
+) -> Result<Measurement, Error> {
+    let path = store.resolve(save_path);
+    let measurement = tokio::task::spawn_blocking(move || measure_file(&path, format))
+        .await
+        .map_err(|e| InfraError::DecodeAudio {
+            path: save_path.to_path_buf(),
+            cause: e.to_string(),
+        })??;
+    Ok(measurement)
+}
+
+fn measure_file(path: &Path, format: AudioFormat) -> Result<Measurement, InfraError> {
+    let decode_error = |cause: SymphoniaError| InfraError::DecodeAudio {
+        path: path.to_path_buf(),
+        cause: cause.to_string(),
+    };
+    let file = std::fs::File::open(path)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(&format.to_string());
+    let mut reader = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(decode_error)?
+        .format;
+    let track = reader.default_track().ok_or_else(|| no_audio(path))?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(decode_error)?;
+
+    // サンプリング周波数とチャンネル数はMP3のようにヘッダから分からないこともあるので、最初に復号したパケットから決める
+    let mut meter = None;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break
+            }
+            Err(e) => return Err(decode_error(e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // 壊れたフレームは飛ばして続ける
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(decode_error(e)),
+        };
+        let spec = *decoded.spec();
+        let channels = u8::try_from(spec.channels.count()).map_err(|_| no_audio(path))?;
+        let meter = meter.get_or_insert_with(|| LoudnessMeter::new(spec.rate, channels));
+        let required = decoded.capacity() * spec.channels.count();
+        let buffer = match &mut buffer {
+            Some(buffer) if buffer.capacity() >= required => buffer,
+            _ => buffer.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+        };
+        buffer.copy_interleaved_ref(decoded);
+        meter.add_samples(buffer.samples());
+    }
+    meter
+        .map(LoudnessMeter::finish)
+        .ok_or_else(|| no_audio(path))
+}
+
+fn no_audio(path: &Path) -> InfraError {
+    InfraError::DecodeAudio {
+        path: PathBuf::from(path),
+        cause: "no audio track".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::measure_stored;
+    use crate::domain::song::AudioFormat;
+    use crate::domain::transcode::{flac, PcmFormat};
+    use crate::infra::resource::blob_store::BlobStore;
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn test_measure_stored() {
+        let root = std::env::temp_dir().join(format!("msr-loudness-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let store = BlobStore::new(root);
+        let format = PcmFormat {
+            sample_rate: 48000,
+            channels: 1,
+            bits_per_sample: 16,
+        };
+        // 1kHz、-20dBFSのサイン波を2秒
+        let samples = (0..96000)
+            .map(|n| {
+                let t = f64::from(n) / 48000.0;
+                (0.1 * 32767.0 * (2.0 * std::f64::consts::PI * 1000.0 * t).sin()) as i32
+            })
+            .collect::<Vec<_>>();
+        let path = PathBuf::from("audio/song.flac");
+        store
+            .put(&path, &flac::encode(format, &samples).unwrap())
+            .await
+            .unwrap();
+        let loudness = measure_stored(&store, &path, AudioFormat::Flac)
+            .await
+            .unwrap()
+            .loudness()
+            .unwrap();
+        assert!((loudness.integrated_lufs + 23.0).abs() < 0.1);
+
+        let broken = PathBuf::from("audio/broken.flac");
+        store.put(&broken, b"not flac").await.unwrap();
+        assert!(measure_stored(&store, &broken, AudioFormat::Flac)
+            .await
+            .is_err());
+        let _ = std::fs::remove_dir_all(store.root());
+    }
+}
@@ -0,0 +1,85 @@
+//! 保存した音声データの再生時間や音質の読み取り。
+
+use crate::domain::probe::{probe, AudioProperties, Probe};
+use crate::domain::song::AudioFormat;
+use crate::errors::domain::DomainError;
+use crate::errors::infra::InfraError;
+use crate::errors::Error;
+use crate::infra::resource::blob_store::BlobStore;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+/// 保存した音声データの先頭を必要なだけ読み込み、再生時間や音質を読み取る。
+/// 壊れているか対応していない音声データの場合は`None`を返し、保存は続けられるようにする。
+pub async fn probe_stored(
+    store: &BlobStore,
+    save_path: &Path,
+    format: AudioFormat,
+) -> Result<Option<AudioProperties>, Error> {
+    let mut file = tokio::fs::File::open(store.resolve(save_path))
+        .await
+        .map_err(InfraError::from)?;
+    let file_len = file.metadata().await.map_err(InfraError::from)?.len();
+    let mut head = vec![];
+    loop {
+        match probe(format, &head, file_len) {
+            Ok(Probe::Complete(properties)) => return Ok(Some(properties)),
+            Ok(Probe::NeedMore(len)) => {
+                let start = head.len();
+                head.resize(len, 0);
+                file.read_exact(&mut head[start..])
+                    .await
+                    .map_err(InfraError::from)?;
+            }
+            Err(
+                DomainError::MalformedAudio { .. }
+                | DomainError::UnsupportedAudio { .. }
+                | DomainError::MalformedTag { .. },
+            ) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::probe_stored;
+    use crate::domain::song::AudioFormat;
+    use crate::domain::transcode::{flac, PcmFormat};
+    use crate::infra::resource::blob_store::BlobStore;
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn test_probe_stored() {
+        let root = std::env::temp_dir().join(format!("msr-probe-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let store = BlobStore::new(root);
+        let format = PcmFormat {
+            sample_rate: 48000,
+            channels: 1,
+            bits_per_sample: 16,
+        };
+        let path = PathBuf::from("audio/song.flac");
+        store
+            .put(&path, &flac::encode(format, &vec![0; 24000]).unwrap())
+            .await
+            .unwrap();
+        let properties = probe_stored(&store, &path, AudioFormat::Flac)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(properties.duration_ms, 500);
+        assert_eq!(properties.channels, 1);
+
+        // FLACとして読めないものは保存を止めない
+        let broken = PathBuf::from("audio/broken.flac");
+        store.put(&broken, b"not flac").await.unwrap();
+        assert_eq!(
+            probe_stored(&store, &broken, AudioFormat::Flac)
+                .await
+                .unwrap(),
+            None
+        );
+        let _ = std::fs::remove_dir_all(store.root());
+    }
+}
@@ -0,0 +1,149 @@
+//! 保存する音声ファイルへのタグの書き込み。
+//! タグを書き込むと内容のハッシュ値が変わるので、書き込んだ後の内容に対応するパスに保存し直す。
+
+use crate::domain::song::{
+    content_addressed_path, hash_addressed_path, AudioFormat, AudioRawData, AUDIO_DIR,
+};
+use crate::domain::tag::{MetadataLen, TagPolicy, Tagger, TrackTags};
+use crate::errors::domain::DomainError;
+use crate::errors::infra::InfraError;
+use crate::errors::Error;
+use crate::infra::resource::blob_store::BlobStore;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncReadExt;
+
+/// タグを書き込んだ音声データを保存し、保存先のパスを返す。
+/// 書き込む内容が既存のタグと同じであれば、保存済みのファイルをそのまま使う。
+/// 一時ファイルにダウンロードしたものは残しておき、楽曲を登録できてから呼び出し元で削除する。
+pub async fn put_tagged(
+    store: &BlobStore,
+    tagger: &dyn Tagger,
+    source: &AudioRawData,
+    tags: &TrackTags,
+    policy: TagPolicy,
+) -> Result<PathBuf, Error> {
+    let extension = source.format.to_string();
+    if let Some(raw) = &source.raw {
+        let tagged = tagger.write_tags(raw, tags, policy)?;
+        let path = content_addressed_path(AUDIO_DIR, &tagged, Some(&extension));
+        store.put(&path, &tagged).await?;
+        return Ok(path);
+    }
+
+    let source_path = match &source.staged_path {
+        Some(staged_path) => staged_path.clone(),
+        None => store.resolve(&source.save_path),
+    };
+    let metadata = read_metadata(tagger, &source_path, source.format).await?;
+    let head = tagger.write_tags(&metadata, tags, policy)?;
+    if source.staged_path.is_none() && head == metadata {
+        return Ok(source.save_path.clone());
+    }
+
+    let staged = store
+        .splice(&source_path, metadata.len() as u64, &head)
+        .await?;
+    let path = hash_addressed_path(AUDIO_DIR, &staged.hash, Some(&extension));
+    store.commit(&staged.path, &path).await?;
+    Ok(path)
+}
+
+/// ファイルの先頭から、[`Tagger::metadata_len`]で判定したタグ部分だけを読み込む
+async fn read_metadata(
+    tagger: &dyn Tagger,
+    path: &Path,
+    format: AudioFormat,
+) -> Result<Vec<u8>, Error> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(InfraError::from)?;
+    let mut metadata = vec![];
+    loop {
+        let len = match tagger.metadata_len(&metadata)? {
+            MetadataLen::Complete(len) => len,
+            MetadataLen::NeedMore(len) => len,
+        };
+        if metadata.len() >= len {
+            metadata.truncate(len);
+            return Ok(metadata);
+        }
+        let start = metadata.len();
+        metadata.resize(len, 0);
+        match file.read_exact(&mut metadata[start..]).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Err(DomainError::MalformedTag {
+                    format,
+                    reason: "truncated metadata".to_string(),
+                }
+                .into())
+            }
+            Err(e) => return Err(InfraError::from(e).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::put_tagged;
+    use crate::domain::song::{content_hash, AlbumId, AudioRawData, SongId};
+    use crate::domain::tag::flac::{self, FlacTagger};
+    use crate::domain::tag::{TagPolicy, TrackTags};
+    use crate::infra::resource::blob_store::BlobStore;
+
+    fn flac_file() -> Vec<u8> {
+        let mut raw = b"fLaC\x80\0\0\x22".to_vec();
+        raw.extend_from_slice(&[0u8; 34]);
+        raw.extend_from_slice(&b"audio frames".repeat(1024));
+        raw
+    }
+
+    fn tags() -> TrackTags {
+        TrackTags {
+            title: "song".into(),
+            song_id: SongId::new(48794).unwrap(),
+            album_id: AlbumId::new(249).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put_tagged_staged_flac() {
+        let root = std::env::temp_dir().join(format!("msr-tag-staged-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let store = BlobStore::new(root);
+        let raw = flac_file();
+        let mut staged = store.stage().await.unwrap();
+        staged.write(&raw).await.unwrap();
+        let staged = staged.finish().await.unwrap();
+        let source =
+            AudioRawData::try_from_staged(&staged.head, &staged.hash, staged.path.clone()).unwrap();
+
+        let path = put_tagged(&store, &FlacTagger, &source, &tags(), TagPolicy::Merge)
+            .await
+            .unwrap();
+        assert!(staged.path.exists());
+        let tagged = store.get(&path).await.unwrap();
+        assert_eq!(flac::read_tags(&tagged).unwrap().get("TITLE"), vec!["song"]);
+        assert_eq!(
+            tagged,
+            flac::write_tags(&raw, &tags(), TagPolicy::Merge).unwrap()
+        );
+
+        // 保存済みのファイルに同じタグを書き込んでも保存し直さない
+        let stored = AudioRawData {
+            raw: None,
+            save_path: path.clone(),
+            staged_path: None,
+            ..source
+        };
+        assert_eq!(
+            put_tagged(&store, &FlacTagger, &stored, &tags(), TagPolicy::Merge)
+                .await
+                .unwrap(),
+            path
+        );
+        assert_ne!(content_hash(&tagged), staged.hash);
+        let _ = std::fs::remove_dir_all(store.root());
+    }
+}
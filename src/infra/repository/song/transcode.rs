@@ -0,0 +1,243 @@
+//! 保存する音声データのフォーマットの変換。
+
+use crate::domain::song::{AudioFormat, AudioRawData, ContentHasher};
+use crate::domain::transcode::flac::{self, BLOCK_SIZE};
+use crate::domain::transcode::wav::{self, Layout};
+use crate::errors::domain::DomainError;
+use crate::errors::infra::InfraError;
+use crate::errors::Error;
+use crate::infra::resource::blob_store::BlobStore;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// WAVの音声データをFLACに変換する。
+/// 変換には時間がかかるので、ブロッキング処理用のスレッドで行う。
+/// 一時ファイルにダウンロードしたものは少しずつ読み込んで変換し、変換したものも一時ファイルに書き込む。
+/// FLACに変換できない形式のWAVや、保存済みの音声データを参照しているだけの場合は`None`を返し、元のまま保存させる。
+pub async fn convert_wav(
+    store: &BlobStore,
+    source: &AudioRawData,
+) -> Result<Option<AudioRawData>, Error> {
+    if source.format != AudioFormat::Wav {
+        return Ok(None);
+    }
+    let converted = match (&source.raw, &source.staged_path) {
+        (Some(raw), _) => {
+            let raw = raw.clone();
+            tokio::task::spawn_blocking(move || {
+                AudioRawData::try_from_wav(&raw).map_err(Error::from)
+            })
+            .await
+        }
+        (None, Some(staged_path)) => {
+            let staged_path = staged_path.clone();
+            let output = store.temp_path();
+            tokio::task::spawn_blocking(move || convert_file(&staged_path, &output)).await
+        }
+        (None, None) => return Ok(None),
+    }
+    .map_err(|e| InfraError::Io {
+        cause: e.to_string(),
+    })?;
+    match converted {
+        Ok(converted) => Ok(Some(converted)),
+        Err(Error::Domain {
+            source: DomainError::UnsupportedAudio { .. },
+        }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// `input`のWAVを[`flac::BLOCK_SIZE`]ごとに読み込んでFLACに変換し、`output`の一時ファイルに書き込む。
+/// フレームごとに復号して元のサンプルと一致することを確かめる。失敗した場合は`output`を削除する。
+fn convert_file(input: &Path, output: &Path) -> Result<AudioRawData, Error> {
+    let result = write_flac(input, output);
+    if result.is_err() {
+        let _ = std::fs::remove_file(output);
+    }
+    result
+}
+
+fn write_flac(input: &Path, output: &Path) -> Result<AudioRawData, Error> {
+    let file_len = std::fs::metadata(input).map_err(InfraError::from)?.len();
+    let mut reader = Hashing::new(BufReader::new(File::open(input).map_err(InfraError::from)?));
+
+    // dataチャンクの手前まで読み込む
+    let mut head = vec![];
+    let chunk = loop {
+        match wav::parse_layout(&head)? {
+            Layout::Complete(chunk) => break chunk,
+            Layout::NeedMore(len) if len as u64 <= file_len => {
+                let start = head.len();
+                head.resize(len, 0);
+                reader
+                    .read_exact(&mut head[start..])
+                    .map_err(InfraError::from)?;
+            }
+            Layout::NeedMore(_) => {
+                return Err(DomainError::MalformedAudio {
+                    format: AudioFormat::Wav,
+                    reason: "missing data chunk".to_string(),
+                }
+                .into())
+            }
+        }
+    };
+    // 書き込み中に止まったWAVはサイズが実際より大きいことがあるので、ファイルの終わりまでにする
+    let frame_len = chunk.frame_len();
+    let data_len = chunk.len.min(file_len.saturating_sub(chunk.offset as u64));
+    let total_samples = data_len / frame_len as u64;
+    let mut encoder = flac::Encoder::new(chunk.format, total_samples)?;
+
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent).map_err(InfraError::from)?;
+    }
+    let mut writer = Hashing::new(BufWriter::new(
+        File::create(output).map_err(InfraError::from)?,
+    ));
+    let header = encoder.header();
+    writer.write_all(&header).map_err(InfraError::from)?;
+    let mut block = vec![0u8; BLOCK_SIZE * frame_len];
+    let mut remaining = total_samples * frame_len as u64;
+    while remaining > 0 {
+        let len = block.len().min(remaining as usize);
+        reader
+            .read_exact(&mut block[..len])
+            .map_err(InfraError::from)?;
+        let frame = encoder.encode_block(&wav::samples(chunk.format, &block[..len]))?;
+        writer.write_all(&frame).map_err(InfraError::from)?;
+        remaining -= len as u64;
+    }
+    // dataチャンクの後ろにあるチャンクも、変換前のハッシュ値に含める
+    std::io::copy(&mut reader, &mut std::io::sink()).map_err(InfraError::from)?;
+    let file = writer
+        .inner
+        .into_inner()
+        .map_err(|e| InfraError::from(e.into_error()))?;
+    file.sync_all().map_err(InfraError::from)?;
+
+    let converted =
+        AudioRawData::try_from_staged(&header, &writer.hasher.finish(), output.to_path_buf())?;
+    Ok(AudioRawData {
+        original_hash: Some(reader.hasher.finish()),
+        ..converted
+    })
+}
+
+/// 読み書きした内容の[`crate::domain::song::content_hash`]を計算する
+struct Hashing<T> {
+    inner: T,
+    hasher: ContentHasher,
+}
+
+impl<T> Hashing<T> {
+    fn new(inner: T) -> Self {
+        Self {
+            inner,
+            hasher: ContentHasher::new(),
+        }
+    }
+}
+
+impl<T: Read> Read for Hashing<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Hashing<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::convert_wav;
+    use crate::domain::song::{
+        content_addressed_path, content_hash, AudioFormat, AudioRawData, AUDIO_DIR,
+    };
+    use crate::domain::transcode::flac;
+    use crate::infra::resource::blob_store::BlobStore;
+    use bytes::Bytes;
+
+    /// 16bitモノラルのWAV
+    fn wav(format_tag: u16, samples: &[i16]) -> Vec<u8> {
+        let data = samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&format_tag.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&44100u32.to_le_bytes());
+        wav.extend_from_slice(&(44100u32 * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&data);
+        wav
+    }
+
+    #[tokio::test]
+    async fn test_convert_staged_wav() {
+        let root = std::env::temp_dir().join(format!("msr-transcode-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let store = BlobStore::new(root);
+        // 複数のフレームに分かれる長さ
+        let samples = (0..10000)
+            .map(|i| (i % 300) as i16 * 100)
+            .collect::<Vec<_>>();
+        let raw = wav(1, &samples);
+        let mut staged = store.stage().await.unwrap();
+        staged.write(&raw).await.unwrap();
+        let staged = staged.finish().await.unwrap();
+        let source =
+            AudioRawData::try_from_staged(&staged.head, &staged.hash, staged.path.clone()).unwrap();
+        assert_eq!(source.format, AudioFormat::Wav);
+
+        let converted = convert_wav(&store, &source).await.unwrap().unwrap();
+        assert_eq!(converted.format, AudioFormat::Flac);
+        assert_eq!(converted.raw, None);
+        assert_eq!(converted.original_hash, Some(content_hash(&raw)));
+        let flac_raw = tokio::fs::read(converted.staged_path.as_ref().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            converted.save_path,
+            content_addressed_path(AUDIO_DIR, &flac_raw, Some("flac"))
+        );
+        let (_, decoded) = flac::decode(&flac_raw).unwrap();
+        assert_eq!(
+            decoded,
+            samples.iter().map(|&s| i32::from(s)).collect::<Vec<_>>()
+        );
+        // メモリ上で変換したものと同じ音声になる
+        let in_memory = convert_wav(&store, &AudioRawData::try_new(Bytes::from(raw)).unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            flac::decode(in_memory.raw.as_ref().unwrap()).unwrap().1,
+            decoded
+        );
+
+        // IEEE浮動小数点のWAVは変換しない
+        let float = AudioRawData::try_new(Bytes::from(wav(3, &samples))).unwrap();
+        assert!(convert_wav(&store, &float).await.unwrap().is_none());
+        let _ = std::fs::remove_dir_all(store.root());
+    }
+}
@@ -0,0 +1,82 @@
+use crate::domain::repository::sync_run_repository::UsesSyncRunRepository;
+use crate::domain::sync_report::{SyncReport, SyncRun};
+use crate::errors::infra::InfraError;
+use crate::errors::Error;
+use crate::errors::Result;
+use crate::infra::resource::database::{
+    execute_and_values, query_all_and_values, read_only_transaction, read_write_transaction,
+    ProvideDatabase,
+};
+use async_trait::async_trait;
+
+pub trait DatabaseSyncRunRepository: ProvideDatabase + Send + Sync + 'static {}
+
+#[async_trait]
+impl<R: DatabaseSyncRunRepository> UsesSyncRunRepository for R {
+    async fn save_sync_run(&self, report: SyncReport) -> Result<()> {
+        let raw_report =
+            serde_json::to_string(&report).map_err(|e| InfraError::MalformedColumn {
+                column: "sync_runs.report".to_string(),
+                cause: e.to_string(),
+            })?;
+        read_write_transaction(self, |txn| {
+            Box::pin(async move {
+                execute_and_values(
+                    txn,
+                    r"INSERT INTO sync_runs (
+                            created_at, succeeded, skipped, failed, bytes_downloaded, elapsed_ms, report
+                        ) VALUES (
+                            strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), ?, ?, ?, ?, ?, ?
+                        )",
+                    [
+                        (report.succeeded.len() as u64).into(),
+                        (report.skipped.len() as u64).into(),
+                        (report.failed.len() as u64).into(),
+                        report.bytes_downloaded.into(),
+                        report.elapsed_ms().into(),
+                        raw_report.into(),
+                    ],
+                )
+                .await?;
+                Ok::<(), Error>(())
+            })
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn get_recent_sync_runs(&self, limit: u32) -> Result<Vec<SyncRun>> {
+        read_only_transaction(self, |txn| {
+            Box::pin(async move {
+                let rows = query_all_and_values(
+                    txn,
+                    r"SELECT id, created_at, report
+                            FROM sync_runs
+                            ORDER BY id DESC
+                            LIMIT ?",
+                    [limit.into()],
+                )
+                .await?;
+
+                rows.into_iter()
+                    .map(|row| {
+                        let report = serde_json::from_str::<SyncReport>(
+                            &row.try_get::<String>("", "report")?,
+                        )
+                        .map_err(|e| InfraError::MalformedColumn {
+                            column: "sync_runs.report".to_string(),
+                            cause: e.to_string(),
+                        })?;
+                        Ok(SyncRun {
+                            id: row.try_get::<u32>("", "id")?,
+                            created_at: row.try_get::<String>("", "created_at")?,
+                            report,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, Error>>()
+            })
+        })
+        .await
+        .map_err(Into::into)
+    }
+}
@@ -0,0 +1,60 @@
+//! テスト用のデータベース。マイグレーションを済ませたインメモリのSQLiteに接続する。
+
+use crate::infra::repository::artist::DatabaseArtistRepository;
+use crate::infra::repository::metadata_history::DatabaseMetadataHistoryRepository;
+use crate::infra::repository::song::DatabaseSongRepository;
+use crate::infra::resource::blob_store::{BlobStore, ProvideBlobStore};
+use crate::infra::resource::database::ProvideDatabase;
+use migration::{Migrator, MigratorTrait};
+use sea_orm::{Database, DatabaseConnection};
+
+pub struct TestDatabase {
+    pub db_connection: DatabaseConnection,
+    pub blob_store: BlobStore,
+}
+
+impl TestDatabase {
+    /// すべてのマイグレーションを実行したデータベースに接続する。
+    /// 音声データやカバー画像は`name`ごとの一時ディレクトリに保存する。
+    pub async fn connect(name: &str) -> Self {
+        let db_connection = Self::connect_without_migration().await;
+        Migrator::up(&db_connection, None).await.unwrap();
+        Self::with_connection(db_connection, name)
+    }
+
+    /// マイグレーションを実行していないデータベースに接続する
+    pub async fn connect_without_migration() -> DatabaseConnection {
+        Database::connect("sqlite::memory:").await.unwrap()
+    }
+
+    pub fn with_connection(db_connection: DatabaseConnection, name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("msr-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        Self {
+            db_connection,
+            blob_store: BlobStore::new(root),
+        }
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(self.blob_store.root());
+    }
+}
+
+impl ProvideDatabase for TestDatabase {
+    fn provide_database(&self) -> &DatabaseConnection {
+        &self.db_connection
+    }
+}
+
+impl ProvideBlobStore for TestDatabase {
+    fn provide_blob_store(&self) -> &BlobStore {
+        &self.blob_store
+    }
+}
+
+impl DatabaseSongRepository for TestDatabase {}
+impl DatabaseArtistRepository for TestDatabase {}
+impl DatabaseMetadataHistoryRepository for TestDatabase {}
@@ -0,0 +1,463 @@
+use crate::domain::song::{content_hash, ContentHasher};
+use crate::errors::infra::InfraError;
+use bytes::Bytes;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// 一時ファイルを置くディレクトリ。ライブラリのルートからの相対パス。
+const TMP_DIR: &str = ".tmp";
+/// 中断したダウンロードを置くディレクトリ。ライブラリのルートからの相対パス。
+const PARTIAL_DIR: &str = ".partial";
+
+/// [`StagedBlob`]が保持するデータの先頭部分の長さ。フォーマットの判定に使う。
+pub const HEAD_LEN: usize = 16;
+
+/// 一時ファイルの名前が衝突しないようにするためのカウンタ
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+pub trait ProvideBlobStore {
+    fn provide_blob_store(&self) -> &BlobStore;
+}
+
+/// 音声データやカバー画像を内容のハッシュ値をキーにしてファイルシステムに保存する。
+/// 保存先のパスは[`crate::domain::song::content_addressed_path`]で生成したものを期待している。
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    root: PathBuf,
+    /// データの保存と、参照されていないデータの削除を排他する。クローン間で共有する。
+    gc_lock: Arc<RwLock<()>>,
+}
+
+impl ProvideBlobStore for BlobStore {
+    fn provide_blob_store(&self) -> &BlobStore {
+        self
+    }
+}
+
+impl BlobStore {
+    /// * root: ライブラリのルートディレクトリ
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            gc_lock: Arc::new(RwLock::new(())),
+        }
+    }
+
+    /// データを保存してから、それを参照するレコードを登録し終えるまでの間に取るロック。
+    /// 同じ内容のデータは同じパスを共有するので、その間に参照されていないとみなして削除されないようにする。
+    pub async fn lock_for_save(&self) -> RwLockReadGuard<'_, ()> {
+        self.gc_lock.read().await
+    }
+
+    /// 参照されていないことを確かめてからデータを削除するまでの間に取るロック。
+    /// 保存中のデータがあれば、その参照が登録されるまで待つ。
+    pub async fn lock_for_gc(&self) -> RwLockWriteGuard<'_, ()> {
+        self.gc_lock.write().await
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// 相対パスをライブラリのルートからの絶対パスに変換する
+    pub fn resolve(&self, relative_path: &Path) -> PathBuf {
+        self.root.join(relative_path)
+    }
+
+    /// データを保存する。既に同じパスにファイルがあれば何もしない。
+    /// 一時ファイルに書き込んでからリネームするので、途中で失敗しても壊れたファイルは残らない。
+    pub async fn put(&self, relative_path: &Path, raw: &[u8]) -> Result<(), InfraError> {
+        let path = self.resolve(relative_path);
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let tmp_path = self.temp_path();
+        if let Some(parent) = tmp_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        if let Err(e) = tokio::fs::write(&tmp_path, raw).await {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(e.into());
+        }
+        if let Err(e) = tokio::fs::rename(&tmp_path, &path).await {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// 大きなデータを少しずつ書き込むための一時ファイルを作成する。
+    /// 書き込みながらハッシュ値を計算するので、書き終えた後に[`BlobStore::commit`]で保存先に移動する。
+    pub async fn stage(&self) -> Result<StagedBlob, InfraError> {
+        self.stage_at(self.temp_path(), None).await
+    }
+
+    /// 中断したダウンロードを再開するための一時ファイルのパス
+    /// * key: ダウンロードを識別するキー。ファイル名に使える文字列を期待している。
+    pub fn partial_path(&self, key: &str) -> PathBuf {
+        self.root.join(PARTIAL_DIR).join(format!("{key}.part"))
+    }
+
+    /// 指定したパスに一時ファイルを作成する。
+    /// * resume_from: 既存のファイルの続きから書き込む場合はその位置。
+    ///   それより後ろは切り捨て、前の部分はハッシュ値の計算に含める。
+    pub async fn stage_at(
+        &self,
+        path: PathBuf,
+        resume_from: Option<u64>,
+    ) -> Result<StagedBlob, InfraError> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let file = match resume_from {
+            Some(offset) => {
+                let file = tokio::fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&path)
+                    .await?;
+                file.set_len(offset).await?;
+                file
+            }
+            None => tokio::fs::File::create(&path).await?,
+        };
+        let mut staged = StagedBlob {
+            file,
+            path,
+            hasher: ContentHasher::new(),
+            size: 0,
+            head: Vec::with_capacity(HEAD_LEN),
+        };
+        if resume_from.is_none() {
+            return Ok(staged);
+        }
+
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = staged.file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            staged.track(&buf[..n]);
+        }
+        staged.file.seek(std::io::SeekFrom::End(0)).await?;
+        Ok(staged)
+    }
+
+    /// 一時ファイルを残したまま、その内容を保存先にコピーする。
+    /// 既に同じパスにファイルがあれば何もしない。
+    pub async fn copy_in(&self, source: &Path, relative_path: &Path) -> Result<(), InfraError> {
+        let path = self.resolve(relative_path);
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let tmp_path = self.temp_path();
+        if let Some(parent) = tmp_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        if let Err(e) = tokio::fs::copy(source, &tmp_path).await {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(e.into());
+        }
+        if let Err(e) = tokio::fs::rename(&tmp_path, &path).await {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// [`BlobStore::stage`]で作成した一時ファイルを保存先に移動する。
+    /// 既に同じパスにファイルがあれば一時ファイルを削除する。
+    pub async fn commit(&self, staged_path: &Path, relative_path: &Path) -> Result<(), InfraError> {
+        let path = self.resolve(relative_path);
+        if tokio::fs::try_exists(&path).await? {
+            tokio::fs::remove_file(staged_path).await?;
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::rename(staged_path, &path).await?;
+        Ok(())
+    }
+
+    /// `source`の先頭`skip`バイトを`head`に置き換えた内容を一時ファイルに書き込む。
+    /// 残りの部分は少しずつ読み込んで書き写すので、大きなファイルでも全体をメモリに載せない。
+    /// * source: 元のファイルの絶対パス
+    pub async fn splice(
+        &self,
+        source: &Path,
+        skip: u64,
+        head: &[u8],
+    ) -> Result<Staged, InfraError> {
+        let mut file = tokio::fs::File::open(source).await?;
+        file.seek(std::io::SeekFrom::Start(skip)).await?;
+        let mut staged = self.stage().await?;
+        staged.write(head).await?;
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = match file.read(&mut buf).await {
+                Ok(n) => n,
+                Err(e) => {
+                    staged.abort().await;
+                    return Err(e.into());
+                }
+            };
+            if n == 0 {
+                break;
+            }
+            staged.write(&buf[..n]).await?;
+        }
+        staged.finish().await
+    }
+
+    /// データを読み込む。
+    /// ファイルが存在しない場合や、内容のハッシュ値がファイル名と一致しない場合は
+    /// [`InfraError::FailedToLoadAudioRawData`]を返す。
+    pub async fn get(&self, relative_path: &Path) -> Result<Bytes, InfraError> {
+        let path = self.resolve(relative_path);
+        let raw = tokio::fs::read(&path)
+            .await
+            .map_err(|_| InfraError::FailedToLoadAudioRawData { path: path.clone() })?;
+        let expected = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| InfraError::FailedToLoadAudioRawData { path: path.clone() })?;
+        if content_hash(&raw) != expected {
+            return Err(InfraError::FailedToLoadAudioRawData { path });
+        }
+        Ok(Bytes::from(raw))
+    }
+
+    /// データを削除する。ファイルが存在しない場合は何もしない。
+    pub async fn remove(&self, relative_path: &Path) -> Result<(), InfraError> {
+        match tokio::fs::remove_file(self.resolve(relative_path)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 一時ファイルのパス。呼ぶたびに異なるパスを返す。
+    pub fn temp_path(&self) -> PathBuf {
+        let count = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        self.root
+            .join(TMP_DIR)
+            .join(format!("{}-{count}.tmp", std::process::id()))
+    }
+}
+
+/// 書き込み中の一時ファイル
+#[derive(Debug)]
+pub struct StagedBlob {
+    file: tokio::fs::File,
+    path: PathBuf,
+    hasher: ContentHasher,
+    size: u64,
+    head: Vec<u8>,
+}
+
+/// 書き終えた一時ファイル
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Staged {
+    /// 一時ファイルの絶対パス
+    pub path: PathBuf,
+    /// 内容の[`content_hash`]
+    pub hash: String,
+    pub size: u64,
+    /// 内容の先頭[`HEAD_LEN`]バイト
+    pub head: Bytes,
+}
+
+impl StagedBlob {
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<(), InfraError> {
+        if let Err(e) = self.file.write_all(chunk).await {
+            let _ = tokio::fs::remove_file(&self.path).await;
+            return Err(e.into());
+        }
+        self.track(chunk);
+        Ok(())
+    }
+
+    /// これまでに書き込んだバイト数
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// 一時ファイルのパス
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 書き込んだ内容をハッシュ値などに反映する
+    fn track(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+        self.size += chunk.len() as u64;
+        if self.head.len() < HEAD_LEN {
+            let rest = HEAD_LEN - self.head.len();
+            self.head.extend_from_slice(&chunk[..rest.min(chunk.len())]);
+        }
+    }
+
+    /// 書き込みを終えてディスクに反映する
+    pub async fn finish(mut self) -> Result<Staged, InfraError> {
+        if let Err(e) = async {
+            self.file.flush().await?;
+            self.file.sync_all().await
+        }
+        .await
+        {
+            let _ = tokio::fs::remove_file(&self.path).await;
+            return Err(e.into());
+        }
+        Ok(Staged {
+            path: self.path,
+            hash: self.hasher.finish(),
+            size: self.size,
+            head: Bytes::from(self.head),
+        })
+    }
+
+    /// 書き込みを中断する。一時ファイルは後で再開できるように残しておく。
+    /// 書き込めたバイト数を返す。
+    pub async fn suspend(mut self) -> Result<u64, InfraError> {
+        self.file.flush().await?;
+        self.file.sync_all().await?;
+        Ok(self.size)
+    }
+
+    /// 書き込みを中断して一時ファイルを削除する
+    pub async fn abort(self) {
+        drop(self.file);
+        let _ = tokio::fs::remove_file(&self.path).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlobStore, HEAD_LEN};
+    use crate::domain::song::{content_addressed_path, content_hash, AUDIO_DIR};
+    use crate::errors::infra::InfraError;
+
+    fn temp_store(name: &str) -> BlobStore {
+        let root =
+            std::env::temp_dir().join(format!("msr-blob-store-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        BlobStore::new(root)
+    }
+
+    #[tokio::test]
+    async fn test_put_and_get() {
+        let store = temp_store("put-and-get");
+        let raw = b"fLaC dummy".to_vec();
+        let path = content_addressed_path(AUDIO_DIR, &raw, Some("flac"));
+
+        store.put(&path, &raw).await.unwrap();
+        // 同じ内容は重複して保存しても問題ない
+        store.put(&path, &raw).await.unwrap();
+
+        assert_eq!(store.get(&path).await.unwrap(), raw);
+        let _ = std::fs::remove_dir_all(store.root());
+    }
+
+    #[tokio::test]
+    async fn test_get_corrupted() {
+        let store = temp_store("corrupted");
+        let raw = b"fLaC dummy".to_vec();
+        let path = content_addressed_path(AUDIO_DIR, &raw, Some("flac"));
+        store.put(&path, &raw).await.unwrap();
+        std::fs::write(store.resolve(&path), b"broken").unwrap();
+
+        assert_eq!(
+            store.get(&path).await,
+            Err(InfraError::FailedToLoadAudioRawData {
+                path: store.resolve(&path)
+            })
+        );
+        let _ = std::fs::remove_dir_all(store.root());
+    }
+
+    #[tokio::test]
+    async fn test_stage_and_commit() {
+        let store = temp_store("stage-and-commit");
+        let raw = b"fLaC dummy audio data".to_vec();
+
+        let mut staged = store.stage().await.unwrap();
+        for chunk in raw.chunks(4) {
+            staged.write(chunk).await.unwrap();
+        }
+        let staged = staged.finish().await.unwrap();
+        assert_eq!(staged.hash, content_hash(&raw));
+        assert_eq!(staged.size, raw.len() as u64);
+        assert_eq!(&staged.head[..], &raw[..HEAD_LEN]);
+
+        let path = content_addressed_path(AUDIO_DIR, &raw, Some("flac"));
+        store.commit(&staged.path, &path).await.unwrap();
+        assert!(!staged.path.exists());
+        assert_eq!(store.get(&path).await.unwrap(), raw);
+        let _ = std::fs::remove_dir_all(store.root());
+    }
+
+    #[tokio::test]
+    async fn test_stage_at_resume() {
+        let store = temp_store("resume");
+        let raw = b"fLaC dummy audio data".to_vec();
+        let path = store.partial_path("resume");
+
+        let mut staged = store.stage_at(path.clone(), None).await.unwrap();
+        staged.write(&raw[..10]).await.unwrap();
+        // 書きかけの余分なデータは再開時に切り捨てられる
+        staged.write(b"garbage").await.unwrap();
+        assert_eq!(staged.suspend().await.unwrap(), 17);
+
+        let mut staged = store.stage_at(path, Some(10)).await.unwrap();
+        assert_eq!(staged.size(), 10);
+        staged.write(&raw[10..]).await.unwrap();
+        let staged = staged.finish().await.unwrap();
+        assert_eq!(staged.hash, content_hash(&raw));
+        assert_eq!(&staged.head[..], &raw[..HEAD_LEN]);
+        assert_eq!(std::fs::read(&staged.path).unwrap(), raw);
+        let _ = std::fs::remove_dir_all(store.root());
+    }
+
+    #[tokio::test]
+    async fn test_splice() {
+        let store = temp_store("splice");
+        let raw = b"fLaC old head, audio data".to_vec();
+        let path = content_addressed_path(AUDIO_DIR, &raw, Some("flac"));
+        store.put(&path, &raw).await.unwrap();
+
+        let staged = store
+            .splice(&store.resolve(&path), 13, b"fLaC new longer head")
+            .await
+            .unwrap();
+        let expected = b"fLaC new longer head, audio data".to_vec();
+        assert_eq!(staged.hash, content_hash(&expected));
+        assert_eq!(std::fs::read(&staged.path).unwrap(), expected);
+        let _ = std::fs::remove_dir_all(store.root());
+    }
+
+    #[tokio::test]
+    async fn test_get_missing() {
+        let store = temp_store("missing");
+        let path = content_addressed_path(AUDIO_DIR, b"missing", Some("flac"));
+
+        assert_eq!(
+            store.get(&path).await,
+            Err(InfraError::FailedToLoadAudioRawData {
+                path: store.resolve(&path)
+            })
+        );
+    }
+}
@@ -1,25 +1,46 @@
+pub mod config;
+
 use std::sync::Arc;
+use crate::domain::disc::{DiscOverrides, ProvideDiscOverrides};
+use crate::domain::repository::artist_repository::ProvideArtistRepository;
+use crate::domain::repository::metadata_history_repository::ProvideMetadataHistoryRepository;
 use crate::domain::repository::msr_repository::ProvideMsrRepository;
 use crate::domain::repository::song_repository::{ProvideSongRepository, UsesSongRepository};
+use crate::domain::repository::sync_run_repository::ProvideSyncRunRepository;
+use crate::domain::tag::TagPolicy;
+use crate::infra::repository::artist::DatabaseArtistRepository;
+use crate::infra::repository::metadata_history::DatabaseMetadataHistoryRepository;
 use crate::infra::repository::msr::WebApiMsrRepository;
 use crate::infra::repository::song::DatabaseSongRepository;
+use crate::infra::repository::sync_run::DatabaseSyncRunRepository;
 use crate::usecase::add_new_song::AddNewSongUseCase;
+use crate::usecase::analyze_loudness::AnalyzeLoudnessUseCase;
+use crate::usecase::reconcile::{ReconcileUseCase, WithdrawnPolicy};
+use crate::usecase::update_metadata::UpdateMetadataUseCase;
 use anyhow::Result;
-use reqwest::header::HeaderMap;
-use sea_orm::DatabaseConnection;
-use url::Url;
+use config::KernelConfig;
+use migration::{Migrator, MigratorTrait};
+use sea_orm::{ConnectOptions, Database, DatabaseConnection};
+use crate::infra::resource::blob_store::{BlobStore, ProvideBlobStore};
 use crate::infra::resource::database::ProvideDatabase;
 
 #[derive(Debug, Clone)]
 pub struct Kernel {
     msr_repository: WebApiMsrRepository,
     song_repository: SongRepositoryImpl,
+    concurrency: usize,
+    withdrawn_policy: WithdrawnPolicy,
+    max_withdrawn_percent: u8,
+    disc_overrides: Arc<DiscOverrides>,
 }
 
 // TODO: もっとふさわしい名前があるはず
 #[derive(Debug, Clone)]
 pub struct SongRepositoryImpl {
     pub db_connection: Arc<DatabaseConnection>,
+    pub blob_store: BlobStore,
+    pub tag_policy: TagPolicy,
+    pub convert_wav_to_flac: bool,
 }
 
 impl ProvideDatabase for SongRepositoryImpl {
@@ -28,18 +49,52 @@ impl ProvideDatabase for SongRepositoryImpl {
     }
 }
 
+impl ProvideBlobStore for SongRepositoryImpl {
+    fn provide_blob_store(&self) -> &BlobStore {
+        &self.blob_store
+    }
+}
+
 impl Kernel {
-    pub fn try_new(header_map: HeaderMap) -> Result<Self> {
+    /// 設定からKernelを作成する。
+    /// データベースに接続し、設定されていればマイグレーションを実行する。
+    pub async fn try_new(config: KernelConfig) -> Result<Self> {
         let client = reqwest::Client::builder()
-            .default_headers(header_map)
+            .user_agent(config.user_agent.clone())
+            .default_headers(config.header_map()?)
+            .connect_timeout(config.connect_timeout())
+            .read_timeout(config.read_timeout())
             .build()?;
-        let db_connection = Arc::new(todo!());
+
+        let mut connect_options = ConnectOptions::new(config.database_url.clone());
+        connect_options
+            .connect_timeout(config.connect_timeout())
+            .sqlx_logging(false);
+        let db_connection = Database::connect(connect_options).await?;
+        if config.run_migrations {
+            Migrator::up(&db_connection, None).await?;
+        }
+
+        let disc_overrides = config.disc_overrides()?;
+        let blob_store = BlobStore::new(config.library_root);
         Ok(Self {
             msr_repository: WebApiMsrRepository::new(
-                client.clone(),
-                Url::parse("https://monster-siren.hypergryph.com/api/")?,
-            ),
-            song_repository: SongRepositoryImpl{db_connection},
+                client,
+                config.msr_base_url,
+                blob_store.clone(),
+            )
+            .with_retry_policy(config.retry)
+            .with_rate_limit(&config.rate_limit),
+            song_repository: SongRepositoryImpl {
+                db_connection: Arc::new(db_connection),
+                blob_store,
+                tag_policy: config.tag_policy,
+                convert_wav_to_flac: config.convert_wav_to_flac,
+            },
+            concurrency: config.concurrency,
+            withdrawn_policy: config.withdrawn_policy,
+            max_withdrawn_percent: config.max_withdrawn_percent,
+            disc_overrides: Arc::new(disc_overrides),
         })
     }
 }
@@ -57,13 +112,83 @@ impl ProvideDatabase for Kernel {
     }
 }
 
+impl ProvideBlobStore for Kernel {
+    fn provide_blob_store(&self) -> &BlobStore {
+        self.song_repository.provide_blob_store()
+    }
+}
+
+impl ProvideDiscOverrides for Kernel {
+    fn provide_disc_overrides(&self) -> &DiscOverrides {
+        &self.disc_overrides
+    }
+}
+
 impl ProvideSongRepository for Kernel {
     type SongRepository = SongRepositoryImpl;
     fn provide_song_repository(&self) -> &Self::SongRepository {
         &self.song_repository
     }
 }
-impl DatabaseSongRepository for SongRepositoryImpl {}
-impl DatabaseSongRepository for Kernel {}
+impl DatabaseSongRepository for SongRepositoryImpl {
+    fn tag_policy(&self) -> TagPolicy {
+        self.tag_policy
+    }
+
+    fn convert_wav_to_flac(&self) -> bool {
+        self.convert_wav_to_flac
+    }
+}
+impl DatabaseSongRepository for Kernel {
+    fn tag_policy(&self) -> TagPolicy {
+        self.song_repository.tag_policy
+    }
 
-impl AddNewSongUseCase for Kernel {}
+    fn convert_wav_to_flac(&self) -> bool {
+        self.song_repository.convert_wav_to_flac
+    }
+}
+
+impl ProvideSyncRunRepository for Kernel {
+    type SyncRunRepository = SongRepositoryImpl;
+    fn provide_sync_run_repository(&self) -> &Self::SyncRunRepository {
+        &self.song_repository
+    }
+}
+impl DatabaseSyncRunRepository for SongRepositoryImpl {}
+
+impl ProvideMetadataHistoryRepository for Kernel {
+    type MetadataHistoryRepository = SongRepositoryImpl;
+    fn provide_metadata_history_repository(&self) -> &Self::MetadataHistoryRepository {
+        &self.song_repository
+    }
+}
+impl DatabaseMetadataHistoryRepository for SongRepositoryImpl {}
+
+impl ProvideArtistRepository for Kernel {
+    type ArtistRepository = SongRepositoryImpl;
+    fn provide_artist_repository(&self) -> &Self::ArtistRepository {
+        &self.song_repository
+    }
+}
+impl DatabaseArtistRepository for SongRepositoryImpl {}
+
+impl AddNewSongUseCase for Kernel {
+    fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+}
+
+impl UpdateMetadataUseCase for Kernel {}
+
+impl AnalyzeLoudnessUseCase for Kernel {}
+
+impl ReconcileUseCase for Kernel {
+    fn withdrawn_policy(&self) -> WithdrawnPolicy {
+        self.withdrawn_policy
+    }
+
+    fn max_withdrawn_percent(&self) -> u8 {
+        self.max_withdrawn_percent
+    }
+}
@@ -0,0 +1,307 @@
+use crate::domain::disc::DiscOverrides;
+use crate::domain::song::AlbumId;
+use crate::domain::tag::TagPolicy;
+use crate::infra::repository::msr::rate_limit::RateLimitConfig;
+use crate::infra::repository::msr::retry::RetryPolicy;
+use crate::usecase::reconcile::{WithdrawnPolicy, DEFAULT_MAX_WITHDRAWN_PERCENT};
+use anyhow::{bail, Context, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use url::Url;
+
+/// 設定ファイルが指定されなかった場合に読み込むファイル
+pub const DEFAULT_CONFIG_PATH: &str = "msr.toml";
+/// 環境変数で設定を上書きする際のプレフィックス
+pub const ENV_PREFIX: &str = "MSR_";
+/// 環境変数でHTTPヘッダを追加する際のプレフィックス。
+/// `MSR_HEADER_ACCEPT_LANGUAGE=ja`は`accept-language: ja`になる。
+pub const ENV_HEADER_PREFIX: &str = "MSR_HEADER_";
+
+/// [`crate::kernel::Kernel`]の設定
+/// TOMLファイルから読み込み、`MSR_*`の環境変数で上書きできる。
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct KernelConfig {
+    /// sea-ormに渡すデータベースのURL
+    pub database_url: String,
+    /// 音声データやカバー画像を保存するディレクトリ
+    pub library_root: PathBuf,
+    /// MSRのAPIのベースURL。末尾の`/`は省略できない。
+    pub msr_base_url: Url,
+    /// MSRへのリクエストに付与するUser-Agent
+    pub user_agent: String,
+    /// すべてのリクエストに付与するHTTPヘッダ
+    pub headers: BTreeMap<String, String>,
+    /// 接続のタイムアウト（秒）
+    pub connect_timeout_secs: u64,
+    /// レスポンスの読み込みのタイムアウト（秒）
+    pub read_timeout_secs: u64,
+    /// MSRへのリクエストが一時的に失敗した場合のリトライの方針
+    pub retry: RetryPolicy,
+    /// MSRへのリクエストの流量制限
+    pub rate_limit: RateLimitConfig,
+    /// 楽曲を同時に取得する数
+    pub concurrency: usize,
+    /// MSRから取り下げられた楽曲やアルバムの扱い
+    pub withdrawn_policy: WithdrawnPolicy,
+    /// 1回の照合で取り下げてよい楽曲やアルバムの割合の上限 (%)。
+    /// これを超えて消えていればMSR側の障害とみなして照合を中断する。
+    pub max_withdrawn_percent: u8,
+    /// アルバムごとのディスクの分け方。キーはアルバムID、値は各ディスクの曲数。
+    /// 指定のないアルバムは曲名から判断する。
+    pub disc_overrides: BTreeMap<String, Vec<u8>>,
+    /// 保存する音声データに元から書き込まれているタグの扱い
+    pub tag_policy: TagPolicy,
+    /// ダウンロードしたWAVをFLACに可逆変換して保存するか
+    pub convert_wav_to_flac: bool,
+    /// 起動時にマイグレーションを実行するか
+    pub run_migrations: bool,
+}
+
+impl Default for KernelConfig {
+    fn default() -> Self {
+        Self {
+            database_url: "sqlite:db.sqlite?mode=rwc".to_string(),
+            library_root: PathBuf::from("library"),
+            msr_base_url: Url::parse("https://monster-siren.hypergryph.com/api/")
+                .expect("default base url must be valid"),
+            user_agent: concat!("msr/", env!("CARGO_PKG_VERSION")).to_string(),
+            headers: BTreeMap::new(),
+            connect_timeout_secs: 10,
+            read_timeout_secs: 60,
+            retry: RetryPolicy::default(),
+            rate_limit: RateLimitConfig::default(),
+            concurrency: 8,
+            withdrawn_policy: WithdrawnPolicy::default(),
+            max_withdrawn_percent: DEFAULT_MAX_WITHDRAWN_PERCENT,
+            disc_overrides: BTreeMap::new(),
+            tag_policy: TagPolicy::default(),
+            convert_wav_to_flac: false,
+            run_migrations: true,
+        }
+    }
+}
+
+impl KernelConfig {
+    /// 設定ファイルと環境変数から設定を読み込む。
+    /// * path: 設定ファイルのパス。`None`の場合は[`DEFAULT_CONFIG_PATH`]があれば読み込む。
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let config = match path {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+        let config = config.apply_env(std::env::vars())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 設定値が取りうる範囲に収まっているかを確かめる
+    pub fn validate(&self) -> Result<()> {
+        if self.concurrency == 0 {
+            bail!("concurrency must be at least 1");
+        }
+        if self.max_withdrawn_percent > 100 {
+            bail!(
+                "max_withdrawn_percent must be at most 100, got {}",
+                self.max_withdrawn_percent
+            );
+        }
+        Ok(())
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        Self::from_toml_str(&s)
+            .with_context(|| format!("Failed to parse config file: {}", path.display()))
+    }
+
+    pub fn from_toml_str(s: &str) -> Result<Self> {
+        Ok(toml::from_str(s)?)
+    }
+
+    /// `MSR_*`の環境変数で設定を上書きする
+    /// * vars: 環境変数の一覧。[`std::env::vars`]を期待している。
+    pub fn apply_env<I>(mut self, vars: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (key, value) in vars {
+            if let Some(name) = key.strip_prefix(ENV_HEADER_PREFIX) {
+                self.headers
+                    .insert(name.to_ascii_lowercase().replace('_', "-"), value);
+                continue;
+            }
+            let Some(name) = key.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            match name {
+                "DATABASE_URL" => self.database_url = value,
+                "LIBRARY_ROOT" => self.library_root = PathBuf::from(value),
+                "USER_AGENT" => self.user_agent = value,
+                "BASE_URL" => {
+                    self.msr_base_url = Url::parse(&value).with_context(|| key.clone())?
+                }
+                "CONNECT_TIMEOUT_SECS" => {
+                    self.connect_timeout_secs = value.parse().with_context(|| key.clone())?
+                }
+                "READ_TIMEOUT_SECS" => {
+                    self.read_timeout_secs = value.parse().with_context(|| key.clone())?
+                }
+                "MAX_RETRIES" => {
+                    self.retry.max_retries = value.parse().with_context(|| key.clone())?
+                }
+                "INITIAL_BACKOFF_MS" => {
+                    self.retry.initial_backoff_ms = value.parse().with_context(|| key.clone())?
+                }
+                "MAX_BACKOFF_MS" => {
+                    self.retry.max_backoff_ms = value.parse().with_context(|| key.clone())?
+                }
+                "API_INTERVAL_MS" => {
+                    self.rate_limit.api.interval_ms = value.parse().with_context(|| key.clone())?
+                }
+                "DOWNLOAD_INTERVAL_MS" => {
+                    self.rate_limit.download.interval_ms =
+                        value.parse().with_context(|| key.clone())?
+                }
+                "CONCURRENCY" => {
+                    self.concurrency = value.parse().with_context(|| key.clone())?
+                }
+                "WITHDRAWN_POLICY" => {
+                    self.withdrawn_policy = value.parse().with_context(|| key.clone())?
+                }
+                "MAX_WITHDRAWN_PERCENT" => {
+                    self.max_withdrawn_percent = value.parse().with_context(|| key.clone())?
+                }
+                "TAG_POLICY" => {
+                    self.tag_policy = value.parse().with_context(|| key.clone())?
+                }
+                "CONVERT_WAV_TO_FLAC" => {
+                    self.convert_wav_to_flac = value.parse().with_context(|| key.clone())?
+                }
+                "RUN_MIGRATIONS" => {
+                    self.run_migrations = value.parse().with_context(|| key.clone())?
+                }
+                _ => {}
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs)
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout_secs)
+    }
+
+    pub fn disc_overrides(&self) -> Result<DiscOverrides> {
+        self.disc_overrides
+            .iter()
+            .map(|(album_id, sizes)| -> Result<_> {
+                Ok((AlbumId::try_new(album_id.clone())?, sizes.clone()))
+            })
+            .collect()
+    }
+
+    pub fn header_map(&self) -> Result<HeaderMap> {
+        self.headers
+            .iter()
+            .map(|(name, value)| -> Result<_> {
+                Ok((
+                    HeaderName::from_bytes(name.as_bytes())?,
+                    HeaderValue::from_str(value)?,
+                ))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KernelConfig;
+    use crate::domain::song::AlbumId;
+    use crate::domain::tag::TagPolicy;
+    use crate::usecase::reconcile::WithdrawnPolicy;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_from_toml_str_and_apply_env() {
+        let config = KernelConfig::from_toml_str(
+            r#"
+            database_url = "sqlite::memory:"
+            concurrency = 2
+            withdrawn_policy = "soft-delete"
+            max_withdrawn_percent = 25
+
+            [retry]
+            max_retries = 5
+
+            [headers]
+            accept-language = "ja"
+
+            [disc_overrides]
+            "0249" = [12, 10]
+            "#,
+        )
+        .unwrap()
+        .apply_env([
+            ("MSR_LIBRARY_ROOT".to_string(), "/srv/msr".to_string()),
+            ("MSR_CONCURRENCY".to_string(), "4".to_string()),
+            ("MSR_TAG_POLICY".to_string(), "strip".to_string()),
+            ("MSR_CONVERT_WAV_TO_FLAC".to_string(), "true".to_string()),
+            ("MSR_HEADER_X_REQUESTED_WITH".to_string(), "msr".to_string()),
+            ("PATH".to_string(), "/usr/bin".to_string()),
+        ])
+        .unwrap();
+
+        assert_eq!(config.database_url, "sqlite::memory:");
+        assert_eq!(config.library_root, PathBuf::from("/srv/msr"));
+        assert_eq!(config.concurrency, 4);
+        assert_eq!(config.retry.max_retries, 5);
+        assert_eq!(config.withdrawn_policy, WithdrawnPolicy::SoftDelete);
+        assert_eq!(config.max_withdrawn_percent, 25);
+        assert_eq!(config.tag_policy, TagPolicy::Strip);
+        assert!(config.convert_wav_to_flac);
+        assert_eq!(config.retry.initial_backoff_ms, 500);
+        assert_eq!(config.headers["accept-language"], "ja");
+        assert_eq!(config.headers["x-requested-with"], "msr");
+        assert_eq!(config.header_map().unwrap().len(), 2);
+        assert_eq!(
+            config.disc_overrides().unwrap()[&AlbumId::new(249).unwrap()],
+            vec![12, 10]
+        );
+        assert_eq!(config.msr_base_url, KernelConfig::default().msr_base_url);
+    }
+
+    #[test]
+    fn test_apply_env_invalid_value() {
+        let result = KernelConfig::default()
+            .apply_env([("MSR_CONCURRENCY".to_string(), "many".to_string())]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(KernelConfig::default().validate().is_ok());
+
+        let config = KernelConfig::default()
+            .apply_env([("MSR_CONCURRENCY".to_string(), "0".to_string())])
+            .unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("concurrency"));
+
+        let config = KernelConfig::from_toml_str("max_withdrawn_percent = 101").unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("max_withdrawn_percent"));
+
+        let config = KernelConfig::from_toml_str("max_withdrawn_percent = 100").unwrap();
+        assert!(config.validate().is_ok());
+    }
+}
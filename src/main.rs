@@ -1,21 +1,15 @@
-use msr::kernel::Kernel;
-use msr::usecase::add_new_song::fetch_and_create_song;
+use clap::Parser;
+use msr::cli::{exit_code, run, Cli};
+use std::process::ExitCode;
 
 #[tokio::main]
-pub async fn main() -> anyhow::Result<()> {
-    let default_header = reqwest::header::HeaderMap::new();
-    let kernel = Kernel::try_new(default_header)?;
-    // let song = kernel
-    //     .provide_msr_repository()
-    //     .fetch_song("953967".to_string())
-    //     .await?;
-    // dbg!(song);
-    // let album = kernel
-    //     .provide_msr_repository()
-    //     .fetch_album("0249".to_string())
-    //     .await?;
-    // dbg!(album);
-    let song = fetch_and_create_song(&kernel, 953967.into()).await?;
-    dbg!(song);
-    Ok(())
+pub async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(exit_code(&e))
+        }
+    }
 }
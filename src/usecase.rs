@@ -1 +1,5 @@
 pub mod add_new_song;
+pub mod analyze_loudness;
+pub mod reconcile;
+pub mod sync_plan;
+pub mod update_metadata;
@@ -1,82 +1,147 @@
+use crate::domain::artist::ArtistResolver;
+use crate::domain::disc::{split_discs, ProvideDiscOverrides};
+use crate::domain::msr::SongSummary;
+use crate::domain::repository::artist_repository::{ProvideArtistRepository, UsesArtistRepository};
 use crate::domain::repository::msr_repository::{ProvideMsrRepository, UsesMsrRepository};
 use crate::domain::repository::song_repository::{ProvideSongRepository, UsesSongRepository};
-use crate::domain::song::{self, AlbumId, AudioRawData, OriginGame, SongId};
+use crate::domain::repository::sync_run_repository::{
+    ProvideSyncRunRepository, UsesSyncRunRepository,
+};
+use crate::domain::song::{self, AlbumId, AudioRawData, SongId};
+use crate::domain::sync_report::{SyncFailure, SyncReport};
+use crate::errors::usecase::UsecaseError;
+use crate::errors::Error;
+use crate::usecase::sync_plan::SyncPlan;
 use anyhow::{Ok, Result};
 use async_trait::async_trait;
 use futures::{StreamExt, TryStreamExt};
-use indexmap::IndexSet;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 use std::vec;
-use crate::errors::domain::DomainError;
 
 /// サイトの更新を見て新しい楽曲を登録するユースケース
 /// Cloneのコストが小さい型に実装することを想定している。
 #[async_trait]
 pub trait UsesAddNewSongUseCase {
+    /// MSRから最新の楽曲情報を取得し、DBと比較して同期の計画を立てる。
+    /// 音声データは取得しない。
+    async fn plan_sync(&self) -> Result<SyncPlan>;
     /// MSRから最新の楽曲情報を取得し、DBと比較して新しい楽曲があれば登録する
     async fn add_new_songs(&self) -> Result<Vec<SongId>>;
+    /// [`UsesAddNewSongUseCase::add_new_songs`]と同様に新しい楽曲を登録し、
+    /// 楽曲ごとの結果を[`SyncReport`]にまとめて記録する。
+    /// * keep_going: `true`なら一部の楽曲が失敗しても残りの楽曲の取得を続ける。
+    ///   `false`なら最初の失敗以降の楽曲はスキップする。
+    async fn sync_new_songs(&self, keep_going: bool) -> Result<SyncReport>;
 }
 
 /// [`UsesAddNewSongUseCase`]に必要な依存
 pub trait AddNewSongUseCase:
-    ProvideSongRepository + ProvideMsrRepository + Send + Sync + 'static + Clone
+    ProvideSongRepository
+    + ProvideMsrRepository
+    + ProvideSyncRunRepository
+    + ProvideArtistRepository
+    + ProvideDiscOverrides
+    + Send
+    + Sync
+    + 'static
+    + Clone
 {
+    /// 楽曲を同時に取得する数
+    fn concurrency(&self) -> usize {
+        8
+    }
 }
 
 #[async_trait]
 impl<R: AddNewSongUseCase> UsesAddNewSongUseCase for R {
+    async fn plan_sync(&self) -> Result<SyncPlan> {
+        let resolver = load_artist_resolver(self).await?;
+        plan_sync_with(self, &resolver).await
+    }
+
     async fn add_new_songs(&self) -> Result<Vec<SongId>> {
-        // MSRからすべての楽曲情報のIDを取得
-        let msr_song_summaries = self
-            .provide_msr_repository()
-            .fetch_all_songs()
-            .await?
-            .list
-            .into_iter()
-            .map(|x| SongId::try_new(x.id))
-            .collect::<Result<Vec<_>, DomainError>>()?;
-
-        // DBからすべての楽曲情報のIDを取得
-        let store_song_summaries = self
-            .provide_song_repository()
-            .get_all_songs_id()
-            .await?
-            .into_iter()
-            .collect::<IndexSet<_>>();
-
-        // 差分のIDを取得
-        let new_songs_id = msr_song_summaries
-            .into_iter()
-            .filter(|x| store_song_summaries.contains(x))
-            .collect::<Vec<_>>();
+        // 同期の間はアーティストの別名は変わらないので、1回だけ読み込む
+        let resolver = load_artist_resolver(self).await?;
+        // DBにない楽曲だけを取得する
+        let new_songs_id = plan_sync_with(self, &resolver).await?.new_songs;
         // 新しい楽曲がなければ何もしない
         if new_songs_id.is_empty() {
             return Ok(vec![]);
         }
 
-        fetch_and_save_songs(self, &new_songs_id).await?;
+        fetch_and_save_songs(self, &new_songs_id, &resolver).await?;
 
         Ok(new_songs_id)
     }
+
+    async fn sync_new_songs(&self, keep_going: bool) -> Result<SyncReport> {
+        let mut report = SyncReport::default();
+
+        let started = Instant::now();
+        let resolver = load_artist_resolver(self).await?;
+        let new_songs_id = plan_sync_with(self, &resolver).await?.new_songs;
+        report.record_phase("plan", started.elapsed());
+
+        let started = Instant::now();
+        fetch_and_save_songs_with_report(self, &new_songs_id, &resolver, keep_going, &mut report)
+            .await;
+        report.record_phase("download", started.elapsed());
+
+        self.provide_sync_run_repository()
+            .save_sync_run(report.clone())
+            .await?;
+        Ok(report)
+    }
+}
+
+/// MSRから最新の楽曲情報を取得し、DBと比較して同期の計画を立てる
+/// * resolver: MSRのクレジットを正式な名前に揃えるのに使う
+async fn plan_sync_with<R: AddNewSongUseCase>(
+    repositories: &R,
+    resolver: &ArtistResolver,
+) -> Result<SyncPlan> {
+    // MSRからすべての楽曲情報を取得
+    // 保存されている楽曲と比較できるように、クレジットは正式な名前に揃える
+    let msr_song_summaries = repositories
+        .provide_msr_repository()
+        .fetch_all_songs()
+        .await?
+        .list
+        .into_iter()
+        .map(|summary| SongSummary {
+            artists: resolver.resolve(&summary.artists),
+            ..summary
+        })
+        .collect::<Vec<_>>();
+    // DBからすべての楽曲情報を取得
+    let stored_songs = repositories
+        .provide_song_repository()
+        .get_all_song()
+        .await?;
+
+    let plan = SyncPlan::compute(&msr_song_summaries, &stored_songs);
+    Ok(plan)
 }
 
 /// 新しい楽曲を取得し、保存する
 /// * repositories: リポジトリ。Cloneのコストが小さいことを期待している。
 /// * song_ids: 楽曲IDのリスト。MSRが提供するIDを期待している。
+/// * resolver: クレジットを正式な名前に揃えるのに使う。[`load_artist_resolver`]で作る。
 pub async fn fetch_and_save_songs<R: AddNewSongUseCase>(
     repositories: &R,
     song_ids: &[SongId],
+    resolver: &ArtistResolver,
 ) -> Result<()> {
     futures::stream::iter(song_ids.iter().copied())
         // Note: try_for_each_concurrentを使うためにResultで包んでいる
         .map(Ok)
-        .try_for_each_concurrent(8, |song_id| {
+        .try_for_each_concurrent(repositories.concurrency(), |song_id| {
             let repositories = repositories.clone();
             async move {
-                let song = fetch_and_create_song(&repositories, song_id).await?;
-                repositories
-                    .provide_song_repository()
-                    .save_song(song)
-                    .await?;
+                fetch_and_save_song(&repositories, song_id, resolver).await?;
                 Ok(())
             }
         })
@@ -84,119 +149,407 @@ pub async fn fetch_and_save_songs<R: AddNewSongUseCase>(
     Ok(())
 }
 
+/// [`fetch_and_save_songs`]と異なり、1曲の失敗で全体を中断せずに楽曲ごとの結果を`report`に記録する。
+/// `keep_going`が`false`の場合は、失敗した後にまだ取得を始めていない楽曲をスキップする。
+/// * repositories: リポジトリ。Cloneのコストが小さいことを期待している。
+/// * song_ids: 楽曲IDのリスト。MSRが提供するIDを期待している。
+/// * resolver: クレジットを正式な名前に揃えるのに使う。[`load_artist_resolver`]で作る。
+pub async fn fetch_and_save_songs_with_report<R: AddNewSongUseCase>(
+    repositories: &R,
+    song_ids: &[SongId],
+    resolver: &ArtistResolver,
+    keep_going: bool,
+    report: &mut SyncReport,
+) {
+    let aborted = Arc::new(AtomicBool::new(false));
+    let outcomes = futures::stream::iter(song_ids.iter().copied())
+        .map(|song_id| {
+            let repositories = repositories.clone();
+            let aborted = aborted.clone();
+            async move {
+                if aborted.load(Ordering::Relaxed) {
+                    return (song_id, None);
+                }
+                let result = fetch_and_save_song(&repositories, song_id, resolver).await;
+                if result.is_err() && !keep_going {
+                    aborted.store(true, Ordering::Relaxed);
+                }
+                (song_id, Some(result))
+            }
+        })
+        // Note: 結果の順番を楽曲IDの順番に揃えるためにbufferedを使っている
+        // Note: buffered(0)は楽曲を1つも取り出さずに止まってしまうので、少なくとも1にする
+        .buffered(repositories.concurrency().max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    for (song_id, outcome) in outcomes {
+        match outcome {
+            Some(std::result::Result::Ok(downloaded)) => {
+                report.succeeded.push(song_id);
+                report.bytes_downloaded += downloaded;
+            }
+            Some(Err(e)) => report.failed.push(SyncFailure::new(song_id, &e)),
+            None => report.skipped.push(song_id),
+        }
+    }
+}
+
+/// 楽曲を1曲取得して保存する。ダウンロードしたバイト数を返す。
+async fn fetch_and_save_song<R: AddNewSongUseCase>(
+    repositories: &R,
+    song_id: SongId,
+    resolver: &ArtistResolver,
+) -> Result<u64> {
+    let (song, downloaded) = fetch_and_create_song(repositories, song_id, resolver).await?;
+    // 保存できた場合はリポジトリが一時ファイルを削除するので、失敗した場合に残らないようにする
+    let _staged = song.source.staged_path.clone().map(StagedFile::new);
+    repositories
+        .provide_song_repository()
+        .save_song(song)
+        .await?;
+    Ok(downloaded)
+}
+
 /// 楽曲情報とアルバム情報を取得し、[`Song`]を作成する。
+/// ダウンロードした音声データのバイト数も返す。
+/// 保存されているアルバムに楽曲が含まれていなければ、アルバムをMSRから1度だけ取得し直す。
+/// [`Song`]を作成できなかった場合は、ダウンロードした一時ファイルを削除する。
 /// * repositories: リポジトリ。Cloneのコストが小さいことを期待している。
 /// * song_id: 楽曲ID。MSRが提供するIDを期待している。
+/// * resolver: クレジットを正式な名前に揃えるのに使う
 pub async fn fetch_and_create_song<R: AddNewSongUseCase>(
     repositories: &R,
     song_id: SongId,
-) -> Result<song::Song> {
+    resolver: &ArtistResolver,
+) -> Result<(song::Song, u64)> {
     let msr_song = repositories
         .provide_msr_repository()
-        .fetch_song(format!("{song_id:0>6}"))
+        .fetch_song(song_id)
         .await?;
-    let belong_album_id = AlbumId::try_new(msr_song.belong_album_id.clone())?;
-    // Repositoryにアルバムが存在するか確認
-    let song_list = if let Some(album) = repositories
-        .provide_song_repository()
-        .get_album(belong_album_id)
+    let belong_album_id = msr_song.belong_album_id;
+    let mut discs = get_or_fetch_album(repositories, belong_album_id, resolver)
         .await?
-    {
-        album.song_list
-    } else {
-        // なければMSRから取得して登録
-        let msr_album = repositories
-            .provide_msr_repository()
-            .fetch_album(msr_song.belong_album_id)
-            .await?;
-        let song_list = repositories
-            .provide_msr_repository()
-            .fetch_album_detail(format!("{belong_album_id:0>4}"))
+        .discs;
+    // 保存されているアルバムが古いと楽曲が含まれていないので、ダウンロードする前に確認する。
+    // 含まれていなければMSRから取得し直し、それでも含まれていなければ諦める
+    if !discs.iter().flatten().any(|&id| id == song_id) {
+        discs = fetch_and_save_album(repositories, belong_album_id, resolver)
             .await?
-            .song_list
-            .into_iter()
-            .map(|raw| SongId::try_new(raw.id))
-            .collect::<Result<Vec<_>, DomainError>>()?;
-        let cover_image = repositories
-            .provide_msr_repository()
-            .fetch_cover_image(msr_album.cover_url)
-            .await?;
-        let album = song::Album::try_new(
-            belong_album_id,
-            msr_album.name,
-            msr_album.intro,
-            OriginGame::try_new(msr_album.belong)?,
-            cover_image,
-            msr_album.artists,
-            song_list.clone(),
-        )?;
-        repositories
-            .provide_song_repository()
-            .save_album(album)
-            .await?;
-        song_list
-    };
-
-    let source = AudioRawData::try_new(
-        repositories
-            .provide_msr_repository()
-            .fetch_raw_song(msr_song.source_url)
-            .await?,
+            .discs;
+    }
+    if !discs.iter().flatten().any(|&id| id == song_id) {
+        return Err(Error::from(UsecaseError::AlbumMissingSong {
+            song_id,
+            album_id: belong_album_id,
+        })
+        .into());
+    }
+
+    // 音声データは大きいのでメモリに載せずに一時ファイルへダウンロードする
+    let downloaded = repositories
+        .provide_msr_repository()
+        .download_raw_song(msr_song.source_url.clone())
+        .await?;
+    let downloaded_size = downloaded.size;
+    let staged = StagedFile::new(downloaded.staged_path.clone());
+    let source = AudioRawData::try_from_staged(
+        &downloaded.head,
+        &downloaded.hash,
+        downloaded.staged_path,
     )?;
+    // 実際のフォーマットとURLの拡張子が食い違っていないか確認
+    source.check_extension(msr_song.source_url.path())?;
 
+    let artists = resolver.resolve(&msr_song.artists);
     let song = song::Song::try_new(
         song_id,
         msr_song.name,
         belong_album_id,
-        &song_list,
+        &discs,
         source,
-        msr_song.artists,
+        artists,
     )?;
+    staged.keep();
+
+    Ok((song, downloaded_size))
+}
+
+/// ダウンロードした一時ファイル。
+/// [`StagedFile::keep`]を呼ばずにドロップした場合は、エラーやキャンセルで処理が中断されたものとして削除する。
+struct StagedFile(Option<PathBuf>);
+
+impl StagedFile {
+    fn new(path: PathBuf) -> Self {
+        Self(Some(path))
+    }
+
+    /// 一時ファイルを削除せずに残す
+    fn keep(mut self) {
+        self.0 = None;
+    }
+}
 
-    Ok(song)
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.0.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// 保存されているアルバムを取得する。なければMSRから取得して保存する。
+/// * album_id: アルバムID。MSRが提供するIDを期待している。
+/// * resolver: クレジットを正式な名前に揃えるのに使う
+pub async fn get_or_fetch_album<R>(
+    repositories: &R,
+    album_id: AlbumId,
+    resolver: &ArtistResolver,
+) -> Result<song::Album>
+where
+    R: ProvideSongRepository + ProvideMsrRepository + ProvideDiscOverrides,
+{
+    // Repositoryにアルバムが存在するか確認
+    if let Some(album) = repositories
+        .provide_song_repository()
+        .get_album(album_id)
+        .await?
+    {
+        return Ok(album);
+    }
+
+    // なければMSRから取得して登録
+    fetch_and_save_album(repositories, album_id, resolver).await
+}
+
+/// MSRからアルバムを取得して保存する。保存されているアルバムは上書きする。
+/// * album_id: アルバムID。MSRが提供するIDを期待している。
+/// * resolver: クレジットを正式な名前に揃えるのに使う
+async fn fetch_and_save_album<R>(
+    repositories: &R,
+    album_id: AlbumId,
+    resolver: &ArtistResolver,
+) -> Result<song::Album>
+where
+    R: ProvideSongRepository + ProvideMsrRepository + ProvideDiscOverrides,
+{
+    let msr_album = repositories
+        .provide_msr_repository()
+        .fetch_album(album_id)
+        .await?;
+    let detail = repositories
+        .provide_msr_repository()
+        .fetch_album_detail(album_id)
+        .await?;
+    let discs = split_discs(
+        album_id,
+        &detail.song_list,
+        repositories.provide_disc_overrides(),
+    )?;
+    let cover_image = repositories
+        .provide_msr_repository()
+        .fetch_cover_image(msr_album.cover_url)
+        .await?;
+    let artists = resolver.resolve(&msr_album.artists);
+    let album = song::Album::try_new(
+        album_id,
+        msr_album.name,
+        msr_album.intro,
+        msr_album.belong,
+        cover_image,
+        artists,
+        discs,
+    )?;
+    repositories
+        .provide_song_repository()
+        .save_album(album.clone())
+        .await?;
+    Ok(album)
+}
+
+/// 保存されているアーティストと別名から、クレジットを正式な名前に揃える[`ArtistResolver`]を作る
+pub async fn load_artist_resolver<R: ProvideArtistRepository>(
+    repositories: &R,
+) -> Result<ArtistResolver> {
+    let artists = repositories
+        .provide_artist_repository()
+        .get_all_artists()
+        .await?;
+    Ok(ArtistResolver::new(&artists))
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::domain::artist::{Artist, ArtistId, ArtistResolver};
+    use crate::domain::disc::DiscOverrides;
     use crate::domain::msr::*;
+    use crate::domain::repository::artist_repository::MockUsesArtistRepository;
     use crate::domain::repository::msr_repository::MockUsesMsrRepository;
     use crate::domain::repository::song_repository::MockUsesSongRepository;
+    use crate::domain::repository::sync_run_repository::MockUsesSyncRunRepository;
+    use crate::domain::song::{AlbumId, OriginGame, SongId};
+    use crate::errors::infra::InfraError;
+    use crate::errors::usecase::UsecaseError;
+    use crate::errors::Error;
     use bytes::Bytes;
     use std::sync::Arc;
     use url::Url;
 
+    #[derive(Clone)]
+    struct Mock {
+        song: Arc<MockUsesSongRepository>,
+        msr: Arc<MockUsesMsrRepository>,
+        sync_run: Arc<MockUsesSyncRunRepository>,
+        artist: Arc<MockUsesArtistRepository>,
+        disc_overrides: DiscOverrides,
+        concurrency: usize,
+    }
+
+    impl super::AddNewSongUseCase for Mock {
+        fn concurrency(&self) -> usize {
+            self.concurrency
+        }
+    }
+    impl super::ProvideSongRepository for Mock {
+        type SongRepository = MockUsesSongRepository;
+        fn provide_song_repository(&self) -> &Self::SongRepository {
+            &self.song
+        }
+    }
+    impl super::ProvideMsrRepository for Mock {
+        type MsrRepository = MockUsesMsrRepository;
+        fn provide_msr_repository(&self) -> &Self::MsrRepository {
+            &self.msr
+        }
+    }
+    impl super::ProvideSyncRunRepository for Mock {
+        type SyncRunRepository = MockUsesSyncRunRepository;
+        fn provide_sync_run_repository(&self) -> &Self::SyncRunRepository {
+            &self.sync_run
+        }
+    }
+    impl super::ProvideArtistRepository for Mock {
+        type ArtistRepository = MockUsesArtistRepository;
+        fn provide_artist_repository(&self) -> &Self::ArtistRepository {
+            &self.artist
+        }
+    }
+    impl super::ProvideDiscOverrides for Mock {
+        fn provide_disc_overrides(&self) -> &DiscOverrides {
+            &self.disc_overrides
+        }
+    }
+
+    fn artist_mock(artists: Vec<Artist>) -> Arc<MockUsesArtistRepository> {
+        let mut artist_mock = MockUsesArtistRepository::new();
+        artist_mock
+            .expect_get_all_artists()
+            .returning(move || Ok(artists.clone()));
+        Arc::new(artist_mock)
+    }
+
+    fn song_summaries() -> SongSummaries {
+        SongSummaries {
+            list: vec![SongSummary {
+                id: SongId::new(1).unwrap(),
+                name: "song".into(),
+                belong_album_id: AlbumId::new(1).unwrap(),
+                artists: vec!["artist".into()],
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_new_songs_skips_stored() {
+        let mut song_mock = MockUsesSongRepository::new();
+        song_mock.expect_get_all_song().returning(|| {
+            Ok(vec![crate::domain::song::Song {
+                id: SongId::new(1).unwrap(),
+                name: "song".into(),
+                belong_album_id: AlbumId::new(1).unwrap(),
+                artists: vec!["artist".into()],
+                ..Default::default()
+            }])
+        });
+        song_mock.expect_save_song().never();
+
+        let mut msr_mock = MockUsesMsrRepository::new();
+        msr_mock
+            .expect_fetch_all_songs()
+            .returning(|| Ok(song_summaries()));
+        msr_mock.expect_fetch_song().never();
+        msr_mock.expect_download_raw_song().never();
+
+        let mock = Mock {
+            song: Arc::new(song_mock),
+            msr: Arc::new(msr_mock),
+            sync_run: Arc::new(MockUsesSyncRunRepository::new()),
+            artist: artist_mock(vec![]),
+            disc_overrides: DiscOverrides::new(),
+            concurrency: 8,
+        };
+        let added = super::UsesAddNewSongUseCase::add_new_songs(&mock)
+            .await
+            .unwrap();
+        assert!(added.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_plan_sync_resolves_aliases() {
+        let mut song_mock = MockUsesSongRepository::new();
+        song_mock.expect_get_all_song().returning(|| {
+            Ok(vec![crate::domain::song::Song {
+                id: SongId::new(1).unwrap(),
+                name: "song".into(),
+                belong_album_id: AlbumId::new(1).unwrap(),
+                artists: vec!["塞壬唱片-MSR".into()],
+                ..Default::default()
+            }])
+        });
+
+        let mut msr_mock = MockUsesMsrRepository::new();
+        msr_mock
+            .expect_fetch_all_songs()
+            .returning(|| Ok(song_summaries()));
+
+        let mock = Mock {
+            song: Arc::new(song_mock),
+            msr: Arc::new(msr_mock),
+            sync_run: Arc::new(MockUsesSyncRunRepository::new()),
+            artist: artist_mock(vec![Artist {
+                id: ArtistId::new(1),
+                name: "塞壬唱片-MSR".into(),
+                aliases: vec!["Artist".into()],
+            }]),
+            disc_overrides: DiscOverrides::new(),
+            concurrency: 8,
+        };
+        // MSRのクレジットは別名なので、正式な名前に揃えれば変更はない
+        let plan = super::UsesAddNewSongUseCase::plan_sync(&mock)
+            .await
+            .unwrap();
+        assert!(plan.is_empty());
+    }
+
     #[tokio::test]
     async fn test_add_new_songs() {
-        #[derive(Clone)]
-        struct Mock {
-            song: Arc<MockUsesSongRepository>,
-            msr: Arc<MockUsesMsrRepository>,
-        }
         let mut song_mock = MockUsesSongRepository::new();
-        song_mock.expect_get_all_songs_id().returning(|| Ok(vec![]));
+        song_mock.expect_get_all_song().returning(|| Ok(vec![]));
         song_mock.expect_save_songs().returning(|_| Ok(()));
         song_mock.expect_get_album().returning(|_| Ok(None));
         song_mock.expect_save_song().returning(|_| Ok(()));
         song_mock.expect_save_album().returning(|_| Ok(()));
 
         let mut msr_mock = MockUsesMsrRepository::new();
-        msr_mock.expect_fetch_all_songs().returning(|| {
-            let list = SongSummaries {
-                list: vec![SongSummary {
-                    id: "000001".into(),
-                    name: "song".into(),
-                    belong_album_id: "0001".into(),
-                    artists: vec!["artist".into()],
-                }],
-            };
-            Ok(list)
-        });
+        msr_mock
+            .expect_fetch_all_songs()
+            .returning(|| Ok(song_summaries()));
         msr_mock.expect_fetch_song().returning(|_| {
             let song = Song {
-                id: "000001".into(),
+                id: SongId::new(1).unwrap(),
                 name: "song".into(),
-                belong_album_id: "0001".into(),
-                source_url: Url::parse("https://example.com").unwrap(),
+                belong_album_id: AlbumId::new(1).unwrap(),
+                source_url: Url::parse("https://example.com/song.flac").unwrap(),
                 artists: vec!["artist".into()],
                 lyric_url: None,
             };
@@ -204,10 +557,10 @@ mod tests {
         });
         msr_mock.expect_fetch_album().returning(|_| {
             let album = Album {
-                id: "0001".into(),
+                id: AlbumId::new(1).unwrap(),
                 name: "album".into(),
                 intro: "intro".into(),
-                belong: "arknights".into(),
+                belong: OriginGame::Arknights,
                 cover_url: Url::parse("https://example.com").unwrap(),
                 cover_de_url: Url::parse("https://example.com").unwrap(),
                 artists: vec!["artist".into()],
@@ -216,14 +569,14 @@ mod tests {
         });
         msr_mock.expect_fetch_album_detail().returning(|_| {
             let detail = AlbumDetail {
-                id: "0001".into(),
+                id: AlbumId::new(1).unwrap(),
                 name: "album".into(),
                 intro: "intro".into(),
-                belong: "arknights".into(),
+                belong: OriginGame::Arknights,
                 cover_url: Url::parse("https://example.com").unwrap(),
                 cover_de_url: Url::parse("https://example.com").unwrap(),
                 song_list: vec![SongShort {
-                    id: "000001".into(),
+                    id: SongId::new(1).unwrap(),
                     name: "song".into(),
                     artists: vec!["artist".into()],
                 }],
@@ -233,30 +586,397 @@ mod tests {
         msr_mock
             .expect_fetch_cover_image()
             .returning(|_| Ok(Bytes::new()));
+        msr_mock.expect_download_raw_song().returning(|_| {
+            Ok(DownloadedAudio {
+                staged_path: "/tmp/000001.tmp".into(),
+                hash: "0123456789abcdef".into(),
+                size: 4,
+                head: Bytes::from_static(b"fLaC"),
+            })
+        });
+
+        let mock = Mock {
+            song: Arc::new(song_mock),
+            msr: Arc::new(msr_mock),
+            sync_run: Arc::new(MockUsesSyncRunRepository::new()),
+            artist: artist_mock(vec![]),
+            disc_overrides: DiscOverrides::new(),
+            concurrency: 8,
+        };
+
+        let (result, downloaded) = super::fetch_and_create_song(
+            &mock,
+            SongId::new(1).unwrap(),
+            &ArtistResolver::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(downloaded, 4);
+        assert_eq!(result.id, SongId::new(1).unwrap());
+        assert_eq!(result.name, "song");
+        assert_eq!(result.belong_album_id, AlbumId::new(1).unwrap());
+        assert_eq!(result.track_number, 1);
+        assert_eq!(result.source.format, super::song::AudioFormat::Flac);
+        assert_eq!(result.source.raw, None);
+        assert_eq!(
+            result.source.staged_path,
+            Some(std::path::PathBuf::from("/tmp/000001.tmp"))
+        );
+        assert_eq!(result.artists, vec!["artist".to_string()]);
+    }
+
+    /// MSRのアルバムが`song_ids`の楽曲を収録しているとしてモックする
+    fn expect_msr_album(msr_mock: &mut MockUsesMsrRepository, song_ids: &'static [u32]) {
+        msr_mock.expect_fetch_album().times(1).returning(|id| {
+            Ok(Album {
+                id,
+                name: "album".into(),
+                intro: "intro".into(),
+                belong: OriginGame::Arknights,
+                cover_url: Url::parse("https://example.com").unwrap(),
+                cover_de_url: Url::parse("https://example.com").unwrap(),
+                artists: vec![],
+            })
+        });
         msr_mock
-            .expect_fetch_raw_song()
+            .expect_fetch_album_detail()
+            .times(1)
+            .returning(move |id| {
+                Ok(AlbumDetail {
+                    id,
+                    name: "album".into(),
+                    intro: "intro".into(),
+                    belong: OriginGame::Arknights,
+                    cover_url: Url::parse("https://example.com").unwrap(),
+                    cover_de_url: Url::parse("https://example.com").unwrap(),
+                    song_list: song_ids
+                        .iter()
+                        .map(|&id| SongShort {
+                            id: SongId::new(id).unwrap(),
+                            name: "song".into(),
+                            artists: vec![],
+                        })
+                        .collect(),
+                })
+            });
+        msr_mock
+            .expect_fetch_cover_image()
             .returning(|_| Ok(Bytes::new()));
+    }
+
+    /// 楽曲2だけを収録した古いアルバムが保存されているとしてモックする
+    fn stale_album_mock() -> MockUsesSongRepository {
+        let mut song_mock = MockUsesSongRepository::new();
+        song_mock.expect_get_album().returning(|_| {
+            Ok(Some(crate::domain::song::Album {
+                id: AlbumId::new(1).unwrap(),
+                discs: vec![vec![SongId::new(2).unwrap()]],
+                ..Default::default()
+            }))
+        });
+        song_mock
+    }
+
+    #[tokio::test]
+    async fn test_fetch_song_refetches_stale_album() {
+        let mut song_mock = stale_album_mock();
+        // 取得し直したアルバムを保存する
+        song_mock
+            .expect_save_album()
+            .withf(|album| album.song_list().len() == 2)
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let mut msr_mock = MockUsesMsrRepository::new();
+        msr_mock.expect_fetch_song().returning(|id| {
+            Ok(Song {
+                id,
+                name: "song".into(),
+                belong_album_id: AlbumId::new(1).unwrap(),
+                source_url: Url::parse("https://example.com/song.flac").unwrap(),
+                artists: vec![],
+                lyric_url: None,
+            })
+        });
+        expect_msr_album(&mut msr_mock, &[2, 1]);
+        msr_mock.expect_download_raw_song().returning(|_| {
+            Ok(DownloadedAudio {
+                staged_path: "/tmp/000001.tmp".into(),
+                hash: "0123456789abcdef".into(),
+                size: 4,
+                head: Bytes::from_static(b"fLaC"),
+            })
+        });
 
         let mock = Mock {
             song: Arc::new(song_mock),
             msr: Arc::new(msr_mock),
+            sync_run: Arc::new(MockUsesSyncRunRepository::new()),
+            artist: artist_mock(vec![]),
+            disc_overrides: DiscOverrides::new(),
+            concurrency: 8,
         };
 
-        impl super::AddNewSongUseCase for Mock {}
-        impl super::ProvideSongRepository for Mock {
-            type SongRepository = MockUsesSongRepository;
-            fn provide_song_repository(&self) -> &Self::SongRepository {
-                &self.song
-            }
-        }
-        impl super::ProvideMsrRepository for Mock {
-            type MsrRepository = MockUsesMsrRepository;
-            fn provide_msr_repository(&self) -> &Self::MsrRepository {
-                &self.msr
+        let (result, _) = super::fetch_and_create_song(
+            &mock,
+            SongId::new(1).unwrap(),
+            &ArtistResolver::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.track_number, 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_song_missing_in_album() {
+        let mut song_mock = stale_album_mock();
+        song_mock.expect_save_album().times(1).returning(|_| Ok(()));
+
+        let mut msr_mock = MockUsesMsrRepository::new();
+        // 取得し直しても楽曲が含まれていない
+        expect_msr_album(&mut msr_mock, &[2]);
+        msr_mock.expect_fetch_song().returning(|id| {
+            Ok(Song {
+                id,
+                name: "song".into(),
+                belong_album_id: AlbumId::new(1).unwrap(),
+                source_url: Url::parse("https://example.com/song.flac").unwrap(),
+                artists: vec![],
+                lyric_url: None,
+            })
+        });
+
+        let mock = Mock {
+            song: Arc::new(song_mock),
+            msr: Arc::new(msr_mock),
+            sync_run: Arc::new(MockUsesSyncRunRepository::new()),
+            artist: artist_mock(vec![]),
+            disc_overrides: DiscOverrides::new(),
+            concurrency: 8,
+        };
+
+        // 音声データはダウンロードしない
+        let song_id = SongId::new(1).unwrap();
+        let err = super::fetch_and_create_song(&mock, song_id, &ArtistResolver::default())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Error>(),
+            Some(&Error::from(UsecaseError::AlbumMissingSong {
+                song_id: SongId::new(1).unwrap(),
+                album_id: AlbumId::new(1).unwrap(),
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_song_removes_staged_file_on_error() {
+        let staged_path =
+            std::env::temp_dir().join(format!("msr-staged-{}.tmp", std::process::id()));
+        std::fs::write(&staged_path, b"fLaC").unwrap();
+
+        let mut song_mock = MockUsesSongRepository::new();
+        song_mock.expect_get_album().returning(|_| {
+            Ok(Some(crate::domain::song::Album {
+                id: AlbumId::new(1).unwrap(),
+                discs: vec![vec![SongId::new(1).unwrap()]],
+                ..Default::default()
+            }))
+        });
+
+        let mut msr_mock = MockUsesMsrRepository::new();
+        msr_mock.expect_fetch_song().returning(|id| {
+            Ok(Song {
+                id,
+                name: "song".into(),
+                belong_album_id: AlbumId::new(1).unwrap(),
+                // 実際のフォーマットと食い違っている
+                source_url: Url::parse("https://example.com/song.mp3").unwrap(),
+                artists: vec![],
+                lyric_url: None,
+            })
+        });
+        let path = staged_path.clone();
+        msr_mock.expect_download_raw_song().returning(move |_| {
+            Ok(DownloadedAudio {
+                staged_path: path.clone(),
+                hash: "0123456789abcdef".into(),
+                size: 4,
+                head: Bytes::from_static(b"fLaC"),
+            })
+        });
+
+        let mock = Mock {
+            song: Arc::new(song_mock),
+            msr: Arc::new(msr_mock),
+            sync_run: Arc::new(MockUsesSyncRunRepository::new()),
+            artist: artist_mock(vec![]),
+            disc_overrides: DiscOverrides::new(),
+            concurrency: 8,
+        };
+
+        assert!(super::fetch_and_create_song(
+            &mock,
+            SongId::new(1).unwrap(),
+            &ArtistResolver::default()
+        )
+        .await
+        .is_err());
+        assert!(!staged_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_sync_new_songs_keep_going() {
+        let mut song_mock = MockUsesSongRepository::new();
+        song_mock.expect_get_all_song().returning(|| Ok(vec![]));
+        song_mock.expect_get_album().returning(|_| {
+            Ok(Some(crate::domain::song::Album {
+                id: AlbumId::new(1).unwrap(),
+                discs: vec![vec![SongId::new(1).unwrap(), SongId::new(2).unwrap()]],
+                ..Default::default()
+            }))
+        });
+        song_mock.expect_save_song().times(1).returning(|_| Ok(()));
+
+        let mut msr_mock = MockUsesMsrRepository::new();
+        msr_mock.expect_fetch_all_songs().returning(|| {
+            let mut summaries = song_summaries();
+            summaries.list.push(SongSummary {
+                id: SongId::new(2).unwrap(),
+                ..summaries.list[0].clone()
+            });
+            Ok(summaries)
+        });
+        msr_mock.expect_fetch_song().returning(|id| {
+            if id == SongId::new(2).unwrap() {
+                return Err(InfraError::Http {
+                    cause: "connection reset".into(),
+                }
+                .into());
             }
-        }
+            Ok(Song {
+                id,
+                name: "song".into(),
+                belong_album_id: AlbumId::new(1).unwrap(),
+                source_url: Url::parse("https://example.com/song.flac").unwrap(),
+                artists: vec!["artist".into()],
+                lyric_url: None,
+            })
+        });
+        msr_mock.expect_download_raw_song().returning(|_| {
+            Ok(DownloadedAudio {
+                staged_path: "/tmp/000001.tmp".into(),
+                hash: "0123456789abcdef".into(),
+                size: 4,
+                head: Bytes::from_static(b"fLaC"),
+            })
+        });
+
+        let mut sync_run_mock = MockUsesSyncRunRepository::new();
+        sync_run_mock
+            .expect_save_sync_run()
+            .times(1)
+            .returning(|_| Ok(()));
+
+        // 別名は同期の最初に1回だけ読み込む
+        let mut artist_mock = MockUsesArtistRepository::new();
+        artist_mock
+            .expect_get_all_artists()
+            .times(1)
+            .returning(|| Ok(vec![]));
+
+        let mock = Mock {
+            song: Arc::new(song_mock),
+            msr: Arc::new(msr_mock),
+            sync_run: Arc::new(sync_run_mock),
+            artist: Arc::new(artist_mock),
+            disc_overrides: DiscOverrides::new(),
+            concurrency: 8,
+        };
+
+        let report = super::UsesAddNewSongUseCase::sync_new_songs(&mock, true)
+            .await
+            .unwrap();
+        assert_eq!(report.succeeded, vec![SongId::new(1).unwrap()]);
+        assert!(report.skipped.is_empty());
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].id, SongId::new(2).unwrap());
+        assert_eq!(
+            report.failed[0].errors,
+            vec!["HTTP error: connection reset".to_string()]
+        );
+        assert_eq!(report.bytes_downloaded, 4);
+        assert_eq!(
+            report
+                .phases
+                .iter()
+                .map(|phase| phase.phase.as_str())
+                .collect::<Vec<_>>(),
+            vec!["plan", "download"]
+        );
+        assert!(!report.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_sync_new_songs_zero_concurrency() {
+        let mut song_mock = MockUsesSongRepository::new();
+        song_mock.expect_get_all_song().returning(|| Ok(vec![]));
+        song_mock.expect_get_album().returning(|_| {
+            Ok(Some(crate::domain::song::Album {
+                id: AlbumId::new(1).unwrap(),
+                discs: vec![vec![SongId::new(1).unwrap()]],
+                ..Default::default()
+            }))
+        });
+        song_mock.expect_save_song().times(1).returning(|_| Ok(()));
+
+        let mut msr_mock = MockUsesMsrRepository::new();
+        msr_mock
+            .expect_fetch_all_songs()
+            .returning(|| Ok(song_summaries()));
+        msr_mock.expect_fetch_song().returning(|id| {
+            Ok(Song {
+                id,
+                name: "song".into(),
+                belong_album_id: AlbumId::new(1).unwrap(),
+                source_url: Url::parse("https://example.com/song.flac").unwrap(),
+                artists: vec!["artist".into()],
+                lyric_url: None,
+            })
+        });
+        msr_mock.expect_download_raw_song().returning(|_| {
+            Ok(DownloadedAudio {
+                staged_path: "/tmp/000001.tmp".into(),
+                hash: "0123456789abcdef".into(),
+                size: 4,
+                head: Bytes::from_static(b"fLaC"),
+            })
+        });
+
+        let mut sync_run_mock = MockUsesSyncRunRepository::new();
+        sync_run_mock
+            .expect_save_sync_run()
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let mock = Mock {
+            song: Arc::new(song_mock),
+            msr: Arc::new(msr_mock),
+            sync_run: Arc::new(sync_run_mock),
+            artist: artist_mock(vec![]),
+            disc_overrides: DiscOverrides::new(),
+            concurrency: 0,
+        };
 
-        let result = super::fetch_and_create_song(&mock, 1.into()).await.unwrap();
-        assert_eq!(result, super::song::Song::default());
+        // 同時に取得する数が0でも止まらずに1曲ずつ取得する
+        let report = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            super::UsesAddNewSongUseCase::sync_new_songs(&mock, true),
+        )
+        .await
+        .expect("sync must not hang with zero concurrency")
+        .unwrap();
+        assert_eq!(report.succeeded, vec![SongId::new(1).unwrap()]);
+        assert!(report.is_success());
     }
 }
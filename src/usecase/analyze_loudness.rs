@@ -0,0 +1,285 @@
+use crate::domain::loudness::Measurement;
+use crate::domain::repository::song_repository::{ProvideSongRepository, UsesSongRepository};
+use crate::domain::song::{Album, AlbumId, AudioRawData, Song, SongId};
+use anyhow::Result;
+use async_trait::async_trait;
+use indexmap::{IndexMap, IndexSet};
+
+/// ラウドネスの解析の結果
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct LoudnessReport {
+    /// ラウドネスを記録した楽曲
+    pub analyzed_songs: Vec<SongId>,
+    /// アルバム全体のラウドネスを記録したアルバム
+    pub analyzed_albums: Vec<AlbumId>,
+    /// 収録曲が揃っていないため、アルバム全体の解析を見送ったアルバム
+    pub pending_albums: Vec<AlbumId>,
+    /// 無音などでラウドネスが測れなかった楽曲。測れなかったことも記録し、解析し直さない。
+    pub unmeasurable_songs: Vec<SongId>,
+    /// 音声データを復号できなかった楽曲
+    pub failed: Vec<LoudnessFailure>,
+}
+
+/// 解析に失敗した楽曲
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct LoudnessFailure {
+    pub id: SongId,
+    pub error: String,
+}
+
+/// 保存されている楽曲のラウドネスを解析するユースケース
+#[async_trait]
+pub trait UsesAnalyzeLoudnessUseCase {
+    /// まだ解析していない楽曲を復号してラウドネスとトゥルーピークを測定し、
+    /// DBに記録してReplayGainのタグを書き込む。
+    /// アルバム全体のラウドネスは、収録曲がすべて保存されているアルバムだけ測定する。
+    async fn analyze_loudness(&self) -> Result<LoudnessReport>;
+}
+
+/// [`UsesAnalyzeLoudnessUseCase`]に必要な依存
+pub trait AnalyzeLoudnessUseCase: ProvideSongRepository + Send + Sync + 'static {}
+
+#[async_trait]
+impl<R: AnalyzeLoudnessUseCase> UsesAnalyzeLoudnessUseCase for R {
+    async fn analyze_loudness(&self) -> Result<LoudnessReport> {
+        let mut report = LoudnessReport::default();
+        let songs = self
+            .provide_song_repository()
+            .get_all_song()
+            .await?
+            .into_iter()
+            .map(|song| (song.id, song))
+            .collect::<IndexMap<_, _>>();
+        // アルバムの解析で記録した楽曲は、楽曲ごとの解析では飛ばす
+        let mut done = IndexSet::new();
+
+        for album in self.provide_song_repository().get_all_album().await? {
+            let song_list = album.song_list();
+            let stale = !album.loudness.is_analyzed()
+                || song_list
+                    .iter()
+                    .any(|id| songs.get(id).is_some_and(needs_analysis));
+            if !stale || song_list.is_empty() {
+                continue;
+            }
+            // アルバム全体のラウドネスは収録曲がすべて揃うまで測らない
+            if !song_list.iter().all(|id| songs.contains_key(id)) {
+                report.pending_albums.push(album.id);
+                continue;
+            }
+            let mut measured = vec![];
+            for id in song_list.iter() {
+                match self
+                    .provide_song_repository()
+                    .measure_loudness(&songs[id])
+                    .await
+                {
+                    Ok(measurement) => measured.push((*id, measurement)),
+                    Err(e) => report.failed.push(LoudnessFailure {
+                        id: *id,
+                        error: format!("{e:#}"),
+                    }),
+                }
+            }
+            // 復号できない楽曲がある場合は、アルバム全体のラウドネスを記録しない。
+            // 楽曲のタグにアルバムの値も書き込むので、アルバムを先に保存する。
+            if measured.len() == song_list.len() {
+                let measurements = measured
+                    .iter()
+                    .map(|(_, measurement)| measurement.clone())
+                    .collect::<Vec<_>>();
+                let album_id = album.id;
+                self.provide_song_repository()
+                    .save_album(Album {
+                        loudness: Measurement::combined(&measurements).into(),
+                        ..album
+                    })
+                    .await?;
+                report.analyzed_albums.push(album_id);
+            }
+            let analyzed = measured
+                .iter()
+                .map(|(id, measurement)| with_measurement(&songs[id], measurement, &mut report))
+                .collect::<Vec<_>>();
+            self.provide_song_repository().save_songs(analyzed).await?;
+            done.extend(song_list);
+        }
+
+        // アルバムが揃っていない楽曲は、楽曲ごとのラウドネスだけ記録する
+        for song in songs.values() {
+            if done.contains(&song.id) || !needs_analysis(song) {
+                continue;
+            }
+            match self.provide_song_repository().measure_loudness(song).await {
+                Ok(measurement) => {
+                    let analyzed = with_measurement(song, &measurement, &mut report);
+                    self.provide_song_repository().save_song(analyzed).await?;
+                }
+                Err(e) => report.failed.push(LoudnessFailure {
+                    id: song.id,
+                    error: format!("{e:#}"),
+                }),
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// ラウドネスをまだ解析していない楽曲か
+fn needs_analysis(song: &Song) -> bool {
+    !song.source.loudness.is_analyzed()
+}
+
+/// 測定結果を記録した楽曲を返し、結果を`report`に追加する
+fn with_measurement(song: &Song, measurement: &Measurement, report: &mut LoudnessReport) -> Song {
+    let loudness = measurement.loudness();
+    match loudness {
+        Some(_) => report.analyzed_songs.push(song.id),
+        None => report.unmeasurable_songs.push(song.id),
+    }
+    Song {
+        source: AudioRawData {
+            loudness: loudness.into(),
+            ..song.source.clone()
+        },
+        ..song.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AnalyzeLoudnessUseCase, UsesAnalyzeLoudnessUseCase};
+    use crate::domain::loudness::{Loudness, LoudnessAnalysis, LoudnessMeter, Measurement};
+    use crate::domain::repository::song_repository::{
+        MockUsesSongRepository, ProvideSongRepository,
+    };
+    use crate::domain::song::{Album, AlbumId, AudioRawData, Song, SongId};
+
+    struct Mock {
+        song: MockUsesSongRepository,
+    }
+
+    impl AnalyzeLoudnessUseCase for Mock {}
+    impl ProvideSongRepository for Mock {
+        type SongRepository = MockUsesSongRepository;
+        fn provide_song_repository(&self) -> &Self::SongRepository {
+            &self.song
+        }
+    }
+
+    const ANALYZED: LoudnessAnalysis = LoudnessAnalysis::Measured(Loudness {
+        integrated_lufs: -12.0,
+        true_peak: 0.9,
+    });
+
+    fn song(id: u32, album_id: u32, loudness: LoudnessAnalysis) -> Song {
+        Song {
+            id: SongId::new(id).unwrap(),
+            belong_album_id: AlbumId::new(album_id).unwrap(),
+            source: AudioRawData {
+                loudness,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// 1秒間のサイン波の測定結果
+    fn measurement() -> Measurement {
+        let mut meter = LoudnessMeter::new(48000, 1);
+        let samples = (0..48000)
+            .map(|n| (n as f32 * 0.13).sin() * 0.5)
+            .collect::<Vec<_>>();
+        meter.add_samples(&samples);
+        meter.finish()
+    }
+
+    #[tokio::test]
+    async fn test_analyze_loudness() {
+        let mut song_mock = MockUsesSongRepository::new();
+        song_mock.expect_get_all_song().returning(|| {
+            Ok(vec![
+                song(1, 1, ANALYZED),
+                song(2, 1, LoudnessAnalysis::NotAnalyzed),
+                song(3, 2, LoudnessAnalysis::NotAnalyzed),
+                song(5, 3, ANALYZED),
+                song(6, 4, LoudnessAnalysis::Unmeasurable),
+            ])
+        });
+        song_mock.expect_get_all_album().returning(|| {
+            Ok(vec![
+                // 楽曲2が未解析なので、アルバム全体を解析し直す
+                Album {
+                    id: AlbumId::new(1).unwrap(),
+                    discs: vec![vec![SongId::new(1).unwrap(), SongId::new(2).unwrap()]],
+                    loudness: ANALYZED,
+                    ..Default::default()
+                },
+                // 楽曲4がまだ保存されていない
+                Album {
+                    id: AlbumId::new(2).unwrap(),
+                    discs: vec![vec![SongId::new(3).unwrap(), SongId::new(4).unwrap()]],
+                    ..Default::default()
+                },
+                // 解析済み
+                Album {
+                    id: AlbumId::new(3).unwrap(),
+                    discs: vec![vec![SongId::new(5).unwrap()]],
+                    loudness: ANALYZED,
+                    ..Default::default()
+                },
+                // 無音の楽曲だけなので測れなかったが、解析は済んでいる
+                Album {
+                    id: AlbumId::new(4).unwrap(),
+                    discs: vec![vec![SongId::new(6).unwrap()]],
+                    loudness: LoudnessAnalysis::Unmeasurable,
+                    ..Default::default()
+                },
+            ])
+        });
+        song_mock
+            .expect_measure_loudness()
+            .withf(|song| song.id != SongId::new(5).unwrap() && song.id != SongId::new(6).unwrap())
+            .times(3)
+            .returning(|_| Ok(measurement()));
+        song_mock
+            .expect_save_album()
+            .withf(|album| {
+                album.id == AlbumId::new(1).unwrap()
+                    && album.loudness.measured().is_some()
+                    && album.loudness != ANALYZED
+            })
+            .times(1)
+            .returning(|_| Ok(()));
+        song_mock
+            .expect_save_songs()
+            .withf(|songs| {
+                songs.iter().map(|song| song.id).collect::<Vec<_>>()
+                    == vec![SongId::new(1).unwrap(), SongId::new(2).unwrap()]
+                    && songs.iter().all(|song| {
+                        song.source.loudness.measured().is_some()
+                            && song.source.loudness != ANALYZED
+                    })
+            })
+            .times(1)
+            .returning(|_| Ok(()));
+        // アルバムが揃っていない楽曲は、楽曲ごとのラウドネスだけ記録する
+        song_mock
+            .expect_save_song()
+            .withf(|song| {
+                song.id == SongId::new(3).unwrap() && song.source.loudness.measured().is_some()
+            })
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let report = Mock { song: song_mock }.analyze_loudness().await.unwrap();
+        assert_eq!(
+            report.analyzed_songs,
+            [1, 2, 3].map(|id| SongId::new(id).unwrap())
+        );
+        assert_eq!(report.analyzed_albums, vec![AlbumId::new(1).unwrap()]);
+        assert_eq!(report.pending_albums, vec![AlbumId::new(2).unwrap()]);
+        assert!(report.failed.is_empty());
+    }
+}
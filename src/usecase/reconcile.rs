@@ -0,0 +1,497 @@
+use crate::domain::repository::msr_repository::{ProvideMsrRepository, UsesMsrRepository};
+use crate::domain::repository::song_repository::{ProvideSongRepository, UsesSongRepository};
+use crate::domain::song::{AlbumId, SongId};
+use crate::errors::usecase::UsecaseError;
+use crate::errors::Error;
+use anyhow::Result;
+use async_trait::async_trait;
+use indexmap::IndexSet;
+
+/// MSRから取り下げられた楽曲やアルバムの扱い
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    strum::Display,
+    strum::EnumString,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum WithdrawnPolicy {
+    /// 取り下げられたことだけを記録し、一覧にも残す
+    #[default]
+    Keep,
+    /// 記録した上で論理削除する。音声データは残す。
+    SoftDelete,
+    /// 記録した上で論理削除し、音声データも削除する
+    Purge,
+}
+
+/// 照合の結果
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct ReconcileReport {
+    pub policy: WithdrawnPolicy,
+    /// 新たに取り下げられた楽曲
+    pub withdrawn_songs: Vec<SongId>,
+    /// 新たに取り下げられたアルバム
+    pub withdrawn_albums: Vec<AlbumId>,
+    /// 取り下げられていたが、MSRに戻ってきた楽曲
+    pub restored_songs: Vec<SongId>,
+    /// 取り下げられていたが、MSRに戻ってきたアルバム
+    pub restored_albums: Vec<AlbumId>,
+}
+
+/// MSRから消えた楽曲やアルバムを照合するユースケース
+#[async_trait]
+pub trait UsesReconcileUseCase {
+    /// MSRの楽曲とアルバムの一覧をDBと照合し、MSRから消えたものを取り下げとして記録する。
+    /// その後の扱いは[`ReconcileUseCase::withdrawn_policy`]に従う。
+    /// 取り下げたものがMSRに戻ってきていれば、取り下げを取り消す。
+    /// MSRの一覧が空だったり、[`ReconcileUseCase::max_withdrawn_percent`]を超えて消えていたりする場合は、
+    /// MSR側の障害とみなして何も変更せずに中断する。
+    /// * force: `true`の場合は中断せずに、消えたものをすべて取り下げる
+    async fn reconcile(&self, force: bool) -> Result<ReconcileReport>;
+}
+
+/// [`UsesReconcileUseCase`]に必要な依存
+pub trait ReconcileUseCase:
+    ProvideSongRepository + ProvideMsrRepository + Send + Sync + 'static
+{
+    fn withdrawn_policy(&self) -> WithdrawnPolicy {
+        WithdrawnPolicy::Keep
+    }
+
+    /// 1回の照合で取り下げてよい楽曲やアルバムの割合の上限 (%)
+    fn max_withdrawn_percent(&self) -> u8 {
+        DEFAULT_MAX_WITHDRAWN_PERCENT
+    }
+}
+
+pub const DEFAULT_MAX_WITHDRAWN_PERCENT: u8 = 10;
+/// 割合に関わらず1回の照合で取り下げてよい数。
+/// これがないと、小さなライブラリでは1つも取り下げられない。
+pub const MIN_WITHDRAWN_ALLOWANCE: usize = 1;
+
+/// MSRの一覧から消えた数が多すぎないか確認する
+fn check_withdrawn(
+    target: &'static str,
+    upstream: usize,
+    withdrawn: usize,
+    total: usize,
+    max_percent: u8,
+) -> Result<(), Error> {
+    let suspicious = (upstream == 0 && total > 0)
+        || (withdrawn > MIN_WITHDRAWN_ALLOWANCE
+            && withdrawn * 100 > total * usize::from(max_percent));
+    if suspicious {
+        return Err(UsecaseError::ReconcileAborted {
+            target,
+            withdrawn,
+            total,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl<R: ReconcileUseCase> UsesReconcileUseCase for R {
+    async fn reconcile(&self, force: bool) -> Result<ReconcileReport> {
+        let policy = self.withdrawn_policy();
+        let mut report = ReconcileReport {
+            policy,
+            ..Default::default()
+        };
+
+        let upstream_songs = self
+            .provide_msr_repository()
+            .fetch_all_songs()
+            .await?
+            .list
+            .into_iter()
+            .map(|summary| summary.id)
+            .collect::<IndexSet<_>>();
+        let upstream_albums = self
+            .provide_msr_repository()
+            .fetch_all_albums()
+            .await?
+            .into_iter()
+            .map(|summary| summary.id)
+            .collect::<IndexSet<_>>();
+
+        // 一覧の取得に失敗して空や一部だけになっていると、すべて取り下げてしまうので先に確認する。
+        // forceが指定された場合は確認しない。
+        let max_percent = self.max_withdrawn_percent();
+        let songs = self.provide_song_repository().get_all_song().await?;
+        let total = songs.len();
+        let withdrawn_songs = songs
+            .into_iter()
+            .filter(|song| !song.withdrawn && !upstream_songs.contains(&song.id))
+            .collect::<Vec<_>>();
+        if !force {
+            check_withdrawn(
+                "songs",
+                upstream_songs.len(),
+                withdrawn_songs.len(),
+                total,
+                max_percent,
+            )?;
+        }
+        let albums = self.provide_song_repository().get_all_album().await?;
+        let total = albums.len();
+        let withdrawn_albums = albums
+            .into_iter()
+            .filter(|album| !album.withdrawn && !upstream_albums.contains(&album.id))
+            .collect::<Vec<_>>();
+        if !force {
+            check_withdrawn(
+                "albums",
+                upstream_albums.len(),
+                withdrawn_albums.len(),
+                total,
+                max_percent,
+            )?;
+        }
+
+        for song in withdrawn_songs {
+            self.provide_song_repository()
+                .set_song_withdrawn(song.id, true)
+                .await?;
+            if policy == WithdrawnPolicy::Purge {
+                self.provide_song_repository().purge_audio(&song).await?;
+            }
+            if policy != WithdrawnPolicy::Keep {
+                self.provide_song_repository().delete_song(song.id).await?;
+            }
+            report.withdrawn_songs.push(song.id);
+        }
+        for album in withdrawn_albums {
+            self.provide_song_repository()
+                .set_album_withdrawn(album.id, true)
+                .await?;
+            if policy != WithdrawnPolicy::Keep {
+                self.provide_song_repository()
+                    .delete_album(album.id)
+                    .await?;
+            }
+            report.withdrawn_albums.push(album.id);
+        }
+
+        // 論理削除したものも含めて、MSRに戻ってきたものを復元する
+        for song_id in self
+            .provide_song_repository()
+            .get_withdrawn_songs_id()
+            .await?
+        {
+            if upstream_songs.contains(&song_id) {
+                self.provide_song_repository().restore_song(song_id).await?;
+                report.restored_songs.push(song_id);
+            }
+        }
+        for album_id in self
+            .provide_song_repository()
+            .get_withdrawn_albums_id()
+            .await?
+        {
+            if upstream_albums.contains(&album_id) {
+                self.provide_song_repository()
+                    .restore_album(album_id)
+                    .await?;
+                report.restored_albums.push(album_id);
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_withdrawn, ReconcileUseCase, UsesReconcileUseCase, WithdrawnPolicy};
+    use crate::domain::msr::{AlbumSummary, SongSummaries, SongSummary};
+    use crate::domain::repository::msr_repository::{MockUsesMsrRepository, ProvideMsrRepository};
+    use crate::domain::repository::song_repository::{
+        MockUsesSongRepository, ProvideSongRepository,
+    };
+    use crate::domain::song::{Album, AlbumId, Song, SongId};
+    use crate::errors::usecase::UsecaseError;
+    use crate::errors::Error;
+    use mockall::predicate::eq;
+    use url::Url;
+
+    struct Mock {
+        song: MockUsesSongRepository,
+        msr: MockUsesMsrRepository,
+        policy: WithdrawnPolicy,
+    }
+
+    impl ReconcileUseCase for Mock {
+        fn withdrawn_policy(&self) -> WithdrawnPolicy {
+            self.policy
+        }
+
+        fn max_withdrawn_percent(&self) -> u8 {
+            50
+        }
+    }
+    impl ProvideSongRepository for Mock {
+        type SongRepository = MockUsesSongRepository;
+        fn provide_song_repository(&self) -> &Self::SongRepository {
+            &self.song
+        }
+    }
+    impl ProvideMsrRepository for Mock {
+        type MsrRepository = MockUsesMsrRepository;
+        fn provide_msr_repository(&self) -> &Self::MsrRepository {
+            &self.msr
+        }
+    }
+
+    /// MSRには楽曲1とアルバム2だけが残っている
+    fn msr_mock() -> MockUsesMsrRepository {
+        let mut msr_mock = MockUsesMsrRepository::new();
+        msr_mock.expect_fetch_all_songs().returning(|| {
+            Ok(SongSummaries {
+                list: vec![SongSummary {
+                    id: SongId::new(1).unwrap(),
+                    name: "song".into(),
+                    belong_album_id: AlbumId::new(2).unwrap(),
+                    artists: vec![],
+                }],
+            })
+        });
+        msr_mock.expect_fetch_all_albums().returning(|| {
+            Ok(vec![AlbumSummary {
+                id: AlbumId::new(2).unwrap(),
+                name: "album".into(),
+                cover_url: Url::parse("https://example.com").unwrap(),
+                artists: vec![],
+            }])
+        });
+        msr_mock
+    }
+
+    fn mock(policy: WithdrawnPolicy, song_mock: MockUsesSongRepository) -> Mock {
+        Mock {
+            song: song_mock,
+            msr: msr_mock(),
+            policy,
+        }
+    }
+
+    fn song_mock() -> MockUsesSongRepository {
+        let mut song_mock = MockUsesSongRepository::new();
+        song_mock.expect_get_all_song().returning(|| {
+            Ok([1, 2, 3]
+                .into_iter()
+                .map(|id| Song {
+                    id: SongId::new(id).unwrap(),
+                    // 楽曲1と楽曲3は以前取り下げられていた。楽曲1だけがMSRに戻ってきている。
+                    withdrawn: id != 2,
+                    ..Default::default()
+                })
+                .collect())
+        });
+        song_mock
+            .expect_get_withdrawn_songs_id()
+            .returning(|| Ok(vec![SongId::new(1).unwrap(), SongId::new(3).unwrap()]));
+        song_mock
+            .expect_restore_song()
+            .with(eq(SongId::new(1).unwrap()))
+            .times(1)
+            .returning(|_| Ok(()));
+        song_mock.expect_get_all_album().returning(|| {
+            Ok([1, 2]
+                .into_iter()
+                .map(|id| Album {
+                    id: AlbumId::new(id).unwrap(),
+                    ..Default::default()
+                })
+                .collect())
+        });
+        // 論理削除されていたアルバム2がMSRに戻ってきている
+        song_mock
+            .expect_get_withdrawn_albums_id()
+            .returning(|| Ok(vec![AlbumId::new(2).unwrap()]));
+        song_mock
+            .expect_restore_album()
+            .with(eq(AlbumId::new(2).unwrap()))
+            .times(1)
+            .returning(|_| Ok(()));
+        song_mock
+            .expect_set_album_withdrawn()
+            .with(eq(AlbumId::new(1).unwrap()), eq(true))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        song_mock
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_keep() {
+        let mut song_mock = song_mock();
+        song_mock
+            .expect_set_song_withdrawn()
+            .with(eq(SongId::new(2).unwrap()), eq(true))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        song_mock.expect_delete_song().never();
+        song_mock.expect_delete_album().never();
+        song_mock.expect_purge_audio().never();
+
+        let report = mock(WithdrawnPolicy::Keep, song_mock)
+            .reconcile(false)
+            .await
+            .unwrap();
+        assert_eq!(report.withdrawn_songs, vec![SongId::new(2).unwrap()]);
+        assert_eq!(report.restored_songs, vec![SongId::new(1).unwrap()]);
+        assert_eq!(report.withdrawn_albums, vec![AlbumId::new(1).unwrap()]);
+        assert_eq!(report.restored_albums, vec![AlbumId::new(2).unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_purge() {
+        let mut song_mock = song_mock();
+        song_mock
+            .expect_set_song_withdrawn()
+            .returning(|_, _| Ok(()));
+        song_mock
+            .expect_purge_audio()
+            .withf(|song| song.id == SongId::new(2).unwrap())
+            .times(1)
+            .returning(|_| Ok(()));
+        song_mock
+            .expect_delete_song()
+            .with(eq(SongId::new(2).unwrap()))
+            .times(1)
+            .returning(|_| Ok(()));
+        song_mock
+            .expect_delete_album()
+            .with(eq(AlbumId::new(1).unwrap()))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let report = mock(WithdrawnPolicy::Purge, song_mock)
+            .reconcile(false)
+            .await
+            .unwrap();
+        assert_eq!(report.policy, WithdrawnPolicy::Purge);
+        assert_eq!(report.withdrawn_songs, vec![SongId::new(2).unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_aborts_on_empty_upstream() {
+        let mut song_mock = MockUsesSongRepository::new();
+        song_mock.expect_get_all_song().returning(|| {
+            Ok(vec![Song {
+                id: SongId::new(1).unwrap(),
+                ..Default::default()
+            }])
+        });
+        song_mock.expect_set_song_withdrawn().never();
+        song_mock.expect_delete_song().never();
+        let mut msr_mock = MockUsesMsrRepository::new();
+        msr_mock
+            .expect_fetch_all_songs()
+            .returning(|| Ok(SongSummaries { list: vec![] }));
+        msr_mock.expect_fetch_all_albums().returning(|| Ok(vec![]));
+        let mock = Mock {
+            song: song_mock,
+            msr: msr_mock,
+            policy: WithdrawnPolicy::Purge,
+        };
+
+        let err = mock.reconcile(false).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Error>(),
+            Some(&Error::from(UsecaseError::ReconcileAborted {
+                target: "songs",
+                withdrawn: 1,
+                total: 1,
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_aborts_on_mass_withdrawal() {
+        let mut song_mock = MockUsesSongRepository::new();
+        // MSRに残っている楽曲1以外の3曲が消えている
+        song_mock.expect_get_all_song().returning(|| {
+            Ok((1..=4)
+                .map(|id| Song {
+                    id: SongId::new(id).unwrap(),
+                    ..Default::default()
+                })
+                .collect())
+        });
+        song_mock.expect_set_song_withdrawn().never();
+        song_mock.expect_set_album_withdrawn().never();
+
+        let err = mock(WithdrawnPolicy::Keep, song_mock)
+            .reconcile(false)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Error>(),
+            Some(&Error::from(UsecaseError::ReconcileAborted {
+                target: "songs",
+                withdrawn: 3,
+                total: 4,
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_force() {
+        let mut song_mock = MockUsesSongRepository::new();
+        song_mock.expect_get_all_song().returning(|| {
+            Ok((1..=4)
+                .map(|id| Song {
+                    id: SongId::new(id).unwrap(),
+                    ..Default::default()
+                })
+                .collect())
+        });
+        song_mock
+            .expect_set_song_withdrawn()
+            .times(3)
+            .returning(|_, _| Ok(()));
+        song_mock.expect_get_all_album().returning(|| Ok(vec![]));
+        song_mock
+            .expect_get_withdrawn_songs_id()
+            .returning(|| Ok(vec![]));
+        song_mock
+            .expect_get_withdrawn_albums_id()
+            .returning(|| Ok(vec![]));
+
+        let report = mock(WithdrawnPolicy::Keep, song_mock)
+            .reconcile(true)
+            .await
+            .unwrap();
+        assert_eq!(
+            report.withdrawn_songs,
+            (2..=4)
+                .map(|id| SongId::new(id).unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_check_withdrawn_small_library() {
+        // 10%では3曲のうち1曲も取り下げられないが、1つまでは割合に関わらず取り下げてよい
+        assert!(check_withdrawn("songs", 2, 1, 3, 10).is_ok());
+        assert!(check_withdrawn("songs", 1, 2, 3, 10).is_err());
+        assert!(check_withdrawn("songs", 99, 1, 100, 10).is_ok());
+        assert!(check_withdrawn("songs", 0, 1, 1, 10).is_err());
+    }
+
+    #[test]
+    fn test_policy_from_str() {
+        assert_eq!("soft-delete".parse(), Ok(WithdrawnPolicy::SoftDelete));
+        assert_eq!(WithdrawnPolicy::Purge.to_string(), "purge");
+    }
+}
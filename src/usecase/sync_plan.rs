@@ -0,0 +1,130 @@
+use crate::domain::msr::SongSummary;
+use crate::domain::song::{AlbumId, Song, SongId};
+use indexmap::{IndexMap, IndexSet};
+
+/// MSRとDBの差分から求めた同期の計画。
+/// 音声データを取得する前に計算できるので、大きな同期を実行する前の確認に使える。
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct SyncPlan {
+    /// DBにまだない楽曲
+    pub new_songs: Vec<SongId>,
+    /// 名前、所属するアルバム、アーティストのいずれかが変わった楽曲
+    pub changed_songs: Vec<SongId>,
+    /// MSRから消えた楽曲
+    pub removed_songs: Vec<SongId>,
+    /// 上記のいずれかの楽曲が所属するアルバム。所属が変わった場合は変更前後の両方を含む。
+    pub touched_albums: Vec<AlbumId>,
+}
+
+impl SyncPlan {
+    /// * msr_songs: MSRから取得した楽曲の一覧
+    /// * stored_songs: DBに保存されている楽曲
+    pub fn compute(msr_songs: &[SongSummary], stored_songs: &[Song]) -> Self {
+        let stored = stored_songs
+            .iter()
+            .map(|song| (song.id, song))
+            .collect::<IndexMap<_, _>>();
+
+        let mut plan = Self::default();
+        let mut touched_albums = IndexSet::new();
+        let mut upstream = IndexSet::new();
+        for summary in msr_songs {
+            let (id, album_id) = (summary.id, summary.belong_album_id);
+            upstream.insert(id);
+            match stored.get(&id) {
+                None => {
+                    plan.new_songs.push(id);
+                    touched_albums.insert(album_id);
+                }
+                Some(song)
+                    if song.name != summary.name
+                        || song.belong_album_id != album_id
+                        || song.artists != summary.artists =>
+                {
+                    plan.changed_songs.push(id);
+                    touched_albums.insert(song.belong_album_id);
+                    touched_albums.insert(album_id);
+                }
+                Some(_) => {}
+            }
+        }
+        for song in stored.values() {
+            if !upstream.contains(&song.id) {
+                plan.removed_songs.push(song.id);
+                touched_albums.insert(song.belong_album_id);
+            }
+        }
+        plan.touched_albums = touched_albums.into_iter().collect();
+        plan
+    }
+
+    /// 何も変更がない
+    pub fn is_empty(&self) -> bool {
+        self.new_songs.is_empty() && self.changed_songs.is_empty() && self.removed_songs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SyncPlan;
+    use crate::domain::msr::SongSummary;
+    use crate::domain::song::{AlbumId, Song, SongId};
+
+    fn summary(id: u32, name: &str, album_id: u32) -> SongSummary {
+        SongSummary {
+            id: SongId::new(id).unwrap(),
+            name: name.into(),
+            belong_album_id: AlbumId::new(album_id).unwrap(),
+            artists: vec!["artist".into()],
+        }
+    }
+
+    fn stored(id: u32, name: &str, album_id: u32) -> Song {
+        Song {
+            id: SongId::new(id).unwrap(),
+            name: name.into(),
+            belong_album_id: AlbumId::new(album_id).unwrap(),
+            artists: vec!["artist".into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compute() {
+        let msr_songs = vec![
+            summary(1, "unchanged", 1),
+            summary(2, "renamed", 1),
+            summary(3, "moved", 3),
+            summary(4, "new", 4),
+        ];
+        let stored_songs = vec![
+            stored(1, "unchanged", 1),
+            stored(2, "old name", 1),
+            stored(3, "moved", 2),
+            stored(5, "removed", 5),
+        ];
+
+        let plan = SyncPlan::compute(&msr_songs, &stored_songs);
+        assert_eq!(plan.new_songs, vec![SongId::new(4).unwrap()]);
+        assert_eq!(
+            plan.changed_songs,
+            vec![SongId::new(2).unwrap(), SongId::new(3).unwrap()]
+        );
+        assert_eq!(plan.removed_songs, vec![SongId::new(5).unwrap()]);
+        assert_eq!(
+            plan.touched_albums,
+            [1, 2, 3, 4, 5].map(|id| AlbumId::new(id).unwrap())
+        );
+        assert!(!plan.is_empty());
+    }
+
+    #[test]
+    fn test_compute_up_to_date() {
+        let msr_songs = vec![summary(1, "song", 1)];
+        let stored_songs = vec![stored(1, "song", 1)];
+
+        let plan = SyncPlan::compute(&msr_songs, &stored_songs);
+        assert!(plan.is_empty());
+        assert!(plan.touched_albums.is_empty());
+    }
+}
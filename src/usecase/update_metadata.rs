@@ -0,0 +1,695 @@
+use crate::domain::artist::ArtistResolver;
+use crate::domain::disc::{split_discs, DiscOverrides, ProvideDiscOverrides};
+use crate::domain::loudness::LoudnessAnalysis;
+use crate::domain::metadata_history::{MetadataChange, MetadataTarget};
+use crate::domain::msr;
+use crate::domain::repository::artist_repository::ProvideArtistRepository;
+use crate::domain::repository::msr_repository::{ProvideMsrRepository, UsesMsrRepository};
+use crate::domain::repository::song_repository::{ProvideSongRepository, UsesSongRepository};
+use crate::domain::song::{Album, AlbumId, Song};
+use crate::errors::domain::DomainError;
+use crate::usecase::add_new_song::{get_or_fetch_album, load_artist_resolver};
+use crate::usecase::sync_plan::SyncPlan;
+use anyhow::Result;
+use async_trait::async_trait;
+use indexmap::{IndexMap, IndexSet};
+
+/// メタデータの更新の結果
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct MetadataUpdateReport {
+    /// 反映した変更
+    pub changes: Vec<MetadataChange>,
+    /// 取得や保存に失敗した楽曲やアルバム。失敗しても残りの更新は続ける。
+    pub failed: Vec<MetadataUpdateFailure>,
+    /// MSRと比較した楽曲とアルバムの数
+    pub checked: usize,
+}
+
+/// メタデータの更新に失敗した楽曲やアルバム
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct MetadataUpdateFailure {
+    pub target: MetadataTarget,
+    /// 楽曲IDまたはアルバムID
+    pub id: u32,
+    /// エラーの原因。外側のエラーから順に並ぶ。
+    pub errors: Vec<String>,
+}
+
+impl MetadataUpdateFailure {
+    pub fn new(target: MetadataTarget, id: u32, error: &anyhow::Error) -> Self {
+        Self {
+            target,
+            id,
+            errors: error.chain().map(ToString::to_string).collect(),
+        }
+    }
+}
+
+/// MSR側で変更された楽曲やアルバムのメタデータを反映するユースケース
+#[async_trait]
+pub trait UsesUpdateMetadataUseCase {
+    /// 保存されている楽曲とアルバムをMSRの最新の情報とフィールドごとに比較し、
+    /// 変更があれば反映して同じトランザクションで履歴に記録する。
+    /// 一部の楽曲やアルバムが失敗しても残りの更新を続け、失敗したものは結果に記録する。
+    async fn update_metadata(&self) -> Result<MetadataUpdateReport>;
+}
+
+/// 楽曲のタグに書き込むアルバムのフィールド。変更があれば収録曲のタグを書き直す。
+const TAGGED_ALBUM_FIELDS: [&str; 4] = ["name", "artists", "total_tracks", "total_disks"];
+
+/// [`UsesUpdateMetadataUseCase`]に必要な依存
+pub trait UpdateMetadataUseCase:
+    ProvideSongRepository
+    + ProvideMsrRepository
+    + ProvideArtistRepository
+    + ProvideDiscOverrides
+    + Send
+    + Sync
+    + 'static
+{
+}
+
+#[async_trait]
+impl<R: UpdateMetadataUseCase> UsesUpdateMetadataUseCase for R {
+    async fn update_metadata(&self) -> Result<MetadataUpdateReport> {
+        let mut report = MetadataUpdateReport::default();
+        // MSRのクレジットは正式な名前に揃えてから比較する
+        let resolver = load_artist_resolver(self).await?;
+
+        // 楽曲のトラック番号は更新後の収録曲から計算するので、アルバムを先に更新する
+        let mut albums = IndexMap::new();
+        let mut retag_albums = IndexSet::new();
+        for stored in self.provide_song_repository().get_all_album().await? {
+            // 取り下げられたアルバムはMSRから取得できない
+            if stored.withdrawn {
+                albums.insert(stored.id, stored);
+                continue;
+            }
+            report.checked += 1;
+            match update_album(self, &stored, &resolver).await {
+                Ok((album, album_changes)) => {
+                    if album_changes
+                        .iter()
+                        .any(|change| TAGGED_ALBUM_FIELDS.contains(&change.field.as_str()))
+                    {
+                        retag_albums.insert(album.id);
+                    }
+                    report.changes.extend(album_changes);
+                    albums.insert(album.id, album);
+                }
+                Err(e) => {
+                    let id = u32::from(stored.id);
+                    report
+                        .failed
+                        .push(MetadataUpdateFailure::new(MetadataTarget::Album, id, &e));
+                    // 更新できなかったアルバムの収録曲は、保存されている収録曲と比較する
+                    albums.insert(stored.id, stored);
+                }
+            }
+        }
+
+        let msr_songs = self
+            .provide_msr_repository()
+            .fetch_all_songs()
+            .await?
+            .list
+            .into_iter()
+            .map(|summary| msr::SongSummary {
+                artists: resolver.resolve(&summary.artists),
+                ..summary
+            })
+            .collect::<Vec<_>>();
+        let stored_songs = self.provide_song_repository().get_all_song().await?;
+        // 一覧の情報が変わっている楽曲だけ詳細を取得する
+        let changed_songs = SyncPlan::compute(&msr_songs, &stored_songs)
+            .changed_songs
+            .into_iter()
+            .collect::<IndexSet<_>>();
+        let upstream = msr_songs
+            .iter()
+            .map(|summary| summary.id)
+            .collect::<IndexSet<_>>();
+
+        for stored in stored_songs {
+            // MSRから消えた楽曲はここでは扱わない
+            if !upstream.contains(&stored.id) {
+                continue;
+            }
+            report.checked += 1;
+            let changed = changed_songs.contains(&stored.id);
+            match update_song(
+                self,
+                &stored,
+                changed,
+                &resolver,
+                &mut albums,
+                &retag_albums,
+            )
+            .await
+            {
+                Ok(song_changes) => report.changes.extend(song_changes),
+                Err(e) => {
+                    let id = u32::from(stored.id);
+                    report
+                        .failed
+                        .push(MetadataUpdateFailure::new(MetadataTarget::Song, id, &e));
+                }
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// アルバムをMSRの最新の情報と比較し、変更があれば履歴とともに保存する。
+/// 更新後のアルバムと反映した変更を返す。
+async fn update_album<R: UpdateMetadataUseCase>(
+    repositories: &R,
+    stored: &Album,
+    resolver: &ArtistResolver,
+) -> Result<(Album, Vec<MetadataChange>)> {
+    let msr_album = repositories
+        .provide_msr_repository()
+        .fetch_album(stored.id)
+        .await?;
+    let msr_album = msr::Album {
+        artists: resolver.resolve(&msr_album.artists),
+        ..msr_album
+    };
+    let detail = repositories
+        .provide_msr_repository()
+        .fetch_album_detail(stored.id)
+        .await?;
+    let (album, changes) = diff_album(
+        stored,
+        &msr_album,
+        &detail,
+        repositories.provide_disc_overrides(),
+    )?;
+    if !changes.is_empty() {
+        repositories
+            .provide_song_repository()
+            .save_album_with_changes(album.clone(), changes.clone())
+            .await?;
+    }
+    Ok((album, changes))
+}
+
+/// 楽曲をMSRの最新の情報と比較し、変更があれば履歴とともに保存する。反映した変更を返す。
+/// 楽曲に変更がなくても、タグに書き込むアルバムの情報が変わっていれば保存し直してタグを書き直す。
+/// * changed: MSRの一覧の情報が変わっているか。変わっていれば楽曲の詳細を取得する。
+/// * albums: 更新後のアルバム。まだ取得していないアルバムは取得して追加する。
+/// * retag_albums: タグに書き込む情報が変わったアルバム
+async fn update_song<R: UpdateMetadataUseCase>(
+    repositories: &R,
+    stored: &Song,
+    changed: bool,
+    resolver: &ArtistResolver,
+    albums: &mut IndexMap<AlbumId, Album>,
+    retag_albums: &IndexSet<AlbumId>,
+) -> Result<Vec<MetadataChange>> {
+    let msr_song = if changed {
+        let msr_song = repositories
+            .provide_msr_repository()
+            .fetch_song(stored.id)
+            .await?;
+        Some(msr::Song {
+            artists: resolver.resolve(&msr_song.artists),
+            ..msr_song
+        })
+    } else {
+        None
+    };
+    let album_id = match &msr_song {
+        Some(msr_song) => msr_song.belong_album_id,
+        None => stored.belong_album_id,
+    };
+    if !albums.contains_key(&album_id) {
+        let album = get_or_fetch_album(repositories, album_id, resolver).await?;
+        albums.insert(album_id, album);
+    }
+
+    let (song, changes) = diff_song(stored, msr_song.as_ref(), &albums[&album_id])?;
+    if !changes.is_empty() || retag_albums.contains(&album_id) {
+        repositories
+            .provide_song_repository()
+            .save_song_with_changes(song, changes.clone())
+            .await?;
+    }
+    Ok(changes)
+}
+
+/// 保存されているアルバムとMSRのアルバムを比較し、更新後のアルバムと変更点を返す。
+/// カバー画像は保存されているものを引き継ぐ。
+/// アルバム全体のラウドネスは、収録曲が変わった場合は解析し直すため引き継がない。
+/// * overrides: ディスクの分け方の設定。[`split_discs`]に渡す。
+pub fn diff_album(
+    stored: &Album,
+    msr_album: &msr::Album,
+    detail: &msr::AlbumDetail,
+    overrides: &DiscOverrides,
+) -> Result<(Album, Vec<MetadataChange>), DomainError> {
+    let discs = split_discs(stored.id, &detail.song_list, overrides)?;
+    let loudness = if discs == stored.discs {
+        stored.loudness
+    } else {
+        LoudnessAnalysis::NotAnalyzed
+    };
+    let album = Album::try_reconstruct(
+        stored.id,
+        msr_album.name.clone(),
+        discs.iter().map(Vec::len).sum::<usize>() as u8,
+        discs.len() as u8,
+        msr_album.intro.clone(),
+        msr_album.belong.clone(),
+        stored.cover_image.clone(),
+        msr_album.artists.clone(),
+        discs,
+        stored.withdrawn,
+        loudness,
+    )?;
+
+    let mut changes = vec![];
+    let (target, id) = (MetadataTarget::Album, u32::from(stored.id));
+    MetadataChange::record(&mut changes, target, id, "name", &stored.name, &album.name);
+    MetadataChange::record(
+        &mut changes,
+        target,
+        id,
+        "intro",
+        &stored.intro,
+        &album.intro,
+    );
+    MetadataChange::record(
+        &mut changes,
+        target,
+        id,
+        "belong",
+        &stored.belong.to_string(),
+        &album.belong.to_string(),
+    );
+    MetadataChange::record(
+        &mut changes,
+        target,
+        id,
+        "artists",
+        &stored.artists,
+        &album.artists,
+    );
+    MetadataChange::record(
+        &mut changes,
+        target,
+        id,
+        "total_tracks",
+        &stored.total_tracks,
+        &album.total_tracks,
+    );
+    MetadataChange::record(
+        &mut changes,
+        target,
+        id,
+        "total_disks",
+        &stored.total_disks,
+        &album.total_disks,
+    );
+    MetadataChange::record(
+        &mut changes,
+        target,
+        id,
+        "discs",
+        &stored.discs,
+        &album.discs,
+    );
+    Ok((album, changes))
+}
+
+/// 保存されている楽曲とMSRの楽曲を比較し、更新後の楽曲と変更点を返す。
+/// ディスク番号とトラック番号は`album`の収録曲から計算し直す。
+/// * msr_song: MSRの楽曲。`None`の場合は名前などは変わっていないものとして扱う。
+/// * album: 楽曲が所属するアルバム。更新後のものを期待している。
+pub fn diff_song(
+    stored: &Song,
+    msr_song: Option<&msr::Song>,
+    album: &Album,
+) -> Result<(Song, Vec<MetadataChange>), DomainError> {
+    let (name, artists) = match msr_song {
+        Some(msr_song) => (msr_song.name.clone(), msr_song.artists.clone()),
+        None => (stored.name.clone(), stored.artists.clone()),
+    };
+    let song = Song::try_new(
+        stored.id,
+        name,
+        album.id,
+        &album.discs,
+        stored.source.clone(),
+        artists,
+    )?;
+
+    let mut changes = vec![];
+    let (target, id) = (MetadataTarget::Song, u32::from(stored.id));
+    MetadataChange::record(&mut changes, target, id, "name", &stored.name, &song.name);
+    MetadataChange::record(
+        &mut changes,
+        target,
+        id,
+        "belong_album_id",
+        &stored.belong_album_id,
+        &song.belong_album_id,
+    );
+    MetadataChange::record(
+        &mut changes,
+        target,
+        id,
+        "artists",
+        &stored.artists,
+        &song.artists,
+    );
+    MetadataChange::record(
+        &mut changes,
+        target,
+        id,
+        "track_number",
+        &stored.track_number,
+        &song.track_number,
+    );
+    MetadataChange::record(
+        &mut changes,
+        target,
+        id,
+        "disk_number",
+        &stored.disk_number,
+        &song.disk_number,
+    );
+    Ok((song, changes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_album, diff_song, UsesUpdateMetadataUseCase};
+    use crate::domain::disc::{DiscOverrides, ProvideDiscOverrides};
+    use crate::domain::loudness::{Loudness, LoudnessAnalysis};
+    use crate::domain::metadata_history::{MetadataChange, MetadataTarget};
+    use crate::domain::msr;
+    use crate::domain::repository::artist_repository::{
+        MockUsesArtistRepository, ProvideArtistRepository,
+    };
+    use crate::domain::repository::msr_repository::{MockUsesMsrRepository, ProvideMsrRepository};
+    use crate::domain::repository::song_repository::{
+        MockUsesSongRepository, ProvideSongRepository,
+    };
+    use crate::domain::song::{Album, AlbumId, OriginGame, Song, SongId};
+    use crate::errors::infra::InfraError;
+    use mockall::predicate::eq;
+    use url::Url;
+
+    struct Mock {
+        song: MockUsesSongRepository,
+        msr: MockUsesMsrRepository,
+        artist: MockUsesArtistRepository,
+        disc_overrides: DiscOverrides,
+    }
+
+    impl super::UpdateMetadataUseCase for Mock {}
+    impl ProvideSongRepository for Mock {
+        type SongRepository = MockUsesSongRepository;
+        fn provide_song_repository(&self) -> &Self::SongRepository {
+            &self.song
+        }
+    }
+    impl ProvideMsrRepository for Mock {
+        type MsrRepository = MockUsesMsrRepository;
+        fn provide_msr_repository(&self) -> &Self::MsrRepository {
+            &self.msr
+        }
+    }
+    impl ProvideArtistRepository for Mock {
+        type ArtistRepository = MockUsesArtistRepository;
+        fn provide_artist_repository(&self) -> &Self::ArtistRepository {
+            &self.artist
+        }
+    }
+    impl ProvideDiscOverrides for Mock {
+        fn provide_disc_overrides(&self) -> &DiscOverrides {
+            &self.disc_overrides
+        }
+    }
+
+    fn stored_album() -> Album {
+        Album {
+            id: AlbumId::new(1).unwrap(),
+            name: "album".into(),
+            intro: "intro".into(),
+            total_tracks: 2,
+            total_disks: 1,
+            artists: vec!["artist".into()],
+            discs: vec![vec![SongId::new(1).unwrap(), SongId::new(2).unwrap()]],
+            loudness: LoudnessAnalysis::Measured(Loudness {
+                integrated_lufs: -12.0,
+                true_peak: 0.9,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn msr_album(name: &str) -> msr::Album {
+        msr::Album {
+            id: AlbumId::new(1).unwrap(),
+            name: name.into(),
+            intro: "intro".into(),
+            belong: OriginGame::Arknights,
+            cover_url: Url::parse("https://example.com").unwrap(),
+            cover_de_url: Url::parse("https://example.com").unwrap(),
+            artists: vec!["artist".into()],
+        }
+    }
+
+    fn detail(song_ids: &[u32]) -> msr::AlbumDetail {
+        msr::AlbumDetail {
+            id: AlbumId::new(1).unwrap(),
+            name: "album".into(),
+            intro: "intro".into(),
+            belong: OriginGame::Arknights,
+            cover_url: Url::parse("https://example.com").unwrap(),
+            cover_de_url: Url::parse("https://example.com").unwrap(),
+            song_list: song_ids
+                .iter()
+                .map(|&id| msr::SongShort {
+                    id: SongId::new(id).unwrap(),
+                    name: "song".into(),
+                    artists: vec![],
+                })
+                .collect(),
+        }
+    }
+
+    fn stored_song(id: u32, track_number: u8) -> Song {
+        Song {
+            id: SongId::new(id).unwrap(),
+            name: "song".into(),
+            belong_album_id: AlbumId::new(1).unwrap(),
+            track_number,
+            disk_number: 1,
+            artists: vec!["artist".into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_album_unchanged() {
+        let (album, changes) = diff_album(
+            &stored_album(),
+            &msr_album("album"),
+            &detail(&[1, 2]),
+            &DiscOverrides::new(),
+        )
+        .unwrap();
+        assert!(changes.is_empty());
+        assert_eq!(album.loudness, stored_album().loudness);
+    }
+
+    #[test]
+    fn test_diff_album_renamed_and_reordered() {
+        let (album, changes) = diff_album(
+            &stored_album(),
+            &msr_album("renamed"),
+            &detail(&[2, 1]),
+            &DiscOverrides::new(),
+        )
+        .unwrap();
+        assert_eq!(album.name, "renamed");
+        assert_eq!(
+            album.discs,
+            vec![vec![SongId::new(2).unwrap(), SongId::new(1).unwrap()]]
+        );
+        // 収録曲が変わったのでアルバム全体のラウドネスは解析し直す
+        assert_eq!(album.loudness, LoudnessAnalysis::NotAnalyzed);
+        assert_eq!(
+            changes,
+            vec![
+                MetadataChange {
+                    target: MetadataTarget::Album,
+                    id: 1,
+                    field: "name".into(),
+                    old_value: r#""album""#.into(),
+                    new_value: r#""renamed""#.into(),
+                },
+                MetadataChange {
+                    target: MetadataTarget::Album,
+                    id: 1,
+                    field: "discs".into(),
+                    old_value: "[[1,2]]".into(),
+                    new_value: "[[2,1]]".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_song_recomputes_track_number() {
+        let album = Album {
+            discs: vec![vec![SongId::new(2).unwrap(), SongId::new(1).unwrap()]],
+            ..stored_album()
+        };
+        let (song, changes) = diff_song(&stored_song(1, 1), None, &album).unwrap();
+        assert_eq!(song.track_number, 2);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].field, "track_number");
+        assert_eq!(changes[0].old_value, "1");
+        assert_eq!(changes[0].new_value, "2");
+    }
+
+    #[test]
+    fn test_diff_song_credits() {
+        let msr_song = msr::Song {
+            id: SongId::new(2).unwrap(),
+            name: "song".into(),
+            belong_album_id: AlbumId::new(1).unwrap(),
+            source_url: Url::parse("https://example.com/song.flac").unwrap(),
+            lyric_url: None,
+            artists: vec!["artist".into(), "featured".into()],
+        };
+        let (song, changes) =
+            diff_song(&stored_song(2, 2), Some(&msr_song), &stored_album()).unwrap();
+        assert_eq!(
+            song.artists,
+            vec!["artist".to_string(), "featured".to_string()]
+        );
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].target, MetadataTarget::Song);
+        assert_eq!(changes[0].field, "artists");
+        assert_eq!(changes[0].new_value, r#"["artist","featured"]"#);
+    }
+
+    #[tokio::test]
+    async fn test_update_metadata_keeps_going() {
+        let album_id = AlbumId::new(2).unwrap();
+        let mut song_mock = MockUsesSongRepository::new();
+        song_mock.expect_get_all_album().returning(move || {
+            Ok(vec![
+                stored_album(),
+                Album {
+                    id: album_id,
+                    total_tracks: 1,
+                    discs: vec![vec![SongId::new(3).unwrap()]],
+                    ..stored_album()
+                },
+            ])
+        });
+        song_mock.expect_get_all_song().returning(move || {
+            Ok(vec![
+                stored_song(1, 1),
+                Song {
+                    belong_album_id: album_id,
+                    ..stored_song(3, 1)
+                },
+            ])
+        });
+        // アルバム1の取得に失敗しても、アルバム2は履歴とともに保存する
+        song_mock
+            .expect_save_album_with_changes()
+            .withf(move |album, changes| {
+                album.id == album_id && changes.len() == 1 && changes[0].field == "name"
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+        // 楽曲3に変更はないが、アルバム2の名前が変わったのでタグを書き直す
+        song_mock
+            .expect_save_song_with_changes()
+            .withf(|song, changes| song.id == SongId::new(3).unwrap() && changes.is_empty())
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let mut msr_mock = MockUsesMsrRepository::new();
+        msr_mock
+            .expect_fetch_album()
+            .with(eq(AlbumId::new(1).unwrap()))
+            .returning(|id| Err(InfraError::AlbumNotFound { id }.into()));
+        msr_mock
+            .expect_fetch_album()
+            .with(eq(album_id))
+            .returning(move |_| {
+                Ok(msr::Album {
+                    id: album_id,
+                    ..msr_album("renamed")
+                })
+            });
+        msr_mock
+            .expect_fetch_album_detail()
+            .with(eq(album_id))
+            .returning(move |_| {
+                Ok(msr::AlbumDetail {
+                    id: album_id,
+                    ..detail(&[3])
+                })
+            });
+        msr_mock.expect_fetch_all_songs().returning(move || {
+            Ok(msr::SongSummaries {
+                list: vec![
+                    msr::SongSummary {
+                        id: SongId::new(1).unwrap(),
+                        name: "renamed".into(),
+                        belong_album_id: AlbumId::new(1).unwrap(),
+                        artists: vec!["artist".into()],
+                    },
+                    msr::SongSummary {
+                        id: SongId::new(3).unwrap(),
+                        name: "song".into(),
+                        belong_album_id: album_id,
+                        artists: vec!["artist".into()],
+                    },
+                ],
+            })
+        });
+        // 楽曲1の取得に失敗しても、楽曲3は比較する
+        msr_mock
+            .expect_fetch_song()
+            .with(eq(SongId::new(1).unwrap()))
+            .times(1)
+            .returning(|id| Err(InfraError::SongNotFound { id }.into()));
+
+        let mut artist_mock = MockUsesArtistRepository::new();
+        artist_mock
+            .expect_get_all_artists()
+            .returning(|| Ok(vec![]));
+
+        let mock = Mock {
+            song: song_mock,
+            msr: msr_mock,
+            artist: artist_mock,
+            disc_overrides: DiscOverrides::new(),
+        };
+        let report = mock.update_metadata().await.unwrap();
+        assert_eq!(report.checked, 4);
+        assert_eq!(report.changes.len(), 1);
+        assert_eq!(report.changes[0].id, 2);
+        assert_eq!(
+            report
+                .failed
+                .iter()
+                .map(|failure| (failure.target, failure.id))
+                .collect::<Vec<_>>(),
+            vec![(MetadataTarget::Album, 1), (MetadataTarget::Song, 1)]
+        );
+        assert!(report.failed[0].errors[0].contains("Album"));
+    }
+}